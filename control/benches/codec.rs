@@ -0,0 +1,53 @@
+//! Benchmarks for the paths that run per-dump or per-event.  Run with
+//! `cargo bench`; see `benches/README.md` for recording and comparing
+//! baselines.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use control::codec::{decode_dump, encode_param_dt1};
+use control::{LedBuffer, SysexMap, GRID_LED_COUNT};
+
+fn fixture_path(rel: &str) -> String {
+    format!("{}/fixtures/{}", env!("CARGO_MANIFEST_DIR"), rel)
+}
+
+fn dump_decode(c: &mut Criterion) {
+    let map = SysexMap::load(&fixture_path("jupx/map.json")).unwrap();
+    let index = map.resolve();
+    let dump = std::fs::read(fixture_path("jupx/scene-dump.syx")).unwrap();
+
+    c.bench_function("decode scene dump", |b| {
+        b.iter(|| decode_dump(&map, &index, black_box(&dump)))
+    });
+}
+
+fn batch_write(c: &mut Criterion) {
+    let map = SysexMap::load(&fixture_path("jupx/map.json")).unwrap();
+    let index = map.resolve();
+
+    // Write every parameter in the map once, which is what a snapshot recall
+    // of the whole map looks like without any coalescing.
+    c.bench_function("encode write per param", |b| {
+        b.iter(|| {
+            index.params.iter()
+                .map(|p| encode_param_dt1(&map, p, black_box(p.entry.discrete_range_low)))
+                .collect::<Vec<_>>()
+        })
+    });
+}
+
+fn led_packing(c: &mut Criterion) {
+    let mut leds = LedBuffer::new();
+
+    c.bench_function("pack full led grid", |b| {
+        b.iter(|| {
+            for i in 0..GRID_LED_COUNT as u8 {
+                leds.set_led(i, black_box(i), 0x40, 0x7f - i);
+            }
+            black_box(leds.as_bytes());
+        })
+    });
+}
+
+criterion_group!(benches, dump_decode, batch_write, led_packing);
+criterion_main!(benches);
@@ -0,0 +1,5 @@
+fn main() {
+    // The gRPC service and client are generated from the proto.
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/mapatron.proto").expect("can't compile proto/mapatron.proto");
+}
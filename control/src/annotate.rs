@@ -0,0 +1,208 @@
+//! Taking a sysex message apart byte by byte with the map, for anything that
+//! shows messages to people, ex: `mapatron monitor`.  Each run of bytes gets
+//! a `Span` saying what it is, down to the params in a write's data, and
+//! `AnnotatedMessage` prints as a hexdump with those down the side:
+//! ```text
+//! f0 41 10 00 00 00 65 12  header (write)
+//! 01 00 00 16              address 0x00200016
+//! 64                       Temporary Scene/Scene Common/Scene Level = 100
+//! 65                       checksum ok
+//! f7                       end
+//! ```
+
+use serde::Serialize;
+
+use std::fmt;
+
+use crate::codec::decode_value;
+use crate::human::format_value;
+use crate::sysex_map::{ParamIndex, SysexMap};
+use crate::template::{unpack, Template, Token};
+
+/// Which of the map's templates a message matched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    Write,
+    Read,
+    /// Neither, ex: another device's sysex.
+    Unknown,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SpanKind {
+    /// The fixed bytes before the address, ex: the manufacturer and model
+    /// IDs and the command.
+    Header,
+    /// A linear address.
+    Address { address: u32 },
+    /// The size asked for by a read.
+    Size { size: u32 },
+    /// A param's bytes.  Params sharing a byte get a span each.
+    Field { param: String, raw: u32, value: String },
+    /// Data bytes no param is mapped to.
+    Data,
+    Checksum { ok: bool },
+    /// The F7, and any other fixed bytes after the address.
+    End,
+    /// All of a message that matched no template.
+    Unknown,
+}
+
+/// `len` bytes of the message from `start`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub len: usize,
+    #[serde(flatten)]
+    pub kind: SpanKind,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AnnotatedMessage {
+    pub bytes: Vec<u8>,
+    pub kind: MessageKind,
+    /// In order of where they start.
+    pub spans: Vec<Span>,
+}
+
+/// Lowercase hex, a space between bytes.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ")
+}
+
+/// The params in the data of a write to `address`, and `Data` spans for the
+/// bytes between them.
+fn fields(index: &ParamIndex, start: usize, address: u32, data: &[u8]) -> Vec<Span> {
+    let mut spans = vec![];
+    let mut covered = vec![false; data.len()];
+    for idx in index.covered_by(address, data.len() as u32) {
+        let param = &index.params[idx];
+        let offset = (param.address - address) as usize;
+        let size = param.size as usize;
+        let raw = decode_value(param, &data[offset..offset + size]);
+        covered[offset..offset + size].iter_mut().for_each(|c| *c = true);
+        spans.push(Span {
+            start: start + offset,
+            len: size,
+            kind: SpanKind::Field {
+                param: param.name.clone(),
+                raw,
+                value: format_value(&param.entry, raw),
+            },
+        });
+    }
+    let mut offset = 0;
+    while offset < data.len() {
+        let len = covered[offset..].iter().take_while(|&&c| c == covered[offset]).count();
+        if !covered[offset] {
+            spans.push(Span { start: start + offset, len, kind: SpanKind::Data });
+        }
+        offset += len;
+    }
+    spans.sort_by_key(|span| span.start);
+    spans
+}
+
+/// Annotate `msg` as a write or a read to the map's device, or as unknown.
+pub fn annotate_sysex(map: &SysexMap, index: &ParamIndex, msg: &[u8]) -> AnnotatedMessage {
+    let matched = [(MessageKind::Write, Template::write_for(map)), (MessageKind::Read, Template::read_for(map))]
+        .iter()
+        .find_map(|(kind, template)| Some((*kind, template.layout(map, msg)?)));
+    let (kind, layout) = match matched {
+        Some(matched) => matched,
+        None => {
+            return AnnotatedMessage {
+                bytes: msg.to_vec(),
+                kind: MessageKind::Unknown,
+                spans: vec![Span { start: 0, len: msg.len(), kind: SpanKind::Unknown }],
+            };
+        },
+    };
+
+    let mut spans: Vec<Span> = vec![];
+    let (mut address, mut body_start) = (0, None);
+    for (token, range) in layout {
+        let (start, len) = (range.start, range.len());
+        let kind = match token {
+            Token::Byte(_) | Token::DeviceId if body_start.is_none() => SpanKind::Header,
+            Token::Byte(_) | Token::DeviceId => SpanKind::End,
+            Token::Address => {
+                body_start = Some(start);
+                address = unpack(&msg[range]);
+                SpanKind::Address { address }
+            },
+            Token::Size => SpanKind::Size { size: unpack(&msg[range]) },
+            Token::Data => {
+                spans.extend(fields(index, start, address, &msg[range]));
+                continue;
+            },
+            Token::Checksum if len == 0 => continue,
+            Token::Checksum => {
+                let expected = map.checksum.compute(&msg[body_start.unwrap_or(start)..start]);
+                SpanKind::Checksum { ok: expected.map(|e| e == msg[start]).unwrap_or(true) }
+            },
+        };
+        // Runs of fixed bytes are one span.
+        match spans.last_mut() {
+            Some(last) if last.kind == kind && last.start + last.len == start => last.len += len,
+            _ => spans.push(Span { start, len, kind }),
+        }
+    }
+    AnnotatedMessage {
+        bytes: msg.to_vec(),
+        kind,
+        spans,
+    }
+}
+
+impl AnnotatedMessage {
+    /// The (param, raw value) of each param the message carries.
+    pub fn params(&self) -> Vec<(&str, u32)> {
+        self.spans.iter().filter_map(|span| match &span.kind {
+            SpanKind::Field { param, raw, .. } => Some((param.as_str(), *raw)),
+            _ => None,
+        }).collect()
+    }
+}
+
+impl fmt::Display for SpanKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SpanKind::Header => f.write_str("header"),
+            SpanKind::Address { address } => write!(f, "address {:#010x}", address),
+            SpanKind::Size { size } => write!(f, "size {}", size),
+            SpanKind::Field { param, value, .. } => write!(f, "{} = {}", param, value),
+            SpanKind::Data => f.write_str("unmapped"),
+            SpanKind::Checksum { ok: true } => f.write_str("checksum ok"),
+            SpanKind::Checksum { ok: false } => f.write_str("checksum BAD"),
+            SpanKind::End => f.write_str("end"),
+            SpanKind::Unknown => f.write_str("not for this map"),
+        }
+    }
+}
+
+impl fmt::Display for AnnotatedMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = self.spans.iter().map(|span| span.len.min(8) * 3).max().unwrap_or(0);
+        for (i, span) in self.spans.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            let bytes = &self.bytes[span.start..span.start + span.len];
+            // Long runs, ex: a bank's unmapped data, are cut short.
+            let shown = if bytes.len() > 8 { format!("{} ..", hex(&bytes[..7])) } else { hex(bytes) };
+            write!(f, "{:width$}{}", shown, span.kind, width = width)?;
+            if span.kind == SpanKind::Header {
+                let kind = match self.kind {
+                    MessageKind::Write => " (write)",
+                    MessageKind::Read => " (read)",
+                    MessageKind::Unknown => "",
+                };
+                f.write_str(kind)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,143 @@
+//! An append-only log of every param write the daemon sees, one JSON object
+//! a line, for working out who changed a patch or what it was before a
+//! crash, ex:
+//! ```json
+//! {"time_ms":1760608800123,"source":"encoder","param":"Temporary Scene/Scene Common/Scene Level","old":100,"new":104}
+//! ```
+//! Writes are found by comparing the store against what was last logged
+//! after each thing the daemon handles, and put down to that thing.  Read
+//! it with `mapatron audit`.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::controllers::ControllerEvent;
+use crate::param_store::ParamStore;
+
+/// Who made a write.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Pad,
+    Encoder,
+    Fader,
+    Knob,
+    /// Anything else on the controller, ex: the menu.
+    Controller,
+    /// Ramps and settled writes from the bindings, as time passes.
+    Binding,
+    /// D-Bus, gRPC, MQTT or a program change.
+    Remote,
+    /// A snapshot, setlist, history or compare recall.
+    Recall,
+    /// The synth itself, ex: its front panel or a dump's replies.
+    Synth,
+}
+
+impl Source {
+    /// What a controller event's writes are put down to.
+    pub fn of(event: &ControllerEvent) -> Source {
+        match event {
+            ControllerEvent::GridButton(..) | ControllerEvent::PadPressure(..) => Source::Pad,
+            ControllerEvent::Encoder(..) => Source::Encoder,
+            ControllerEvent::Fader(..) => Source::Fader,
+            ControllerEvent::Knob(..) => Source::Knob,
+            _ => Source::Controller,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Milliseconds since the Unix epoch.
+    pub time_ms: u64,
+    pub source: Source,
+    pub param: String,
+    pub old: u32,
+    pub new: u32,
+}
+
+/// The open log, and the values it last saw.
+pub struct AuditLog {
+    file: BufWriter<File>,
+    last: Vec<u32>,
+}
+
+/// Milliseconds since the Unix epoch.
+pub fn now_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+/// `ms` since the Unix epoch as a UTC date and time.
+pub fn utc(ms: u64) -> String {
+    let (days, ms) = (ms / 86_400_000, ms % 86_400_000);
+    // Howard Hinnant's days-to-civil, for days after 1970.
+    let z = days + 719_468;
+    let (era, doe) = (z / 146_097, z % 146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}", year, month, day,
+            ms / 3_600_000, ms / 60_000 % 60, ms / 1000 % 60, ms % 1000)
+}
+
+impl AuditLog {
+    /// Open the log at `path` to append to, starting from what's in `store`.
+    pub fn open(path: &str, store: &ParamStore) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|e| format!("can't open {}: {}", path, e))?;
+        Ok(AuditLog {
+            file: BufWriter::new(file),
+            last: store.snapshot(),
+        })
+    }
+
+    /// Log every param that's changed since the last call as `source`'s
+    /// doing.
+    pub fn note(&mut self, source: Source, store: &ParamStore) {
+        let AuditLog { file, last } = self;
+        let time_ms = now_ms();
+        let mut wrote = false;
+        for (param, last) in last.iter_mut().enumerate() {
+            let new = store.get(param);
+            if new == *last {
+                continue;
+            }
+            let entry = AuditEntry {
+                time_ms,
+                source,
+                param: store.index().params[param].name.clone(),
+                old: *last,
+                new,
+            };
+            *last = new;
+            wrote = true;
+            let written = serde_json::to_writer(&mut *file, &entry).map_err(|e| e.to_string())
+                .and_then(|_| file.write_all(b"\n").map_err(|e| e.to_string()));
+            if let Err(e) = written {
+                warn!("can't write the audit log: {}", e);
+            }
+        }
+        // Flushed as it goes, so a crash loses nothing.
+        if wrote {
+            if let Err(e) = file.flush() {
+                warn!("can't write the audit log: {}", e);
+            }
+        }
+    }
+}
+
+/// Every entry in the log at `path`, oldest first.  Lines that don't parse,
+/// ex: half-written at a crash, are skipped.
+pub fn read(path: &str) -> Result<Vec<AuditEntry>, Box<dyn Error>> {
+    let text = fs::read_to_string(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    Ok(text.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+}
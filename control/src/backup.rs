@@ -0,0 +1,81 @@
+//! A copy of the synth's whole patch, saved after it's read on connect, as a
+//! safety net against overwriting a sound by accident, ex:
+//! `"backup": { "dir": "library/backups", "every": "day" }`
+//! saves `library/backups/jupx-2026-10-16-093012.syx` the first time the
+//! synth's read each day.  Backups are ordinary snapshots, so they load like
+//! any other, and pointing `dir` into the `sync` library keeps them with the
+//! rest.
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::audit::{now_ms, utc};
+use crate::codec::encode_spans;
+use crate::param_store::ParamStore;
+use crate::snapshot::{fingerprint, fingerprint_message};
+use crate::sysex_map::SysexMap;
+
+/// How often to back up.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupEvery {
+    /// The first time the synth's read each day (UTC).
+    #[default]
+    Day,
+    /// Every time it's read, ex: each reconnect.
+    Connect,
+}
+
+/// The `backup` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BackupConfig {
+    #[serde(default = "default_dir")]
+    pub dir: String,
+    #[serde(default)]
+    pub every: BackupEvery,
+}
+
+fn default_dir() -> String {
+    "backups".to_string()
+}
+
+/// The `YYYY-MM-DD` and `HHMMSS` of `ms` since the Unix epoch, for file
+/// names.
+fn stamp(ms: u64) -> (String, String) {
+    let utc = utc(ms);
+    (utc[..10].to_string(), utc[11..19].replace(':', ""))
+}
+
+/// Whether `dir` already has a backup of `device` from the day `today`.
+fn backed_up(dir: &Path, device: &str, today: &str) -> bool {
+    let prefix = format!("{}-{}-", device, today);
+    fs::read_dir(dir)
+        .map(|entries| entries.filter_map(|e| e.ok()).any(|e| {
+            e.file_name().to_str().map(|name| name.starts_with(&prefix) && name.ends_with(".syx"))
+                .unwrap_or(false)
+        }))
+        .unwrap_or(false)
+}
+
+/// Save everything in `store` as a snapshot of `device` in the backup dir,
+/// unless `config` says it's not due.  Returns where it went, if anywhere.
+pub fn back_up(config: &BackupConfig, device: &str, map: &SysexMap, store: &ParamStore)
+               -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let dir = Path::new(&config.dir);
+    let (today, time) = stamp(now_ms());
+    if config.every == BackupEvery::Day && backed_up(dir, device, &today) {
+        return Ok(None);
+    }
+    fs::create_dir_all(dir).map_err(|e| format!("can't make {}: {}", dir.display(), e))?;
+    let spans = map.dump_spans(store.index());
+    let mut messages = vec![fingerprint_message(fingerprint(map, store.index()))];
+    messages.extend(encode_spans(map, store.index(), &spans, |idx| store.get(idx)));
+    let path = dir.join(format!("{}-{}-{}.syx", device, today, time));
+    fs::write(&path, messages.concat()).map_err(|e| format!("can't write {}: {}", path.display(), e))?;
+    info!("backed up {} to {}", device, path.display());
+    Ok(Some(path))
+}
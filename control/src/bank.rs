@@ -0,0 +1,147 @@
+//! Splitting an all-programs bank dump, the way Korg and plenty of others
+//! send their memory, into a dump per program and putting chosen programs
+//! back together as a bank to send, ex, in the map:
+//! ```json
+//! "bank": {
+//!   "bank_header": [66, 48, 0, 1, 81, 76], "program_header": [66, 48, 0, 1, 81, 64],
+//!   "programs": 128, "program_size": 1024, "packing": "korg",
+//!   "name": { "offset": 0, "size": 12 }
+//! }
+//! ```
+//! Offsets and sizes are in the unpacked data.
+
+use serde::{Deserialize, Serialize};
+
+use crate::codec::split_sysex;
+
+/// How 8-bit data is carried in sysex's 7-bit bytes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Packing {
+    /// The data's already 7-bit.
+    #[default]
+    Plain,
+    /// Each 7 bytes go as 8: a byte of their top bits (the first byte's in
+    /// bit 0), then the 7 with their top bits cleared.
+    Korg,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ByteRange {
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// Where the programs are in a device's bank dump.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BankLayout {
+    /// The bytes after F0 that start a bank dump.
+    pub bank_header: Vec<u8>,
+    /// The bytes after F0 that start a single program's dump.
+    pub program_header: Vec<u8>,
+    pub programs: u32,
+    pub program_size: u32,
+    /// Where the first program starts, after any data that isn't a program.
+    #[serde(default)]
+    pub first_offset: u32,
+    #[serde(default)]
+    pub packing: Packing,
+    /// The program's name within a program, as ASCII.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<ByteRange>,
+}
+
+pub fn pack(packing: Packing, data: &[u8]) -> Vec<u8> {
+    match packing {
+        Packing::Plain => data.to_vec(),
+        Packing::Korg => data.chunks(7).flat_map(|chunk| {
+            let top = chunk.iter().enumerate().fold(0, |top, (i, b)| top | (b >> 7) << i);
+            Some(top).into_iter().chain(chunk.iter().map(|b| b & 0x7f))
+        }).collect(),
+    }
+}
+
+pub fn unpack(packing: Packing, bytes: &[u8]) -> Vec<u8> {
+    match packing {
+        Packing::Plain => bytes.to_vec(),
+        Packing::Korg => bytes.chunks(8).flat_map(|chunk| {
+            let top = chunk[0];
+            chunk[1..].iter().enumerate().map(move |(i, b)| b & 0x7f | (top >> i & 1) << 7)
+        }).collect(),
+    }
+}
+
+impl BankLayout {
+    /// The unpacked data of the first message in `bytes` that starts with
+    /// `header`.
+    fn data(&self, bytes: &[u8], header: &[u8], what: &str) -> Result<Vec<u8>, String> {
+        let msg = split_sysex(bytes).into_iter()
+            .find(|msg| msg.len() >= header.len() + 2 && &msg[1..header.len() + 1] == header)
+            .ok_or_else(|| format!("no {} dump", what))?;
+        Ok(unpack(self.packing, &msg[header.len() + 1..msg.len() - 1]))
+    }
+
+    fn message(&self, header: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut msg = vec![0xf0];
+        msg.extend_from_slice(header);
+        msg.extend(pack(self.packing, data));
+        msg.push(0xf7);
+        msg
+    }
+
+    fn bank_size(&self) -> usize {
+        (self.first_offset + self.programs * self.program_size) as usize
+    }
+
+    /// The unpacked data of each program in the bank dump in `bytes`.
+    pub fn split(&self, bytes: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+        let data = self.data(bytes, &self.bank_header, "bank")?;
+        if data.len() < self.bank_size() {
+            return Err(format!("bank is {} bytes, not {}", data.len(), self.bank_size()));
+        }
+        Ok(data[self.first_offset as usize..self.bank_size()].chunks(self.program_size as usize)
+            .map(|program| program.to_vec())
+            .collect())
+    }
+
+    /// The unpacked data of the program dump in `bytes`.
+    pub fn program(&self, bytes: &[u8]) -> Result<Vec<u8>, String> {
+        let data = self.data(bytes, &self.program_header, "program")?;
+        if data.len() < self.program_size as usize {
+            return Err(format!("program is {} bytes, not {}", data.len(), self.program_size));
+        }
+        Ok(data[..self.program_size as usize].to_vec())
+    }
+
+    /// A program dump of `program`.
+    pub fn program_message(&self, program: &[u8]) -> Vec<u8> {
+        self.message(&self.program_header, program)
+    }
+
+    /// A bank dump with `programs` put in from slot `at` on, over the bank
+    /// dump in `base` if given, or over zeros if not.
+    pub fn join(&self, base: Option<&[u8]>, at: u32, programs: &[Vec<u8>]) -> Result<Vec<u8>, String> {
+        if at as usize + programs.len() > self.programs as usize {
+            return Err(format!("the bank only holds {} programs", self.programs));
+        }
+        let mut data = match base {
+            Some(base) => self.data(base, &self.bank_header, "bank")?,
+            None => vec![],
+        };
+        data.resize(data.len().max(self.bank_size()), 0);
+        for (slot, program) in (at..).zip(programs) {
+            let start = (self.first_offset + slot * self.program_size) as usize;
+            let size = program.len().min(self.program_size as usize);
+            data[start..start + size].copy_from_slice(&program[..size]);
+        }
+        Ok(self.message(&self.bank_header, &data))
+    }
+
+    /// The name stored in `program`, if the layout says where it is.
+    pub fn name(&self, program: &[u8]) -> Option<String> {
+        let range = self.name?;
+        let bytes = program.get(range.offset as usize..(range.offset + range.size) as usize)?;
+        let name: String = bytes.iter().map(|&b| if (0x20..0x7f).contains(&b) { b as char } else { ' ' }).collect();
+        Some(name.trim().to_string()).filter(|name| !name.is_empty())
+    }
+}
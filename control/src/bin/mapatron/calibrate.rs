@@ -0,0 +1,61 @@
+use serde_json::json;
+use tokio::time;
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use control::config::Config;
+use control::{attach_fires, Calibrator, GRID_LED_COUNT};
+
+use super::fail;
+
+/// Pads light up as they're pressed, brighter the wider the range seen.
+fn pad_color(min: u8, max: u8) -> (u8, u8, u8) {
+    let spread = max - min;
+    (0x7f - spread.min(0x7f), spread, 0x10)
+}
+
+/// Record the velocity range of every pad pressed on the Fire for `seconds`
+/// and save it as the config's `pad_calibration`.
+pub async fn calibrate(config: Option<&Path>, profile: Option<&str>, seconds: u64, curve: f32) {
+    let mut fire = attach_fires().into_iter().next().unwrap_or_else(|| fail("no Fire connected".to_string()));
+    let mut events = fire.take_events().unwrap_or_else(|| fail("Fire events already taken".to_string()));
+    for i in 0..GRID_LED_COUNT as u8 {
+        fire.set_led(i, 0, 0, 0);
+    }
+    fire.update_leds();
+    let display = fire.display_mut();
+    display.clear();
+    display.draw_text(0, 0, "CALIBRATING", true);
+    display.draw_text(0, 8, "PRESS EACH PAD", true);
+    display.draw_text(0, 16, "SOFT THEN HARD", true);
+    fire.update_display();
+    println!("Press every pad as softly and then as hard as you play it; {}s to go.", seconds);
+
+    let mut calibrator = Calibrator::new();
+    let deadline = Instant::now() + Duration::from_secs(seconds);
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let event = match time::timeout(remaining, events.recv()).await {
+            Ok(Some(event)) => event,
+            Ok(None) => fail("Fire disconnected".to_string()),
+            Err(_) => break,
+        };
+        if let Some(pad) = calibrator.record(&event) {
+            let range = calibrator.range(pad).expect("pad was just recorded");
+            let (r, g, b) = pad_color(range.min, range.max);
+            fire.set_led(pad, r, g, b);
+            fire.update_leds();
+        }
+    }
+
+    let calibration = calibrator.finish(curve);
+    let path = Config::save_setting(config, profile, "pad_calibration", json!(calibration))
+        .unwrap_or_else(|e| fail(format!("can't save calibration: {}", e)));
+    println!("Calibrated {} of {} pads; saved to {}", calibration.pads.len(), GRID_LED_COUNT,
+             path.display());
+    let display = fire.display_mut();
+    display.clear();
+    display.draw_text(0, 0, "CALIBRATED", true);
+    fire.update_display();
+}
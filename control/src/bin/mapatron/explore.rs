@@ -0,0 +1,126 @@
+//! `mapatron explore`: poke at an address range of a synth we don't have a
+//! (complete) map for, and write out a skeleton map of what seems writable.
+//!
+//! Every byte in the range is read, written with its low bit flipped, read
+//! back and then restored.  Anything else the synth sends in between is
+//! reported as an acknowledgment, since some devices echo or announce edits
+//! that change the sound.
+
+use tokio::time;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use control::synth::{Synth, READ_TIMEOUT};
+use control::sysex_map::{linear_address, packed_address, SysexMapTypeEntry, SysexMapValueEntry,
+                         WriteCost, ROOT_TABLE};
+use control::ControllerEvent;
+
+use crate::{attach, fail};
+
+/// How long to listen for the synth reacting to a probe write.
+const ACK_WINDOW: Duration = Duration::from_millis(100);
+/// Largest single read request while fetching the original values.
+const READ_CHUNK: u32 = 0x80;
+/// Name of the table holding everything found.
+const EXPLORED_TABLE: &str = "Explored";
+
+pub fn parse_hex(text: &str) -> Result<u32, String> {
+    let digits: String = text.trim_start_matches("0x").split_whitespace().collect();
+    u32::from_str_radix(&digits, 16).map_err(|e| e.to_string())
+}
+
+/// Count what the synth sends of its own accord within `ACK_WINDOW`.
+async fn acks(synth: &mut Synth) -> usize {
+    let mut count = 0;
+    while let Ok(Some(event)) = time::timeout(ACK_WINDOW, synth.next_event()).await {
+        if let ControllerEvent::Sysex(_) = event {
+            count += 1;
+        }
+    }
+    count
+}
+
+/// `start` and `size` are packed, like the addresses in the synth's manual.
+pub async fn explore(device: &str, start: u32, size: u32, out: Option<&Path>) {
+    let mut synth = attach(device);
+    let (start, size) = (linear_address(start), linear_address(size));
+
+    let mut original: BTreeMap<u32, u8> = BTreeMap::new();
+    let mut chunk_start = start;
+    while chunk_start < start + size {
+        let len = READ_CHUNK.min(start + size - chunk_start);
+        for (address, data) in synth.read_raw(chunk_start, len, READ_TIMEOUT).await {
+            for (i, b) in data.iter().enumerate() {
+                original.insert(address + i as u32, *b);
+            }
+        }
+        chunk_start += len;
+    }
+
+    let mut values = vec![];
+    for address in start..start + size {
+        let packed = packed_address(address);
+        let before = match original.get(&address) {
+            Some(before) => *before,
+            None => {
+                println!("{:08x}: no reply", packed);
+                continue;
+            },
+        };
+
+        synth.write_raw(address, &[before ^ 1]);
+        let acked = acks(&mut synth).await;
+        let after = synth.read_raw(address, 1, READ_TIMEOUT).await
+            .first().and_then(|(_, data)| data.first().copied());
+        synth.write_raw(address, &[before]);
+        acks(&mut synth).await;
+
+        let writable = after == Some(before ^ 1);
+        println!("{:08x}: {:02x} {} {} ack(s)", packed, before,
+                 if writable { "writable" } else { "read-only" }, acked);
+        if writable {
+            let offset = packed_address(address - start);
+            values.push(SysexMapValueEntry {
+                name: format!("Unknown {:08x}", packed),
+                display_name: None,
+                first_offset_start: offset,
+                last_offset_start: offset,
+                bitmask: 0x7f,
+                discrete_range_low: 0,
+                discrete_range_high: 0x7f,
+                human_value_list: None,
+                human_value_base: None,
+                human_value_units: None,
+                human_value_formula: None,
+                human_value_bipolar: None,
+                visible_when: None,
+                depends_on: vec![],
+                aliases: vec![],
+                write_cost: WriteCost::Volatile,
+                identifying: None,
+            });
+        }
+    }
+
+    let out = match out {
+        Some(out) => out,
+        None => return,
+    };
+    let mut map = synth.map().clone();
+    map.dump_regions.clear();
+    map.type_entries = BTreeMap::new();
+    map.type_entries.insert(ROOT_TABLE.to_string(), vec![SysexMapTypeEntry {
+        name: EXPLORED_TABLE.to_string(),
+        first_offset_start: packed_address(start),
+        last_offset_start: packed_address(start),
+        type_name: EXPLORED_TABLE.to_string(),
+        stride: None,
+    }]);
+    map.value_entries = BTreeMap::new();
+    map.value_entries.insert(EXPLORED_TABLE.to_string(), values);
+    let json = serde_json::to_string_pretty(&map).unwrap();
+    fs::write(out, json).unwrap_or_else(|e| fail(format!("can't write {:?}: {}", out, e)));
+}
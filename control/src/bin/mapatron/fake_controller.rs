@@ -0,0 +1,121 @@
+use midir::os::unix::{VirtualInput, VirtualOutput};
+use midir::{Ignore, MidiInput, MidiOutput};
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+use tokio::time;
+
+use std::time::Duration;
+
+use control::{ButtonState, ControllerEvent, FIRE_PORT_PREFIX, GRID_LED_COUNT};
+
+use super::fail;
+
+/// Where the LED message's pad entries start, and how long each is.
+const LED_HEADER: usize = 7;
+const LED_ENTRY: usize = 4;
+
+enum Step {
+    Send(ControllerEvent),
+    Wait(Duration),
+}
+
+/// Parse a script line like `press 3 100`, `release 3`, `turn 0 -2`,
+/// `select 1`, `push`, `let-go` or `wait 500` (milliseconds).
+fn parse(line: &str) -> Result<Option<Step>, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let number = |i: usize| -> Result<i64, String> {
+        let word = words.get(i).ok_or_else(|| format!("'{}' is missing a number", line))?;
+        word.parse().map_err(|_| format!("'{}' isn't a number", word))
+    };
+    let pad = |i: usize| -> Result<u8, String> {
+        let pad = number(i)?;
+        if !(0..GRID_LED_COUNT as i64).contains(&pad) {
+            return Err(format!("no pad {}", pad));
+        }
+        Ok(pad as u8)
+    };
+    let event = match words.first().copied() {
+        None => return Ok(None),
+        Some(comment) if comment.starts_with('#') => return Ok(None),
+        Some("press") => {
+            let pad = pad(1)?;
+            let velocity = if words.len() > 2 { number(2)?.clamp(1, 0x7f) as u8 } else { 0x7f };
+            ControllerEvent::GridButton(pad, pad / 16, pad % 16, ButtonState::Down, velocity)
+        },
+        Some("release") => {
+            let pad = pad(1)?;
+            ControllerEvent::GridButton(pad, pad / 16, pad % 16, ButtonState::Up, 0)
+        },
+        Some("turn") => {
+            ControllerEvent::Encoder(number(1)?.clamp(0, 3) as u8, number(2)?.clamp(-63, 63) as i8)
+        },
+        Some("select") => ControllerEvent::Select(number(1)?.clamp(-63, 63) as i8),
+        Some("push") => ControllerEvent::SelectButton(ButtonState::Down),
+        Some("let-go") => ControllerEvent::SelectButton(ButtonState::Up),
+        Some("wait") => return Ok(Some(Step::Wait(Duration::from_millis(number(1)?.max(0) as u64)))),
+        Some(other) => return Err(format!("unknown command '{}'", other)),
+    };
+    Ok(Some(Step::Send(event)))
+}
+
+/// Describe what the mapper sent: pads whose colors changed since
+/// `leds`, or that the display was redrawn.
+fn describe(msg: &[u8], leds: &mut [(u8, u8, u8); GRID_LED_COUNT]) -> Option<String> {
+    match msg {
+        [0xf0, 0x47, 0x7f, 0x43, 0x65, ..] => {
+            let mut changed = vec![];
+            let entries = msg.get(LED_HEADER..msg.len() - 1).unwrap_or_default();
+            for entry in entries.chunks_exact(LED_ENTRY) {
+                let (pad, color) = (entry[0] as usize, (entry[1], entry[2], entry[3]));
+                if pad < leds.len() && leds[pad] != color {
+                    leds[pad] = color;
+                    changed.push(format!("{}={:02x}{:02x}{:02x}", pad, color.0, color.1, color.2));
+                }
+            }
+            if changed.is_empty() {
+                None
+            } else {
+                Some(format!("leds {}", changed.join(" ")))
+            }
+        },
+        [0xf0, 0x47, 0x7f, 0x43, 0x0e, ..] => Some("display".to_string()),
+        _ => Some(msg.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(" ")),
+    }
+}
+
+/// Pretend to be a Fire on virtual ports named like one, so `mapatron run`
+/// attaches to it.  Script lines from stdin are sent as the Fire's events,
+/// and what the mapper sends back is printed until interrupted.
+pub async fn fake_controller(name: &str) {
+    let port_name = format!("{} {}", FIRE_PORT_PREFIX, name);
+    let mut midi_in = MidiInput::new("mapatron-fake-fire").unwrap_or_else(|e| fail(e.to_string()));
+    midi_in.ignore(Ignore::None);
+    let midi_out = MidiOutput::new("mapatron-fake-fire").unwrap_or_else(|e| fail(e.to_string()));
+
+    let mut leds = [(0, 0, 0); GRID_LED_COUNT];
+    let _input = midi_in.create_virtual(&port_name, move |_stamp, msg, _| {
+        if let Some(line) = describe(msg, &mut leds) {
+            println!("< {}", line);
+        }
+    }, ()).unwrap_or_else(|e| fail(format!("can't create the input: {}", e)));
+    let mut output = midi_out.create_virtual(&port_name)
+        .unwrap_or_else(|e| fail(format!("can't create the output: {}", e)));
+    eprintln!("pretending to be '{}'", port_name);
+
+    let mut lines = BufReader::new(io::stdin()).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        match parse(&line) {
+            Ok(Some(Step::Send(event))) => {
+                // Everything `parse` makes is something a Fire sends.
+                let msg = event.to_midi().expect("a Fire event");
+                if let Err(e) = output.send(&msg) {
+                    fail(format!("can't send: {}", e));
+                }
+            },
+            Ok(Some(Step::Wait(delay))) => time::delay_for(delay).await,
+            Ok(None) => {},
+            Err(e) => eprintln!("{}", e),
+        }
+    }
+    // Keep showing what the mapper sends once the script's done.
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,574 @@
+//! The `mapatron` command line: runs the mapper and provides one-shot tools for
+//! poking at synths and maps from shell scripts and test rigs, ex:
+//! ```shell
+//! mapatron set jupx "Temporary Scene/Scene Common/Scene Level" 100
+//! mapatron get --json jupx "Temporary Scene/Scene Common/Scene Level"
+//! mapatron completions bash > /etc/bash_completion.d/mapatron
+//! ```
+//! Values are given and printed in human terms (ex: "ON", "-12"), as described
+//! by the map.  Query commands take `--json` for machine-readable output.
+//! `--simulate` talks to an in-memory synth built from the map instead of the
+//! hardware.
+
+mod calibrate;
+mod explore;
+#[cfg(unix)]
+mod fake_controller;
+mod pad_test;
+mod query;
+mod repl;
+mod test_map;
+mod wiggle;
+
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use serde_json::json;
+
+use std::io;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use control::bus::EventBus;
+use control::config::Config;
+use control::daemon;
+use control::plugin::PluginRegistry;
+use control::human::{format_value, parse_value};
+use control::synth::{Synth, VerifyMode};
+use control::SysexMap;
+
+#[derive(Parser)]
+#[clap(name = "mapatron", about = "Map controller surfaces onto synth parameters via sysex")]
+struct Cli {
+    /// Use a synth simulated from the map instead of the hardware.
+    #[clap(long, global = true)]
+    simulate: bool,
+    /// Take ports another mapatron has open instead of giving up on them.
+    #[clap(long, global = true)]
+    take_over: bool,
+    #[clap(subcommand)]
+    command: Command,
+}
+
+/// Set from `--simulate` before any command runs.
+static SIMULATE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Subcommand)]
+enum Command {
+    /// Map a connected Fire onto a synth using a bindings file.
+    Run {
+        device: String,
+        /// Overrides the bindings file named in the config.
+        #[clap(long)]
+        bindings: Option<String>,
+        /// Config file, instead of $MAPATRON_CONFIG or mapatron.json.
+        #[clap(long)]
+        config: Option<PathBuf>,
+        /// Config profile to layer over the base settings, ex: "stage".
+        #[clap(long)]
+        profile: Option<String>,
+    },
+    /// Print everything the synth sends, decoded with its map.
+    Monitor {
+        device: String,
+        #[clap(long)]
+        json: bool,
+    },
+    /// Check a map (and optionally a bindings file) for problems.
+    Validate {
+        device: String,
+        #[clap(long)]
+        bindings: Option<String>,
+        #[clap(long)]
+        json: bool,
+    },
+    /// Generate a starting bindings file from a map.
+    Layout {
+        device: String,
+        /// Only lay out params matching this glob, ex: "temporary_scene.scene_part_1.*".
+        #[clap(long = "match")]
+        pattern: Option<String>,
+        /// Controller description, instead of the Fire's.
+        #[clap(long)]
+        surface: Option<String>,
+        /// Where to write the bindings, instead of stdout.
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+    /// Draw the bindings as an SVG overlay for the Fire's top panel.
+    Overlay {
+        device: String,
+        /// Overrides the bindings file named in the config.
+        #[clap(long)]
+        bindings: Option<String>,
+        /// Config file, instead of $MAPATRON_CONFIG or mapatron.json.
+        #[clap(long)]
+        config: Option<PathBuf>,
+        #[clap(long)]
+        profile: Option<String>,
+        /// Where to write the SVG, instead of stdout.
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+    /// Run the map's tests against the synth: writes read back, reads and
+    /// dumps checked against what the map's author saw.
+    TestMap {
+        device: String,
+        #[clap(long)]
+        json: bool,
+    },
+    /// Write a parameter.
+    Set {
+        device: String,
+        param: String,
+        value: String,
+        /// Read the value back afterwards and fail if it didn't stick.
+        #[clap(long)]
+        verify: bool,
+        /// With --verify, how many times to rewrite before giving up.
+        #[clap(long, default_value = "0")]
+        retries: u32,
+    },
+    /// Read a parameter from the synth.
+    Get {
+        device: String,
+        param: String,
+        #[clap(long)]
+        json: bool,
+    },
+    /// Read every mapped parameter from the synth.
+    Dump {
+        device: String,
+        #[clap(long)]
+        json: bool,
+        /// Also save the patch as a .syx file.
+        #[clap(long)]
+        syx: Option<PathBuf>,
+        /// Encrypt the .syx file with the library key.
+        #[clap(long)]
+        encrypt: bool,
+        /// Only list params matching this glob, ex: "temporary_scene.scene_part_*.*".
+        #[clap(long = "match")]
+        pattern: Option<String>,
+    },
+    /// Compare two .syx files parameter by parameter.
+    Diff {
+        device: String,
+        a: PathBuf,
+        b: PathBuf,
+        #[clap(long)]
+        json: bool,
+    },
+    /// Copy a .syx file with the params the map marks identifying (ex:
+    /// patch names) scrubbed, for sharing.
+    Scrub {
+        device: String,
+        file: PathBuf,
+        /// Where to write the copy, instead of next to the file with
+        /// "-scrubbed" added to its name.
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+    /// Split a bank dump into a .syx file per program, as laid out by the
+    /// map's `bank`.
+    SplitBank {
+        device: String,
+        file: PathBuf,
+        /// Directory for the programs, instead of next to the bank.
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+    /// Put programs split from a bank together into a bank dump to send.
+    JoinBank {
+        device: String,
+        /// Where to write the bank.
+        out: PathBuf,
+        #[clap(required = true)]
+        programs: Vec<PathBuf>,
+        /// A bank dump to take the other slots from, instead of zeros.
+        #[clap(long)]
+        base: Option<PathBuf>,
+        /// The slot for the first program, counting from 0.
+        #[clap(long, default_value = "0")]
+        at: u32,
+    },
+    /// Show the param writes in the audit log, oldest first.
+    Audit {
+        /// Config file, instead of $MAPATRON_CONFIG or mapatron.json.
+        #[clap(long)]
+        config: Option<PathBuf>,
+        #[clap(long)]
+        profile: Option<String>,
+        /// The log to read, instead of the config's `audit_log`.
+        #[clap(long)]
+        log: Option<String>,
+        /// Only params whose names contain this.
+        #[clap(long)]
+        param: Option<String>,
+        /// Only writes from this source, ex: "encoder" or "remote".
+        #[clap(long)]
+        source: Option<String>,
+        /// Print each param's last value instead, as JSON.
+        #[clap(long)]
+        state: bool,
+        #[clap(long)]
+        json: bool,
+    },
+    /// Probe an address range for writable bytes and write a skeleton map.
+    /// Addresses are hex, packed like in the synth's manual.
+    Explore {
+        device: String,
+        #[clap(value_parser = explore::parse_hex)]
+        start: u32,
+        #[clap(value_parser = explore::parse_hex)]
+        size: u32,
+        /// Where to write the skeleton map.
+        #[clap(long)]
+        out: Option<PathBuf>,
+    },
+    /// Map params by turning them on the synth: each change it sends is
+    /// turned into a value entry and added to the map.
+    Wiggle {
+        device: String,
+    },
+    /// Work out the param that differs between two dumps taken before and
+    /// after changing it, and print its value entry.
+    Infer {
+        device: String,
+        before: PathBuf,
+        after: PathBuf,
+        /// Add the entry to the map under this name.
+        #[clap(long)]
+        name: Option<String>,
+    },
+    /// Record each Fire pad's velocity range and save it to the config, or
+    /// to one of its profiles.
+    Calibrate {
+        /// Config file, instead of $MAPATRON_CONFIG or mapatron.json.
+        #[clap(long)]
+        config: Option<PathBuf>,
+        #[clap(long)]
+        profile: Option<String>,
+        /// How long to record for.
+        #[clap(long, default_value = "30")]
+        seconds: u64,
+        /// Exponent for the velocity curve; above 1 makes high velocities
+        /// harder to reach.
+        #[clap(long, default_value = "1.0")]
+        curve: f32,
+    },
+    /// Light up every connected Fire and each pad as it's pressed, for
+    /// checking pads and connections.
+    PadTest,
+    /// Pretend to be a Fire on virtual MIDI ports, sending the events in a
+    /// script read from stdin, ex: "press 3", "turn 0 -2", "wait 500".
+    #[cfg(unix)]
+    FakeController {
+        /// Added to the port name after the Fire's.
+        #[clap(long, default_value = "(fake)")]
+        name: String,
+    },
+    /// Interactive get/set session with a connected synth.
+    Repl {
+        device: String,
+    },
+    /// Commit changes to the snapshot library and sync it with its remote.
+    #[cfg(feature = "sync")]
+    Sync {
+        /// Config file, instead of $MAPATRON_CONFIG or mapatron.json.
+        #[clap(long)]
+        config: Option<PathBuf>,
+        #[clap(long)]
+        profile: Option<String>,
+    },
+    /// Print a new random library key for encrypting snapshots.
+    #[cfg(feature = "encrypt")]
+    Keygen {
+        /// Keep it in the OS keyring instead of printing it.
+        #[clap(long)]
+        keyring: bool,
+    },
+    /// Encrypt .syx files in place with the library key.
+    #[cfg(feature = "encrypt")]
+    Encrypt {
+        files: Vec<PathBuf>,
+    },
+    /// Let browser editors use the MIDI ports over a WebSocket.
+    #[cfg(feature = "webmidi")]
+    Bridge {
+        /// Config file, instead of $MAPATRON_CONFIG or mapatron.json.
+        #[clap(long)]
+        config: Option<PathBuf>,
+        #[clap(long)]
+        profile: Option<String>,
+        /// Address to listen on, instead of the config's.
+        #[clap(long)]
+        listen: Option<String>,
+    },
+    /// List and install maps from the community index.
+    #[cfg(feature = "maps")]
+    Maps {
+        /// Config file, instead of $MAPATRON_CONFIG or mapatron.json.
+        #[clap(long)]
+        config: Option<PathBuf>,
+        #[clap(long)]
+        profile: Option<String>,
+        /// Index URL or path, instead of the config's.
+        #[clap(long)]
+        index: Option<String>,
+        #[clap(subcommand)]
+        command: MapsCommand,
+    },
+    /// Print a shell completion script.
+    Completions {
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[cfg(feature = "maps")]
+#[derive(Subcommand)]
+enum MapsCommand {
+    /// List the maps in the index.
+    List,
+    /// Install a map, ex: "roland/jupiter-x", into the map directory.
+    Install {
+        name: String,
+    },
+}
+
+pub fn fail(msg: String) -> ! {
+    eprintln!("mapatron: {}", msg);
+    process::exit(1);
+}
+
+pub fn load_map(device: &str) -> SysexMap {
+    SysexMap::load_device(device)
+        .unwrap_or_else(|e| fail(format!("can't load map for {}: {}", device, e)))
+}
+
+pub fn attach(device: &str) -> Synth {
+    if SIMULATE.load(Ordering::Relaxed) {
+        return Synth::simulate(load_map(device), EventBus::new());
+    }
+    Synth::attach(load_map(device), EventBus::new())
+        .unwrap_or_else(|| fail(format!("no {} connected", device)))
+}
+
+pub fn param_index(synth: &Synth, param: &str) -> usize {
+    synth.store().index().index_of(param)
+        .unwrap_or_else(|| fail(format!("no param '{}'", param)))
+}
+
+async fn get(device: &str, param: &str, json: bool) {
+    let mut synth = attach(device);
+    let idx = param_index(&synth, param);
+    let value = synth.read_param_at(idx).await
+        .unwrap_or_else(|e| fail(format!("{} reading '{}' from {}", e, param, device)));
+
+    let human = format_value(&synth.store().index().params[idx].entry, value);
+    if json {
+        println!("{}", json!({ "param": param, "raw": value, "value": human }));
+    } else {
+        println!("{}", human);
+    }
+}
+
+async fn set(device: &str, param: &str, value: &str, verify: VerifyMode) {
+    let mut synth = attach(device);
+    let idx = param_index(&synth, param);
+    let entry = synth.store().index().params[idx].entry.clone();
+    let raw = parse_value(&entry, value)
+        .unwrap_or_else(|| fail(format!("'{}' isn't a valid value for '{}'", value, param)));
+    synth.set_verify(verify);
+    if let Some(mismatch) = synth.write_verified(&[(idx, raw)]).await.first() {
+        let read = mismatch.read.map(|read| format_value(&entry, read));
+        fail(format!("'{}' read back as {} after writing {}", param,
+                     read.unwrap_or_else(|| "nothing".to_string()), format_value(&entry, raw)));
+    }
+    println!("{}", format_value(&entry, raw));
+}
+
+#[cfg(feature = "sync")]
+fn sync(config: Option<&std::path::Path>, profile: Option<&str>) {
+    use control::sync::Library;
+
+    let config = Config::load(config, profile).unwrap_or_else(|e| fail(format!("can't load config: {}", e)));
+    let sync = config.sync.as_ref().unwrap_or_else(|| fail("no sync section in the config".to_string()));
+    let report = Library::open(sync).and_then(|library| library.sync())
+        .unwrap_or_else(|e| fail(format!("sync failed: {}", e)));
+    match report.committed {
+        Some(oid) => println!("committed {}", oid),
+        None => println!("nothing to commit"),
+    }
+    if report.pulled {
+        println!("pulled changes");
+    }
+    if report.pushed {
+        println!("pushed to {}", sync.remote.as_deref().unwrap_or_default());
+    }
+}
+
+#[cfg(feature = "encrypt")]
+fn encrypt_files(files: &[PathBuf]) {
+    use control::crypt::{encrypt, is_encrypted, load_key};
+
+    let key = load_key().unwrap_or_else(|e| fail(e));
+    for path in files {
+        let bytes = std::fs::read(path).unwrap_or_else(|e| fail(format!("can't read {:?}: {}", path, e)));
+        if is_encrypted(&bytes) {
+            println!("{}: already encrypted", path.display());
+            continue;
+        }
+        std::fs::write(path, encrypt(&bytes, &key))
+            .unwrap_or_else(|e| fail(format!("can't write {:?}: {}", path, e)));
+        println!("{}: encrypted", path.display());
+    }
+}
+
+#[cfg(feature = "maps")]
+fn maps(config: Option<&std::path::Path>, profile: Option<&str>, index: Option<String>, command: MapsCommand) {
+    use control::community::{fetch_index, install, MapIndexConfig};
+    use control::sysex_map::map_path_for;
+
+    let config = Config::load(config, profile).unwrap_or_else(|e| fail(format!("can't load config: {}", e)));
+    let index_config = match (index, config.map_index) {
+        (Some(url), _) => MapIndexConfig { url, public_key: None },
+        (None, Some(index_config)) => index_config,
+        (None, None) => fail("no map_index in the config; give one with --index".to_string()),
+    };
+    let index = fetch_index(&index_config).unwrap_or_else(|e| fail(format!("can't get the index: {}", e)));
+    match command {
+        MapsCommand::List => {
+            for entry in &index.maps {
+                println!("{}  ({})", entry.name, entry.device);
+            }
+        },
+        MapsCommand::Install { name } => {
+            let device = index.maps.iter().find(|m| m.name == name).map(|m| m.device.as_str()).unwrap_or("");
+            let dir = map_path_for(device).parent().map(PathBuf::from).unwrap_or_default();
+            let written = install(&index_config, &index, &name, &dir)
+                .unwrap_or_else(|e| fail(format!("can't install {}: {}", name, e)));
+            for path in written {
+                println!("{}", path.display());
+            }
+        },
+    }
+}
+
+/// The plugins compiled in.  None are yet; register yours behind a cargo
+/// feature, ex:
+/// ```ignore
+/// let mut registry = PluginRegistry::new();
+/// #[cfg(feature = "sparkles")]
+/// registry.register(Box::new(sparkles::Sparkles::new()));
+/// registry
+/// ```
+fn plugins() -> PluginRegistry {
+    PluginRegistry::new()
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = Cli::parse();
+    SIMULATE.store(cli.simulate, Ordering::Relaxed);
+    control::set_take_over(cli.take_over);
+    match cli.command {
+        Command::Run { device, bindings, config, profile } => {
+            let mut config = Config::load(config.as_deref(), profile.as_deref())
+                .unwrap_or_else(|e| fail(format!("can't load config: {}", e)));
+            if bindings.is_some() {
+                config.bindings = bindings;
+            }
+            config.simulate |= cli.simulate;
+            if let Err(e) = daemon::run(&device, &config, plugins()).await {
+                fail(e.to_string());
+            }
+        },
+        Command::Monitor { device, json } => query::monitor(&device, json).await,
+        Command::Validate { device, bindings, json } => {
+            query::validate(&device, bindings.as_deref(), json)
+        },
+        Command::Layout { device, pattern, surface, out } => {
+            query::layout(&device, pattern.as_deref(), surface.as_deref(), out.as_deref())
+        },
+        Command::Overlay { device, bindings, config, profile, out } => {
+            let mut config = Config::load(config.as_deref(), profile.as_deref())
+                .unwrap_or_else(|e| fail(format!("can't load config: {}", e)));
+            if bindings.is_some() {
+                config.bindings = bindings;
+            }
+            query::overlay(&device, &config, out.as_deref())
+        },
+        Command::TestMap { device, json } => test_map::test_map(&device, json).await,
+        Command::Set { device, param, value, verify, retries } => {
+            let verify = match (verify, retries) {
+                (false, _) => VerifyMode::Off,
+                (true, 0) => VerifyMode::Log,
+                (true, retries) => VerifyMode::Retry(retries),
+            };
+            set(&device, &param, &value, verify).await
+        },
+        Command::Get { device, param, json } => get(&device, &param, json).await,
+        Command::Dump { device, json, syx, encrypt, pattern } => {
+            query::dump(&device, json, syx.as_deref(), encrypt, pattern.as_deref()).await
+        },
+        Command::Diff { device, a, b, json } => query::diff(&device, &a, &b, json),
+        Command::Scrub { device, file, out } => query::scrub(&device, &file, out.as_deref()),
+        Command::SplitBank { device, file, out } => query::split_bank(&device, &file, out.as_deref()),
+        Command::JoinBank { device, out, programs, base, at } => {
+            query::join_bank(&device, &out, &programs, base.as_deref(), at)
+        },
+        Command::Audit { config, profile, log, param, source, state, json } => {
+            let config = Config::load(config.as_deref(), profile.as_deref())
+                .unwrap_or_else(|e| fail(format!("can't load config: {}", e)));
+            let log = log.or(config.audit_log)
+                .unwrap_or_else(|| fail("no audit log configured".to_string()));
+            query::audit(&log, param.as_deref(), source.as_deref(), state, json)
+        },
+        Command::Explore { device, start, size, out } => {
+            explore::explore(&device, start, size, out.as_deref()).await
+        },
+        Command::Wiggle { device } => wiggle::wiggle(&device).await,
+        Command::Infer { device, before, after, name } => {
+            wiggle::from_dumps(&device, &before, &after, name.as_deref())
+        },
+        Command::Calibrate { config, profile, seconds, curve } => {
+            calibrate::calibrate(config.as_deref(), profile.as_deref(), seconds, curve).await
+        },
+        Command::PadTest => pad_test::pad_test().await,
+        Command::Repl { device } => repl::run(&device).await,
+        #[cfg(unix)]
+        Command::FakeController { name } => fake_controller::fake_controller(&name).await,
+        #[cfg(feature = "encrypt")]
+        Command::Keygen { keyring: false } => println!("{}", control::crypt::generate_key()),
+        #[cfg(feature = "encrypt")]
+        Command::Keygen { keyring: true } => {
+            control::crypt::store_key(&control::crypt::generate_key()).unwrap_or_else(|e| fail(e));
+            println!("Stored a new library key in the keyring.");
+        },
+        #[cfg(feature = "encrypt")]
+        Command::Encrypt { files } => encrypt_files(&files),
+        #[cfg(feature = "sync")]
+        Command::Sync { config, profile } => sync(config.as_deref(), profile.as_deref()),
+        #[cfg(feature = "webmidi")]
+        Command::Bridge { config, profile, listen } => {
+            let config = Config::load(config.as_deref(), profile.as_deref())
+                .unwrap_or_else(|e| fail(format!("can't load config: {}", e)));
+            let mut bridge = config.bridge.unwrap_or_default();
+            if let Some(listen) = listen {
+                bridge.listen = listen;
+            }
+            if let Err(e) = control::webmidi::serve(&bridge).await {
+                fail(format!("bridge failed: {}", e));
+            }
+        },
+        #[cfg(feature = "maps")]
+        Command::Maps { config, profile, index, command } => {
+            maps(config.as_deref(), profile.as_deref(), index, command)
+        },
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "mapatron", &mut io::stdout());
+        },
+    }
+}
@@ -0,0 +1,62 @@
+use tokio::stream::{StreamExt, StreamMap};
+use tokio::time;
+
+use std::time::Duration;
+
+use control::status::Splash;
+use control::{attach_fires, ButtonState, ControllerCaps, ControllerEvent, SysexController, GRID_LED_COUNT};
+
+use super::fail;
+
+/// Lit red while its Fire's gone quiet.
+const SILENCE_PAD: u8 = 63;
+
+fn show(fire: &mut SysexController, event: ControllerEvent) {
+    match event {
+        ControllerEvent::GridButton(pad, _, _, ButtonState::Down, _) => fire.set_led(pad, 0x7f, 0x7f, 0x7f),
+        ControllerEvent::GridButton(pad, _, _, ButtonState::Up, _) => fire.set_led(pad, 0, 0, 0),
+        ControllerEvent::DeviceSilent => fire.set_led(SILENCE_PAD, 0x7f, 0, 0),
+        ControllerEvent::DeviceActive => fire.set_led(SILENCE_PAD, 0, 0, 0),
+        // Put back what was lit before it went.
+        ControllerEvent::Recovered => (),
+        _ => return,
+    }
+    fire.update_leds();
+}
+
+/// Light up every connected Fire with the color cube, then each pad white
+/// while it's held and the bottom right pad red while the Fire's stopped
+/// sending Active Sensing, for checking pads and cables.
+pub async fn pad_test() {
+    let mut fires = attach_fires();
+    if fires.is_empty() {
+        fail("no Fire connected".to_string());
+    }
+    let mut events = StreamMap::new();
+    for (i, fire) in fires.iter_mut().enumerate() {
+        for pad in 0..GRID_LED_COUNT as u8 {
+            let (r, g, b) = Splash::ColorCube.color(&ControllerCaps::FIRE, pad, Duration::default());
+            fire.set_led(pad, r, g, b);
+        }
+        fire.update_leds();
+        if let Some(rx) = fire.take_events() {
+            events.insert(i, rx);
+        }
+    }
+    println!("Press pads on {} Fire(s); Ctrl-C to stop.", fires.len());
+
+    let mut watchdog = time::interval(Duration::from_secs(1));
+    loop {
+        tokio::select! {
+            event = events.next() => match event {
+                Some((i, event)) => show(&mut fires[i], event),
+                None => fail("every Fire disconnected".to_string()),
+            },
+            _ = watchdog.tick() => {
+                for fire in fires.iter_mut() {
+                    fire.poll_watchdog();
+                }
+            },
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use serde_json::{json, Map, Value};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+
+use control::annotate::{annotate_sysex, hex};
+use control::audit::{self, utc};
+use control::bank::BankLayout;
+use control::bindings::{BindingEngine, BindingsFile};
+use control::bus::EventBus;
+use control::config::Config;
+use control::codec::{decode_dump, encode_spans};
+use control::human::format_value;
+use control::favorites::Favorites;
+use control::layout::{generate, Surface};
+use control::overlay;
+use control::param_store::ParamStore;
+use control::snapshot::{fingerprint, fingerprint_message, read_plain_snapshot};
+use control::{ControllerCaps, ControllerEvent};
+
+use crate::{attach, fail, load_map};
+
+pub async fn monitor(device: &str, json: bool) {
+    let mut synth = attach(device);
+    while let Some(event) = synth.next_event().await {
+        let msg = match event {
+            ControllerEvent::Sysex(msg) => msg,
+            _ => continue,
+        };
+        let annotated = annotate_sysex(synth.map(), synth.store().index(), &msg);
+        if json {
+            println!("{}", json!({ "sysex": hex(&msg), "kind": annotated.kind, "spans": annotated.spans }));
+        } else {
+            println!("{}\n", annotated);
+        }
+    }
+}
+
+pub fn validate(device: &str, bindings: Option<&str>, json: bool) {
+    let map = load_map(device);
+    let mut problems = map.validate();
+    // Deprecated names still work, so they don't fail validation.
+    let mut warnings = vec![];
+    if let Some(path) = bindings {
+        let store = Arc::new(ParamStore::new(map.resolve(), EventBus::new()));
+        let result = BindingsFile::load(path).and_then(|file| {
+            for entry in &file.bindings {
+                for name in std::iter::once(&entry.param).chain(&entry.param_y) {
+                    if let Some(renamed) = store.index().renamed(name) {
+                        warnings.push(format!("{}: '{}' is deprecated, use '{}'", path, name, renamed));
+                    }
+                }
+            }
+            BindingEngine::new(&map, &file, store).map(|_| ())
+        });
+        if let Err(e) = result {
+            problems.push(format!("{}: {}", path, e));
+        }
+    }
+
+    if json {
+        println!("{}", json!({ "ok": problems.is_empty(), "problems": problems, "warnings": warnings }));
+    } else {
+        for warning in &warnings {
+            println!("warning: {}", warning);
+        }
+        for problem in &problems {
+            println!("{}", problem);
+        }
+    }
+    if !problems.is_empty() {
+        process::exit(1);
+    }
+}
+
+pub fn layout(device: &str, pattern: Option<&str>, surface: Option<&str>, out: Option<&Path>) {
+    let map = load_map(device);
+    let index = map.resolve();
+    let surface = match surface {
+        Some(path) => Surface::load(path).unwrap_or_else(|e| fail(format!("can't load {}: {}", path, e))),
+        None => Surface::default(),
+    };
+    let params: Vec<usize> = match pattern {
+        Some(pattern) => index.params_matching(pattern),
+        None => (0..index.params.len()).collect(),
+    };
+    if params.is_empty() {
+        fail("no params to lay out".to_string());
+    }
+
+    let (file, left_out) = generate(&index, &params, &surface);
+    let json = serde_json::to_string_pretty(&file).expect("bindings serialize") + "\n";
+    match out {
+        Some(path) => fs::write(path, json).unwrap_or_else(|e| fail(format!("can't write {:?}: {}", path, e))),
+        None => print!("{}", json),
+    }
+    if !left_out.is_empty() {
+        eprintln!("{} params didn't fit:", left_out.len());
+        for name in &left_out {
+            eprintln!("  {}", name);
+        }
+    }
+}
+
+pub fn overlay(device: &str, config: &Config, out: Option<&Path>) {
+    let map = config.load_map(device).unwrap_or_else(|e| fail(format!("can't load map for {}: {}", device, e)));
+    let index = map.resolve();
+    let path = config.bindings.as_deref().unwrap_or_else(|| fail("no bindings file configured".to_string()));
+    let mut bindings = BindingsFile::load(path).unwrap_or_else(|e| fail(format!("can't load {}: {}", path, e)));
+    config.apply_overrides(&mut bindings);
+    let favorites = Favorites::load(&config.favorites_dir, device).top(&index, ControllerCaps::FIRE.encoders as usize);
+
+    let (svg, left_out) = overlay::render(&bindings, &index, &favorites).unwrap_or_else(|e| fail(e.to_string()));
+    match out {
+        Some(path) => fs::write(path, svg).unwrap_or_else(|e| fail(format!("can't write {:?}: {}", path, e))),
+        None => print!("{}", svg),
+    }
+    if !left_out.is_empty() {
+        eprintln!("{} bindings aren't on the Fire:", left_out.len());
+        for name in &left_out {
+            eprintln!("  {}", name);
+        }
+    }
+}
+
+pub fn scrub(device: &str, file: &Path, out: Option<&Path>) {
+    let map = load_map(device);
+    let index = map.resolve();
+    let bytes = read_plain_snapshot(&file.to_string_lossy()).unwrap_or_else(|e| fail(e));
+    let (scrubbed, count) = control::scrub::scrub(&map, &index, &bytes);
+    let out = out.map(PathBuf::from).unwrap_or_else(|| {
+        let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+        file.with_file_name(format!("{}-scrubbed.syx", stem))
+    });
+    fs::write(&out, scrubbed).unwrap_or_else(|e| fail(format!("can't write {:?}: {}", out, e)));
+    println!("{}: scrubbed {} params", out.display(), count);
+}
+
+fn bank_layout(device: &str) -> BankLayout {
+    load_map(device).bank.unwrap_or_else(|| fail(format!("the {} map has no bank layout", device)))
+}
+
+pub fn split_bank(device: &str, file: &Path, out: Option<&Path>) {
+    let layout = bank_layout(device);
+    let bytes = read_plain_snapshot(&file.to_string_lossy()).unwrap_or_else(|e| fail(e));
+    let programs = layout.split(&bytes).unwrap_or_else(|e| fail(format!("{:?}: {}", file, e)));
+    let dir = out.map(PathBuf::from).unwrap_or_else(|| file.with_file_name(""));
+    fs::create_dir_all(&dir).unwrap_or_else(|e| fail(format!("can't make {:?}: {}", dir, e)));
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    for (slot, program) in programs.iter().enumerate() {
+        let name = match layout.name(program) {
+            Some(name) => {
+                let name = name.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+                format!("{}-{:03}-{}.syx", stem, slot, name)
+            },
+            None => format!("{}-{:03}.syx", stem, slot),
+        };
+        let path = dir.join(name);
+        fs::write(&path, layout.program_message(program))
+            .unwrap_or_else(|e| fail(format!("can't write {:?}: {}", path, e)));
+        println!("{}", path.display());
+    }
+}
+
+pub fn join_bank(device: &str, out: &Path, programs: &[PathBuf], base: Option<&Path>, at: u32) {
+    let layout = bank_layout(device);
+    let programs: Vec<Vec<u8>> = programs.iter().map(|path| {
+        let bytes = read_plain_snapshot(&path.to_string_lossy()).unwrap_or_else(|e| fail(e));
+        layout.program(&bytes).unwrap_or_else(|e| fail(format!("{:?}: {}", path, e)))
+    }).collect();
+    let base = base.map(|path| read_plain_snapshot(&path.to_string_lossy()).unwrap_or_else(|e| fail(e)));
+    let bank = layout.join(base.as_deref(), at, &programs).unwrap_or_else(|e| fail(e));
+    fs::write(out, bank).unwrap_or_else(|e| fail(format!("can't write {:?}: {}", out, e)));
+    println!("{}: {} programs from slot {}", out.display(), programs.len(), at);
+}
+
+pub fn audit(log: &str, param: Option<&str>, source: Option<&str>, state: bool, json: bool) {
+    let entries: Vec<audit::AuditEntry> = audit::read(log).unwrap_or_else(|e| fail(e.to_string())).into_iter()
+        .filter(|e| param.map(|p| e.param.contains(p)).unwrap_or(true))
+        .filter(|e| source.map(|s| json!(e.source) == json!(s)).unwrap_or(true))
+        .collect();
+    if state {
+        let last: BTreeMap<&str, u32> = entries.iter().map(|e| (e.param.as_str(), e.new)).collect();
+        println!("{}", serde_json::to_string_pretty(&last).unwrap());
+        return;
+    }
+    for entry in &entries {
+        if json {
+            println!("{}", json!(entry));
+        } else {
+            println!("{}  {:<10} {} {} -> {}", utc(entry.time_ms), json!(entry.source).as_str().unwrap_or(""),
+                     entry.param, entry.old, entry.new);
+        }
+    }
+}
+
+#[cfg(feature = "encrypt")]
+fn seal(bytes: Vec<u8>) -> Vec<u8> {
+    let key = control::crypt::load_key().unwrap_or_else(|e| fail(e));
+    control::crypt::encrypt(&bytes, &key)
+}
+
+#[cfg(not(feature = "encrypt"))]
+fn seal(_bytes: Vec<u8>) -> Vec<u8> {
+    fail("--encrypt needs mapatron built with the \"encrypt\" feature".to_string())
+}
+
+pub async fn dump(device: &str, json: bool, syx: Option<&Path>, encrypt: bool, pattern: Option<&str>) {
+    let mut synth = attach(device);
+    synth.read_patch().await;
+
+    let store = synth.store();
+    if let Some(path) = syx {
+        let spans = synth.map().dump_spans(store.index());
+        let mut messages = vec![fingerprint_message(fingerprint(synth.map(), store.index()))];
+        messages.extend(encode_spans(synth.map(), store.index(), &spans, |idx| store.get(idx)));
+        let mut bytes = messages.concat();
+        if encrypt {
+            bytes = seal(bytes);
+        }
+        fs::write(path, bytes)
+            .unwrap_or_else(|e| fail(format!("can't write {:?}: {}", path, e)));
+    }
+    let listed: Vec<usize> = match pattern {
+        Some(pattern) => store.index().params_matching(pattern),
+        None => (0..store.index().params.len()).collect(),
+    };
+    let mut out = Map::new();
+    for idx in listed {
+        let p = &store.index().params[idx];
+        let raw = store.get(idx);
+        let human = format_value(&store.display_entry(idx), raw);
+        let visible = store.is_visible(idx);
+        if json {
+            out.insert(p.name.clone(), json!({ "raw": raw, "value": human, "visible": visible }));
+        } else if visible {
+            println!("{} = {}", p.name, human);
+        }
+    }
+    if json {
+        println!("{}", Value::Object(out));
+    }
+}
+
+pub fn diff(device: &str, a: &Path, b: &Path, json: bool) {
+    let map = load_map(device);
+    let index = map.resolve();
+    let read = |path: &Path| read_plain_snapshot(&path.to_string_lossy()).unwrap_or_else(|e| fail(e));
+    let a_values = decode_dump(&map, &index, &read(a));
+    let b_values = decode_dump(&map, &index, &read(b));
+
+    let names: BTreeSet<&String> = a_values.keys().chain(b_values.keys()).collect();
+    let mut diffs = vec![];
+    for name in names {
+        let (a_raw, b_raw) = (a_values.get(name), b_values.get(name));
+        if a_raw == b_raw {
+            continue;
+        }
+        let entry = &index.get(name).unwrap().entry;
+        let human = |raw: Option<&u32>| raw.map(|raw| format_value(entry, *raw));
+        if json {
+            diffs.push(json!({ "param": name, "a": human(a_raw), "b": human(b_raw) }));
+        } else {
+            println!("{}: {} -> {}", name,
+                     human(a_raw).unwrap_or_else(|| "-".to_string()),
+                     human(b_raw).unwrap_or_else(|| "-".to_string()));
+        }
+    }
+    if json {
+        println!("{}", Value::Array(diffs));
+    }
+}
@@ -0,0 +1,65 @@
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+
+use control::human::{format_value, parse_value};
+
+use crate::attach;
+
+const HELP: &str = "commands:
+  get <param>
+  set <param> <value>
+  list [prefix]
+  quit";
+
+/// Param names have spaces in them, so a `set` takes the last word as the
+/// value and everything before it as the name.
+pub async fn run(device: &str) {
+    let mut synth = attach(device);
+    let mut lines = BufReader::new(io::stdin()).lines();
+    println!("{}", HELP);
+
+    while let Ok(Some(line)) = lines.next_line().await {
+        let line = line.trim();
+        let (command, rest) = match line.find(' ') {
+            Some(i) => (&line[..i], line[i + 1..].trim()),
+            None => (line, ""),
+        };
+        match command {
+            "" => (),
+            "get" => {
+                let idx = match synth.store().index().index_of(rest) {
+                    Some(idx) => idx,
+                    None => { println!("no param '{}'", rest); continue; },
+                };
+                match synth.read_param_at(idx).await {
+                    Ok(raw) => println!("{}", format_value(&synth.store().index().params[idx].entry, raw)),
+                    Err(e) => println!("{}", e),
+                }
+            },
+            "set" => {
+                let (name, value) = match rest.rfind(' ') {
+                    Some(i) => (rest[..i].trim(), &rest[i + 1..]),
+                    None => { println!("{}", HELP); continue; },
+                };
+                let idx = match synth.store().index().index_of(name) {
+                    Some(idx) => idx,
+                    None => { println!("no param '{}'", name); continue; },
+                };
+                let entry = synth.store().index().params[idx].entry.clone();
+                match parse_value(&entry, value) {
+                    Some(raw) => {
+                        synth.write(idx, raw);
+                        println!("{}", format_value(&entry, raw));
+                    },
+                    None => println!("'{}' isn't a valid value", value),
+                }
+            },
+            "list" => {
+                for p in synth.store().index().params.iter().filter(|p| p.name.starts_with(rest)) {
+                    println!("{}", p.name);
+                }
+            },
+            "quit" | "exit" => break,
+            _ => println!("{}", HELP),
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use serde_json::json;
+
+use control::synth::{Synth, READ_TIMEOUT};
+use control::sysex_map::{linear_address, MapTest};
+
+use crate::{attach, fail};
+
+fn param_index(synth: &Synth, param: &str) -> Result<usize, String> {
+    synth.store().index().index_of(param).ok_or_else(|| format!("no param '{}'", param))
+}
+
+/// Run one test, returning what went wrong if it failed.
+async fn run(synth: &mut Synth, test: &MapTest) -> Result<(), String> {
+    match test {
+        MapTest::Write { param, value, expect } => {
+            let idx = param_index(synth, param)?;
+            let old = synth.read_param_at(idx).await.map_err(|e| format!("reading before: {}", e))?;
+            synth.write(idx, *value);
+            let read = synth.read_param_at(idx).await.map_err(|e| format!("reading back: {}", e));
+            synth.write(idx, old);
+            let (read, expect) = (read?, expect.unwrap_or(*value));
+            if read != expect {
+                return Err(format!("read back {}, expected {}", read, expect));
+            }
+        },
+        MapTest::Read { param, expect } => {
+            let idx = param_index(synth, param)?;
+            let read = synth.read_param_at(idx).await.map_err(|e| e.to_string())?;
+            if read != *expect {
+                return Err(format!("read {}, expected {}", read, expect));
+            }
+        },
+        MapTest::Dump { region, expect_bytes } => {
+            let spans = match region {
+                Some(name) => {
+                    let region = synth.map().dump_regions.iter().find(|r| &r.name == name)
+                        .ok_or_else(|| format!("no dump region '{}'", name))?;
+                    vec![(linear_address(region.address), linear_address(region.size))]
+                },
+                None => synth.map().dump_spans(synth.store().index()),
+            };
+            let mut received = 0;
+            for (address, size) in spans {
+                received += synth.read_raw(address, size, READ_TIMEOUT).await.iter()
+                    .map(|(_, data)| data.len() as u32)
+                    .sum::<u32>();
+            }
+            if received != *expect_bytes {
+                return Err(format!("got {} bytes, expected {}", received, expect_bytes));
+            }
+        },
+    }
+    Ok(())
+}
+
+fn describe(test: &MapTest) -> String {
+    match test {
+        MapTest::Write { param, value, .. } => format!("write {} = {}", param, value),
+        MapTest::Read { param, .. } => format!("read {}", param),
+        MapTest::Dump { region: Some(region), .. } => format!("dump {}", region),
+        MapTest::Dump { region: None, .. } => "dump patch".to_string(),
+    }
+}
+
+/// Run the map's `tests` against the synth, one line per test, and fail if
+/// any of them did.
+pub async fn test_map(device: &str, json: bool) {
+    let mut synth = attach(device);
+    let tests = synth.map().tests.clone();
+    if tests.is_empty() {
+        fail(format!("the {} map has no tests", device));
+    }
+    let mut failed = 0;
+    for test in &tests {
+        let result = run(&mut synth, test).await;
+        if json {
+            println!("{}", json!({ "test": describe(test), "error": result.as_ref().err() }));
+        } else {
+            match &result {
+                Ok(()) => println!("ok    {}", describe(test)),
+                Err(e) => println!("FAIL  {}: {}", describe(test), e),
+            }
+        }
+        failed += result.is_err() as usize;
+    }
+    if failed > 0 {
+        fail(format!("{} of {} tests failed", failed, tests.len()));
+    }
+}
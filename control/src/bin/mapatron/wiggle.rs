@@ -0,0 +1,139 @@
+//! `mapatron wiggle`: map params by moving them.  Turn a control on the synth
+//! through its range and the DT1s it sends are captured until it goes quiet;
+//! whatever run of bytes changed becomes a new value entry, named at the
+//! prompt and saved straight into the device's map.
+//!
+//! `mapatron infer` does the same from two dumps taken before and after
+//! changing one param, for devices that don't send edits as they happen.
+
+use serde_json::json;
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+use tokio::time;
+
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use control::codec::{parse_dt1, split_sysex};
+use control::infer::{append, infer, locate, overlapping, Capture};
+use control::snapshot::read_plain_snapshot;
+use control::sysex_map::{map_path_for, packed_address, SysexMap, NAME_SEPARATOR};
+use control::ControllerEvent;
+
+use crate::{attach, fail, load_map};
+
+/// How long the synth has to stay quiet for a capture to be over.
+const QUIET: Duration = Duration::from_millis(1500);
+
+fn save_map(map: &SysexMap, path: &Path) {
+    let json = serde_json::to_string_pretty(map).unwrap() + "\n";
+    fs::write(path, json).unwrap_or_else(|e| fail(format!("can't write {:?}: {}", path, e)));
+}
+
+pub async fn wiggle(device: &str) {
+    let mut synth = attach(device);
+    let mut map = synth.map().clone();
+    let path = map_path_for(device);
+    let mut lines = BufReader::new(io::stdin()).lines();
+
+    loop {
+        // Whatever came in while we were waiting at the prompt isn't part of
+        // the next capture.
+        if !synth.poll_events() {
+            fail(format!("{} disconnected", device));
+        }
+        println!("Turn one control on the synth through its whole range, then leave it alone.");
+        let mut capture = Capture::new();
+        loop {
+            match time::timeout(QUIET, synth.next_event()).await {
+                Ok(Some(ControllerEvent::Sysex(msg))) => {
+                    if let Some(data_set) = parse_dt1(&map, &msg) {
+                        capture.record(data_set.address, data_set.data);
+                        capture.mark();
+                    }
+                },
+                Ok(Some(_)) => (),
+                Ok(None) => fail(format!("{} disconnected", device)),
+                Err(_) if capture.states() > 0 => break,
+                Err(_) => (),
+            }
+        }
+
+        let inferred = match infer(&capture) {
+            Ok(inferred) => inferred,
+            Err(e) => {
+                println!("{}; try again.", e);
+                continue;
+            },
+        };
+        println!("{:08x}: {} byte(s), bitmask {:02x}, {} to {} over {} messages",
+                 packed_address(inferred.address), inferred.size, inferred.bitmask, inferred.low,
+                 inferred.high, capture.states());
+        let taken = overlapping(&map.resolve(), &inferred);
+        if !taken.is_empty() {
+            println!("Already mapped as {}.", taken.join(", "));
+            continue;
+        }
+
+        let (table, base) = locate(&map, inferred.address);
+        print!("Name for it in {} (blank to skip, \"quit\" to stop): ", table);
+        std::io::stdout().flush().ok();
+        let name = match lines.next_line().await {
+            Ok(Some(line)) => line.trim().to_string(),
+            _ => break,
+        };
+        match name.as_str() {
+            "" => continue,
+            "quit" => break,
+            _ if name.contains(NAME_SEPARATOR) => {
+                println!("Names can't contain \"{}\".", NAME_SEPARATOR);
+                continue;
+            },
+            _ => (),
+        }
+
+        append(&mut map, &table, inferred.entry(&name, base));
+        save_map(&map, &path);
+        println!("Added {} to {}.", name, path.display());
+    }
+}
+
+/// Infer the param that differs between the dumps `before` and `after` and
+/// print its entry, adding it to the map as `name` if given.  The range
+/// can't be told from two values, so it's as wide as the bytes allow.
+pub fn from_dumps(device: &str, before: &Path, after: &Path, name: Option<&str>) {
+    let mut map = load_map(device);
+    let mut capture = Capture::new();
+    for path in &[before, after] {
+        let bytes = read_plain_snapshot(&path.to_string_lossy()).unwrap_or_else(|e| fail(e));
+        for msg in split_sysex(&bytes) {
+            if let Some(data_set) = parse_dt1(&map, msg) {
+                capture.record(data_set.address, data_set.data);
+            }
+        }
+        capture.mark();
+    }
+
+    let mut inferred = infer(&capture).unwrap_or_else(|e| fail(e));
+    inferred.widen();
+    let taken = overlapping(&map.resolve(), &inferred);
+    if !taken.is_empty() {
+        fail(format!("{:08x} is already mapped as {}", packed_address(inferred.address),
+                     taken.join(", ")));
+    }
+    let (table, base) = locate(&map, inferred.address);
+    let entry = inferred.entry(name.unwrap_or("Unknown"), base);
+    let out = json!({ "table": table, "entry": entry });
+    println!("{}", serde_json::to_string_pretty(&out).unwrap());
+
+    if let Some(name) = name {
+        if name.contains(NAME_SEPARATOR) {
+            fail(format!("names can't contain \"{}\"", NAME_SEPARATOR));
+        }
+        let path = map_path_for(device);
+        append(&mut map, &table, entry);
+        save_map(&map, &path);
+        eprintln!("Added {} to {}.", name, path.display());
+    }
+}
@@ -0,0 +1,792 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::codec::Dt1Buffer;
+use crate::command::CommandTemplate;
+use crate::controllers::{ButtonState, ControllerCaps, ControllerEvent, RingMode};
+use crate::favorites::FAVORITES_PAGE;
+use crate::motion::MotionBinding;
+use crate::param_store::ParamStore;
+use crate::router::{ZoneAdjust, ZoneState, Zones};
+use crate::rules::Rule;
+use crate::scheduler::Scheduler;
+use crate::sysex_map::{ParamIndex, SysexMap};
+
+/// Radio pad colors, lit and unlit.
+const RADIO_ON: (u8, u8, u8) = (0x7f, 0x40, 0x00);
+const RADIO_OFF: (u8, u8, u8) = (0x08, 0x04, 0x00);
+/// XY pad colors, for the pad at the current position and the rest.
+const XY_ON: (u8, u8, u8) = (0x00, 0x7f, 0x40);
+const XY_OFF: (u8, u8, u8) = (0x00, 0x08, 0x04);
+/// How long an encoder, fader or knob has to be left alone for the gesture
+/// on it to be over.
+pub const GESTURE_END: Duration = Duration::from_millis(300);
+
+/// A physical control on the controller.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Control {
+    /// Grid pad by index, `row * 16 + col`.
+    Pad(u8),
+    Encoder(u8),
+    Fader(u8),
+    /// An absolute knob, as opposed to an endless encoder.
+    Knob(u8),
+    /// A grid row of radio pads, one per value of an enum param starting
+    /// from the left, ex: `{ "control": { "radio_row": 3 }, "param": "..." }`.
+    RadioRow(u8),
+    /// A square of pads with `pad` at the top left, as an XY controller for
+    /// `param` (left to right) and `param_y` (bottom to top).
+    Xy {
+        pad: u8,
+        #[serde(default = "default_xy_size")]
+        size: u8,
+    },
+}
+
+fn default_xy_size() -> u8 {
+    4
+}
+
+/// What a pad does when pressed, ex: `"action": "momentary"` or
+/// `"action": { "step": { "step": -1, "wrap": true } }`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PadAction {
+    /// Write `value`.
+    #[default]
+    Set,
+    /// Flip between `value` and `off` (the bottom of the binding's range, after
+    /// `min`, by default).
+    Toggle {
+        #[serde(default)]
+        off: Option<u32>,
+    },
+    /// Write `value` while held and put back the old value on release.
+    Momentary,
+    /// Add `step` (negative to decrement), wrapping around the range or
+    /// stopping at the ends.
+    Step {
+        step: i32,
+        #[serde(default)]
+        wrap: bool,
+    },
+    /// Write `value`, as one of a group of pads bound to the same param.
+    Radio,
+}
+
+/// How an absolute control, ex: a fader, takes over a param whose value
+/// doesn't match where the control is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Takeover {
+    /// Write wherever the control is as soon as it moves.
+    Jump,
+    /// Leave the param alone until the control meets or passes its value.
+    #[default]
+    Pickup,
+}
+
+/// A param on another synth that follows the binding's param, ex:
+/// `{ "device": "jdxi", "param": "Program/Common/Cutoff" }`.  Values are
+/// scaled between the two params' ranges.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BroadcastTarget {
+    /// Map name of the other synth, as for `SysexMap::load_device`.
+    pub device: String,
+    /// The param on the other synth, if its name differs from the binding's.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub param: Option<String>,
+}
+
+/// A single entry in a bindings file, ex:
+/// `{ "control": { "pad": 3 }, "param": "Temporary Scene/Scene Common/Scene Level", "value": 100 }`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BindingEntry {
+    pub control: Control,
+    /// Parameter name as resolved from the map.
+    pub param: String,
+    /// For pads, the raw value written when the pad is pressed.  Ignored for
+    /// encoders, which adjust the current value.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<u32>,
+    /// For pads, what pressing does with `value`.
+    #[serde(default)]
+    pub action: PadAction,
+    /// For XY pads, the param on the vertical axis.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub param_y: Option<String>,
+    /// Ramp to new values over this many milliseconds instead of jumping,
+    /// for params that zipper.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub slew_ms: Option<u32>,
+    /// Restrict the control to part of the param's raw range.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<u32>,
+    /// Turn encoders, faders and knobs the other way.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub invert: bool,
+    /// For faders and knobs, what happens when they don't match the param.
+    #[serde(default)]
+    pub takeover: Takeover,
+    /// For encoders with LED rings, how the ring shows the value.
+    #[serde(default)]
+    pub ring: RingMode,
+    /// Also write the param's value to these synths, for layered
+    /// performances.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub broadcast: Vec<BroadcastTarget>,
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// A pad or encoder that adjusts a keyboard zone in the router, ex:
+/// `{ "control": { "encoder": 3 }, "zone": "lower", "adjust": "transpose", "step": 12 }`.
+/// Encoders move by `step` per detent, pads by `step` per press.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ZoneBinding {
+    pub control: Control,
+    pub zone: String,
+    pub adjust: ZoneAdjust,
+    #[serde(default = "default_zone_step")]
+    pub step: i8,
+}
+
+fn default_zone_step() -> i8 {
+    1
+}
+
+/// A pad that sends a raw message, for device commands that aren't params,
+/// ex: `{ "control": { "pad": 15 }, "message": "F0 41 {device_id} ... F7", "value": 1 }`.
+/// See `command` for the placeholders.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommandBinding {
+    pub control: Control,
+    pub message: CommandTemplate,
+    /// What `{value:N}` is filled in with.
+    #[serde(default)]
+    pub value: u32,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BindingsFile {
+    pub bindings: Vec<BindingEntry>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub zones: Vec<ZoneBinding>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<CommandBinding>,
+    /// See `rules`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+    /// See `motion`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub motions: Vec<MotionBinding>,
+}
+
+impl BindingsFile {
+    pub fn load(path: &str) -> Result<BindingsFile, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let bindings = serde_json::from_reader(BufReader::new(file))?;
+        Ok(bindings)
+    }
+}
+
+enum Action {
+    SetValue(u32),
+    Radio(u32),
+    Toggle { on: u32, off: u32 },
+    /// `restore` is the value from before the pad went down.
+    Momentary { value: u32, restore: Option<u32> },
+    Step { step: i64, wrap: bool },
+    Adjust,
+    /// `last` is the value the control last stood for.
+    Absolute { takeover: Takeover, last: Option<u32> },
+}
+
+/// Replace `radio_row` entries with the radio pad bindings they stand for.
+pub(crate) fn expand_radio_rows(file: &BindingsFile, index: &ParamIndex, caps: &ControllerCaps)
+                                -> Result<Vec<BindingEntry>, Box<dyn Error>> {
+    let mut entries = vec![];
+    for entry in &file.bindings {
+        let row = match entry.control {
+            Control::RadioRow(row) => row,
+            _ => {
+                entries.push(entry.clone());
+                continue;
+            },
+        };
+        let param = index.get(&entry.param)
+            .ok_or_else(|| format!("binding for unknown param '{}'", entry.param))?;
+        let low = entry.min.unwrap_or(0).max(param.entry.discrete_range_low);
+        let high = entry.max.unwrap_or(u32::MAX).min(param.entry.discrete_range_high);
+        if row >= caps.rows || high < low || high - low >= caps.columns as u32 {
+            return Err(format!("'{}' doesn't fit on radio row {}", entry.param, row).into());
+        }
+        for value in low..=high {
+            entries.push(BindingEntry {
+                control: Control::Pad(caps.pad_at(row, (value - low) as u8)),
+                value: Some(value),
+                action: PadAction::Radio,
+                ..entry.clone()
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// A binding with everything we need at event time looked up in advance.
+struct ResolvedBinding {
+    /// Index into the store's `ParamIndex`.
+    param: usize,
+    action: Action,
+    low: u32,
+    high: u32,
+    invert: bool,
+    slew: Option<Duration>,
+    ring: RingMode,
+    msg: Dt1Buffer,
+}
+
+/// One axis of an XY pad.
+struct XyAxis {
+    param: usize,
+    low: u32,
+    high: u32,
+    msg: Dt1Buffer,
+}
+
+impl XyAxis {
+    fn new(map: &SysexMap, index: &ParamIndex, name: &str) -> Result<XyAxis, Box<dyn Error>> {
+        let param = index.index_of(name)
+            .ok_or_else(|| format!("binding for unknown param '{}'", name))?;
+        let p = &index.params[param];
+        Ok(XyAxis {
+            param,
+            low: p.entry.discrete_range_low,
+            high: p.entry.discrete_range_high,
+            msg: Dt1Buffer::new(map, p),
+        })
+    }
+
+    /// The value for pad `pos` of `size` along the axis.
+    fn value_at(&self, pos: u8, size: u8) -> u32 {
+        self.low + ((self.high - self.low) as u64 * pos as u64 / (size - 1) as u64) as u32
+    }
+
+    /// The pad along the axis closest to `value`.
+    fn pos_of(&self, value: u32, size: u8) -> u8 {
+        let span = (self.high - self.low).max(1) as f64;
+        ((value.saturating_sub(self.low) as f64 / span) * (size - 1) as f64).round() as u8
+    }
+}
+
+struct XyPad {
+    row: u8,
+    col: u8,
+    size: u8,
+    x: XyAxis,
+    y: XyAxis,
+}
+
+impl XyPad {
+    /// The (column, row) within the square of grid pad `idx`, if it's in it.
+    fn locate(&self, idx: u8, caps: &ControllerCaps) -> Option<(u8, u8)> {
+        let (row, col) = caps.row_col(idx);
+        if row >= self.row && row < self.row + self.size &&
+           col >= self.col && col < self.col + self.size {
+            Some((col - self.col, row - self.row))
+        } else {
+            None
+        }
+    }
+}
+
+/// Something the engine noticed, handed back from `tick`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BindingEvent {
+    /// `control` stopped moving `param`, ex: so writes to a persistent
+    /// param, undo steps or verification happen once per gesture.
+    GestureEnd { control: Control, param: usize },
+}
+
+/// An encoder, fader or knob that's moving a param.
+struct Gesture {
+    control: Control,
+    param: usize,
+    last: Instant,
+}
+
+struct ZoneControl {
+    control: Control,
+    adjust: ZoneAdjust,
+    step: i32,
+    zone: Arc<ZoneState>,
+}
+
+/// Turns controller events into sysex writes.  All of the name lookups and
+/// message construction happen once in `new`; `handle` just indexes into
+/// tables and rewrites preformed messages in place, so the per-event path
+/// doesn't allocate.
+pub struct BindingEngine {
+    store: Arc<ParamStore>,
+    caps: ControllerCaps,
+    pads: Vec<Option<ResolvedBinding>>,
+    encoders: Vec<Option<ResolvedBinding>>,
+    faders: Vec<Option<ResolvedBinding>>,
+    knobs: Vec<Option<ResolvedBinding>>,
+    /// What the encoders do on the favorites page.
+    favorites: Vec<Option<ResolvedBinding>>,
+    page: usize,
+    xy_pads: Vec<XyPad>,
+    zone_controls: Vec<ZoneControl>,
+    /// The rendered message of each pad bound to a command.
+    commands: Vec<Option<Vec<u8>>>,
+    scheduler: Scheduler,
+    gestures: Vec<Gesture>,
+    /// Messages for the writes that come out of the scheduler: slewed
+    /// bindings' ramp steps and persistent params' settled values.
+    scheduled_msgs: HashMap<usize, Dt1Buffer>,
+}
+
+impl BindingEngine {
+    /// An engine for a Fire.  `store` must have been created from `map`.
+    pub fn new(map: &SysexMap, file: &BindingsFile, store: Arc<ParamStore>)
+               -> Result<BindingEngine, Box<dyn Error>> {
+        Self::with_caps(map, file, store, ControllerCaps::FIRE)
+    }
+
+    /// An engine for a controller with `caps`.  Bindings to controls it
+    /// doesn't have are errors.
+    pub fn with_caps(map: &SysexMap, file: &BindingsFile, store: Arc<ParamStore>, caps: ControllerCaps)
+                     -> Result<BindingEngine, Box<dyn Error>> {
+        let index = store.index();
+        let mut pads: Vec<Option<ResolvedBinding>> = (0..caps.pads()).map(|_| None).collect();
+        let mut encoders: Vec<Option<ResolvedBinding>> = (0..caps.encoders).map(|_| None).collect();
+        let mut faders: Vec<Option<ResolvedBinding>> = (0..caps.faders).map(|_| None).collect();
+        let mut knobs: Vec<Option<ResolvedBinding>> = (0..caps.knobs).map(|_| None).collect();
+        let absolute = |entry: &BindingEntry| Action::Absolute {
+            takeover: entry.takeover,
+            last: None,
+        };
+        let mut scheduled_msgs = HashMap::new();
+        let mut xy_pads = vec![];
+        // The pads under the XY squares.
+        let mut xy_cells = vec![false; caps.pads()];
+
+        for entry in &expand_radio_rows(file, index, &caps)? {
+            if let Control::Xy { pad, size } = entry.control {
+                let (row, col) = caps.row_col(pad);
+                if size < 2 || row as usize + size as usize > caps.rows as usize ||
+                   col as usize + size as usize > caps.columns as usize {
+                    return Err(format!("XY pad at {} of size {} doesn't fit the grid", pad, size).into());
+                }
+                let param_y = entry.param_y.as_ref()
+                    .ok_or_else(|| format!("XY pad at {} needs a param_y", pad))?;
+                for r in 0..size {
+                    for c in 0..size {
+                        let cell = caps.pad_at(row + r, col + c) as usize;
+                        if pads[cell].is_some() || xy_cells[cell] {
+                            return Err(format!("pad {} is bound twice", cell).into());
+                        }
+                        xy_cells[cell] = true;
+                    }
+                }
+                xy_pads.push(XyPad {
+                    row,
+                    col,
+                    size,
+                    x: XyAxis::new(map, index, &entry.param)?,
+                    y: XyAxis::new(map, index, param_y)?,
+                });
+                continue;
+            }
+
+            let param_idx = index.index_of(&entry.param)
+                .ok_or_else(|| format!("binding for unknown param '{}'", entry.param))?;
+            let param = &index.params[param_idx];
+            let low = entry.min.unwrap_or(0).max(param.entry.discrete_range_low);
+            let high = entry.max.unwrap_or(u32::MAX).min(param.entry.discrete_range_high);
+            if low > high {
+                return Err(format!("{:?} binding's min/max are outside '{}'s range",
+                                   entry.control, entry.param).into());
+            }
+            let (slot, action) = match entry.control {
+                Control::Pad(i) => {
+                    let value = entry.value.ok_or_else(|| format!("pad {} binding needs a value", i));
+                    let action = match entry.action {
+                        PadAction::Set => Action::SetValue(value?),
+                        PadAction::Radio => Action::Radio(value?),
+                        PadAction::Toggle { off } => Action::Toggle {
+                            on: value?,
+                            off: off.unwrap_or(low),
+                        },
+                        PadAction::Momentary => Action::Momentary {
+                            value: value?,
+                            restore: None,
+                        },
+                        PadAction::Step { step, wrap } => Action::Step {
+                            step: step as i64,
+                            wrap,
+                        },
+                    };
+                    (pads.get_mut(i as usize), action)
+                },
+                Control::Encoder(i) => (encoders.get_mut(i as usize), Action::Adjust),
+                Control::Fader(i) => (faders.get_mut(i as usize), absolute(entry)),
+                Control::Knob(i) => (knobs.get_mut(i as usize), absolute(entry)),
+                Control::RadioRow(_) | Control::Xy { .. } => unreachable!("handled above"),
+            };
+            let slot = slot.ok_or_else(|| format!("no such control: {:?}", entry.control))?;
+            let under_xy = matches!(entry.control, Control::Pad(i) if xy_cells[i as usize]);
+            if slot.is_some() || under_xy {
+                return Err(match entry.control {
+                    Control::Pad(i) => format!("pad {} is bound twice", i),
+                    control => format!("{:?} is bound twice", control),
+                }.into());
+            }
+            let slew = entry.slew_ms.map(|ms| Duration::from_millis(ms as u64));
+            if slew.is_some() || !param.entry.write_cost.is_volatile() {
+                scheduled_msgs.insert(param_idx, Dt1Buffer::new(map, param));
+            }
+            *slot = Some(ResolvedBinding {
+                param: param_idx,
+                action,
+                low,
+                high,
+                invert: entry.invert,
+                slew,
+                ring: entry.ring,
+                msg: Dt1Buffer::new(map, param),
+            });
+        }
+
+        let mut commands: Vec<Option<Vec<u8>>> = (0..caps.pads()).map(|_| None).collect();
+        for command in &file.commands {
+            let pad = match command.control {
+                Control::Pad(i) if (i as usize) < caps.pads() => i as usize,
+                control => return Err(format!("commands can't be bound to {:?}", control).into()),
+            };
+            if pads[pad].is_some() || xy_cells[pad] || commands[pad].is_some() {
+                return Err(format!("pad {} is bound twice", pad).into());
+            }
+            let msg = command.message.render(map, command.value)
+                .map_err(|e| format!("pad {} command: {}", pad, e))?;
+            commands[pad] = Some(msg);
+        }
+
+        Ok(BindingEngine {
+            store,
+            caps,
+            pads,
+            encoders,
+            faders,
+            knobs,
+            favorites: vec![],
+            page: 0,
+            xy_pads,
+            zone_controls: vec![],
+            commands,
+            scheduler: Scheduler::new(),
+            gestures: vec![],
+            scheduled_msgs,
+        })
+    }
+
+    pub fn store(&self) -> &Arc<ParamStore> {
+        &self.store
+    }
+
+    /// The controller the bindings were laid out for.
+    pub fn caps(&self) -> &ControllerCaps {
+        &self.caps
+    }
+
+    /// Hook up `file`'s zone bindings to the router's zones.  Zone controls
+    /// take precedence over param bindings on the same control.
+    pub fn attach_zones(&mut self, file: &BindingsFile, zones: &Zones) -> Result<(), Box<dyn Error>> {
+        for binding in &file.zones {
+            let zone = zones.get(&binding.zone)
+                .ok_or_else(|| format!("no route has a zone named '{}'", binding.zone))?;
+            match binding.control {
+                Control::Pad(_) | Control::Encoder(_) => (),
+                control => return Err(format!("zones can't be bound to {:?}", control).into()),
+            }
+            self.zone_controls.push(ZoneControl {
+                control: binding.control,
+                adjust: binding.adjust,
+                step: binding.step as i32,
+                zone: zone.clone(),
+            });
+        }
+        Ok(())
+    }
+
+    /// The encoders' bindings on the current page.
+    fn page_encoders(&self) -> &[Option<ResolvedBinding>] {
+        if self.page == FAVORITES_PAGE { &self.favorites } else { &self.encoders }
+    }
+
+    /// The param each encoder is bound to on the current page, if any.
+    pub fn encoder_params(&self) -> Vec<Option<usize>> {
+        self.page_encoders().iter().map(|e| e.as_ref().map(|b| b.param)).collect()
+    }
+
+    /// Put `params` on the encoders of the favorites page, left to right,
+    /// each over its whole range.
+    pub fn set_favorites(&mut self, map: &SysexMap, params: &[usize]) {
+        let index = self.store.index();
+        let scheduled_msgs = &mut self.scheduled_msgs;
+        self.favorites = (0..self.caps.encoders as usize).map(|i| {
+            let param = *params.get(i)?;
+            let p = &index.params[param];
+            if !p.entry.write_cost.is_volatile() {
+                scheduled_msgs.entry(param).or_insert_with(|| Dt1Buffer::new(map, p));
+            }
+            Some(ResolvedBinding {
+                param,
+                action: Action::Adjust,
+                low: p.entry.discrete_range_low,
+                high: p.entry.discrete_range_high,
+                invert: false,
+                slew: None,
+                ring: RingMode::default(),
+                msg: Dt1Buffer::new(map, p),
+            })
+        }).collect();
+    }
+
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    /// Switch the encoders between the bindings (page 0) and the favorites.
+    pub fn set_page(&mut self, page: usize) {
+        self.page = page;
+    }
+
+    /// Process a controller event, passing the sysex to send to the synth to
+    /// `send`.  Slewed bindings and persistent params send nothing here;
+    /// their writes come out of `tick`.
+    pub fn handle<F: FnMut(&[u8])>(&mut self, event: &ControllerEvent, mut send: F) {
+        let (control, delta) = match *event {
+            ControllerEvent::GridButton(idx, _, _, ButtonState::Down, _) => (Some(Control::Pad(idx)), 1),
+            ControllerEvent::Encoder(idx, delta) => (Some(Control::Encoder(idx)), delta as i32),
+            _ => (None, 0),
+        };
+        if let Some(zc) = self.zone_controls.iter().find(|zc| Some(zc.control) == control) {
+            zc.zone.adjust(zc.adjust, zc.step * delta);
+            return;
+        }
+        if let ControllerEvent::GridButton(idx, _, _, ButtonState::Down, _) = *event {
+            if let Some(Some(msg)) = self.commands.get(idx as usize) {
+                send(msg);
+                return;
+            }
+            for xy in self.xy_pads.iter_mut() {
+                if let Some((col, row)) = xy.locate(idx, &self.caps) {
+                    // Top row is the top of the Y range.
+                    let (x, y) = (xy.x.value_at(col, xy.size), xy.y.value_at(xy.size - 1 - row, xy.size));
+                    for (axis, value) in [(&mut xy.x, x), (&mut xy.y, y)] {
+                        self.store.set(axis.param, value);
+                        axis.msg.set_value(&self.store.index().params[axis.param], value);
+                        send(axis.msg.as_bytes());
+                    }
+                    return;
+                }
+            }
+        }
+        if let Some(msg) = self.handle_binding(event) {
+            send(msg);
+        }
+    }
+
+    /// Handle an event for a plain pad, encoder, fader or knob binding.  The
+    /// returned slice borrows a buffer owned by the engine.
+    fn handle_binding(&mut self, event: &ControllerEvent) -> Option<&[u8]> {
+        // Which continuous control it is, if it is one.
+        let (binding, delta, down, position, moving) = match *event {
+            ControllerEvent::GridButton(idx, _, _, state, _) => {
+                (self.pads.get_mut(idx as usize)?.as_mut()?, 0, state == ButtonState::Down, 0, None)
+            },
+            ControllerEvent::Encoder(idx, delta) => {
+                let encoders = if self.page == FAVORITES_PAGE { &mut self.favorites } else { &mut self.encoders };
+                (encoders.get_mut(idx as usize)?.as_mut()?, delta as i64, true, 0, Some(Control::Encoder(idx)))
+            },
+            ControllerEvent::Fader(idx, position) => {
+                (self.faders.get_mut(idx as usize)?.as_mut()?, 0, true, position.min(0x7f),
+                 Some(Control::Fader(idx)))
+            },
+            ControllerEvent::Knob(idx, position) => {
+                (self.knobs.get_mut(idx as usize)?.as_mut()?, 0, true, position.min(0x7f),
+                 Some(Control::Knob(idx)))
+            },
+            _ => return None,
+        };
+        if let Some(control) = moving {
+            let now = Instant::now();
+            match self.gestures.iter_mut().find(|g| g.control == control && g.param == binding.param) {
+                Some(gesture) => gesture.last = now,
+                None => self.gestures.push(Gesture { control, param: binding.param, last: now }),
+            }
+        }
+
+        // Adjust relative to where a ramp is headed, not where it's got to.
+        let sent = self.store.get(binding.param);
+        let current = self.scheduler.target(binding.param).unwrap_or(sent);
+        let delta = if binding.invert { -delta } else { delta };
+        let (low, high) = (binding.low, binding.high);
+        let value = match &mut binding.action {
+            Action::SetValue(value) | Action::Radio(value) if down => (*value).max(low).min(high),
+            Action::Toggle { on, off } if down => {
+                let (on, off) = ((*on).max(low).min(high), (*off).max(low).min(high));
+                if current == on { off } else { on }
+            },
+            Action::Momentary { value, restore } => {
+                if down {
+                    *restore = Some(current);
+                    (*value).max(low).min(high)
+                } else {
+                    restore.take()?
+                }
+            },
+            Action::Step { step, wrap } if down => {
+                let stepped = current as i64 + *step;
+                if *wrap {
+                    let span = (high - low) as i64 + 1;
+                    (low as i64 + (stepped - low as i64).rem_euclid(span)) as u32
+                } else {
+                    stepped.max(low as i64).min(high as i64) as u32
+                }
+            },
+            Action::Adjust => {
+                let adjusted = current as i64 + delta;
+                adjusted.max(low as i64).min(high as i64) as u32
+            },
+            Action::Absolute { takeover, last } => {
+                let position = if binding.invert { 0x7f - position } else { position };
+                let value = low + ((high - low) as u64 * position as u64 / 0x7f) as u32;
+                let previous = last.replace(value);
+                // Still in control if the param is where we left it.
+                if *takeover == Takeover::Pickup && previous != Some(current) {
+                    let met = match previous {
+                        Some(previous) => (previous.min(value)..=previous.max(value)).contains(&current),
+                        None => value == current,
+                    };
+                    if !met {
+                        return None;
+                    }
+                }
+                if value == current {
+                    return None;
+                }
+                value
+            },
+            // Releases only matter to momentary pads.
+            _ => return None,
+        };
+        if value == current && delta != 0 {
+            // Turning past the end of the range; nothing to send.
+            return None;
+        }
+
+        let p = &self.store.index().params[binding.param];
+        if moving.is_some() && !p.entry.write_cost.is_volatile() {
+            // Shown straight away, written once the gesture's over.
+            self.store.set(binding.param, value);
+            self.scheduler.defer(binding.param, value);
+            return None;
+        }
+        if let Some(slew) = binding.slew {
+            self.scheduler.ramp(binding.param, sent, value, slew, Instant::now());
+            return None;
+        }
+        self.store.set(binding.param, value);
+        binding.msg.set_value(&self.store.index().params[binding.param], value);
+        Some(binding.msg.as_bytes())
+    }
+
+    /// Feed the colors of the pads that show state to `set_led`: radio pads
+    /// light the one matching the param's value, XY pads the current
+    /// position.
+    pub fn render_pads<F: FnMut(u8, u8, u8, u8)>(&self, mut set_led: F) {
+        for (i, binding) in self.pads.iter().enumerate() {
+            if let Some(ResolvedBinding { param, action: Action::Radio(value), .. }) = binding {
+                let (r, g, b) = if self.store.get(*param) == *value { RADIO_ON } else { RADIO_OFF };
+                set_led(i as u8, r, g, b);
+            }
+        }
+        for xy in &self.xy_pads {
+            let col = xy.x.pos_of(self.store.get(xy.x.param), xy.size);
+            let row = xy.size - 1 - xy.y.pos_of(self.store.get(xy.y.param), xy.size);
+            for r in 0..xy.size {
+                for c in 0..xy.size {
+                    let (red, green, blue) = if (c, r) == (col, row) { XY_ON } else { XY_OFF };
+                    set_led(self.caps.pad_at(xy.row + r, xy.col + c), red, green, blue);
+                }
+            }
+        }
+    }
+
+    /// Feed each bound encoder's ring mode and value, scaled to 0 to 0x7f,
+    /// to `set_ring`.  Nothing on controllers without rings.
+    pub fn render_rings<F: FnMut(u8, RingMode, u8)>(&self, mut set_ring: F) {
+        if !self.caps.encoder_rings {
+            return;
+        }
+        for (i, binding) in self.page_encoders().iter().enumerate() {
+            if let Some(binding) = binding {
+                let value = self.store.get(binding.param).max(binding.low).min(binding.high);
+                let span = (binding.high - binding.low).max(1) as u64;
+                let level = ((value - binding.low) as u64 * 0x7f / span) as u8;
+                set_ring(i as u8, binding.ring, if binding.invert { 0x7f - level } else { level });
+            }
+        }
+    }
+
+    /// Stop any ramps in progress, ex: on panic.
+    pub fn cancel_ramps(&mut self) {
+        self.scheduler.clear();
+    }
+
+    /// Emit the ramp steps due at `now` through `send`, and the writes held
+    /// for gestures that have ended.  Returns the gestures that ended.  Call
+    /// every `scheduler::TICK`.
+    pub fn tick<F: FnMut(&[u8])>(&mut self, now: Instant, mut send: F) -> Vec<BindingEvent> {
+        let mut events = vec![];
+        if self.scheduler.is_idle() && self.gestures.is_empty() {
+            return events;
+        }
+        self.gestures.retain(|g| {
+            let ended = now.saturating_duration_since(g.last) >= GESTURE_END;
+            if ended {
+                events.push(BindingEvent::GestureEnd { control: g.control, param: g.param });
+            }
+            !ended
+        });
+        let mut writes = self.scheduler.tick(now);
+        for event in &events {
+            let BindingEvent::GestureEnd { param, .. } = *event;
+            if let Some(value) = self.scheduler.take_deferred(param) {
+                writes.push((param, value));
+            }
+        }
+        for (param, value) in writes {
+            if let Some(msg) = self.scheduled_msgs.get_mut(&param) {
+                self.store.set(param, value);
+                msg.set_value(&self.store.index().params[param], value);
+                send(msg.as_bytes());
+            }
+        }
+        events
+    }
+}
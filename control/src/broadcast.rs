@@ -0,0 +1,141 @@
+use log::info;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::bindings::BindingsFile;
+use crate::bus::EventBus;
+use crate::decode::DecodePool;
+use crate::param_store::{ParamChange, ParamStore};
+use crate::synth::Synth;
+use crate::sysex_map::SysexMap;
+
+/// A param on a follower synth.
+struct Link {
+    follower: usize,
+    param: usize,
+}
+
+/// Mirrors changes to the primary synth's bound params onto the params they
+/// broadcast to on other synths.  Followers get their own buses, so their
+/// changes never look like the primary's.
+pub struct Broadcaster {
+    primary: Arc<ParamStore>,
+    followers: Vec<Synth>,
+    /// By primary param.
+    links: HashMap<usize, Vec<Link>>,
+}
+
+/// Scale `value` from one param's range to another's.
+fn scale(value: u32, from: (u32, u32), to: (u32, u32)) -> u32 {
+    if from == to {
+        return value;
+    }
+    let t = (value.max(from.0).min(from.1) - from.0) as f64 / (from.1 - from.0).max(1) as f64;
+    (to.0 as f64 + t * (to.1 - to.0) as f64).round() as u32
+}
+
+impl Broadcaster {
+    /// Attach a synth (or simulate one) for every device named in `file`'s
+    /// broadcast targets.
+    pub fn attach(file: &BindingsFile, primary: Arc<ParamStore>, simulate: bool)
+                  -> Result<Broadcaster, Box<dyn Error>> {
+        let mut devices: Vec<String> = vec![];
+        let mut followers: Vec<Synth> = vec![];
+        let mut links: HashMap<usize, Vec<Link>> = HashMap::new();
+
+        for entry in &file.bindings {
+            for target in &entry.broadcast {
+                let param = primary.index().index_of(&entry.param)
+                    .ok_or_else(|| format!("binding for unknown param '{}'", entry.param))?;
+                let follower = match devices.iter().position(|d| *d == target.device) {
+                    Some(follower) => follower,
+                    None => {
+                        let map = SysexMap::load_device(&target.device)?;
+                        let mut synth = if simulate {
+                            Synth::simulate(map, EventBus::new())
+                        } else {
+                            Synth::attach(map, EventBus::new())
+                                .ok_or_else(|| format!("no {} connected", target.device))?
+                        };
+                        info!("broadcasting to {}", synth.controller().port_name());
+                        devices.push(target.device.clone());
+                        followers.push(synth);
+                        followers.len() - 1
+                    },
+                };
+                let name = target.param.as_ref().unwrap_or(&entry.param);
+                let target_param = followers[follower].store().index().index_of(name)
+                    .ok_or_else(|| format!("{} has no param '{}'", target.device, name))?;
+                let param_links = links.entry(param).or_default();
+                if !param_links.iter().any(|l| l.follower == follower && l.param == target_param) {
+                    param_links.push(Link {
+                        follower,
+                        param: target_param,
+                    });
+                }
+            }
+        }
+
+        Ok(Broadcaster {
+            primary,
+            followers,
+            links,
+        })
+    }
+
+    /// Write a change on the primary to whatever follows it.
+    pub fn follow(&mut self, change: &ParamChange) {
+        let links = match self.links.get(&change.param) {
+            Some(links) => links,
+            None => return,
+        };
+        let from = &self.primary.index().params[change.param].entry;
+        for link in links {
+            let synth = &mut self.followers[link.follower];
+            let to = &synth.store().index().params[link.param].entry;
+            let value = scale(change.value, (from.discrete_range_low, from.discrete_range_high),
+                              (to.discrete_range_low, to.discrete_range_high));
+            synth.write(link.param, value);
+        }
+    }
+
+    /// Write every linked param's current value, ex: after missing changes.
+    pub fn resync(&mut self) {
+        let params: Vec<usize> = self.links.keys().copied().collect();
+        for param in params {
+            self.follow(&ParamChange {
+                param,
+                value: self.primary.get(param),
+            });
+        }
+    }
+
+    /// Send a raw message to every follower.
+    pub fn send_all(&mut self, msg: &[u8]) {
+        for synth in self.followers.iter_mut() {
+            synth.send(msg);
+        }
+    }
+
+    /// Decode what the followers send on lanes of `pool`.
+    pub fn decode_in(&mut self, pool: &DecodePool) {
+        for synth in self.followers.iter_mut() {
+            synth.decode_in(pool.lane());
+        }
+    }
+
+    /// Keep the followers' connections serviced.  Call regularly.
+    pub fn poll(&mut self) {
+        for synth in self.followers.iter_mut() {
+            synth.poll_events();
+        }
+    }
+
+    pub fn poll_watchdogs(&mut self) {
+        for synth in self.followers.iter_mut() {
+            synth.controller().poll_watchdog();
+        }
+    }
+}
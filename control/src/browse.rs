@@ -0,0 +1,161 @@
+//! Auditioning the library's patches from the controller, ex:
+//! ```json
+//! "browse": { "dir": "library/pads", "encoder": 2, "commit": { "pad": 11 }, "favorites": "picks.json" }
+//! ```
+//! Turning the encoder steps through the .syx files in `dir` in name order,
+//! and the one stepped to is recalled once the encoder's been left alone for
+//! `debounce_ms`, so spinning past a dozen patches doesn't send each one.
+//! The commit pad adds the patch that's playing to `favorites`, a setlist
+//! file, so the keepers can be stepped through like any other setlist.
+
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::bindings::Control;
+use crate::controllers::{ButtonState, ControllerEvent};
+use crate::program::Recall;
+use crate::setlist::{SetlistFile, Song};
+
+/// The `browse` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BrowseConfig {
+    /// Where the patches are.
+    pub dir: String,
+    /// The encoder that steps through them.
+    pub encoder: u8,
+    pub commit: Control,
+    /// The setlist file committed patches are added to.
+    pub favorites: String,
+    #[serde(default = "default_debounce_ms")]
+    pub debounce_ms: u64,
+}
+
+fn default_debounce_ms() -> u64 {
+    300
+}
+
+pub struct Browse {
+    encoder: u8,
+    commit: u8,
+    favorites: String,
+    debounce: Duration,
+    patches: Vec<PathBuf>,
+    position: usize,
+    /// When to recall the patch stepped to, once the encoder's left alone.
+    due: Option<Instant>,
+}
+
+fn patches_in(dir: &str) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let mut patches: Vec<PathBuf> = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir, e))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("syx"))
+        .collect();
+    patches.sort();
+    Ok(patches)
+}
+
+fn name_of(path: &Path) -> String {
+    path.file_stem().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+impl Browse {
+    /// Positioned at the first patch.  Nothing's recalled until the encoder
+    /// turns.
+    pub fn new(config: &BrowseConfig) -> Result<Browse, Box<dyn Error>> {
+        let commit = match config.commit {
+            Control::Pad(pad) => pad,
+            control => return Err(format!("browse controls must be pads, not {:?}", control).into()),
+        };
+        let patches = patches_in(&config.dir)?;
+        if patches.is_empty() {
+            return Err(format!("{} has no patches", config.dir).into());
+        }
+        Ok(Browse {
+            encoder: config.encoder,
+            commit,
+            favorites: config.favorites.clone(),
+            debounce: Duration::from_millis(config.debounce_ms),
+            patches,
+            position: 0,
+            due: None,
+        })
+    }
+
+    /// Whether `event` is for the browse encoder or commit pad.
+    pub fn claims(&self, event: &ControllerEvent) -> bool {
+        match *event {
+            ControllerEvent::Encoder(idx, _) => idx == self.encoder,
+            ControllerEvent::GridButton(pad, ..) => pad == self.commit,
+            _ => false,
+        }
+    }
+
+    /// Step for a turn of the encoder, or commit for a press of the pad.
+    /// Returns whether the label changed.
+    pub fn handle(&mut self, event: &ControllerEvent, now: Instant) -> Result<bool, Box<dyn Error>> {
+        match *event {
+            ControllerEvent::Encoder(_, delta) => {
+                let to = (self.position as i64 + delta as i64).max(0).min(self.patches.len() as i64 - 1);
+                if to as usize == self.position {
+                    return Ok(false);
+                }
+                self.position = to as usize;
+                self.due = Some(now + self.debounce);
+                Ok(true)
+            },
+            ControllerEvent::GridButton(_, _, _, ButtonState::Down, _) => {
+                self.commit()?;
+                Ok(false)
+            },
+            _ => Ok(false),
+        }
+    }
+
+    /// The patch to recall, once the encoder's been left alone long enough.
+    /// Call every tick or so.
+    pub fn poll(&mut self, now: Instant) -> Option<Song> {
+        self.due.filter(|&due| now >= due)?;
+        self.due = None;
+        Some(self.song())
+    }
+
+    fn song(&self) -> Song {
+        let path = &self.patches[self.position];
+        Song {
+            name: name_of(path),
+            recall: Recall {
+                snapshot: Some(path.to_string_lossy().into_owned()),
+                program: None,
+            },
+        }
+    }
+
+    /// Add the current patch to the favorites, unless it's there already.
+    fn commit(&self) -> Result<(), Box<dyn Error>> {
+        let mut file = match File::open(&self.favorites) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))
+                .map_err(|e| format!("{}: {}", self.favorites, e))?,
+            Err(_) => SetlistFile::default(),
+        };
+        let song = self.song();
+        if file.songs.iter().any(|s| s.recall == song.recall) {
+            return Ok(());
+        }
+        info!("adding {} to {}", song.name, self.favorites);
+        file.songs.push(song);
+        fs::write(&self.favorites, serde_json::to_string_pretty(&file)?)
+            .map_err(|e| format!("can't write {}: {}", self.favorites, e))?;
+        Ok(())
+    }
+
+    /// Where we are, ex: "3/40 WARM PAD".
+    pub fn label(&self) -> String {
+        format!("{}/{} {}", self.position + 1, self.patches.len(), name_of(&self.patches[self.position]))
+    }
+}
@@ -0,0 +1,111 @@
+use tokio::sync::broadcast;
+
+use crate::param_store::ParamChange;
+use crate::progress::Progress;
+
+/// How far a subscriber can fall behind before it starts seeing `Lagged`.
+const BUS_BUFFER: usize = 1024;
+
+/// Things that happen in the engine that more than one subsystem might care
+/// about.  Producers publish these without knowing who's listening, so adding
+/// a new frontend means adding a subscriber rather than threading calls
+/// through everything that makes changes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum EngineEvent {
+    ParamChanged(ParamChange),
+    /// Changes held back during a bulk operation, published together once
+    /// it's over.  See `ParamStore::hold_changes`.
+    ParamsChanged(Vec<ParamChange>),
+    /// The controller switched to a different page of bindings.
+    PageChanged(usize),
+    /// A device was attached (or re-attached after recovery), by port name.
+    DeviceAttached(String),
+    /// Tempo in BPM.
+    TempoChanged(f32),
+    /// A long transfer (ex: a snapshot recall) moved along.
+    Progress(Progress),
+}
+
+/// The kinds of `EngineEvent`, for subscribing to only some of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventKind {
+    Param,
+    Page,
+    Device,
+    Tempo,
+    Progress,
+}
+
+impl EngineEvent {
+    pub fn kind(&self) -> EventKind {
+        match self {
+            EngineEvent::ParamChanged(_) | EngineEvent::ParamsChanged(_) => EventKind::Param,
+            EngineEvent::PageChanged(_) => EventKind::Page,
+            EngineEvent::DeviceAttached(_) => EventKind::Device,
+            EngineEvent::TempoChanged(_) => EventKind::Tempo,
+            EngineEvent::Progress(_) => EventKind::Progress,
+        }
+    }
+}
+
+fn kind_bit(kind: EventKind) -> u32 {
+    1 << kind as u32
+}
+
+/// Cheap to clone; every clone publishes to the same subscribers.
+#[derive(Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<EngineEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(BUS_BUFFER);
+        EventBus {
+            tx,
+        }
+    }
+
+    pub fn publish(&self, event: EngineEvent) {
+        // An error just means nobody is subscribed right now.
+        let _ = self.tx.send(event);
+    }
+
+    /// Subscribe to the given kinds of events; everything else is skipped.
+    pub fn subscribe(&self, kinds: &[EventKind]) -> Subscription {
+        Subscription {
+            rx: self.tx.subscribe(),
+            kinds: kinds.iter().fold(0, |acc, k| acc | kind_bit(*k)),
+        }
+    }
+
+    /// Subscribe to everything, ex: for logging.
+    pub fn subscribe_all(&self) -> Subscription {
+        self.subscribe(&[EventKind::Param, EventKind::Page, EventKind::Device, EventKind::Tempo,
+                         EventKind::Progress])
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Subscription {
+    rx: broadcast::Receiver<EngineEvent>,
+    kinds: u32,
+}
+
+impl Subscription {
+    /// Wait for the next event of a subscribed kind.  `Lagged` means we missed
+    /// events and the subscriber should re-read whatever state it mirrors.
+    pub async fn recv(&mut self) -> Result<EngineEvent, broadcast::RecvError> {
+        loop {
+            let event = self.rx.recv().await?;
+            if self.kinds & kind_bit(event.kind()) != 0 {
+                return Ok(event);
+            }
+        }
+    }
+}
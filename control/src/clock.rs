@@ -0,0 +1,193 @@
+//! Following an incoming MIDI clock, for writes that have to land on the
+//! beat rather than whenever they're made, ex: a tempo-synced param
+//! animation.  `Clock` takes the realtime messages as they arrive and
+//! smooths out their jitter into a steady tempo and phase; `ClockQueue`
+//! holds messages for clock positions and lets them go `lookahead` ahead of
+//! when those come round, to make up for the time they take to get to the
+//! synth.
+
+use serde::{Deserialize, Serialize};
+
+use std::time::{Duration, Instant};
+
+/// MIDI clock ticks per quarter note.
+pub const PPQN: u64 = 24;
+
+const TIMING_CLOCK: u8 = 0xf8;
+const START: u8 = 0xfa;
+const CONTINUE: u8 = 0xfb;
+const STOP: u8 = 0xfc;
+const SONG_POSITION: u8 = 0xf2;
+
+/// The `clock` section of the config, ex:
+/// `{ "port": "TR-8S", "lookahead_ms": 15, "smoothing": 0.05 }`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ClockConfig {
+    /// The port the clock comes in on, matched like a route's `from`.
+    pub port: String,
+    /// How early messages go out, ex: the output's latency.
+    #[serde(default = "default_lookahead_ms")]
+    pub lookahead_ms: u64,
+    /// How much each tick moves the estimate, from 0 (not at all) to 1
+    /// (all the way, so no smoothing).
+    #[serde(default = "default_smoothing")]
+    pub smoothing: f64,
+}
+
+fn default_lookahead_ms() -> u64 {
+    20
+}
+
+fn default_smoothing() -> f64 {
+    0.1
+}
+
+pub struct Clock {
+    smoothing: f64,
+    running: bool,
+    /// Ticks since the start, or since where a song position put it.
+    position: u64,
+    /// When the tick at `position` is reckoned to have come, smoothed.
+    at: Option<Instant>,
+    /// Seconds between ticks, smoothed.
+    period: Option<f64>,
+}
+
+impl Clock {
+    pub fn new(config: &ClockConfig) -> Self {
+        Clock {
+            smoothing: config.smoothing.clamp(0.0, 1.0),
+            running: false,
+            position: 0,
+            at: None,
+            period: None,
+        }
+    }
+
+    /// Take a message that arrived at `at`.  Anything but clock, start,
+    /// continue, stop and song position is ignored.
+    pub fn handle(&mut self, msg: &[u8], at: Instant) {
+        match msg {
+            [TIMING_CLOCK] if self.running => self.tick(at),
+            [START] => {
+                self.running = true;
+                self.position = 0;
+                self.at = None;
+            },
+            [CONTINUE] => self.running = true,
+            [STOP] => self.running = false,
+            // In sixteenths, six ticks each.
+            [SONG_POSITION, lsb, msb] => {
+                self.position = (*lsb as u64 | (*msb as u64) << 7) * 6;
+                self.at = None;
+            },
+            _ => (),
+        }
+    }
+
+    fn tick(&mut self, at: Instant) {
+        let (last, period) = match (self.at, self.period) {
+            (Some(last), period) => (last, period),
+            // The first tick after a start is the start.
+            (None, _) => {
+                self.at = Some(at);
+                return;
+            },
+        };
+        self.position += 1;
+        let measured = at.saturating_duration_since(last).as_secs_f64();
+        let period = match period {
+            Some(period) => period + (measured - period) * self.smoothing,
+            None => measured,
+        };
+        // Where this tick should have been going by the old estimate, moved
+        // part of the way toward where it was.
+        let expected = last + Duration::from_secs_f64(period);
+        let error = at.saturating_duration_since(expected).as_secs_f64() -
+                    expected.saturating_duration_since(at).as_secs_f64();
+        let smoothed = error * self.smoothing;
+        self.at = Some(if smoothed >= 0.0 {
+            expected + Duration::from_secs_f64(smoothed)
+        } else {
+            expected - Duration::from_secs_f64(-smoothed)
+        });
+        self.period = Some(period);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Ticks since the start.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    pub fn bpm(&self) -> Option<f32> {
+        self.period.map(|period| (60.0 / (period * PPQN as f64)) as f32)
+    }
+
+    /// When tick `position` is due, if the clock's running and its tempo is
+    /// known.  Ticks already gone are when they were reckoned to be.
+    pub fn time_of(&self, position: u64) -> Option<Instant> {
+        let (at, period) = (self.at?, self.period?);
+        if !self.running {
+            return None;
+        }
+        let ticks = position as f64 - self.position as f64;
+        Some(if ticks >= 0.0 {
+            at + Duration::from_secs_f64(ticks * period)
+        } else {
+            at.checked_sub(Duration::from_secs_f64(-ticks * period)).unwrap_or(at)
+        })
+    }
+}
+
+/// Messages waiting for clock positions.  They can carry more than the
+/// bytes, ex: the param a write is of.
+pub struct ClockQueue<T = Vec<u8>> {
+    lookahead: Duration,
+    /// Kept in position order.
+    queued: Vec<(u64, T)>,
+}
+
+impl<T> ClockQueue<T> {
+    pub fn new(config: &ClockConfig) -> Self {
+        ClockQueue {
+            lookahead: Duration::from_millis(config.lookahead_ms),
+            queued: vec![],
+        }
+    }
+
+    /// Send `msg` so it lands at tick `position`.
+    pub fn schedule(&mut self, position: u64, msg: T) {
+        let at = self.queued.partition_point(|(queued, _)| *queued <= position);
+        self.queued.insert(at, (position, msg));
+    }
+
+    pub fn len(&self) -> usize {
+        self.queued.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    /// Drop everything queued, ex: when the clock stops.
+    pub fn clear(&mut self) {
+        self.queued.clear();
+    }
+
+    /// Pass the messages due by `now` plus the lookahead to `send`, in
+    /// order.  Nothing's due while the clock's stopped or its tempo isn't
+    /// known yet.
+    pub fn flush<F: FnMut(T)>(&mut self, clock: &Clock, now: Instant, mut send: F) {
+        let horizon = now + self.lookahead;
+        let due = self.queued.iter()
+            .take_while(|(position, _)| clock.time_of(*position).is_some_and(|at| at <= horizon))
+            .count();
+        for (_, msg) in self.queued.drain(..due) {
+            send(msg);
+        }
+    }
+}
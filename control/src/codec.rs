@@ -0,0 +1,252 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+
+use crate::sysex_map::{MappedParam, ParamIndex, SysexMap};
+use crate::template::Template;
+
+/// Roland manufacturer ID.
+pub const ROLAND_ID: u8 = 0x41;
+/// Roland "Data Request 1" command ID.
+pub const RQ1: u8 = 0x11;
+/// Roland "Data Set 1" command ID.
+pub const DT1: u8 = 0x12;
+
+/// Roland addresses and RQ1 sizes are always 4 bytes on the gear we care about.
+pub const ADDRESS_LEN: usize = 4;
+
+/// Roland's checksum: the value that makes the sum of the address, data and
+/// checksum bytes come out to 0 in the low 7 bits.
+pub fn roland_checksum(bytes: &[u8]) -> u8 {
+    let sum: u32 = bytes.iter().map(|b| *b as u32).sum();
+    ((0x80 - (sum & 0x7f)) & 0x7f) as u8
+}
+
+/// Two's complement of the sum, in 7 bits, as used by Kawai.  Numerically the
+/// same as Roland's, but Kawai only sums the data bytes, not the address.
+pub fn twos_complement_checksum(bytes: &[u8]) -> u8 {
+    roland_checksum(bytes)
+}
+
+/// XOR of all the bytes, masked to 7 bits.
+pub fn xor_checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |acc, b| acc ^ b) & 0x7f
+}
+
+/// Which checksum a device puts before the trailing 0xf7, if any.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Checksum {
+    /// Covers the address and data bytes.
+    #[default]
+    Roland,
+    /// Covers only the data bytes.
+    Kawai,
+    /// Covers the address and data bytes.
+    Xor,
+    /// No checksum byte at all.
+    None,
+}
+
+impl Checksum {
+    /// Whether messages carry a checksum byte.
+    pub fn is_present(self) -> bool {
+        self != Checksum::None
+    }
+
+    /// Compute the checksum over a message body (address onwards, up to but
+    /// not including the checksum byte).
+    pub fn compute(self, body: &[u8]) -> Option<u8> {
+        match self {
+            Checksum::Roland => Some(roland_checksum(body)),
+            Checksum::Kawai => Some(twos_complement_checksum(body.get(ADDRESS_LEN..).unwrap_or(&[]))),
+            Checksum::Xor => Some(xor_checksum(body)),
+            Checksum::None => None,
+        }
+    }
+}
+
+/// What to do with an incoming message whose checksum doesn't match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumPolicy {
+    /// Drop the message.
+    #[default]
+    Reject,
+    /// Log a warning and use the message anyway.
+    Warn,
+    /// Silently use the message anyway.
+    Accept,
+}
+
+/// A parsed "Data Set 1" message.
+pub struct DataSet<'a> {
+    /// Linear address of the first data byte.
+    pub address: u32,
+    pub data: &'a [u8],
+}
+
+/// Build a DT1 message writing `data` starting at linear address `address`.
+pub fn encode_dt1(map: &SysexMap, address: u32, data: &[u8]) -> Vec<u8> {
+    Template::write_for(map).render(map, address, 0, data).msg
+}
+
+/// Build an RQ1 message requesting `size` bytes starting at linear address
+/// `address`.  The size uses the same 7-bit packing as addresses.
+pub fn encode_rq1(map: &SysexMap, address: u32, size: u32) -> Vec<u8> {
+    Template::read_for(map).render(map, address, size, &[]).msg
+}
+
+/// Parse a DT1 message addressed to the device described by `map`, returning
+/// None if it's something else or the checksum doesn't match (unless the
+/// map's `checksum_policy` says to let that slide).
+pub fn parse_dt1<'a>(map: &SysexMap, msg: &'a [u8]) -> Option<DataSet<'a>> {
+    let matched = Template::write_for(map).parse(map, msg)?;
+    if matched.data.is_empty() {
+        return None;
+    }
+    if let Some((body, actual)) = matched.checksum {
+        let expected = map.checksum.compute(body).unwrap_or(actual);
+        if expected != actual {
+            match map.checksum_policy {
+                ChecksumPolicy::Reject => return None,
+                ChecksumPolicy::Warn => warn!("Checksum mismatch: expected {:#04x}, got {:#04x}",
+                                              expected, actual),
+                ChecksumPolicy::Accept => (),
+            }
+        }
+    }
+
+    Some(DataSet {
+        address: matched.address,
+        data: matched.data,
+    })
+}
+
+fn bits_in(bitmask: u32) -> u32 {
+    32 - bitmask.leading_zeros()
+}
+
+/// Extract a parameter's value from its bytes, most significant byte first.
+/// Multi-byte values use only the bits in the bitmask of each byte, ex: a
+/// "0000 aaaa" nibble per byte.
+pub fn decode_value(param: &MappedParam, bytes: &[u8]) -> u32 {
+    let mask = param.entry.bitmask & 0x7f;
+    let bits = bits_in(mask);
+    bytes.iter().fold(0, |acc, b| (acc << bits) | (*b as u32 & mask))
+}
+
+/// Inverse of `decode_value`; `out` must be `param.size` bytes long.
+pub fn encode_value(param: &MappedParam, value: u32, out: &mut [u8]) {
+    let mask = param.entry.bitmask & 0x7f;
+    let bits = bits_in(mask);
+    let mut remaining = value;
+    for b in out.iter_mut().rev() {
+        *b = (remaining & mask) as u8;
+        remaining >>= bits;
+    }
+}
+
+/// Build the DT1 message that sets a single parameter to `value`.
+pub fn encode_param_dt1(map: &SysexMap, param: &MappedParam, value: u32) -> Vec<u8> {
+    let mut data = vec![0; param.size as usize];
+    encode_value(param, value, &mut data);
+    encode_dt1(map, param.address, &data)
+}
+
+/// Build one DT1 per (linear address, size) span holding the params' values,
+/// ex: to send a whole patch back or save it as a .syx.  Bytes that aren't
+/// part of any param are sent as 0.
+pub fn encode_spans<F>(map: &SysexMap, index: &ParamIndex, spans: &[(u32, u32)], value_of: F)
+                       -> Vec<Vec<u8>>
+        where F: Fn(usize) -> u32 {
+    spans.iter().map(|(start, size)| {
+        let mut data = vec![0; *size as usize];
+        for idx in index.covered_by(*start, *size) {
+            let param = &index.params[idx];
+            let offset = (param.address - start) as usize;
+            encode_value(param, value_of(idx), &mut data[offset..offset + param.size as usize]);
+        }
+        encode_dt1(map, *start, &data)
+    }).collect()
+}
+
+/// A preformed DT1 message for a single parameter that can be rewritten in
+/// place for each new value, so the per-event path doesn't allocate.
+pub struct Dt1Buffer {
+    msg: Vec<u8>,
+    body_start: usize,
+    data_start: usize,
+    data_end: usize,
+    checksum: Checksum,
+}
+
+impl Dt1Buffer {
+    pub fn new(map: &SysexMap, param: &MappedParam) -> Self {
+        let data = vec![0; param.size as usize];
+        let rendered = Template::write_for(map).render(map, param.address, 0, &data);
+        Dt1Buffer {
+            msg: rendered.msg,
+            body_start: rendered.body_start,
+            data_start: rendered.data_start,
+            data_end: rendered.data_end,
+            checksum: map.checksum,
+        }
+    }
+
+    /// Encode `value` into the data bytes and fix up the checksum.
+    pub fn set_value(&mut self, param: &MappedParam, value: u32) {
+        encode_value(param, value, &mut self.msg[self.data_start..self.data_end]);
+        // Templates put the checksum right after the data.
+        if let Some(checksum) = self.checksum.compute(&self.msg[self.body_start..self.data_end]) {
+            self.msg[self.data_end] = checksum;
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.msg
+    }
+}
+
+/// Decode every parameter fully covered by a DT1 message.
+pub fn decode_data_set(index: &ParamIndex, data_set: &DataSet) -> Vec<(usize, u32)> {
+    index.covered_by(data_set.address, data_set.data.len() as u32).map(|idx| {
+        let param = &index.params[idx];
+        let offset = (param.address - data_set.address) as usize;
+        let bytes = &data_set.data[offset..offset + param.size as usize];
+        (idx, decode_value(param, bytes))
+    }).collect()
+}
+
+/// Split a buffer of concatenated sysex messages (ex: the contents of a .syx
+/// file) into the individual messages.  Anything outside of F0..F7 is dropped.
+pub fn split_sysex(bytes: &[u8]) -> Vec<&[u8]> {
+    let mut messages = vec![];
+    let mut start = None;
+    for (i, b) in bytes.iter().enumerate() {
+        match (*b, start) {
+            (0xf0, _) => start = Some(i),
+            (0xf7, Some(s)) => {
+                messages.push(&bytes[s..=i]);
+                start = None;
+            },
+            _ => (),
+        }
+    }
+    messages
+}
+
+/// Decode all the DT1 messages in `bytes` into a map from parameter name to
+/// raw value.  Later messages win if they overlap.
+pub fn decode_dump(map: &SysexMap, index: &ParamIndex, bytes: &[u8]) -> BTreeMap<String, u32> {
+    let mut values = BTreeMap::new();
+    for msg in split_sysex(bytes) {
+        if let Some(data_set) = parse_dt1(map, msg) {
+            for (idx, value) in decode_data_set(index, &data_set) {
+                values.insert(index.params[idx].name.clone(), value);
+            }
+        }
+    }
+    values
+}
@@ -0,0 +1,131 @@
+//! Raw messages for device commands that aren't params, ex: switching a
+//! Jupiter-X to Scene mode, written in a bindings file's `commands` as hex
+//! with placeholders:
+//! ```text
+//! F0 41 {device_id} 00 00 00 65 12 01 00 00 00 {value:7} {checksum} F7
+//! ```
+//! `{value:N}` is the binding's value in N bits, seven to a byte with the
+//! most significant first, so `{value:14}` takes two bytes.  `{checksum}`
+//! covers everything after the bytes the map's write template puts before
+//! the address, the same as a write's.
+
+use serde::{Deserialize, Serialize};
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::sysex_map::SysexMap;
+use crate::template::{Template, Token};
+
+/// The most bits a `{value:N}` can have.
+const MAX_VALUE_BITS: u8 = 28;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Part {
+    Byte(u8),
+    DeviceId,
+    /// The value, in this many bits.
+    Value(u8),
+    Checksum,
+}
+
+impl Part {
+    fn parse(text: &str) -> Result<Part, String> {
+        let bits = |bits: &str| match bits.parse::<u8>() {
+            Ok(bits) if (1..=MAX_VALUE_BITS).contains(&bits) => Ok(Part::Value(bits)),
+            _ => Err(format!("'{}' needs 1 to {} bits", text, MAX_VALUE_BITS)),
+        };
+        match text {
+            "{device_id}" => Ok(Part::DeviceId),
+            "{value}" => Ok(Part::Value(7)),
+            "{checksum}" => Ok(Part::Checksum),
+            _ if text.starts_with("{value:") && text.ends_with('}') => bits(&text[7..text.len() - 1]),
+            _ => u8::from_str_radix(text, 16).map(Part::Byte)
+                .map_err(|_| format!("bad command token '{}'", text)),
+        }
+    }
+}
+
+impl fmt::Display for Part {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Part::Byte(b) => write!(f, "{:02X}", b),
+            Part::DeviceId => f.write_str("{device_id}"),
+            Part::Value(bits) => write!(f, "{{value:{}}}", bits),
+            Part::Checksum => f.write_str("{checksum}"),
+        }
+    }
+}
+
+/// A raw message with placeholders.  Parsing checks it's a single sysex
+/// message; `render` checks what depends on the map and the value.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CommandTemplate {
+    parts: Vec<Part>,
+}
+
+impl TryFrom<String> for CommandTemplate {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let parts = text.split_whitespace().map(Part::parse).collect::<Result<Vec<_>, _>>()?;
+        if parts.first() != Some(&Part::Byte(0xf0)) || parts.last() != Some(&Part::Byte(0xf7)) {
+            return Err(format!("command '{}' isn't F0 ... F7", text));
+        }
+        let inner = &parts[1..parts.len() - 1];
+        if inner.iter().any(|p| matches!(p, Part::Byte(b) if *b > 0x7f)) {
+            return Err(format!("command '{}' has a status byte inside it", text));
+        }
+        if inner.iter().filter(|p| **p == Part::Checksum).count() > 1 {
+            return Err(format!("command '{}' has more than one checksum", text));
+        }
+        Ok(CommandTemplate {
+            parts,
+        })
+    }
+}
+
+impl From<CommandTemplate> for String {
+    fn from(template: CommandTemplate) -> String {
+        template.parts.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+impl CommandTemplate {
+    /// The message for `value` to the map's device.  Errors if the value
+    /// doesn't fit a `{value:N}`, or if there's a `{checksum}` and the map
+    /// doesn't say where its checksums start.
+    pub fn render(&self, map: &SysexMap, value: u32) -> Result<Vec<u8>, String> {
+        let mut msg = vec![];
+        for part in &self.parts {
+            match *part {
+                Part::Byte(b) => msg.push(b),
+                Part::DeviceId => msg.push(map.device_id),
+                Part::Value(bits) => {
+                    if (value as u64) >> bits != 0 {
+                        return Err(format!("{} doesn't fit in {} bits", value, bits));
+                    }
+                    let len = (bits as usize).div_ceil(7);
+                    msg.extend((0..len).rev().map(|i| (value >> (7 * i)) as u8 & 0x7f));
+                },
+                Part::Checksum => {
+                    let start = checksum_start(map)
+                        .ok_or("the map's write template has no {address} for the checksum to start at")?;
+                    let body = msg.get(start..).unwrap_or(&[]);
+                    if let Some(checksum) = map.checksum.compute(body) {
+                        msg.push(checksum);
+                    }
+                },
+            }
+        }
+        Ok(msg)
+    }
+}
+
+/// Where a write's checksummed bytes start, going by the header before the
+/// address being a byte a token.
+fn checksum_start(map: &SysexMap) -> Option<usize> {
+    let write = Template::write_for(map);
+    write.tokens().iter().position(|t| *t == Token::Address)
+}
@@ -0,0 +1,132 @@
+//! Maps other people have made, installed from an index of them, ex:
+//! `mapatron maps install roland/jupiter-x`.  The index is a JSON file that
+//! can be served from anywhere, a static site or a git repo's raw files:
+//! ```json
+//! { "maps": [
+//!   { "name": "roland/jupiter-x", "device": "jupx",
+//!     "files": [{ "path": "roland/jupx.json", "sha256": "9f2c..." },
+//!               { "path": "roland/jupx.de.strings.json", "sha256": "41b0..." }] }
+//! ] }
+//! ```
+//! File paths are relative to the index.  Every file's hash is checked
+//! before anything's installed, and with a `public_key` configured the index
+//! itself has to come with a minisign signature (`index.json.minisig` next to
+//! `index.json`), so the hashes can be trusted too.  Files go in the map
+//! directory, see `sysex_map::map_path_for`.
+
+use minisign_verify::{PublicKey, Signature};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use std::error::Error;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::sysex_map::SysexMap;
+
+/// Index files over this size are refused rather than read into memory.
+const MAX_DOWNLOAD: u64 = 16 * 1024 * 1024;
+
+/// The `map_index` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MapIndexConfig {
+    /// An http(s) URL or a local path, ex: of a clone of the index's repo.
+    pub url: String,
+    /// The minisign public key the index is signed with, in base64 as on
+    /// the second line of `minisign.pub`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IndexFile {
+    /// Relative to the index.
+    pub path: String,
+    /// Lowercase hex.
+    pub sha256: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// What it's installed by, ex: "roland/jupiter-x".
+    pub name: String,
+    /// The short device name the map is installed under, ex: "jupx".
+    pub device: String,
+    pub files: Vec<IndexFile>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Index {
+    pub maps: Vec<IndexEntry>,
+}
+
+/// `path` relative to `base`, a URL or a local path of a file.
+fn sibling(base: &str, path: &str) -> String {
+    match base.rfind('/') {
+        Some(slash) => format!("{}/{}", &base[..slash], path),
+        None => path.to_string(),
+    }
+}
+
+fn fetch(url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Ok(fs::read(url).map_err(|e| format!("{}: {}", url, e))?);
+    }
+    let response = ureq::get(url).call().map_err(|e| format!("{}: {}", url, e))?;
+    let mut bytes = vec![];
+    response.into_reader().take(MAX_DOWNLOAD + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_DOWNLOAD {
+        return Err(format!("{} is too big", url).into());
+    }
+    Ok(bytes)
+}
+
+fn sha256(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Download the index, checking its signature if there's a key to check it
+/// with.
+pub fn fetch_index(config: &MapIndexConfig) -> Result<Index, Box<dyn Error>> {
+    let bytes = fetch(&config.url)?;
+    if let Some(key) = &config.public_key {
+        let key = PublicKey::from_base64(key).map_err(|e| format!("bad public key: {}", e))?;
+        let signature = String::from_utf8(fetch(&format!("{}.minisig", config.url))?)?;
+        let signature = Signature::decode(&signature).map_err(|e| format!("bad index signature: {}", e))?;
+        key.verify(&bytes, &signature, false).map_err(|e| format!("index signature doesn't verify: {}", e))?;
+    }
+    Ok(serde_json::from_slice(&bytes)?)
+}
+
+/// Download `name`'s files from the index and write them to `dir`, once
+/// they've all been checked.  Returns the paths written.
+pub fn install(config: &MapIndexConfig, index: &Index, name: &str, dir: &Path)
+               -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let entry = index.maps.iter().find(|m| m.name == name)
+        .ok_or_else(|| format!("no map '{}' in the index", name))?;
+    let mut files = vec![];
+    for file in &entry.files {
+        // Only ever the file name, so the index can't write outside `dir`
+        // or over another device's files.
+        let file_name = file.path.rsplit('/').next().unwrap_or(&file.path);
+        if !file_name.starts_with(&format!("{}.", entry.device)) {
+            return Err(format!("{} isn't one of {}'s files", file.path, entry.device).into());
+        }
+        let bytes = fetch(&sibling(&config.url, &file.path))?;
+        if sha256(&bytes) != file.sha256.to_lowercase() {
+            return Err(format!("{} doesn't match its hash", file.path).into());
+        }
+        files.push((dir.join(file_name), bytes));
+    }
+    let map_name = format!("{}.json", entry.device);
+    let (_, map) = files.iter().find(|(path, _)| path.ends_with(&map_name))
+        .ok_or_else(|| format!("'{}' has no {}", name, map_name))?;
+    serde_json::from_slice::<SysexMap>(map).map_err(|e| format!("{}: {}", map_name, e))?;
+
+    fs::create_dir_all(dir)?;
+    for (path, bytes) in &files {
+        fs::write(path, bytes).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    Ok(files.into_iter().map(|(path, _)| path).collect())
+}
@@ -0,0 +1,104 @@
+//! A/B compare, like the compare button on a synth's front panel: one pad
+//! marks the current sound as the compare point, and another flips between
+//! it and the edits made since, ex:
+//! `"compare": { "mark": { "pad": 12 }, "toggle": { "pad": 13 } }`.
+//! Only the params that differ get written on each flip.  Edits made while
+//! listening to the compare point are dropped when flipping back.
+
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::bindings::Control;
+use crate::controllers::{ButtonState, ControllerEvent};
+use crate::param_store::ParamStore;
+
+/// The toggle pad's color while the compare point is what's playing.
+const COMPARING: (u8, u8, u8) = (0x7f, 0x20, 0x00);
+/// And while the edits are.
+const EDITING: (u8, u8, u8) = (0x08, 0x02, 0x00);
+
+/// The `compare` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CompareConfig {
+    pub mark: Control,
+    pub toggle: Control,
+}
+
+pub struct Compare {
+    mark: u8,
+    toggle: u8,
+    /// Every param's value when the compare point was marked.
+    point: Option<Vec<u32>>,
+    /// Every param's value when we flipped over to the compare point, while
+    /// it's playing.
+    edits: Option<Vec<u32>>,
+}
+
+fn pad_of(control: Control) -> Result<u8, Box<dyn Error>> {
+    match control {
+        Control::Pad(pad) => Ok(pad),
+        control => Err(format!("compare controls must be pads, not {:?}", control).into()),
+    }
+}
+
+impl Compare {
+    pub fn new(config: &CompareConfig) -> Result<Compare, Box<dyn Error>> {
+        Ok(Compare {
+            mark: pad_of(config.mark)?,
+            toggle: pad_of(config.toggle)?,
+            point: None,
+            edits: None,
+        })
+    }
+
+    /// Whether the compare point is what's playing.
+    pub fn is_comparing(&self) -> bool {
+        self.edits.is_some()
+    }
+
+    /// Whether `event` is for one of the compare pads.
+    pub fn claims(&self, event: &ControllerEvent) -> bool {
+        match *event {
+            ControllerEvent::GridButton(pad, ..) => pad == self.mark || pad == self.toggle,
+            _ => false,
+        }
+    }
+
+    /// Mark or flip for a press, returning the writes for the flip.
+    /// Marking while comparing goes back to the edits first.
+    pub fn handle(&mut self, event: &ControllerEvent, store: &ParamStore) -> Vec<(usize, u32)> {
+        let pad = match *event {
+            ControllerEvent::GridButton(pad, _, _, ButtonState::Down, _) => pad,
+            _ => return vec![],
+        };
+        if pad == self.mark {
+            let writes = match self.edits.take() {
+                Some(edits) => store.writes_to(&edits),
+                None => vec![],
+            };
+            let mut point = store.snapshot();
+            for (param, value) in &writes {
+                point[*param] = *value;
+            }
+            self.point = Some(point);
+            return writes;
+        }
+        match (&self.point, self.edits.take()) {
+            (Some(_), Some(edits)) => store.writes_to(&edits),
+            (Some(point), None) => {
+                let writes = store.writes_to(point);
+                self.edits = Some(store.snapshot());
+                writes
+            },
+            // Nothing to compare with yet.
+            (None, _) => vec![],
+        }
+    }
+
+    /// Light the toggle pad by whether the compare point is playing.
+    pub fn render<F: FnMut(u8, u8, u8, u8)>(&self, mut set_led: F) {
+        let (r, g, b) = if self.is_comparing() { COMPARING } else { EDITING };
+        set_led(self.toggle, r, g, b);
+    }
+}
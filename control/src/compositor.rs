@@ -0,0 +1,73 @@
+//! Putting together what the pads show from everything that draws on them.
+//! Each draws into its own `Layer` of a frame, and a pad shows the highest
+//! layer that drew on it, so ex: the connection status covers the bindings
+//! without either knowing about the other.  The composed frame goes to the
+//! controller, which only sends the pads that changed since the last one.
+//! The `Palette` is applied to the composed frame.
+
+use crate::controllers::GRID_LED_COUNT;
+use crate::theme::Palette;
+
+/// What draws on a layer, lowest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    /// The bindings, and anything that stands in for a binding's pad, ex:
+    /// the compare pads.
+    Base,
+    /// Animations and diagnostics, ex: the heatmap.
+    Overlay,
+    /// The splash and the connection.
+    Status,
+}
+
+const LAYERS: usize = 3;
+
+type Pixels = [Option<(u8, u8, u8)>; GRID_LED_COUNT];
+
+pub struct Compositor {
+    layers: [Pixels; LAYERS],
+    palette: Palette,
+}
+
+impl Compositor {
+    pub fn new() -> Self {
+        Compositor {
+            layers: [[None; GRID_LED_COUNT]; LAYERS],
+            palette: Palette::default(),
+        }
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Start a new frame, with nothing drawn on any layer.
+    pub fn clear(&mut self) {
+        for layer in &mut self.layers {
+            *layer = [None; GRID_LED_COUNT];
+        }
+    }
+
+    /// Draw pad `i` on `layer`.  Pads past the end of the grid are ignored.
+    pub fn set(&mut self, layer: Layer, i: u8, r: u8, g: u8, b: u8) {
+        if let Some(pixel) = self.layers[layer as usize].get_mut(i as usize) {
+            *pixel = Some((r, g, b));
+        }
+    }
+
+    /// Send each pad's color through `set_led`: the highest layer's that
+    /// drew on it, or off if none did, in the palette's colors.
+    pub fn render<F: FnMut(u8, u8, u8, u8)>(&self, mut set_led: F) {
+        for i in 0..GRID_LED_COUNT {
+            let color = self.layers.iter().rev().find_map(|layer| layer[i]).unwrap_or((0, 0, 0));
+            let (r, g, b) = self.palette.apply(color);
+            set_led(i as u8, r, g, b);
+        }
+    }
+}
+
+impl Default for Compositor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,393 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use crate::backup::BackupConfig;
+use crate::bindings::{BindingEntry, BindingsFile};
+use crate::browse::BrowseConfig;
+use crate::clock::ClockConfig;
+use crate::compare::CompareConfig;
+use crate::controllers::{DebounceConfig, PadCalibration, SysexLimits};
+#[cfg(feature = "grpc")]
+use crate::grpc::GrpcConfig;
+#[cfg(feature = "maps")]
+use crate::community::MapIndexConfig;
+use crate::health::HealthConfig;
+use crate::heatmap::HeatmapConfig;
+use crate::history::HistoryConfig;
+use crate::idle::IdleConfig;
+use crate::launcher::LauncherConfig;
+use crate::marquee::MarqueeConfig;
+#[cfg(feature = "mqtt")]
+use crate::mqtt::MqttConfig;
+use crate::panic::PanicConfig;
+use crate::ports::{port_names, resolve, PortAliases};
+use crate::program::ProgramChangeConfig;
+use crate::progress::{CancelPolicy, RecallDiff};
+use crate::router::Route;
+use crate::setlist::SetlistConfig;
+#[cfg(feature = "webmidi")]
+use crate::webmidi::BridgeConfig;
+use crate::snapshot::FingerprintPolicy;
+use crate::status::StatusConfig;
+#[cfg(feature = "sync")]
+use crate::sync::SyncConfig;
+use crate::sysex_map::{strings_path_for, MapStrings, SysexMap};
+use crate::theme::Palette;
+use crate::timers::TimersConfig;
+
+/// Where the config is looked for if not given explicitly.
+const DEFAULT_CONFIG_PATH: &str = "mapatron.json";
+
+/// Settings for a mapping session, ex:
+/// ```json
+/// {
+///   "bindings": "jupx-bindings.json",
+///   "led_brightness": 100,
+///   "profiles": {
+///     "stage": { "led_brightness": 40, "binding_overrides": [ ... ] }
+///   }
+/// }
+/// ```
+/// A profile is layered over the rest of the file: objects are merged key by
+/// key and anything else is replaced, so a profile only lists what differs.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Config {
+    /// Path of the bindings file, if not given on the command line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bindings: Option<String>,
+    /// Replace (by control) or add to the bindings from the bindings file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub binding_overrides: Vec<BindingEntry>,
+    /// Percentage applied to all controller LED colors.
+    #[serde(default = "default_led_brightness")]
+    pub led_brightness: u8,
+    /// Colors for the pads, ex: "deuteranopia" for colorblind-safe ones.
+    #[serde(default)]
+    pub palette: Palette,
+    /// Paths of `ControllerProfile`s for controllers without built-in
+    /// support.  They're looked for after the built-in ones.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub controller_profiles: Vec<String>,
+    /// Where each synth's edit counts for the favorites page are kept.
+    #[serde(default = "default_favorites_dir")]
+    pub favorites_dir: String,
+    /// Show params in this language where the map has strings for it, ex:
+    /// "de" for `jupx.de.strings.json` next to `jupx.json`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locale: Option<String>,
+    /// Paths of `MapStrings` of your own, merged over the map's (and the
+    /// locale's) in order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub strings: Vec<String>,
+    /// Chatter suppression for the controller's pads.
+    #[serde(default)]
+    pub debounce: DebounceConfig,
+    /// Per-pad velocity ranges from `mapatron calibrate`.
+    #[serde(default)]
+    pub pad_calibration: PadCalibration,
+    /// LED dimming and the display screensaver when nothing's happening.
+    #[serde(default)]
+    pub idle: IdleConfig,
+    /// The startup splash and how the pads show the synth's connection.
+    #[serde(default)]
+    pub status: StatusConfig,
+    /// How the menu's pad hit heatmap fades.
+    #[serde(default)]
+    pub heatmap: HeatmapConfig,
+    /// Scroll param changes across the pads of controllers without a
+    /// display.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marquee: Option<MarqueeConfig>,
+    /// Logical names for ports, usable anywhere else a port's named, see
+    /// `ports`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub port_aliases: PortAliases,
+    /// Only use this port for the synth, rather than the first the map's
+    /// `port_names` match, ex: an alias for one of two of the same synth.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synth_port: Option<String>,
+    /// MIDI routes between other ports, run alongside the mapping.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<Route>,
+    /// Recall snapshots on incoming program changes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub program_changes: Option<ProgramChangeConfig>,
+    /// Step through a setlist's songs from the controller.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub setlist: Option<SetlistConfig>,
+    /// A bank of pads for launching a DAW's clips, see `launcher`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub launcher: Option<LauncherConfig>,
+    /// A MIDI clock to follow, for `beats` motions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock: Option<ClockConfig>,
+    /// Whether to load snapshots saved with a different version of the map.
+    #[serde(default)]
+    pub snapshot_mismatch: FingerprintPolicy,
+    /// How big incoming sysex may get.
+    #[serde(default)]
+    pub sysex_limits: SysexLimits,
+    /// Milliseconds between bulk messages to the synth, instead of what the
+    /// map and its manufacturer's profile say.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pacing_ms: Option<u64>,
+    /// What happens to the rest of a recall or dump that another cuts short.
+    #[serde(default)]
+    pub bulk_cancel: CancelPolicy,
+    /// Whether snapshot recalls skip params that are already right.
+    #[serde(default)]
+    pub recall_diff: RecallDiff,
+    /// Append every param write to this file, see `audit`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit_log: Option<String>,
+    /// Save the synth's patch when it's read on connect, see `backup`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<BackupConfig>,
+    /// Actions at times of day or after a while, see `timers`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timers: Option<TimersConfig>,
+    /// Self-checks for systemd's watchdog, see `health`.
+    #[serde(default)]
+    pub health: HealthConfig,
+    /// Pads for flipping between a marked sound and the edits since.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compare: Option<CompareConfig>,
+    /// An encoder for scrubbing back through the synth's past states.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<HistoryConfig>,
+    /// An encoder for auditioning the library's patches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub browse: Option<BrowseConfig>,
+    /// The pad combination for the emergency stop.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub panic: Option<PanicConfig>,
+    /// Use in-memory synths built from their maps instead of the hardware.
+    #[serde(default)]
+    pub simulate: bool,
+    /// Serve the D-Bus interface, if built with the "dbus" feature.
+    #[serde(default)]
+    pub dbus: bool,
+    /// Bridge to an MQTT broker, if built with the "mqtt" feature.
+    #[cfg(feature = "mqtt")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mqtt: Option<MqttConfig>,
+    /// Serve the gRPC control API, if built with the "grpc" feature.
+    #[cfg(feature = "grpc")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub grpc: Option<GrpcConfig>,
+    /// For `mapatron bridge`, if built with the "webmidi" feature.
+    #[cfg(feature = "webmidi")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bridge: Option<BridgeConfig>,
+    /// Where `mapatron maps` gets maps from, if built with the "maps"
+    /// feature.
+    #[cfg(feature = "maps")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub map_index: Option<MapIndexConfig>,
+    /// The git-backed snapshot library, if built with the "sync" feature.
+    #[cfg(feature = "sync")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync: Option<SyncConfig>,
+}
+
+fn default_led_brightness() -> u8 {
+    100
+}
+
+fn default_favorites_dir() -> String {
+    "favorites".to_string()
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            bindings: None,
+            binding_overrides: vec![],
+            led_brightness: default_led_brightness(),
+            palette: Palette::default(),
+            controller_profiles: vec![],
+            favorites_dir: default_favorites_dir(),
+            locale: None,
+            strings: vec![],
+            debounce: DebounceConfig::default(),
+            pad_calibration: PadCalibration::default(),
+            idle: IdleConfig::default(),
+            status: StatusConfig::default(),
+            heatmap: HeatmapConfig::default(),
+            marquee: None,
+            port_aliases: PortAliases::new(),
+            synth_port: None,
+            routes: vec![],
+            program_changes: None,
+            setlist: None,
+            launcher: None,
+            clock: None,
+            snapshot_mismatch: FingerprintPolicy::default(),
+            sysex_limits: SysexLimits::default(),
+            pacing_ms: None,
+            bulk_cancel: CancelPolicy::default(),
+            recall_diff: RecallDiff::default(),
+            audit_log: None,
+            backup: None,
+            timers: None,
+            health: HealthConfig::default(),
+            compare: None,
+            history: None,
+            browse: None,
+            panic: None,
+            simulate: false,
+            dbus: false,
+            #[cfg(feature = "mqtt")]
+            mqtt: None,
+            #[cfg(feature = "grpc")]
+            grpc: None,
+            #[cfg(feature = "webmidi")]
+            bridge: None,
+            #[cfg(feature = "maps")]
+            map_index: None,
+            #[cfg(feature = "sync")]
+            sync: None,
+        }
+    }
+}
+
+/// `$MAPATRON_CONFIG` if set, otherwise `mapatron.json`.
+pub fn config_path() -> PathBuf {
+    env::var_os("MAPATRON_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH))
+}
+
+fn merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Object(base), Value::Object(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    },
+                }
+            }
+        },
+        (base, over) => *base = over,
+    }
+}
+
+impl Config {
+    /// Load the config at `path` (or `config_path()`) with `profile` layered
+    /// over it.  A missing file is fine unless a profile was asked for.
+    pub fn load(path: Option<&Path>, profile: Option<&str>) -> Result<Config, Box<dyn Error>> {
+        let path = path.map(PathBuf::from).unwrap_or_else(config_path);
+        if !path.exists() && profile.is_none() {
+            return Ok(Config::default());
+        }
+        let file = File::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let mut value: Value = serde_json::from_reader(BufReader::new(file))?;
+
+        let profiles = value.as_object_mut().and_then(|obj| obj.remove("profiles"));
+        if let Some(profile) = profile {
+            let over = profiles.as_ref().and_then(|p| p.get(profile))
+                .ok_or_else(|| format!("no profile '{}' in {}", profile, path.display()))?;
+            merge(&mut value, over.clone());
+        }
+        let mut config: Config = serde_json::from_value(value)?;
+        config.resolve_port_aliases();
+        Ok(config)
+    }
+
+    /// Swap the aliases the config uses for the ports they match now.  Ones
+    /// that match nothing are left for whatever opens the port to complain
+    /// about.
+    fn resolve_port_aliases(&mut self) {
+        if self.port_aliases.is_empty() {
+            return;
+        }
+        let ports = port_names();
+        let aliases = &self.port_aliases;
+        let swap = |name: &mut String| match resolve(aliases, name, &ports) {
+            Ok(port) => *name = port,
+            Err(e) => warn!("{}", e),
+        };
+        for route in &mut self.routes {
+            swap(&mut route.from);
+            swap(&mut route.to);
+        }
+        if let Some(program_changes) = &mut self.program_changes {
+            swap(&mut program_changes.port);
+        }
+        if let Some(port) = &mut self.synth_port {
+            swap(port);
+        }
+        if let Some(launcher) = &mut self.launcher {
+            swap(&mut launcher.port);
+        }
+        if let Some(clock) = &mut self.clock {
+            swap(&mut clock.port);
+        }
+    }
+
+    /// Set `key` to `setting` in the config file at `path` (or
+    /// `config_path()`), or in its `profile` if given, leaving the rest of the
+    /// file as it was.  The file is created if it doesn't exist.  Returns the
+    /// path written.
+    pub fn save_setting(path: Option<&Path>, profile: Option<&str>, key: &str, setting: Value)
+                        -> Result<PathBuf, Box<dyn Error>> {
+        let path = path.map(PathBuf::from).unwrap_or_else(config_path);
+        let mut value: Value = if path.exists() {
+            let file = File::open(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+            serde_json::from_reader(BufReader::new(file))?
+        } else {
+            Value::Object(Default::default())
+        };
+        let mut section = value.as_object_mut()
+            .ok_or_else(|| format!("{} isn't a json object", path.display()))?;
+        if let Some(profile) = profile {
+            section = section.entry("profiles").or_insert_with(|| Value::Object(Default::default()))
+                .as_object_mut().ok_or("'profiles' isn't an object")?
+                .entry(profile).or_insert_with(|| Value::Object(Default::default()))
+                .as_object_mut().ok_or_else(|| format!("profile '{}' isn't an object", profile))?;
+        }
+        section.insert(key.to_string(), setting);
+        fs::write(&path, serde_json::to_string_pretty(&value)? + "\n")
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(path)
+    }
+
+    /// Load the map for `device` with the `locale`'s strings, if the map has
+    /// any, and then `strings` merged over it.
+    pub fn load_map(&self, device: &str) -> Result<SysexMap, Box<dyn Error>> {
+        let mut map = SysexMap::load_device(device)?;
+        if let Some(port) = &self.synth_port {
+            map.port_names = vec![port.clone()];
+            map.ignore_port_names.clear();
+        }
+        let locale = self.locale.as_ref().map(|locale| strings_path_for(device, locale))
+            .filter(|path| path.exists());
+        let paths = locale.iter().map(|path| path.to_string_lossy().into_owned())
+            .chain(self.strings.iter().cloned());
+        for path in paths {
+            let strings = MapStrings::load(&path).map_err(|e| format!("{}: {}", path, e))?;
+            for name in map.apply_strings(&strings) {
+                warn!("{}: '{}' isn't in the {} map", path, name, device);
+            }
+        }
+        Ok(map)
+    }
+
+    /// Apply `binding_overrides` to a loaded bindings file.
+    pub fn apply_overrides(&self, file: &mut BindingsFile) {
+        for over in &self.binding_overrides {
+            file.bindings.retain(|b| b.control != over.control);
+            file.bindings.push(over.clone());
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! The Akai APC mini: an 8x8 grid of three-color pads and 9 faders.  The pads
+//! are notes with the bottom row first, and are lit by sending the same
+//! notes back with a velocity picking the color.  The faders are absolute,
+//! so bindings on them need a takeover mode; see `bindings::Takeover`.
+
+use std::sync::Arc;
+
+use super::model::Model;
+use super::sysex_mapped::Controller;
+use super::{ButtonState, ControllerCaps, ControllerEvent, LedBuffer};
+
+/// The APC mini's MIDI ports start with this.  The mk2 names its ports
+/// differently and speaks a different protocol.
+pub const APC_MINI_PORT_PREFIX: &str = "APC MINI";
+
+const GRID_SIZE: u8 = 8;
+const FADER_CC_FIRST: u8 = 0x30;
+const FADER_CC_LAST: u8 = 0x38;
+
+// Pad LED velocities.  The odd ones after each color blink it.
+const LED_OFF: u8 = 0;
+const LED_GREEN: u8 = 1;
+const LED_RED: u8 = 3;
+const LED_YELLOW: u8 = 5;
+
+pub struct ApcMini;
+
+impl ApcMini {
+    /// Our pads are numbered from the top left; the APC's notes from the
+    /// bottom left.  The mapping is its own inverse.
+    fn flip(idx: u8) -> u8 {
+        (GRID_SIZE - 1 - idx / GRID_SIZE) * GRID_SIZE + idx % GRID_SIZE
+    }
+
+    /// The nearest of the pad's colors.  Blue shows as green.
+    fn velocity_for((r, g, b): (u8, u8, u8)) -> u8 {
+        match (r > 0, g > 0 || b > 0) {
+            (true, true) => LED_YELLOW,
+            (true, false) => LED_RED,
+            (false, true) => LED_GREEN,
+            (false, false) => LED_OFF,
+        }
+    }
+}
+
+impl Model for ApcMini {
+    fn caps(&self) -> ControllerCaps {
+        ControllerCaps {
+            rows: GRID_SIZE,
+            columns: GRID_SIZE,
+            has_display: false,
+            encoders: 0,
+            encoder_rings: false,
+            faders: FADER_CC_LAST - FADER_CC_FIRST + 1,
+            knobs: 0,
+            rgb_bits: 1,
+            aftertouch: false,
+        }
+    }
+
+    fn decode(&self, msg: &[u8]) -> Option<ControllerEvent> {
+        match *msg {
+            [status, note, velocity] if (status & 0xf0 == 0x90 || status & 0xf0 == 0x80) &&
+                                        note < GRID_SIZE * GRID_SIZE => {
+                let idx = Self::flip(note);
+                let state = if status & 0xf0 == 0x90 && velocity > 0 {
+                    ButtonState::Down
+                } else {
+                    ButtonState::Up
+                };
+                // The pads aren't velocity sensitive; presses are all 0x7f.
+                Some(ControllerEvent::GridButton(idx, idx / GRID_SIZE, idx % GRID_SIZE, state, velocity))
+            },
+            [status, cc, value] if status & 0xf0 == 0xb0 && (FADER_CC_FIRST..=FADER_CC_LAST).contains(&cc) => {
+                Some(ControllerEvent::Fader(cc - FADER_CC_FIRST, value))
+            },
+            _ => None,
+        }
+    }
+
+    fn send_leds(&self, leds: &LedBuffer, pads: &[u8], send: &mut dyn FnMut(&[u8])) {
+        for &idx in pads.iter().filter(|&&idx| idx < GRID_SIZE * GRID_SIZE) {
+            send(&[0x90, Self::flip(idx), Self::velocity_for(leds.led(idx))]);
+        }
+    }
+}
+
+/// Finds all APC minis on the system and returns them in a vector.
+pub fn attach_apc_minis() -> Vec<Controller> {
+    Controller::attach_model(&[APC_MINI_PORT_PREFIX], Arc::new(ApcMini))
+}
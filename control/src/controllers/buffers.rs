@@ -0,0 +1,146 @@
+//! Buffers for incoming sysex.  Each port's input callback copies messages
+//! into buffers from its pool, which go back to the pool when the event is
+//! dropped, so a stream of big dumps reuses a few allocations instead of
+//! making new ones.  Messages over `max_message` bytes are dropped at the
+//! port, so a device gone haywire can't run the daemon out of memory, ex:
+//! `"sysex_limits": { "max_message": 131072, "pooled": 8 }`.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SysexLimits {
+    /// The longest message accepted, in bytes, 0xf0 and 0xf7 included.
+    #[serde(default = "default_max_message")]
+    pub max_message: usize,
+    /// How many free buffers each port keeps around.
+    #[serde(default = "default_pooled")]
+    pub pooled: usize,
+}
+
+fn default_max_message() -> usize {
+    256 * 1024
+}
+
+fn default_pooled() -> usize {
+    8
+}
+
+impl Default for SysexLimits {
+    fn default() -> Self {
+        SysexLimits {
+            max_message: default_max_message(),
+            pooled: default_pooled(),
+        }
+    }
+}
+
+struct PoolState {
+    limits: SysexLimits,
+    free: Vec<Vec<u8>>,
+    /// Messages dropped for being too long.
+    rejected: usize,
+}
+
+pub struct BufferPool {
+    state: Mutex<PoolState>,
+}
+
+impl BufferPool {
+    pub fn new(limits: SysexLimits) -> Arc<BufferPool> {
+        Arc::new(BufferPool {
+            state: Mutex::new(PoolState {
+                limits,
+                free: vec![],
+                rejected: 0,
+            }),
+        })
+    }
+
+    /// Change the limits.  Free buffers over the new ones are let go.
+    pub fn set_limits(&self, limits: SysexLimits) {
+        let mut state = self.state.lock().unwrap();
+        state.free.retain(|buf| buf.capacity() <= limits.max_message);
+        state.free.truncate(limits.pooled);
+        state.limits = limits;
+    }
+
+    /// A copy of `msg` in a pooled buffer, or None if it's over the limit.
+    pub fn copy(self: &Arc<Self>, msg: &[u8]) -> Option<SysexBuf> {
+        let mut state = self.state.lock().unwrap();
+        if msg.len() > state.limits.max_message {
+            state.rejected += 1;
+            warn!("dropping a {} byte sysex message; the limit is {}", msg.len(),
+                  state.limits.max_message);
+            return None;
+        }
+        let mut data = state.free.pop().unwrap_or_default();
+        drop(state);
+        data.extend_from_slice(msg);
+        Some(SysexBuf {
+            data,
+            pool: Some(self.clone()),
+        })
+    }
+
+    /// How many messages have been dropped for being too long.
+    pub fn rejected(&self) -> usize {
+        self.state.lock().unwrap().rejected
+    }
+
+    fn give_back(&self, mut data: Vec<u8>) {
+        let mut state = self.state.lock().unwrap();
+        if data.capacity() <= state.limits.max_message && state.free.len() < state.limits.pooled {
+            data.clear();
+            state.free.push(data);
+        }
+    }
+}
+
+/// A complete sysex message, possibly on loan from a `BufferPool`.
+pub struct SysexBuf {
+    data: Vec<u8>,
+    pool: Option<Arc<BufferPool>>,
+}
+
+impl From<Vec<u8>> for SysexBuf {
+    fn from(data: Vec<u8>) -> Self {
+        SysexBuf {
+            data,
+            pool: None,
+        }
+    }
+}
+
+impl Deref for SysexBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+impl Clone for SysexBuf {
+    /// Clones don't come from the pool.
+    fn clone(&self) -> Self {
+        SysexBuf::from(self.data.clone())
+    }
+}
+
+impl fmt::Debug for SysexBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.data.fmt(f)
+    }
+}
+
+impl Drop for SysexBuf {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.give_back(std::mem::take(&mut self.data));
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Evening out the Fire's pads, which don't all respond to the same press
+//! with the same velocity.  `mapatron calibrate` records the softest and
+//! hardest presses each pad reports and saves them to the config, ex:
+//! `"pad_calibration": { "curve": 1.5, "pads": { "0": { "min": 12, "max": 118 } } }`,
+//! and from then on each pad's range is stretched to the full 1-127 before
+//! `curve` is applied.
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+use super::{ButtonState, ControllerEvent, GRID_LED_COUNT};
+
+/// The velocities a pad was seen to produce.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PadRange {
+    pub min: u8,
+    pub max: u8,
+}
+
+/// The `pad_calibration` section of the config.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PadCalibration {
+    /// Exponent applied after normalizing: above 1 needs harder presses for
+    /// high velocities, below 1 softer ones.
+    #[serde(default = "default_curve")]
+    pub curve: f32,
+    /// By pad index.  Pads not listed are taken to cover the full range.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub pads: HashMap<u8, PadRange>,
+}
+
+fn default_curve() -> f32 {
+    1.0
+}
+
+impl Default for PadCalibration {
+    fn default() -> Self {
+        PadCalibration {
+            curve: default_curve(),
+            pads: HashMap::new(),
+        }
+    }
+}
+
+impl PadCalibration {
+    /// `event` with a press's velocity normalized.  Releases and everything
+    /// else pass through as they are.
+    pub fn apply(&self, event: ControllerEvent) -> ControllerEvent {
+        let (idx, row, col, velocity) = match event {
+            ControllerEvent::GridButton(idx, row, col, ButtonState::Down, velocity) => (idx, row, col, velocity),
+            _ => return event,
+        };
+        let range = self.pads.get(&idx).copied().unwrap_or(PadRange { min: 1, max: 127 });
+        if range.max <= range.min || (range.min, range.max, self.curve) == (1, 127, 1.0) {
+            return event;
+        }
+        let t = (velocity.saturating_sub(range.min) as f32 / (range.max - range.min) as f32).min(1.0);
+        let velocity = 1 + (t.powf(self.curve) * 126.0).round() as u8;
+        ControllerEvent::GridButton(idx, row, col, ButtonState::Down, velocity)
+    }
+}
+
+/// Collects the range of velocities each pad produces while calibrating.
+pub struct Calibrator {
+    ranges: Vec<Option<PadRange>>,
+}
+
+impl Calibrator {
+    pub fn new() -> Self {
+        Calibrator {
+            ranges: vec![None; GRID_LED_COUNT],
+        }
+    }
+
+    /// Note the velocity of a press.  Returns the pad it was for, if it was
+    /// one.
+    pub fn record(&mut self, event: &ControllerEvent) -> Option<u8> {
+        let (idx, velocity) = match *event {
+            ControllerEvent::GridButton(idx, _, _, ButtonState::Down, velocity) => (idx, velocity),
+            _ => return None,
+        };
+        let range = self.ranges.get_mut(idx as usize)?;
+        *range = Some(match *range {
+            Some(r) => PadRange { min: r.min.min(velocity), max: r.max.max(velocity) },
+            None => PadRange { min: velocity, max: velocity },
+        });
+        Some(idx)
+    }
+
+    /// The range recorded for pad `idx`, if it's been pressed.
+    pub fn range(&self, idx: u8) -> Option<PadRange> {
+        self.ranges.get(idx as usize).copied().flatten()
+    }
+
+    /// The calibration for everything recorded.  Pads pressed at only one
+    /// velocity don't tell us anything and are left out.
+    pub fn finish(&self, curve: f32) -> PadCalibration {
+        let pads = self.ranges.iter().enumerate()
+            .filter_map(|(i, r)| r.filter(|r| r.max > r.min).map(|r| (i as u8, r)))
+            .collect();
+        PadCalibration {
+            curve,
+            pads,
+        }
+    }
+}
+
+impl Default for Calibrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,77 @@
+//! What a controller has to work with.  Bindings, layouts and LED rendering
+//! go by these rather than by the Fire's dimensions, so a map's bindings
+//! can be checked against, and drawn on, whatever surface is attached.
+
+use serde::{Deserialize, Serialize};
+
+use super::GRID_LED_COUNT;
+
+/// A controller's surface, ex: `{ "rows": 8, "columns": 8, "faders": 9,
+/// "rgb_bits": 1 }`.  The default is no surface at all, as on a synth's
+/// ports.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControllerCaps {
+    /// The pad grid, numbered row-major from the top left.
+    pub rows: u8,
+    pub columns: u8,
+    pub has_display: bool,
+    pub encoders: u8,
+    /// Whether the encoders have LED rings to show their values on.
+    pub encoder_rings: bool,
+    pub faders: u8,
+    /// Knobs that turn between end stops, unlike encoders.
+    pub knobs: u8,
+    /// Bits per color channel the pads can show, 0 for single-color pads.
+    pub rgb_bits: u8,
+    /// Whether pads report pressure after the initial hit.
+    pub aftertouch: bool,
+}
+
+impl ControllerCaps {
+    /// The Akai Fire: 4 rows of 16 RGB pads, an OLED and 4 encoders.
+    pub const FIRE: ControllerCaps = ControllerCaps {
+        rows: 4,
+        columns: 16,
+        has_display: true,
+        encoders: 4,
+        encoder_rings: false,
+        faders: 0,
+        knobs: 0,
+        rgb_bits: 7,
+        aftertouch: false,
+    };
+
+    /// How many pads there are, up to the `GRID_LED_COUNT` we can light.
+    pub fn pads(&self) -> usize {
+        (self.rows as usize * self.columns as usize).min(GRID_LED_COUNT)
+    }
+
+    /// The (row, column) of pad `pad`.
+    pub fn row_col(&self, pad: u8) -> (u8, u8) {
+        let columns = self.columns.max(1);
+        (pad / columns, pad % columns)
+    }
+
+    /// The index of the pad at `row`, `col`.
+    pub fn pad_at(&self, row: u8, col: u8) -> u8 {
+        row * self.columns + col
+    }
+
+    /// Reduce a 7-bit color to what the pads can show.  Single-color pads
+    /// are lit by whichever channel is brightest.
+    pub fn quantize(&self, r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+        match self.rgb_bits {
+            0 => {
+                let level = r.max(g).max(b);
+                (level, level, level)
+            },
+            bits if bits < 7 => {
+                let drop = 7 - bits;
+                let q = |c: u8| (c.min(0x7f) >> drop) << drop;
+                (q(r), q(g), q(b))
+            },
+            _ => (r, g, b),
+        }
+    }
+}
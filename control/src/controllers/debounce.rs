@@ -0,0 +1,103 @@
+//! Suppressing chatter from worn pads, ex:
+//! `"debounce": { "window_ms": 15, "pads": { "37": 40 } }`.
+//!
+//! The first edge of a press or release goes through immediately, so clean
+//! presses aren't delayed.  Edges within the window after it are held back,
+//! and if the pad has ended up in a different state once the window is over,
+//! that state is reported then.
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use super::{ButtonState, ControllerEvent, GRID_LED_COUNT};
+
+/// The `debounce` section of the config.  A window of 0 turns debouncing off.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DebounceConfig {
+    /// For every pad not listed in `pads`.
+    #[serde(default)]
+    pub window_ms: u32,
+    /// Windows for particular pads, by index.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub pads: HashMap<u8, u32>,
+}
+
+#[derive(Clone, Copy)]
+struct Pad {
+    window: Duration,
+    /// What we last passed on.
+    reported: ButtonState,
+    /// What the pad last said, with its velocity.
+    raw: (ButtonState, u8),
+    /// When the window opened by the last reported edge closes.
+    settles_at: Option<Instant>,
+}
+
+pub struct Debouncer {
+    pads: Vec<Pad>,
+}
+
+impl Debouncer {
+    pub fn new(config: &DebounceConfig) -> Self {
+        let pads = (0..GRID_LED_COUNT as u8).map(|i| {
+            let ms = config.pads.get(&i).copied().unwrap_or(config.window_ms);
+            Pad {
+                window: Duration::from_millis(ms as u64),
+                reported: ButtonState::Up,
+                raw: (ButtonState::Up, 0),
+                settles_at: None,
+            }
+        }).collect();
+        Debouncer {
+            pads,
+        }
+    }
+
+    /// Pass `event` on, or None if it's a bounce.  Events other than pads
+    /// always go through.
+    pub fn filter(&mut self, event: ControllerEvent, now: Instant) -> Option<ControllerEvent> {
+        let (idx, state, velocity) = match event {
+            ControllerEvent::GridButton(idx, _, _, state, velocity) => (idx, state, velocity),
+            _ => return Some(event),
+        };
+        let pad = match self.pads.get_mut(idx as usize) {
+            Some(pad) if pad.window > Duration::from_millis(0) => pad,
+            _ => return Some(event),
+        };
+        pad.raw = (state, velocity);
+        if let Some(settles_at) = pad.settles_at {
+            if now < settles_at {
+                return None;
+            }
+        }
+        pad.reported = state;
+        pad.settles_at = Some(now + pad.window);
+        Some(event)
+    }
+
+    /// Events for pads whose window has closed with them in a different state
+    /// than was last reported.  Call often (ex: every `scheduler::TICK`) so
+    /// that held-back releases aren't late.
+    pub fn settle(&mut self, now: Instant) -> Vec<ControllerEvent> {
+        let mut events = vec![];
+        for (idx, pad) in self.pads.iter_mut().enumerate() {
+            match pad.settles_at {
+                Some(settles_at) if settles_at <= now => (),
+                _ => continue,
+            }
+            let (state, velocity) = pad.raw;
+            if state == pad.reported {
+                pad.settles_at = None;
+                continue;
+            }
+            // This counts as a new edge, with its own window.
+            pad.reported = state;
+            pad.settles_at = Some(now + pad.window);
+            let idx = idx as u8;
+            events.push(ControllerEvent::GridButton(idx, idx / 16, idx % 16, state, velocity));
+        }
+        events
+    }
+}
@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use super::buffers::{BufferPool, SysexBuf};
+use super::lifecycle::Phase;
+use super::model::Model;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ButtonState {
+    Down,
+    Up,
+}
+
+/// Events produced by a connected controller.  These are sent over the
+/// controller's `event_rx` channel from the midir callback thread, so they need
+/// to be cheap to construct and self-contained.
+#[derive(Clone, Debug)]
+pub enum ControllerEvent {
+    /// A grid pad was pressed or released.  The payload is
+    /// (index, row, column, state, velocity) where index is `row * 16 + col`.
+    GridButton(u8, u8, u8, ButtonState, u8),
+    /// One of the 4 encoders above the grid was turned by a signed number of
+    /// detents: (index, delta).
+    Encoder(u8, i8),
+    /// A fader was moved: (index, position), 0 at the bottom to 0x7f.
+    Fader(u8, u8),
+    /// A knob with end stops was turned: (index, position).
+    Knob(u8, u8),
+    /// A held pad's pressure changed: (index, pressure).
+    PadPressure(u8, u8),
+    /// The select encoder was turned by a signed number of detents.
+    Select(i8),
+    /// The select encoder was pushed or let go.
+    SelectButton(ButtonState),
+    /// A complete sysex message, including the leading 0xf0 and trailing 0xf7.
+    Sysex(SysexBuf),
+    /// The output connection stalled and was torn down and re-established.
+    /// Anything that was previously sent to the device (LEDs, display
+    /// contents) should be assumed lost and re-sent.
+    Recovered,
+    /// The device had been transmitting Active Sensing and has now gone quiet
+    /// for longer than the spec allows.  Probably a dead cable or a crashed
+    /// device.
+    DeviceSilent,
+    /// A device previously reported as `DeviceSilent` is being heard from
+    /// again.
+    DeviceActive,
+    /// The connection moved on to this phase of its lifecycle.
+    PhaseChanged(Phase),
+}
+
+// The Fire's grid pads are notes 0x36 through 0x75, row-major.
+const GRID_NOTE_FIRST: u8 = 0x36;
+const GRID_NOTE_LAST: u8 = 0x75;
+// The 4 encoders send relative CCs 0x10 through 0x13.
+const ENCODER_CC_FIRST: u8 = 0x10;
+const ENCODER_CC_LAST: u8 = 0x13;
+// The select encoder turns as CC 0x76 and pushes as note 0x19.
+const SELECT_CC: u8 = 0x76;
+const SELECT_NOTE: u8 = 0x19;
+
+/// A 7-bit two's complement encoder value as detents: 1 is one clockwise,
+/// 0x7f one counter-clockwise.
+fn detents(value: u8) -> i8 {
+    if value < 0x40 { value as i8 } else { (value as i16 - 0x80) as i8 }
+}
+
+impl ControllerEvent {
+    /// The event for `msg` from a `model` controller.  Sysex is copied into
+    /// a buffer from `pool` and dropped if it's over the pool's limit.
+    pub fn from_midi_in(msg: &[u8], pool: &Arc<BufferPool>, model: &dyn Model) -> Option<ControllerEvent> {
+        match msg {
+            [0xf0, .., 0xf7] => pool.copy(msg).map(ControllerEvent::Sysex),
+            _ => model.decode(msg),
+        }
+    }
+
+    /// The event for `msg` from a Fire.
+    pub fn from_midi(msg: &[u8]) -> Option<ControllerEvent> {
+        match msg {
+            [0xf0, .., 0xf7] => Some(ControllerEvent::Sysex(SysexBuf::from(msg.to_vec()))),
+            [status, note, velocity] if (status & 0xf0 == 0x90 || status & 0xf0 == 0x80) &&
+                                        *note >= GRID_NOTE_FIRST && *note <= GRID_NOTE_LAST => {
+                let idx = note - GRID_NOTE_FIRST;
+                // Note-on with a velocity of 0 is a note-off by convention.
+                let state = if status & 0xf0 == 0x90 && *velocity > 0 {
+                    ButtonState::Down
+                } else {
+                    ButtonState::Up
+                };
+                Some(ControllerEvent::GridButton(idx, idx / 16, idx % 16, state, *velocity))
+            },
+            [status, cc, value] if status & 0xf0 == 0xb0 &&
+                                    *cc >= ENCODER_CC_FIRST && *cc <= ENCODER_CC_LAST => {
+                Some(ControllerEvent::Encoder(cc - ENCODER_CC_FIRST, detents(*value)))
+            },
+            [status, SELECT_CC, value] if status & 0xf0 == 0xb0 => Some(ControllerEvent::Select(detents(*value))),
+            [status, SELECT_NOTE, velocity] if status & 0xf0 == 0x90 || status & 0xf0 == 0x80 => {
+                let down = status & 0xf0 == 0x90 && *velocity > 0;
+                Some(ControllerEvent::SelectButton(if down { ButtonState::Down } else { ButtonState::Up }))
+            },
+            _ => None
+        }
+    }
+
+    /// The message a Fire sends for this event, the inverse of `from_midi`,
+    /// if a Fire can send it.
+    pub fn to_midi(&self) -> Option<Vec<u8>> {
+        let relative = |detents: i8| (detents as u8) & 0x7f;
+        match *self {
+            ControllerEvent::GridButton(idx, _, _, state, velocity) if idx <= GRID_NOTE_LAST - GRID_NOTE_FIRST => {
+                Some(match state {
+                    ButtonState::Down => vec![0x90, GRID_NOTE_FIRST + idx, velocity.max(1)],
+                    ButtonState::Up => vec![0x80, GRID_NOTE_FIRST + idx, 0],
+                })
+            },
+            ControllerEvent::Encoder(idx, delta) if idx <= ENCODER_CC_LAST - ENCODER_CC_FIRST => {
+                Some(vec![0xb0, ENCODER_CC_FIRST + idx, relative(delta)])
+            },
+            ControllerEvent::Select(delta) => Some(vec![0xb0, SELECT_CC, relative(delta)]),
+            ControllerEvent::SelectButton(ButtonState::Down) => Some(vec![0x90, SELECT_NOTE, 0x7f]),
+            ControllerEvent::SelectButton(ButtonState::Up) => Some(vec![0x80, SELECT_NOTE, 0]),
+            ControllerEvent::Sysex(ref msg) => Some(msg.to_vec()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use super::model::Model;
+use super::sysex_mapped::Controller;
+use super::{ControllerCaps, ControllerEvent, LedBuffer, OledBuffer};
+
+/// All of the Akai Fire's MIDI ports start with this.
+pub const FIRE_PORT_PREFIX: &str = "FL STUDIO FIRE";
+
+/// The Fire takes the whole grid's colors in one sysex message.
+pub struct Fire;
+
+impl Model for Fire {
+    fn caps(&self) -> ControllerCaps {
+        ControllerCaps::FIRE
+    }
+
+    fn decode(&self, msg: &[u8]) -> Option<ControllerEvent> {
+        ControllerEvent::from_midi(msg)
+    }
+
+    fn send_leds(&self, leds: &LedBuffer, pads: &[u8], send: &mut dyn FnMut(&[u8])) {
+        send(&leds.message_for(pads));
+    }
+
+    fn send_display(&self, oled: &OledBuffer, send: &mut dyn FnMut(&[u8])) {
+        send(oled.as_bytes());
+    }
+}
+
+/// Finds all Fire controllers on the system and returns them in a vector.
+pub fn attach_fires() -> Vec<Controller> {
+    Controller::attach_model(&[FIRE_PORT_PREFIX], Arc::new(Fire))
+}
@@ -0,0 +1,106 @@
+//! The Novation Launch Control XL: 3 rows of 8 knobs, 8 faders and 2 rows
+//! of 8 buttons, which stand in for a 2x8 grid of pads.  The knobs and
+//! faders are absolute, so bindings on them pick up params the way their
+//! `takeover` says.  We switch it to the first factory template when it
+//! connects, so the messages are the ones below whatever the user last
+//! picked, and light the buttons with the template LED sysex.
+
+use std::sync::Arc;
+
+use super::model::Model;
+use super::sysex_mapped::Controller;
+use super::{ButtonState, ControllerCaps, ControllerEvent, LedBuffer};
+
+/// The Launch Control XL's MIDI ports start with this.
+pub const LAUNCH_CONTROL_XL_PORT_PREFIX: &str = "Launch Control XL";
+
+/// Factory template 1, which sends on channel 9.
+const TEMPLATE: u8 = 8;
+const CHANNEL: u8 = 8;
+const COLUMNS: u8 = 8;
+/// The first CC of each row of knobs, top to bottom.
+const KNOB_ROW_CCS: [u8; 3] = [13, 29, 49];
+const FADER_CC_FIRST: u8 = 77;
+/// The notes of the buttons, track focus then track control.
+const BUTTON_NOTES: [u8; 16] = [
+    41, 42, 43, 44, 57, 58, 59, 60,
+    73, 74, 75, 76, 89, 90, 91, 92,
+];
+/// The LED index of the first button, after the knobs'.
+const BUTTON_LED_FIRST: u8 = 24;
+
+const SYSEX_HEADER: [u8; 6] = [0xf0, 0x00, 0x20, 0x29, 0x02, 0x11];
+const SELECT_TEMPLATE: u8 = 0x77;
+const SET_LEDS: u8 = 0x78;
+/// Added to an LED color to have it shown straight away.
+const LED_COPY_CLEAR: u8 = 0x0c;
+
+pub struct LaunchControlXl;
+
+impl LaunchControlXl {
+    /// Red and green are 2 bits each; blue shows as green.
+    fn led_value((r, g, b): (u8, u8, u8)) -> u8 {
+        ((g.max(b) >> 5) << 4) | (r >> 5) | LED_COPY_CLEAR
+    }
+}
+
+impl Model for LaunchControlXl {
+    fn caps(&self) -> ControllerCaps {
+        ControllerCaps {
+            rows: BUTTON_NOTES.len() as u8 / COLUMNS,
+            columns: COLUMNS,
+            has_display: false,
+            encoders: 0,
+            encoder_rings: false,
+            faders: COLUMNS,
+            knobs: KNOB_ROW_CCS.len() as u8 * COLUMNS,
+            rgb_bits: 2,
+            aftertouch: false,
+        }
+    }
+
+    fn start_messages(&self) -> Vec<Vec<u8>> {
+        let mut select = SYSEX_HEADER.to_vec();
+        select.extend_from_slice(&[SELECT_TEMPLATE, TEMPLATE, 0xf7]);
+        vec![select]
+    }
+
+    fn decode(&self, msg: &[u8]) -> Option<ControllerEvent> {
+        match *msg {
+            [status, note, velocity] if status == 0x90 | CHANNEL || status == 0x80 | CHANNEL => {
+                let idx = BUTTON_NOTES.iter().position(|n| *n == note)? as u8;
+                let state = if status & 0xf0 == 0x90 && velocity > 0 {
+                    ButtonState::Down
+                } else {
+                    ButtonState::Up
+                };
+                Some(ControllerEvent::GridButton(idx, idx / COLUMNS, idx % COLUMNS, state, velocity))
+            },
+            [status, cc, value] if status == 0xb0 | CHANNEL => {
+                if (FADER_CC_FIRST..FADER_CC_FIRST + COLUMNS).contains(&cc) {
+                    return Some(ControllerEvent::Fader(cc - FADER_CC_FIRST, value));
+                }
+                KNOB_ROW_CCS.iter().enumerate()
+                    .find(|(_, first)| (**first..**first + COLUMNS).contains(&cc))
+                    .map(|(row, first)| ControllerEvent::Knob(row as u8 * COLUMNS + cc - first, value))
+            },
+            _ => None,
+        }
+    }
+
+    fn send_leds(&self, leds: &LedBuffer, pads: &[u8], send: &mut dyn FnMut(&[u8])) {
+        let mut msg = [0; 11];
+        msg[..6].copy_from_slice(&SYSEX_HEADER);
+        msg[6..8].copy_from_slice(&[SET_LEDS, TEMPLATE]);
+        msg[10] = 0xf7;
+        for &idx in pads.iter().filter(|&&idx| (idx as usize) < BUTTON_NOTES.len()) {
+            msg[8..10].copy_from_slice(&[BUTTON_LED_FIRST + idx, Self::led_value(leds.led(idx))]);
+            send(&msg);
+        }
+    }
+}
+
+/// Finds all Launch Control XLs on the system and returns them in a vector.
+pub fn attach_launch_control_xls() -> Vec<Controller> {
+    Controller::attach_model(&[LAUNCH_CONTROL_XL_PORT_PREFIX], Arc::new(LaunchControlXl))
+}
@@ -0,0 +1,95 @@
+use std::cmp::min;
+
+pub const GRID_LED_COUNT: usize = 64;
+
+// 7 header bytes + (4 bytes per grid led * 64 leds) + 1 end byte.
+const LED_MSG_LEN: usize = 7 + 4 * GRID_LED_COUNT + 1;
+
+/// The Fire's "set pad colors" sysex message for the whole grid, kept fully
+/// formed so that updating the LEDs is just a matter of sending the buffer.
+/// A controller keeps one it draws into and a copy of the last one it sent,
+/// so only the pads that changed between them need sending.
+#[derive(Clone)]
+pub struct LedBuffer {
+    buf: [u8; LED_MSG_LEN],
+    /// Percentage applied to colors as they're set.
+    brightness: u8,
+}
+
+impl LedBuffer {
+    pub fn new() -> Self {
+        let mut leds = LedBuffer {
+            buf: [0; LED_MSG_LEN],
+            brightness: 100,
+        };
+
+        let len: u16 = 4 * GRID_LED_COUNT as u16;
+        leds.buf[0..7].copy_from_slice(
+            &[0xf0, 0x47, 0x7f, 0x43, 0x65, ((len >> 7)&0x7f) as u8, (len&0x7f) as u8]);
+
+        // The first byte of each 4-byte tuple is the index of the button to
+        // update.
+        for i in 0..GRID_LED_COUNT {
+            leds.buf[7 + i * 4] = i as u8;
+        }
+        leds.buf[LED_MSG_LEN - 1] = 0xf7;
+        leds
+    }
+
+    /// Scale colors set from now on to `percent` of what's asked for.
+    pub fn set_brightness(&mut self, percent: u8) {
+        self.brightness = min(100, percent);
+    }
+
+    fn scale(&self, c: u8) -> u8 {
+        (min(0x7f, c) as u16 * self.brightness as u16 / 100) as u8
+    }
+
+    /// Colors are 7-bit; anything larger gets clamped.
+    pub fn set_led(&mut self, i: u8, r: u8, g: u8, b: u8) {
+        let base = 7 + (i as usize) * 4;
+        self.buf[base + 1] = self.scale(r);
+        self.buf[base + 2] = self.scale(g);
+        self.buf[base + 3] = self.scale(b);
+    }
+
+    /// The color pad `i` is set to, brightness applied.
+    pub fn led(&self, i: u8) -> (u8, u8, u8) {
+        let base = 7 + (i as usize) * 4;
+        (self.buf[base + 1], self.buf[base + 2], self.buf[base + 3])
+    }
+
+    /// The complete sysex message.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// The pads whose colors differ from `sent`'s, or every pad if nothing's
+    /// been sent.
+    pub fn changed_since(&self, sent: Option<&LedBuffer>) -> Vec<u8> {
+        (0..GRID_LED_COUNT as u8)
+            .filter(|&i| sent.map(|sent| sent.led(i) != self.led(i)).unwrap_or(true))
+            .collect()
+    }
+
+    /// A "set pad colors" message for just `pads`.
+    pub fn message_for(&self, pads: &[u8]) -> Vec<u8> {
+        if pads.len() == GRID_LED_COUNT {
+            return self.buf.to_vec();
+        }
+        let len = 4 * pads.len() as u16;
+        let mut msg = vec![0xf0, 0x47, 0x7f, 0x43, 0x65, ((len >> 7)&0x7f) as u8, (len&0x7f) as u8];
+        for &i in pads {
+            let base = 7 + (i as usize) * 4;
+            msg.extend_from_slice(&self.buf[base..base + 4]);
+        }
+        msg.push(0xf7);
+        msg
+    }
+}
+
+impl Default for LedBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
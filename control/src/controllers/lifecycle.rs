@@ -0,0 +1,129 @@
+use std::time::{Duration, Instant};
+
+/// How long a device gets to answer the identity request sent when it
+/// connects.  Plenty don't answer at all, so it's then taken as identified
+/// anyway.
+pub const IDENTIFY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where a controller's connection is at.  It goes
+/// `Discovered` → `Connecting` → `Identifying` → `Syncing` → `Ready`, skipping
+/// `Syncing` unless its owner reads the device's state on connect, then
+/// between `Ready` and `Degraded` as the device goes quiet and comes back,
+/// and to `Disconnected` (and back round through `Connecting`) when the
+/// connection's lost.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+    /// Its port was found but hasn't been opened.
+    Discovered,
+    /// Its ports are being opened.
+    Connecting,
+    /// Waiting for an answer to the identity request.
+    Identifying,
+    /// Its owner is reading the device's state, ex: a synth's patch, and
+    /// says when that's done with `Controller::synced`.
+    Syncing,
+    Ready,
+    /// Connected, but the device stopped sending Active Sensing.
+    Degraded,
+    /// The ports are closed until the watchdog reconnects them.
+    Disconnected,
+}
+
+/// What moves a `Lifecycle` between phases.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transition {
+    /// Started opening the ports.
+    Connect,
+    Connected,
+    ConnectFailed,
+    /// The device answered the identity request, or took too long to.
+    Identified,
+    /// The owner's done reading the device's state.
+    Synced,
+    /// The device stopped sending Active Sensing.
+    Silent,
+    /// ...and started again.
+    Active,
+    /// A send failed, the device stopped answering identity requests or the
+    /// input handler panicked.
+    Lost,
+}
+
+/// The state machine behind `Phase`.  It only says where the controller's
+/// at; the controller does the connecting, and reports each change as a
+/// `ControllerEvent::PhaseChanged` for its owner to hook into.
+pub struct Lifecycle {
+    phase: Phase,
+    since: Instant,
+    sync_on_connect: bool,
+}
+
+impl Lifecycle {
+    pub fn new(now: Instant) -> Self {
+        Lifecycle {
+            phase: Phase::Discovered,
+            since: now,
+            sync_on_connect: false,
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.phase
+    }
+
+    /// When it moved to the current phase.
+    pub fn since(&self) -> Instant {
+        self.since
+    }
+
+    /// Go through `Syncing` on the way to `Ready`, after connecting and
+    /// after the device comes back from going quiet.
+    pub fn set_sync_on_connect(&mut self, sync: bool) {
+        self.sync_on_connect = sync;
+    }
+
+    fn identified(&self) -> Phase {
+        if self.sync_on_connect {
+            Phase::Syncing
+        } else {
+            Phase::Ready
+        }
+    }
+
+    /// Where `transition` goes from the current phase, or None if it
+    /// doesn't apply there.
+    pub fn next(&self, transition: Transition) -> Option<Phase> {
+        use Phase::*;
+        use Transition::*;
+        match (self.phase, transition) {
+            (Discovered, Connect) | (Disconnected, Connect) => Some(Connecting),
+            (Connecting, Connected) => Some(Identifying),
+            (Connecting, ConnectFailed) => Some(Disconnected),
+            (Identifying, Identified) => Some(self.identified()),
+            (Syncing, Synced) => Some(Ready),
+            (Identifying, Silent) | (Syncing, Silent) | (Ready, Silent) => Some(Degraded),
+            (Degraded, Active) => Some(self.identified()),
+            (Discovered, Lost) | (Disconnected, Lost) => None,
+            (_, Lost) => Some(Disconnected),
+            _ => None,
+        }
+    }
+
+    /// Apply `transition`, returning the new phase if it changed.
+    pub fn apply(&mut self, transition: Transition, now: Instant) -> Option<Phase> {
+        let next = self.next(transition).filter(|&next| next != self.phase)?;
+        self.phase = next;
+        self.since = now;
+        Some(next)
+    }
+
+    /// The transition that's timed out as of `now`, if any.
+    pub fn timed_out(&self, now: Instant) -> Option<Transition> {
+        match self.phase {
+            Phase::Identifying if now.saturating_duration_since(self.since) >= IDENTIFY_TIMEOUT => {
+                Some(Transition::Identified)
+            },
+            _ => None,
+        }
+    }
+}
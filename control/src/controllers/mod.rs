@@ -0,0 +1,31 @@
+pub mod apc;
+mod buffers;
+mod calibration;
+mod caps;
+mod debounce;
+mod event;
+pub mod fire;
+pub mod launch_control;
+mod leds;
+mod lifecycle;
+pub mod model;
+mod oled;
+mod port_lock;
+pub mod profile;
+pub mod push2;
+#[cfg(feature = "push2")]
+pub mod push2_display;
+pub mod sysex_mapped;
+pub mod xtouch;
+
+pub use buffers::{BufferPool, SysexBuf, SysexLimits};
+pub use calibration::{Calibrator, PadCalibration, PadRange};
+pub use caps::ControllerCaps;
+pub use debounce::{DebounceConfig, Debouncer};
+pub use event::{ButtonState, ControllerEvent};
+pub use leds::{LedBuffer, GRID_LED_COUNT};
+pub use lifecycle::{Lifecycle, Phase, Transition, IDENTIFY_TIMEOUT};
+pub use model::{Model, RingMode};
+pub(crate) use oled::glyph;
+pub use oled::{OledBuffer, CHAR_ADVANCE, GLYPH_HEIGHT, GLYPH_WIDTH, OLED_HEIGHT, OLED_WIDTH};
+pub use port_lock::{set_take_over, LockError, PortLock};
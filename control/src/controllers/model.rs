@@ -0,0 +1,49 @@
+//! What differs between makes of controller: the surface they have, how
+//! their MIDI turns into `ControllerEvent`s and how their pads get lit.
+//! A `Controller` attached with a model decodes its input and sends its
+//! LEDs through it; everything above works in terms of the events and the
+//! `ControllerCaps` the model reports.
+
+use serde::{Deserialize, Serialize};
+
+use super::{ControllerCaps, ControllerEvent, LedBuffer, OledBuffer};
+
+/// How an encoder's LED ring shows its param, ex: `"ring": "pan"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RingMode {
+    /// One LED at the value.
+    #[default]
+    Single,
+    /// Lit from the middle out to the value, for bipolar params.
+    Pan,
+    /// Lit from the bottom up to the value.
+    Fan,
+}
+
+pub trait Model: Send + Sync {
+    fn caps(&self) -> ControllerCaps;
+
+    /// Messages to send whenever the controller is connected, ex: to set up
+    /// a palette.
+    fn start_messages(&self) -> Vec<Vec<u8>> {
+        vec![]
+    }
+
+    /// The event for a message from the controller.  Sysex never gets here.
+    fn decode(&self, msg: &[u8]) -> Option<ControllerEvent>;
+
+    /// Light `pads` as `leds` has them, passing each message to `send`.  The
+    /// rest are as they were last sent.
+    fn send_leds(&self, leds: &LedBuffer, pads: &[u8], send: &mut dyn FnMut(&[u8]));
+
+    /// Show `oled` on the controller's display, for controllers that take
+    /// the Fire's format.  The rest draw their displays some other way.
+    fn send_display(&self, _oled: &OledBuffer, _send: &mut dyn FnMut(&[u8])) {}
+
+    /// The message showing `level`, 0 to 0x7f, on encoder `encoder`'s LED
+    /// ring.  None for controllers without rings.
+    fn ring_message(&self, _encoder: u8, _mode: RingMode, _level: u8) -> Option<[u8; 3]> {
+        None
+    }
+}
@@ -0,0 +1,178 @@
+pub const OLED_WIDTH: usize = 128;
+pub const OLED_HEIGHT: usize = 64;
+
+/// The display is written in 8-pixel-high bands, a column at a time, with the
+/// bits packed 7 to a byte: 8192 bits in 1171 bytes.
+const BITMAP_LEN: usize = 1171;
+// Band and column range header + bitmap.
+const PAYLOAD_LEN: usize = 4 + BITMAP_LEN;
+// 5 header bytes + 2 length bytes + payload + 1 end byte.
+const OLED_MSG_LEN: usize = 5 + 2 + PAYLOAD_LEN + 1;
+const BITMAP_START: usize = 5 + 2 + 4;
+
+/// Where each bit of a 7-column run of a band lands in its 8 packed bytes,
+/// by row: `bit / 7` is the byte and `bit % 7` the bit within it.
+const BIT_MUTATE: [[u8; 7]; 8] = [
+    [13, 19, 25, 31, 37, 43, 49],
+    [0, 20, 26, 32, 38, 44, 50],
+    [1, 7, 27, 33, 39, 45, 51],
+    [2, 8, 14, 34, 40, 46, 52],
+    [3, 9, 15, 21, 41, 47, 53],
+    [4, 10, 16, 22, 28, 48, 54],
+    [5, 11, 17, 23, 29, 35, 55],
+    [6, 12, 18, 24, 30, 36, 42],
+];
+
+/// Glyphs are 3x5, a row per 3 bits from the top, the high bit leftmost.
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+/// Horizontal distance between the starts of consecutive characters.
+pub const CHAR_ADVANCE: usize = GLYPH_WIDTH + 1;
+
+pub(crate) fn glyph(c: char) -> u16 {
+    match c.to_ascii_uppercase() {
+        '0' => 0b111_101_101_101_111,
+        '1' => 0b010_110_010_010_111,
+        '2' => 0b111_001_111_100_111,
+        '3' => 0b111_001_111_001_111,
+        '4' => 0b101_101_111_001_001,
+        '5' => 0b111_100_111_001_111,
+        '6' => 0b111_100_111_101_111,
+        '7' => 0b111_001_010_010_010,
+        '8' => 0b111_101_111_101_111,
+        '9' => 0b111_101_111_001_111,
+        'A' => 0b010_101_111_101_101,
+        'B' => 0b110_101_110_101_110,
+        'C' => 0b011_100_100_100_011,
+        'D' => 0b110_101_101_101_110,
+        'E' => 0b111_100_110_100_111,
+        'F' => 0b111_100_110_100_100,
+        'G' => 0b011_100_101_101_011,
+        'H' => 0b101_101_111_101_101,
+        'I' => 0b111_010_010_010_111,
+        'J' => 0b001_001_001_101_010,
+        'K' => 0b101_101_110_101_101,
+        'L' => 0b100_100_100_100_111,
+        'M' => 0b101_111_111_101_101,
+        'N' => 0b110_101_101_101_101,
+        'O' => 0b010_101_101_101_010,
+        'P' => 0b110_101_110_100_100,
+        'Q' => 0b010_101_101_110_011,
+        'R' => 0b110_101_110_101_101,
+        'S' => 0b011_100_010_001_110,
+        'T' => 0b111_010_010_010_010,
+        'U' => 0b101_101_101_101_111,
+        'V' => 0b101_101_101_101_010,
+        'W' => 0b101_101_111_111_101,
+        'X' => 0b101_101_010_101_101,
+        'Y' => 0b101_101_010_010_010,
+        'Z' => 0b111_001_010_100_111,
+        ' ' => 0,
+        '-' => 0b000_000_111_000_000,
+        '+' => 0b000_010_111_010_000,
+        '.' => 0b000_000_000_000_010,
+        ':' => 0b000_010_000_010_000,
+        '/' => 0b001_001_010_100_100,
+        '%' => 0b101_001_010_100_101,
+        '#' => 0b101_111_101_111_101,
+        '(' => 0b010_100_100_100_010,
+        ')' => 0b010_001_001_001_010,
+        '_' => 0b000_000_000_000_111,
+        // Anything we don't have a glyph for.
+        _ => 0b111_001_010_000_010,
+    }
+}
+
+/// The Fire's "write OLED" sysex message for the whole 128x64 display, kept
+/// fully formed like `LedBuffer` so that updating the display is just a
+/// matter of sending the buffer.
+pub struct OledBuffer {
+    buf: Box<[u8; OLED_MSG_LEN]>,
+}
+
+impl OledBuffer {
+    pub fn new() -> Self {
+        let mut oled = OledBuffer {
+            buf: Box::new([0; OLED_MSG_LEN]),
+        };
+
+        let len = PAYLOAD_LEN as u16;
+        oled.buf[0..7].copy_from_slice(
+            &[0xf0, 0x47, 0x7f, 0x43, 0x0e, ((len >> 7)&0x7f) as u8, (len&0x7f) as u8]);
+        // Always write every band and column.
+        oled.buf[7..11].copy_from_slice(&[0x00, 0x07, 0x00, 0x7f]);
+        oled.buf[OLED_MSG_LEN - 1] = 0xf7;
+        oled
+    }
+
+    pub fn clear(&mut self) {
+        for b in self.buf[BITMAP_START..OLED_MSG_LEN - 1].iter_mut() {
+            *b = 0;
+        }
+    }
+
+    /// Pixels off the edge of the display are ignored.
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        if x >= OLED_WIDTH || y >= OLED_HEIGHT {
+            return;
+        }
+        let x = x + OLED_WIDTH * (y / 8);
+        let bit = BIT_MUTATE[y % 8][x % 7] as usize;
+        let byte = &mut self.buf[BITMAP_START + x / 7 * 8 + bit / 7];
+        if on {
+            *byte |= 1 << (bit % 7);
+        } else {
+            *byte &= !(1 << (bit % 7));
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, on: bool) {
+        for py in y..y + h {
+            for px in x..x + w {
+                self.set_pixel(px, py, on);
+            }
+        }
+    }
+
+    /// A one pixel outline.
+    pub fn draw_rect(&mut self, x: usize, y: usize, w: usize, h: usize) {
+        if w == 0 || h == 0 {
+            return;
+        }
+        self.fill_rect(x, y, w, 1, true);
+        self.fill_rect(x, y + h - 1, w, 1, true);
+        self.fill_rect(x, y, 1, h, true);
+        self.fill_rect(x + w - 1, y, 1, h, true);
+    }
+
+    /// Draw `text` with its top left at (x, y), uppercased, clipped at the
+    /// right edge.  `on` false draws dark text, ex: over a filled box.
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, on: bool) {
+        for (i, c) in text.chars().enumerate() {
+            let cx = x + i * CHAR_ADVANCE;
+            if cx + GLYPH_WIDTH > OLED_WIDTH {
+                break;
+            }
+            let bits = glyph(c);
+            for row in 0..GLYPH_HEIGHT {
+                for col in 0..GLYPH_WIDTH {
+                    let shift = (GLYPH_HEIGHT - 1 - row) * GLYPH_WIDTH + (GLYPH_WIDTH - 1 - col);
+                    if bits & (1 << shift) != 0 {
+                        self.set_pixel(cx + col, y + row, on);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The complete sysex message.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..]
+    }
+}
+
+impl Default for OledBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,96 @@
+//! Keeping two mapatrons off the same port.  Most backends let both open it,
+//! and then each gets some of the input and both write, which half works in
+//! confusing ways.  So whoever opens a port first holds a lock file for it
+//! with their pid in, and anyone else is refused unless they take over, in
+//! which case the old holder notices on its next watchdog poll and lets go.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set from `--take-over`: take ports from other instances on first
+/// connecting instead of giving up on them.
+static TAKE_OVER: AtomicBool = AtomicBool::new(false);
+
+pub fn set_take_over(take_over: bool) {
+    TAKE_OVER.store(take_over, Ordering::Relaxed);
+}
+
+pub(crate) fn take_over() -> bool {
+    TAKE_OVER.load(Ordering::Relaxed)
+}
+
+/// Why a port couldn't be locked.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LockError {
+    /// Another live process has it.
+    Held { pid: u32 },
+    Io(String),
+}
+
+/// Where the lock files go: `$XDG_RUNTIME_DIR`, or the temp dir.
+fn lock_dir() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(env::temp_dir)
+}
+
+fn lock_path(port: &str) -> PathBuf {
+    let name: String = port.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    lock_dir().join(format!("mapatron-{}.lock", name))
+}
+
+fn holder(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Whether `pid` is running, as far as we can tell.  Without `/proc` they're
+/// all assumed to be, so a stale lock needs taking over.
+fn is_alive(pid: u32) -> bool {
+    let proc = Path::new("/proc");
+    !proc.is_dir() || proc.join(pid.to_string()).exists()
+}
+
+/// The lock on one port, released on drop.
+pub struct PortLock {
+    path: PathBuf,
+}
+
+impl PortLock {
+    /// Lock `port`.  Locks left by processes that have gone are cleared;
+    /// ones held by live processes are taken if `take_over`.
+    pub fn acquire(port: &str, take_over: bool) -> Result<PortLock, LockError> {
+        let path = lock_path(port);
+        let ours = process::id();
+        let io = |e: std::io::Error| LockError::Io(format!("{}: {}", path.display(), e));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => write!(file, "{}", ours).map_err(io)?,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                match holder(&path) {
+                    Some(pid) if pid != ours && is_alive(pid) && !take_over => {
+                        return Err(LockError::Held { pid });
+                    },
+                    _ => fs::write(&path, ours.to_string()).map_err(io)?,
+                }
+            },
+            Err(e) => return Err(io(e)),
+        }
+        Ok(PortLock {
+            path,
+        })
+    }
+
+    /// False once another instance has taken the port over.
+    pub fn is_held(&self) -> bool {
+        holder(&self.path) == Some(process::id())
+    }
+}
+
+impl Drop for PortLock {
+    fn drop(&mut self) {
+        if self.is_held() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
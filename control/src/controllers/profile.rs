@@ -0,0 +1,264 @@
+//! Controllers described in JSON instead of code.  A profile lists the
+//! notes and CCs of a controller's pads, encoders, knobs and faders; a
+//! `GenericController` built from it decodes them and lights the pads by
+//! sending each pad's own message back with the value of the nearest color
+//! in `led_colors`, ex:
+//! ```json
+//! {
+//!   "port_names": ["nanoKONTROL2"],
+//!   "columns": 8,
+//!   "pads": [{ "cc": 32 }, { "cc": 33 }, { "cc": 34 }],
+//!   "knobs": [{ "cc": 16 }, { "cc": 17 }],
+//!   "faders": [{ "cc": 0 }, { "cc": 1 }],
+//!   "led_colors": [{ "color": [0, 0, 0], "value": 0 }, { "color": [127, 0, 0], "value": 127 }],
+//!   "start": ["F0 42 40 00 01 13 00 00 00 01 F7"]
+//! }
+//! ```
+//! Controls are on `channel` (0-15) unless they say otherwise.
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use super::model::Model;
+use super::sysex_mapped::Controller;
+use super::{ButtonState, ControllerCaps, ControllerEvent, LedBuffer, GRID_LED_COUNT};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Note(u8),
+    Cc(u8),
+}
+
+/// One control's message, ex: `{ "note": 36, "channel": 9 }`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProfileControl {
+    #[serde(flatten)]
+    pub source: Source,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<u8>,
+}
+
+/// How encoders say which way they turned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EncoderMode {
+    /// 1 is one detent clockwise, 0x7f one counter-clockwise.
+    #[default]
+    TwosComplement,
+    /// 0x01 clockwise, 0x41 counter-clockwise.
+    SignMagnitude,
+    /// 0x41 clockwise, 0x3f counter-clockwise.
+    Offset,
+}
+
+impl EncoderMode {
+    fn delta(self, value: u8) -> i8 {
+        match self {
+            EncoderMode::TwosComplement => {
+                if value < 0x40 { value as i8 } else { (value as i16 - 0x80) as i8 }
+            },
+            EncoderMode::SignMagnitude => {
+                let detents = (value & 0x3f) as i8;
+                if value & 0x40 != 0 { -detents } else { detents }
+            },
+            EncoderMode::Offset => (value as i16 - 0x40) as i8,
+        }
+    }
+}
+
+/// A pad LED color and the value that shows it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedColor {
+    pub color: (u8, u8, u8),
+    pub value: u8,
+}
+
+/// A message written as hex bytes, ex: `"F0 7E 7F 06 01 F7"`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct HexMessage(pub Vec<u8>);
+
+impl TryFrom<String> for HexMessage {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        text.split_whitespace()
+            .map(|b| u8::from_str_radix(b, 16).map_err(|_| format!("bad byte '{}' in '{}'", b, text)))
+            .collect::<Result<Vec<u8>, String>>()
+            .map(HexMessage)
+    }
+}
+
+impl From<HexMessage> for String {
+    fn from(msg: HexMessage) -> String {
+        msg.0.iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+    }
+}
+
+fn default_columns() -> u8 {
+    8
+}
+
+fn default_led_colors() -> Vec<LedColor> {
+    vec![
+        LedColor { color: (0, 0, 0), value: 0 },
+        LedColor { color: (0x7f, 0x7f, 0x7f), value: 0x7f },
+    ]
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ControllerProfile {
+    /// Prefixes of the controller's port names.
+    pub port_names: Vec<String>,
+    #[serde(default)]
+    pub channel: u8,
+    /// Pads are laid out in rows this long, from the top left.
+    #[serde(default = "default_columns")]
+    pub columns: u8,
+    #[serde(default)]
+    pub pads: Vec<ProfileControl>,
+    #[serde(default)]
+    pub encoders: Vec<ProfileControl>,
+    #[serde(default)]
+    pub encoder_mode: EncoderMode,
+    #[serde(default)]
+    pub knobs: Vec<ProfileControl>,
+    #[serde(default)]
+    pub faders: Vec<ProfileControl>,
+    /// The colors the pads can show.  Off and on by default.
+    #[serde(default = "default_led_colors")]
+    pub led_colors: Vec<LedColor>,
+    /// Sent whenever the controller connects, ex: to pick a template.
+    #[serde(default)]
+    pub start: Vec<HexMessage>,
+}
+
+impl ControllerProfile {
+    pub fn load(path: &str) -> Result<ControllerProfile, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let profile: ControllerProfile = serde_json::from_reader(BufReader::new(file))?;
+        if profile.pads.is_empty() && profile.encoders.is_empty() && profile.knobs.is_empty() &&
+           profile.faders.is_empty() {
+            return Err(format!("{} has no controls", path).into());
+        }
+        if profile.pads.len() > GRID_LED_COUNT {
+            return Err(format!("{} has more than {} pads", path, GRID_LED_COUNT).into());
+        }
+        if profile.led_colors.is_empty() {
+            return Err(format!("{} has no led_colors", path).into());
+        }
+        Ok(profile)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Target {
+    Pad(u8),
+    Encoder(u8),
+    Knob(u8),
+    Fader(u8),
+}
+
+/// A controller that works the way its profile says.
+pub struct GenericController {
+    profile: ControllerProfile,
+    /// What each (source, channel) is.
+    targets: HashMap<(Source, u8), Target>,
+}
+
+impl GenericController {
+    pub fn new(profile: ControllerProfile) -> Self {
+        let mut targets = HashMap::new();
+        let mut add = |controls: &[ProfileControl], target: fn(u8) -> Target| {
+            for (i, control) in controls.iter().enumerate() {
+                targets.insert((control.source, control.channel.unwrap_or(profile.channel)), target(i as u8));
+            }
+        };
+        add(&profile.pads, Target::Pad);
+        add(&profile.encoders, Target::Encoder);
+        add(&profile.knobs, Target::Knob);
+        add(&profile.faders, Target::Fader);
+        GenericController {
+            profile,
+            targets,
+        }
+    }
+
+    /// The value of the LED color closest to `color`.
+    fn led_value(&self, (r, g, b): (u8, u8, u8)) -> u8 {
+        let distance = |c: &LedColor| {
+            let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+            d(c.color.0, r) + d(c.color.1, g) + d(c.color.2, b)
+        };
+        self.profile.led_colors.iter().min_by_key(|c| distance(c)).map(|c| c.value).unwrap_or(0)
+    }
+}
+
+impl Model for GenericController {
+    fn caps(&self) -> ControllerCaps {
+        let (pads, columns) = (self.profile.pads.len() as u8, self.profile.columns.max(1));
+        ControllerCaps {
+            rows: pads / columns + if pads % columns > 0 { 1 } else { 0 },
+            columns,
+            has_display: false,
+            encoders: self.profile.encoders.len() as u8,
+            encoder_rings: false,
+            faders: self.profile.faders.len() as u8,
+            knobs: self.profile.knobs.len() as u8,
+            rgb_bits: 7,
+            aftertouch: false,
+        }
+    }
+
+    fn start_messages(&self) -> Vec<Vec<u8>> {
+        self.profile.start.iter().map(|msg| msg.0.clone()).collect()
+    }
+
+    fn decode(&self, msg: &[u8]) -> Option<ControllerEvent> {
+        let (source, channel, value, note_on) = match *msg {
+            [status, note, velocity] if status & 0xf0 == 0x90 => (Source::Note(note), status & 0x0f, velocity, true),
+            [status, note, velocity] if status & 0xf0 == 0x80 => (Source::Note(note), status & 0x0f, velocity, false),
+            [status, cc, value] if status & 0xf0 == 0xb0 => (Source::Cc(cc), status & 0x0f, value, true),
+            _ => return None,
+        };
+        let columns = self.profile.columns.max(1);
+        Some(match *self.targets.get(&(source, channel))? {
+            Target::Pad(idx) => {
+                // Pads on CCs are down while the value is.
+                let state = if note_on && value > 0 { ButtonState::Down } else { ButtonState::Up };
+                ControllerEvent::GridButton(idx, idx / columns, idx % columns, state, value)
+            },
+            Target::Encoder(idx) => ControllerEvent::Encoder(idx, self.profile.encoder_mode.delta(value)),
+            Target::Knob(idx) => ControllerEvent::Knob(idx, value),
+            Target::Fader(idx) => ControllerEvent::Fader(idx, value),
+        })
+    }
+
+    fn send_leds(&self, leds: &LedBuffer, pads: &[u8], send: &mut dyn FnMut(&[u8])) {
+        for &idx in pads {
+            let pad = match self.profile.pads.get(idx as usize) {
+                Some(pad) => pad,
+                None => continue,
+            };
+            let channel = pad.channel.unwrap_or(self.profile.channel) & 0x0f;
+            let value = self.led_value(leds.led(idx));
+            match pad.source {
+                Source::Note(note) => send(&[0x90 | channel, note, value]),
+                Source::Cc(cc) => send(&[0xb0 | channel, cc, value]),
+            }
+        }
+    }
+}
+
+/// Finds all controllers matching `profile` on the system and returns them
+/// in a vector.
+pub fn attach_profiled(profile: &ControllerProfile) -> Vec<Controller> {
+    Controller::attach_model(&profile.port_names, Arc::new(GenericController::new(profile.clone())))
+}
@@ -0,0 +1,198 @@
+//! The Ableton Push 2: an 8x8 grid of RGB pads with pressure, 8 encoders
+//! over a 960x160 color display.  The pads and encoders are MIDI; pad
+//! colors are indexes into a palette, which we fill with a color cube when
+//! it connects.  The display is fed frames over USB instead, by the
+//! `push2` feature's `Push2Display`; here is just the `Frame` it sends.
+
+use std::sync::Arc;
+
+use super::model::Model;
+use super::oled::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+use super::sysex_mapped::Controller;
+use super::{ButtonState, ControllerCaps, ControllerEvent, LedBuffer, CHAR_ADVANCE};
+
+/// The Push 2's MIDI ports start with this.
+pub const PUSH2_PORT_PREFIX: &str = "Ableton Push 2";
+
+pub const PUSH2_DISPLAY_WIDTH: usize = 960;
+pub const PUSH2_DISPLAY_HEIGHT: usize = 160;
+
+const GRID_SIZE: u8 = 8;
+/// The pads are notes 36 through 99, bottom row first.
+const PAD_NOTE_FIRST: u8 = 36;
+/// The encoders over the display send relative CCs 71 through 78.
+const ENCODER_CC_FIRST: u8 = 71;
+const ENCODER_COUNT: u8 = 8;
+/// Levels per channel in the palette's color cube.
+const CUBE_LEVELS: u8 = 4;
+/// Where the cube starts in the palette.  0 stays black.
+const CUBE_FIRST: u8 = 1;
+
+const SYSEX_HEADER: [u8; 6] = [0xf0, 0x00, 0x21, 0x1d, 0x01, 0x01];
+const SET_PALETTE_ENTRY: u8 = 0x03;
+const REAPPLY_PALETTE: u8 = 0x05;
+
+pub struct Push2;
+
+impl Push2 {
+    /// Our pads are numbered from the top left; the Push's notes from the
+    /// bottom left.
+    fn flip(idx: u8) -> u8 {
+        (GRID_SIZE - 1 - idx / GRID_SIZE) * GRID_SIZE + idx % GRID_SIZE
+    }
+
+    fn pad_for_note(note: u8) -> Option<u8> {
+        let offset = note.checked_sub(PAD_NOTE_FIRST)?;
+        if offset < GRID_SIZE * GRID_SIZE { Some(Self::flip(offset)) } else { None }
+    }
+
+    /// The palette index for a color already cut down to 2 bits a channel.
+    fn palette_index((r, g, b): (u8, u8, u8)) -> u8 {
+        let (r, g, b) = (r >> 5, g >> 5, b >> 5);
+        if (r, g, b) == (0, 0, 0) {
+            0
+        } else {
+            CUBE_FIRST + r * CUBE_LEVELS * CUBE_LEVELS + g * CUBE_LEVELS + b
+        }
+    }
+
+    /// Sets palette entry `index` to an 8-bit color, with the white LED off.
+    fn palette_entry(index: u8, r: u8, g: u8, b: u8) -> Vec<u8> {
+        let mut msg = SYSEX_HEADER.to_vec();
+        msg.extend_from_slice(&[SET_PALETTE_ENTRY, index]);
+        for c in &[r, g, b, 0] {
+            msg.extend_from_slice(&[c & 0x7f, c >> 7]);
+        }
+        msg.push(0xf7);
+        msg
+    }
+}
+
+impl Model for Push2 {
+    fn caps(&self) -> ControllerCaps {
+        ControllerCaps {
+            rows: GRID_SIZE,
+            columns: GRID_SIZE,
+            has_display: true,
+            encoders: ENCODER_COUNT,
+            encoder_rings: false,
+            faders: 0,
+            knobs: 0,
+            rgb_bits: 2,
+            aftertouch: true,
+        }
+    }
+
+    fn start_messages(&self) -> Vec<Vec<u8>> {
+        let step = 0xff / (CUBE_LEVELS - 1);
+        let mut msgs = vec![];
+        for r in 0..CUBE_LEVELS {
+            for g in 0..CUBE_LEVELS {
+                for b in 0..CUBE_LEVELS {
+                    let index = CUBE_FIRST + r * CUBE_LEVELS * CUBE_LEVELS + g * CUBE_LEVELS + b;
+                    msgs.push(Self::palette_entry(index, r * step, g * step, b * step));
+                }
+            }
+        }
+        let mut reapply = SYSEX_HEADER.to_vec();
+        reapply.extend_from_slice(&[REAPPLY_PALETTE, 0xf7]);
+        msgs.push(reapply);
+        msgs
+    }
+
+    fn decode(&self, msg: &[u8]) -> Option<ControllerEvent> {
+        match *msg {
+            [status, note, velocity] if status & 0xf0 == 0x90 || status & 0xf0 == 0x80 => {
+                let idx = Self::pad_for_note(note)?;
+                let state = if status & 0xf0 == 0x90 && velocity > 0 {
+                    ButtonState::Down
+                } else {
+                    ButtonState::Up
+                };
+                Some(ControllerEvent::GridButton(idx, idx / GRID_SIZE, idx % GRID_SIZE, state, velocity))
+            },
+            [status, note, pressure] if status & 0xf0 == 0xa0 => {
+                Some(ControllerEvent::PadPressure(Self::pad_for_note(note)?, pressure))
+            },
+            [status, cc, value] if status & 0xf0 == 0xb0 &&
+                                    (ENCODER_CC_FIRST..ENCODER_CC_FIRST + ENCODER_COUNT).contains(&cc) => {
+                // 7-bit two's complement, as on the Fire.
+                let delta = if value < 0x40 { value as i8 } else { (value as i16 - 0x80) as i8 };
+                Some(ControllerEvent::Encoder(cc - ENCODER_CC_FIRST, delta))
+            },
+            _ => None,
+        }
+    }
+
+    fn send_leds(&self, leds: &LedBuffer, pads: &[u8], send: &mut dyn FnMut(&[u8])) {
+        for &idx in pads.iter().filter(|&&idx| idx < GRID_SIZE * GRID_SIZE) {
+            send(&[0x90, PAD_NOTE_FIRST + Self::flip(idx), Self::palette_index(leds.led(idx))]);
+        }
+    }
+}
+
+/// Finds all Push 2s on the system and returns them in a vector.  Their
+/// displays are separate; see `Push2Display`.
+pub fn attach_push2s() -> Vec<Controller> {
+    Controller::attach_model(&[PUSH2_PORT_PREFIX], Arc::new(Push2))
+}
+
+/// A 16-bit color, as the display takes it: 5 bits of blue at the top, 6 of
+/// green, then 5 of red.
+pub const fn rgb565(r: u8, g: u8, b: u8) -> u16 {
+    ((b as u16 >> 3) << 11) | ((g as u16 >> 2) << 5) | (r as u16 >> 3)
+}
+
+/// A picture for the Push 2's display.
+pub struct Frame {
+    pixels: Vec<u16>,
+}
+
+impl Frame {
+    pub fn new() -> Self {
+        Frame {
+            pixels: vec![0; PUSH2_DISPLAY_WIDTH * PUSH2_DISPLAY_HEIGHT],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.iter_mut().for_each(|p| *p = 0);
+    }
+
+    /// Anything off the edge of the display is clipped.
+    pub fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, color: u16) {
+        for py in y..(y + h).min(PUSH2_DISPLAY_HEIGHT) {
+            for px in x..(x + w).min(PUSH2_DISPLAY_WIDTH) {
+                self.pixels[py * PUSH2_DISPLAY_WIDTH + px] = color;
+            }
+        }
+    }
+
+    /// Draw `text` in the OLED's font blown up `scale` times, with its top
+    /// left at (x, y).
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, scale: usize, color: u16) {
+        for (i, c) in text.chars().enumerate() {
+            let cx = x + i * CHAR_ADVANCE * scale;
+            let bits = glyph(c);
+            for row in 0..GLYPH_HEIGHT {
+                for col in 0..GLYPH_WIDTH {
+                    let shift = (GLYPH_HEIGHT - 1 - row) * GLYPH_WIDTH + (GLYPH_WIDTH - 1 - col);
+                    if bits & (1 << shift) != 0 {
+                        self.fill_rect(cx + col * scale, y + row * scale, scale, scale, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The pixels, row by row from the top left.
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,86 @@
+//! Sending `Frame`s to the Push 2's display, which is a USB bulk endpoint
+//! rather than anything MIDI.  Each frame is a header and then the pixels a
+//! line at a time, padded and XORed with a fixed pattern.  The display goes
+//! dark if it hasn't had a frame in 2 seconds, so frames get resent even
+//! when nothing has changed.
+
+use log::warn;
+use rusb::{DeviceHandle, GlobalContext};
+
+use std::time::Duration;
+
+use super::push2::{Frame, PUSH2_DISPLAY_HEIGHT, PUSH2_DISPLAY_WIDTH, PUSH2_PORT_PREFIX};
+use super::sysex_mapped::Controller;
+
+const VENDOR_ID: u16 = 0x2982;
+const PRODUCT_ID: u16 = 0x1967;
+const INTERFACE: u8 = 0;
+const ENDPOINT: u8 = 0x01;
+const FRAME_HEADER: [u8; 16] = [0xff, 0xcc, 0xaa, 0x88, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+/// Each line is its pixels and then padding, for 2048 bytes.
+const LINE_LEN: usize = 2048;
+const SHAPING: [u8; 4] = [0xe7, 0xf3, 0xe7, 0xff];
+const TIMEOUT: Duration = Duration::from_millis(1000);
+
+pub struct Push2Display {
+    handle: DeviceHandle<GlobalContext>,
+    frame: Frame,
+    /// The encoded lines, kept to save allocating a frame's worth each time.
+    buf: Vec<u8>,
+}
+
+impl Push2Display {
+    /// The first Push 2 display on the system.
+    pub fn open() -> Result<Push2Display, String> {
+        let handle = rusb::open_device_with_vid_pid(VENDOR_ID, PRODUCT_ID)
+            .ok_or("no Push 2 display found")?;
+        handle.claim_interface(INTERFACE)
+            .map_err(|e| format!("can't claim the Push 2 display: {}", e))?;
+        Ok(Push2Display {
+            handle,
+            frame: Frame::new(),
+            buf: vec![0; LINE_LEN * PUSH2_DISPLAY_HEIGHT],
+        })
+    }
+
+    /// The display for `controller`, if it's a Push 2 and the display can
+    /// be opened.
+    pub fn for_controller(controller: &Controller) -> Option<Push2Display> {
+        if !controller.port_name().starts_with(PUSH2_PORT_PREFIX) {
+            return None;
+        }
+        Self::open().map_err(|e| warn!("{}", e)).ok()
+    }
+
+    /// The picture, sent by `send`.
+    pub fn frame_mut(&mut self) -> &mut Frame {
+        &mut self.frame
+    }
+
+    /// Send the frame.  Call at least once a second or so even when it
+    /// hasn't changed.
+    pub fn send(&mut self) {
+        if let Err(e) = self.write_frame() {
+            warn!("{}", e);
+        }
+    }
+
+    fn write_frame(&mut self) -> Result<(), String> {
+        let pixels = self.frame.pixels().chunks(PUSH2_DISPLAY_WIDTH);
+        for (line, pixels) in self.buf.chunks_mut(LINE_LEN).zip(pixels) {
+            for (i, pixel) in pixels.iter().enumerate() {
+                line[i * 2..i * 2 + 2].copy_from_slice(&pixel.to_le_bytes());
+            }
+            for (i, b) in line.iter_mut().enumerate() {
+                if i >= PUSH2_DISPLAY_WIDTH * 2 {
+                    *b = 0;
+                }
+                *b ^= SHAPING[i % SHAPING.len()];
+            }
+        }
+        self.handle.write_bulk(ENDPOINT, &FRAME_HEADER, TIMEOUT)
+            .and_then(|_| self.handle.write_bulk(ENDPOINT, &self.buf, TIMEOUT))
+            .map(|_| ())
+            .map_err(|e| format!("can't send to the Push 2 display: {}", e))
+    }
+}
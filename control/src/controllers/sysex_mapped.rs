@@ -1,50 +1,187 @@
-use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
-use serde::{Deserialize, Serialize};
-use serde_json::Result;
+use log::{error, info, warn};
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
 
-use std::cmp::{Eq, PartialEq, min};
+use std::cmp::{Eq, PartialEq};
 use std::hash::{Hash, Hasher};
-use tokio::stream::{StreamExt, StreamMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
 
-#[derive(Serialize, Deserialize)]
-struct SysexMapTypeEntry {
-    name: String,
-    first_offset_start: uint32_t,
-    last_offset_start: uint32_t,
-    type: String,
-    stride: Maybe<uint32_t>,
-}
+use super::fire::Fire;
+use super::lifecycle::{Lifecycle, Phase, Transition};
+use super::port_lock::{take_over, LockError, PortLock};
+use super::{BufferPool, ControllerCaps, ControllerEvent, DebounceConfig, Debouncer, LedBuffer, Model, OledBuffer,
+            PadCalibration, RingMode, SysexLimits};
+use crate::isolate::guarded;
+use crate::router::RouteFilter;
+use crate::sysex_map::{InputFilter, SysexMap};
 
-#[derive(Serialize, Deserialize)]
-struct SysexMapValueEntry {
-    name: String,
-    first_offset_start: uint32_t,
-    last_offset_start: uint32_t,
-    bitmask: uint32_t,
-    discrete_range_low: uint32_t,
-    discrete_range_high: uint32_t,
-    human_value_list: Maybe<Vec<String>>,
-    human_value_base: Maybe<int32_t>,
-    human_value_units: Maybe<String>,
+struct ConnectedController {
+    /// Only held to keep the input open; dropping it disconnects.
+    _in_conn: MidiInputConnection<()>,
+    out_conn: MidiOutputConnection,
+    /// None if the lock file couldn't be written.
+    lock: Option<PortLock>,
 }
 
-#[derive(Serialize, Deserialize)]
-struct SysexMap {
-    port_names: Vec<String>,
-    ignore_port_names: Vec<String>,
-    type_entries: BTreeMap<String, SysexMapTypeEntry>,
-    value_entries: BTreeMap<String, SysexMapValueEntry>,
+/// The lock on `port`, or None if we're going ahead without one since the
+/// lock file couldn't be written.  Errors with the pid of the other
+/// instance holding it.
+fn lock_port(port: &str, take_over: bool) -> Result<Option<PortLock>, u32> {
+    match PortLock::acquire(port, take_over) {
+        Ok(lock) => Ok(Some(lock)),
+        Err(LockError::Held { pid }) => Err(pid),
+        Err(LockError::Io(e)) => {
+            warn!("can't lock {}, going ahead anyway: {}", port, e);
+            Ok(None)
+        },
+    }
 }
 
-struct ConnectedController {
-    in_conn: MidiInputConnection<()>,
-    out_conn: MidiOutputConnection,
+/// Something standing in for the device on the other end of the port, ex: a
+/// simulated synth.  It's handed everything sent to the device and returns
+/// whatever the device would send back.
+pub trait VirtualDevice: Send {
+    fn receive(&mut self, msg: &[u8]) -> Vec<Vec<u8>>;
 }
 
+/// What the controller's talking to; where it's at in connecting to it is
+/// the `Lifecycle`'s to track.
 enum ControllerState {
     Disconnected,
     Connected(ConnectedController),
+    Virtual(Box<dyn VirtualDevice>),
+}
+
+/// Universal non-realtime Identity Request, addressed to all devices.
+const IDENTITY_REQUEST: [u8; 6] = [0xf0, 0x7e, 0x7f, 0x06, 0x01, 0xf7];
+
+/// How often we probe an otherwise idle connection with an identity request.
+const WATCHDOG_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+/// How long we wait for an identity reply before deciding the port is stalled.
+const WATCHDOG_REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+/// The MIDI spec says a receiver that has seen Active Sensing may assume the
+/// connection is gone if nothing at all arrives for 300ms.
+const ACTIVE_SENSING_TIMEOUT: Duration = Duration::from_millis(300);
+
+/// What the midir input callback has seen recently.  The callback runs on its
+/// own thread, so this is shared via an `Arc`.
+struct PortActivity {
+    /// When we last got an identity reply.
+    identity_reply: Mutex<Option<Instant>>,
+    /// When we last got any message at all.
+    last_seen: Mutex<Option<Instant>>,
+    /// Whether the device transmits Active Sensing.  We only judge silence for
+    /// devices that do, since others are legitimately quiet when idle.
+    saw_active_sensing: AtomicBool,
+    /// Set when the callback panics; it ignores everything after that until
+    /// the watchdog has torn the connection down.
+    crashed: AtomicBool,
+}
+
+impl PortActivity {
+    fn note_message(&self, msg: &[u8]) {
+        let now = Instant::now();
+        *self.last_seen.lock().unwrap() = Some(now);
+        if msg == [0xfe] {
+            self.saw_active_sensing.store(true, Ordering::Relaxed);
+        } else if is_identity_reply(msg) {
+            *self.identity_reply.lock().unwrap() = Some(now);
+        }
+    }
+}
+
+/// Tracks whether the device is still answering us.
+struct Watchdog {
+    last_probe: Option<Instant>,
+    activity: Arc<PortActivity>,
+    /// Whether we've reported the device as silent and not yet as active.
+    silent: bool,
+}
+
+impl Watchdog {
+    fn new() -> Self {
+        Watchdog {
+            last_probe: None,
+            activity: Arc::new(PortActivity {
+                identity_reply: Mutex::new(None),
+                last_seen: Mutex::new(None),
+                saw_active_sensing: AtomicBool::new(false),
+                crashed: AtomicBool::new(false),
+            }),
+            silent: false,
+        }
+    }
+
+    /// True if the device has been sending Active Sensing but we haven't heard
+    /// anything from it within `ACTIVE_SENSING_TIMEOUT`.
+    fn is_silent(&self, now: Instant) -> bool {
+        if !self.activity.saw_active_sensing.load(Ordering::Relaxed) {
+            return false;
+        }
+        match *self.activity.last_seen.lock().unwrap() {
+            Some(seen) => now.duration_since(seen) >= ACTIVE_SENSING_TIMEOUT,
+            None => false,
+        }
+    }
+
+    /// True if we sent a probe long enough ago that a reply should have
+    /// arrived, but none did.  Devices that have never answered an identity
+    /// request are never considered stalled; for them we only have send
+    /// errors to go on.
+    fn is_stalled(&self, now: Instant) -> bool {
+        match self.last_probe {
+            Some(probe) => {
+                let replied = match *self.activity.identity_reply.lock().unwrap() {
+                    Some(reply) => reply >= probe,
+                    None => true,
+                };
+                !replied && now.duration_since(probe) >= WATCHDOG_REPLY_TIMEOUT
+            },
+            None => false,
+        }
+    }
+
+    fn wants_probe(&self, now: Instant) -> bool {
+        match self.last_probe {
+            Some(probe) => now.duration_since(probe) >= WATCHDOG_PROBE_INTERVAL,
+            None => true,
+        }
+    }
+}
+
+/// What pad events go through between the input callback and the event
+/// channel: velocities get calibrated, then bounces dropped.
+struct PadInput {
+    calibration: PadCalibration,
+    debouncer: Debouncer,
+}
+
+impl PadInput {
+    fn new() -> Self {
+        PadInput {
+            calibration: PadCalibration::default(),
+            debouncer: Debouncer::new(&DebounceConfig::default()),
+        }
+    }
+
+    fn filter(&mut self, event: ControllerEvent, now: Instant) -> Option<ControllerEvent> {
+        let event = self.calibration.apply(event);
+        self.debouncer.filter(event, now)
+    }
+}
+
+/// A panic in the input callback mid-filter poisons the lock, but the pad
+/// state is still good enough to carry on with.
+fn lock_pads(pad_input: &Mutex<PadInput>) -> MutexGuard<'_, PadInput> {
+    pad_input.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn is_identity_reply(msg: &[u8]) -> bool {
+    // F0 7E <device id> 06 02 ...
+    msg.len() >= 5 && msg[0] == 0xf0 && msg[1] == 0x7e && msg[3] == 0x06 && msg[4] == 0x02
 }
 
 pub struct Controller {
@@ -52,120 +189,544 @@ pub struct Controller {
     /// of the device extracted via sysex or the USB path to the device.  Right
     /// now it's just a one-up.
     id: u32,
+    /// The midir port name we are connected to, retained so that we can
+    /// reconnect to the same port if the connection stalls.
+    port_name: String,
     state: ControllerState,
+    lifecycle: Lifecycle,
     event_rx: Option<mpsc::Receiver<ControllerEvent>>,
+    /// Kept so that we can hand a new sender to the input callback when we
+    /// reconnect and so we can report `ControllerEvent::Recovered`.
+    event_tx: mpsc::Sender<ControllerEvent>,
+    watchdog: Watchdog,
+    /// Shared with the input callback, which filters pad events through it.
+    pad_input: Arc<Mutex<PadInput>>,
+    /// Where the input callback copies sysex to.
+    buffers: Arc<BufferPool>,
+    /// What the input callback lets through, from the map's `input_filters`.
+    filters: Arc<Vec<RouteFilter>>,
+    /// How the input callback decodes messages and how the LEDs are sent.
+    model: Arc<dyn Model>,
+    caps: ControllerCaps,
 
-    // 7 header bytes + (4 bytes per grid led * 64 leds) + 1 end byte.
-    led_msg_buf: [u8; 7 + 4 * 64 + 1],
+    leds: LedBuffer,
+    /// The LEDs as they were last sent, so only the changes go out.  None
+    /// until something's sent on this connection.
+    sent_leds: Option<LedBuffer>,
+    /// What each encoder's LED ring shows, sent along with the LEDs.
+    rings: Vec<Option<(RingMode, u8)>>,
+    /// What was last sent to the rings, empty if nothing has been.
+    sent_rings: Vec<Option<(RingMode, u8)>>,
+    display: OledBuffer,
 }
 
 
 impl Controller {
-    /// Finds all Fire controllers on the system and returns them in a vector.
-    pub fn attach_to_all(config_path: &str) -> Vec<Controller> {
+    /// Finds all devices on the system matching the map's `port_names` (but
+    /// not its `ignore_port_names`) and returns them in a vector.
+    pub fn attach_to_all(map: &SysexMap) -> Vec<Controller> {
+        Self::attach_filtered(&map.port_names, &map.ignore_port_names, &map.input_filters, Arc::new(Fire))
+    }
+
+    /// Finds all devices whose port names start with one of `port_names` but
+    /// none of `ignore_port_names` and returns them in a vector.  Their
+    /// input is decoded as a Fire's, but they report no surface.
+    pub fn attach_matching<S: AsRef<str>>(port_names: &[S], ignore_port_names: &[S]) -> Vec<Controller> {
+        Self::attach_filtered(port_names, ignore_port_names, &[], Arc::new(Fire))
+    }
+
+    /// Finds all `model` controllers, by the prefixes their ports start
+    /// with.
+    pub fn attach_model<S: AsRef<str>>(port_names: &[S], model: Arc<dyn Model>) -> Vec<Controller> {
+        let mut controllers = Self::attach_filtered(port_names, &[], &[], model.clone());
+        for controller in controllers.iter_mut() {
+            controller.caps = model.caps();
+            controller.rings = vec![None; controller.caps.encoders as usize];
+            controller.start();
+        }
+        controllers
+    }
+
+    /// Like `attach_matching`, with each port's input passed through the
+    /// filters for it and decoded by `model`.
+    fn attach_filtered<S: AsRef<str>>(port_names: &[S], ignore_port_names: &[S],
+                                      input_filters: &[InputFilter], model: Arc<dyn Model>)
+                                      -> Vec<Controller> {
         let mut controllers: Vec<Controller> = vec![];
 
-        // We iterate over all input ports and for those that match the prefix,
+        // We iterate over all input ports and for those that match a prefix,
         // we find the exact matching output port.  The ownership model is that
         // calling connect() on a MidiInput consumes (moves) it, so we do a
         // pass to figure out the port names we want, and then a pass that
         // creates MidiInput and MidiOutput instances to connect to that
         // specific instance.
 
+        let desired_names = Self::matching_ports(port_names, ignore_port_names);
+
+        for (i, desired_name) in desired_names.into_iter().enumerate() {
+            let (tx, rx) = mpsc::channel::<ControllerEvent>(100);
+            let watchdog = Watchdog::new();
+            let pad_input = Arc::new(Mutex::new(PadInput::new()));
+            let buffers = BufferPool::new(SysexLimits::default());
+            let filters: Arc<Vec<RouteFilter>> = Arc::new(input_filters.iter()
+                .filter(|input| desired_name.starts_with(&input.port))
+                .map(|input| input.filter.clone())
+                .collect());
+
+            let lock = match lock_port(&desired_name, take_over()) {
+                Ok(lock) => lock,
+                Err(pid) => {
+                    error!("{} is in use by another mapatron (pid {}); --take-over takes it from it",
+                           desired_name, pid);
+                    continue;
+                },
+            };
+            let connected = match Self::connect(&desired_name, tx.clone(), watchdog.activity.clone(),
+                                                pad_input.clone(), buffers.clone(), filters.clone(),
+                                                model.clone()) {
+                Some(connected) => ConnectedController { lock, ..connected },
+                None => continue,
+            };
+
+            let mut controller = Controller {
+                id: i as u32,
+                port_name: desired_name,
+                state: ControllerState::Connected(connected),
+                lifecycle: Lifecycle::new(Instant::now()),
+                event_rx: Some(rx),
+                event_tx: tx,
+                watchdog,
+                pad_input,
+                buffers,
+                filters,
+                model: model.clone(),
+                caps: ControllerCaps::default(),
+                leds: LedBuffer::new(),
+                sent_leds: None,
+                rings: vec![],
+                sent_rings: vec![],
+                display: OledBuffer::new(),
+            };
+            controller.transition(Transition::Connect);
+            controller.transition(Transition::Connected);
+            controllers.push(controller);
+        }
+
+        controllers
+    }
+
+    /// The names of the input ports that start with one of `port_names` but
+    /// none of `ignore_port_names`.
+    pub fn matching_ports<S: AsRef<str>>(port_names: &[S], ignore_port_names: &[S]) -> Vec<String> {
         let walk_in = MidiInput::new("Fire-Walk").unwrap();
         // Accumulate the list of ports completely first so there's no overlap
         // of MidiInput lifetimes.
-        let desired_names : Vec<String> = walk_in.ports().into_iter().filter_map(|p| {
+        walk_in.ports().into_iter().filter_map(|p| {
             let name = walk_in.port_name(&p).unwrap();
-            if name.starts_with(MIDI_INPUT_PORT_PREFIX) {
+            let wanted = port_names.iter().any(|prefix| name.starts_with(prefix.as_ref()));
+            let ignored = ignore_port_names.iter().any(|prefix| name.starts_with(prefix.as_ref()));
+            if wanted && !ignored {
                 Some(name)
             } else {
                 None
             }
-        }).collect();
+        }).collect()
+    }
 
-        for (i, desired_name) in desired_names.into_iter().enumerate() {
-            let midi_in = MidiInput::new("Fire-Walk").unwrap();
-            let midi_out = MidiOutput::new("Fire").unwrap();
+    /// A controller talking to `device` instead of a port.  It never needs
+    /// recovering.
+    pub fn attach_virtual(port_name: &str, device: Box<dyn VirtualDevice>) -> Controller {
+        let (tx, rx) = mpsc::channel::<ControllerEvent>(100);
+        let mut controller = Controller {
+            id: 0,
+            port_name: port_name.to_string(),
+            state: ControllerState::Virtual(device),
+            lifecycle: Lifecycle::new(Instant::now()),
+            event_rx: Some(rx),
+            event_tx: tx,
+            watchdog: Watchdog::new(),
+            pad_input: Arc::new(Mutex::new(PadInput::new())),
+            buffers: BufferPool::new(SysexLimits::default()),
+            filters: Arc::new(vec![]),
+            model: Arc::new(Fire),
+            caps: ControllerCaps::default(),
+            leds: LedBuffer::new(),
+            sent_leds: None,
+            rings: vec![],
+            sent_rings: vec![],
+            display: OledBuffer::new(),
+        };
+        controller.transition(Transition::Connect);
+        controller.transition(Transition::Connected);
+        controller
+    }
 
-            let (mut tx, mut rx) = mpsc::channel::<ControllerEvent>(100);
+    /// Opens the input and output ports named `desired_name`, returning None if
+    /// MIDI isn't available or either port can't be found or opened.  The caller hands over the
+    /// port's lock.  Every incoming message is
+    /// noted in `activity` for the watchdog; everything that passes
+    /// `filters`, `model` decodes, isn't a bounce and fits in `buffers` is
+    /// sent to `tx`.
+    fn connect(desired_name: &str, mut tx: mpsc::Sender<ControllerEvent>,
+               activity: Arc<PortActivity>, pad_input: Arc<Mutex<PadInput>>,
+               buffers: Arc<BufferPool>, filters: Arc<Vec<RouteFilter>>, model: Arc<dyn Model>)
+               -> Option<ConnectedController> {
+        let mut midi_in = MidiInput::new("Fire-Walk").ok()?;
+        let midi_out = MidiOutput::new("Fire").ok()?;
+        // We need to see Active Sensing (and sysex) for the watchdog.
+        midi_in.ignore(Ignore::None);
 
-            let in_port = midi_in.ports().into_iter().find_map(|p| {
-                if midi_in.port_name(&p).unwrap() == desired_name {
-                    Some(p)
-                } else {
-                    None
+        let in_port = midi_in.ports().into_iter().find(|p| {
+            midi_in.port_name(p).map(|name| name == desired_name).unwrap_or(false)
+        })?;
+        let port_name = desired_name.to_string();
+        let in_conn = midi_in.connect(
+            &in_port, "fire-in", move |_stamp, msg, _| {
+                if activity.crashed.load(Ordering::Relaxed) {
+                    return;
                 }
-            }).unwrap();
-            let in_conn = midi_in.connect(
-                &in_port, "fire-in", move |_stamp, msg, _| {
-                    if let Some(event) = ControllerEvent::from_midi(msg) {
-                        tx.try_send(event).expect("Send exploded");
+                let handled = guarded(&port_name, || {
+                    activity.note_message(msg);
+                    if is_identity_reply(msg) || msg == [0xfe] ||
+                       !filters.iter().all(|filter| filter.passes(msg)) {
+                        return;
+                    }
+                    let event = ControllerEvent::from_midi_in(msg, &buffers, &*model)
+                        .and_then(|event| lock_pads(&pad_input).filter(event, Instant::now()));
+                    if let Some(event) = event {
+                        // Closed just means nobody's listening any more.
+                        if let Err(TrySendError::Full(_)) = tx.try_send(event) {
+                            warn!("{}: event queue full, dropping event", port_name);
+                        }
                     }
-                }, ()).unwrap();
-
-            // The out port should have the same name as the in name.
-            let out_port = midi_out.ports().into_iter().find_map(|p| {
-                if midi_out.port_name(&p).unwrap() == desired_name {
-                    Some(p)
-                } else {
-                    None
+                });
+                if handled.is_none() {
+                    activity.crashed.store(true, Ordering::Relaxed);
                 }
-            }).unwrap();
-            let out_conn = midi_out.connect(&out_port, "fire-out").unwrap();
+            }, ()).ok()?;
 
-            let mut controller = Controller {
-                id: i as u32,
-                state: ControllerState::Connected(ConnectedController {
-                    in_conn,
-                    out_conn,
-                }),
-                event_rx: Some(rx),
-                led_msg_buf: [0; 264],
-            };
-            controller.init();
-            controllers.push(controller);
+        // The out port should have the same name as the in name.
+        let out_port = midi_out.ports().into_iter().find(|p| {
+            midi_out.port_name(p).map(|name| name == desired_name).unwrap_or(false)
+        })?;
+        let out_conn = midi_out.connect(&out_port, "fire-out").ok()?;
+
+        Some(ConnectedController {
+            _in_conn: in_conn,
+            out_conn,
+            lock: None,
+        })
+    }
+
+    /// The events from this device.  This can only be taken once.
+    pub fn take_events(&mut self) -> Option<mpsc::Receiver<ControllerEvent>> {
+        self.event_rx.take()
+    }
+
+    pub fn port_name(&self) -> &str {
+        &self.port_name
+    }
+
+    /// Where the connection's at.
+    pub fn phase(&self) -> Phase {
+        self.lifecycle.phase()
+    }
+
+    /// Go through `Phase::Syncing` on the way to `Phase::Ready`, until the
+    /// owner calls `synced`.
+    pub fn set_sync_on_connect(&mut self, sync: bool) {
+        self.lifecycle.set_sync_on_connect(sync);
+    }
+
+    /// The owner's done reading the device's state.
+    pub fn synced(&mut self) {
+        self.transition(Transition::Synced);
+    }
+
+    /// Move the lifecycle along, reporting the phase it moved to.
+    fn transition(&mut self, transition: Transition) {
+        if let Some(phase) = self.lifecycle.apply(transition, Instant::now()) {
+            info!("{}: {:?}", self.port_name, phase);
+            if self.event_tx.try_send(ControllerEvent::PhaseChanged(phase)).is_err() {
+                warn!("{}: event queue full, dropping phase change", self.port_name);
+            }
         }
+    }
 
-        controllers
+    /// Whether this talks to a `VirtualDevice` rather than a port.
+    pub fn is_virtual(&self) -> bool {
+        matches!(self.state, ControllerState::Virtual(_))
+    }
+
+    /// Send a message to the device, treating a send error as a stalled
+    /// connection that needs recovery.
+    pub fn send(&mut self, msg: &[u8]) {
+        let failed = match &mut self.state {
+            ControllerState::Connected(cs) => cs.out_conn.send(msg).is_err(),
+            ControllerState::Disconnected => false,
+            ControllerState::Virtual(device) => {
+                for reply in device.receive(msg) {
+                    if let Some(event) = ControllerEvent::from_midi_in(&reply, &self.buffers, &*self.model) {
+                        if self.event_tx.try_send(event).is_err() {
+                            warn!("{}: event queue full, dropping reply", self.port_name);
+                        }
+                    }
+                }
+                false
+            },
+        };
+        if failed {
+            self.recover();
+        }
+    }
+
+    /// Should be called periodically (every second or so is fine, more often
+    /// if you want faster silence detection) by whoever is driving the
+    /// controller.  Sends identity requests to idle devices and recovers
+    /// connections that stopped replying to them.  Disconnected controllers get
+    /// a reconnection attempt, including ones whose input callback panicked.
+    /// Devices that transmit Active Sensing and then stop get a
+    /// `ControllerEvent::DeviceSilent`, and a `ControllerEvent::DeviceActive`
+    /// once they're heard from again.  The lifecycle's timeouts are checked
+    /// here too.
+    pub fn poll_watchdog(&mut self) {
+        if self.watchdog.activity.crashed.swap(false, Ordering::Relaxed) {
+            warn!("{}: input handler panicked, disconnecting", self.port_name);
+            self.state = ControllerState::Disconnected;
+            self.transition(Transition::Lost);
+            return;
+        }
+        if let ControllerState::Connected(cs) = &self.state {
+            if cs.lock.as_ref().is_some_and(|lock| !lock.is_held()) {
+                warn!("{}: taken over by another mapatron, letting go", self.port_name);
+                self.state = ControllerState::Disconnected;
+                self.transition(Transition::Lost);
+                return;
+            }
+        }
+        let now = Instant::now();
+        self.check_silence(now);
+        match self.state {
+            ControllerState::Disconnected => {
+                self.recover();
+            },
+            ControllerState::Connected(_) => {
+                let replied = self.watchdog.activity.identity_reply.lock().unwrap().is_some();
+                if replied && self.phase() == Phase::Identifying {
+                    self.transition(Transition::Identified);
+                } else if let Some(transition) = self.lifecycle.timed_out(now) {
+                    self.transition(transition);
+                }
+                if self.watchdog.is_stalled(now) {
+                    self.recover();
+                } else if self.watchdog.wants_probe(now) {
+                    self.watchdog.last_probe = Some(now);
+                    self.send(&IDENTITY_REQUEST);
+                }
+            },
+            // There's nothing to ask who it is.
+            ControllerState::Virtual(_) => self.transition(Transition::Identified),
+        }
+    }
+
+    fn check_silence(&mut self, now: Instant) {
+        let silent = self.watchdog.is_silent(now);
+        if silent == self.watchdog.silent {
+            return;
+        }
+        self.watchdog.silent = silent;
+        if silent {
+            warn!("{}: no Active Sensing for {:?}, device silent", self.port_name,
+                  ACTIVE_SENSING_TIMEOUT);
+            if let Err(err) = self.event_tx.try_send(ControllerEvent::DeviceSilent) {
+                warn!("{}: dropping silence notice: {}", self.port_name, err);
+            }
+            self.transition(Transition::Silent);
+        } else {
+            info!("{}: device active again", self.port_name);
+            if let Err(err) = self.event_tx.try_send(ControllerEvent::DeviceActive) {
+                warn!("{}: dropping activity notice: {}", self.port_name, err);
+            }
+            self.transition(Transition::Active);
+        }
+    }
+
+    /// Suppress pad chatter as `config` says, replacing any earlier settings.
+    pub fn set_debounce(&mut self, config: &DebounceConfig) {
+        lock_pads(&self.pad_input).debouncer = Debouncer::new(config);
     }
 
-    /// Initializes any pre-allocated buffers.
-    fn init(&mut self) {
-        let len: u16 = 4 * 64;
-        self.led_msg_buf[0..7].copy_from_slice(
-            &[0xf0, 0x47, 0x7f, 0x43, 0x65, ((len >> 7)&0x7f) as u8, (len&0x7f) as u8]);
+    /// Cap incoming sysex and the buffers kept for it.
+    pub fn set_sysex_limits(&mut self, limits: &SysexLimits) {
+        self.buffers.set_limits(*limits);
+    }
 
-        // The first byte of each 4-byte tuple is the index of the button to
-        // update.
-        for i in 0..64 {
-            self.led_msg_buf[7 + i * 4] = i as u8;
+    /// Normalize pad velocities with `calibration`.
+    pub fn set_calibration(&mut self, calibration: &PadCalibration) {
+        lock_pads(&self.pad_input).calibration = calibration.clone();
+    }
+
+    /// Report pads that settled in a different state than their first edge
+    /// said.  Call every `scheduler::TICK` or so when debouncing.
+    pub fn poll_debounce(&mut self) {
+        let settled = lock_pads(&self.pad_input).debouncer.settle(Instant::now());
+        for event in settled {
+            if self.event_tx.try_send(event).is_err() {
+                warn!("{}: event queue full, dropping pad event", self.port_name);
+            }
         }
-        self.led_msg_buf[self.led_msg_buf.len() - 1] = 0xf7;
     }
 
+    /// True if the device transmits Active Sensing and has gone quiet.
+    pub fn is_silent(&self) -> bool {
+        self.watchdog.silent
+    }
+
+    /// Tear down the current connection (if any) and try to re-establish it.
+    /// On success a `ControllerEvent::Recovered` is emitted so the consumer
+    /// can re-send LED/display state; on failure we stay `Disconnected` and the
+    /// next `poll_watchdog` call will try again.
+    fn recover(&mut self) {
+        // Dropping the connections closes them.  This has to happen before we
+        // reconnect because some backends won't let us open a port twice.
+        self.state = ControllerState::Disconnected;
+        self.transition(Transition::Lost);
+        // Keep the activity tracking so silence is judged across reconnects,
+        // but forget about identity probes sent over the old connection.
+        self.watchdog.last_probe = None;
+        *self.watchdog.activity.identity_reply.lock().unwrap() = None;
 
-    /// Do a basic 4x4 color cube cut into 4 slices.
-    pub fn set_color_cube(&mut self) {
-        for i in 0..64 {
-            let x: u8 = i % 4;
-            let y: u8 = i / 16;
-            let z: u8 = (i % 16) / 4;
-            self.led_msg_buf[7 + (i as usize) * 4 + 1] = min(0x7f, x * 0x20);
-            self.led_msg_buf[7 + (i as usize) * 4 + 2] = min(0x7f, y * 0x20);
-            self.led_msg_buf[7 + (i as usize) * 4 + 3] = min(0x7f, z * 0x20);
+        self.transition(Transition::Connect);
+        // Never taken back from another instance: it's theirs until they let
+        // go of it.
+        let lock = match lock_port(&self.port_name, false) {
+            Ok(lock) => lock,
+            Err(_) => {
+                self.transition(Transition::ConnectFailed);
+                return;
+            },
+        };
+        let connected = Self::connect(&self.port_name, self.event_tx.clone(),
+                                      self.watchdog.activity.clone(), self.pad_input.clone(),
+                                      self.buffers.clone(), self.filters.clone(), self.model.clone());
+        match connected {
+            Some(connected) => {
+                self.state = ControllerState::Connected(ConnectedController { lock, ..connected });
+                self.transition(Transition::Connected);
+                self.start();
+                if let Err(err) = self.event_tx.try_send(ControllerEvent::Recovered) {
+                    warn!("{}: dropping recovery notice: {}", self.port_name, err);
+                }
+            },
+            None => self.transition(Transition::ConnectFailed),
         }
     }
 
+    /// Move over to the port named `port_name`, which should be the same
+    /// kind of device since the input filters and model stay as they are.
+    /// Reports `ControllerEvent::Recovered` once connected, like a
+    /// recovery; until then the watchdog keeps trying.  Virtual controllers
+    /// stay put.
+    pub fn retarget(&mut self, port_name: &str) {
+        if self.is_virtual() {
+            return;
+        }
+        self.port_name = port_name.to_string();
+        self.recover();
+    }
+
+    /// Send the model's setup messages.  Failures show up on the next
+    /// send.  The LEDs are all sent next time, since the device may have
+    /// forgotten them.
+    fn start(&mut self) {
+        self.sent_leds = None;
+        self.sent_rings.clear();
+        if let ControllerState::Connected(cs) = &mut self.state {
+            for msg in self.model.start_messages() {
+                cs.out_conn.send(&msg).ok();
+            }
+        }
+    }
+
+    pub fn set_led_brightness(&mut self, percent: u8) {
+        self.leds.set_brightness(percent);
+    }
+
+    /// What the controller has to work with, from its model.  Nothing for
+    /// ports attached without one.
+    pub fn caps(&self) -> ControllerCaps {
+        self.caps
+    }
+
+    pub fn set_caps(&mut self, caps: ControllerCaps) {
+        self.caps = caps;
+    }
+
+    /// Pads past the end of the grid are ignored and colors are reduced to
+    /// what the pads can show.
     pub fn set_led(&mut self, i: u8, r: u8, g: u8, b: u8) {
-        self.led_msg_buf[7 + (i as usize) * 4 + 1] = min(0x7f, r);
-        self.led_msg_buf[7 + (i as usize) * 4 + 2] = min(0x7f, g);
-        self.led_msg_buf[7 + (i as usize) * 4 + 3] = min(0x7f, b);
+        if (i as usize) < self.caps.pads() {
+            let (r, g, b) = self.caps.quantize(r, g, b);
+            self.leds.set_led(i, r, g, b);
+        }
     }
 
+    /// Show `level`, 0 to 0x7f, on encoder `i`'s LED ring, if it has one.
+    pub fn set_ring(&mut self, i: u8, mode: RingMode, level: u8) {
+        if let Some(ring) = self.rings.get_mut(i as usize) {
+            *ring = Some((mode, level));
+        }
+    }
+
+    /// Send the LEDs and rings that changed since they were last sent.
     pub fn update_leds(&mut self) {
-        if let ControllerState::Connected(cs) = &mut self.state {
-            cs.out_conn.send(&self.led_msg_buf).unwrap();
+        let failed = match &mut self.state {
+            ControllerState::Connected(cs) => {
+                let (model, mut failed) = (&self.model, false);
+                let pads = self.leds.changed_since(self.sent_leds.as_ref());
+                if !pads.is_empty() {
+                    model.send_leds(&self.leds, &pads, &mut |msg| failed |= cs.out_conn.send(msg).is_err());
+                }
+                for (i, ring) in self.rings.iter().enumerate() {
+                    if self.sent_rings.get(i) == Some(ring) {
+                        continue;
+                    }
+                    let msg = ring.and_then(|(mode, level)| model.ring_message(i as u8, mode, level));
+                    if let Some(msg) = msg {
+                        failed |= cs.out_conn.send(&msg).is_err();
+                    }
+                }
+                self.sent_leds = Some(self.leds.clone());
+                self.sent_rings = self.rings.clone();
+                failed
+            },
+            ControllerState::Disconnected | ControllerState::Virtual(_) => false,
+        };
+        if failed {
+            self.recover();
+        }
+    }
+
+    /// The display contents, sent by `update_display`.
+    pub fn display_mut(&mut self) -> &mut OledBuffer {
+        &mut self.display
+    }
+
+    /// Does nothing on controllers without a display.
+    pub fn update_display(&mut self) {
+        if !self.caps.has_display {
+            return;
+        }
+        let failed = match &mut self.state {
+            ControllerState::Connected(cs) => {
+                let mut failed = false;
+                self.model.send_display(&self.display, &mut |msg| failed |= cs.out_conn.send(msg).is_err());
+                failed
+            },
+            ControllerState::Disconnected | ControllerState::Virtual(_) => false,
+        };
+        if failed {
+            self.recover();
         }
     }
 }
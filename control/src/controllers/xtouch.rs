@@ -0,0 +1,101 @@
+//! The Behringer X-Touch Mini in its Mackie Control mode: 8 encoders with
+//! LED rings, 2 rows of 8 lit buttons and a fader.  The buttons stand in
+//! for a 2x8 grid of pads.  In MC mode the rings are ours to drive, so each
+//! shows its encoder's param the way the binding's `ring` asks.
+
+use std::sync::Arc;
+
+use super::model::{Model, RingMode};
+use super::sysex_mapped::Controller;
+use super::{ButtonState, ControllerCaps, ControllerEvent, LedBuffer};
+
+/// The X-Touch Mini's MIDI ports start with this.
+pub const XTOUCH_MINI_PORT_PREFIX: &str = "X-TOUCH MINI";
+
+const ENCODER_COUNT: u8 = 8;
+const COLUMNS: u8 = 8;
+/// The encoders send relative CCs 0x10 through 0x17, and take their ring
+/// values on 0x30 through 0x37.
+const ENCODER_CC_FIRST: u8 = 0x10;
+const RING_CC_FIRST: u8 = 0x30;
+/// The fader sends pitch bend on channel 9.
+const FADER_STATUS: u8 = 0xe8;
+/// The notes of the buttons, top row then bottom, left to right.
+const BUTTON_NOTES: [u8; 16] = [
+    0x59, 0x5a, 0x28, 0x29, 0x2a, 0x2b, 0x2c, 0x2d,
+    0x57, 0x58, 0x5b, 0x5c, 0x56, 0x5d, 0x5e, 0x5f,
+];
+/// The ring has 11 LEDs.
+const RING_STEPS: u32 = 11;
+
+pub struct XTouchMini;
+
+impl Model for XTouchMini {
+    fn caps(&self) -> ControllerCaps {
+        ControllerCaps {
+            rows: BUTTON_NOTES.len() as u8 / COLUMNS,
+            columns: COLUMNS,
+            has_display: false,
+            encoders: ENCODER_COUNT,
+            encoder_rings: true,
+            faders: 1,
+            knobs: 0,
+            rgb_bits: 0,
+            aftertouch: false,
+        }
+    }
+
+    fn decode(&self, msg: &[u8]) -> Option<ControllerEvent> {
+        match *msg {
+            [status, note, velocity] if status & 0xf0 == 0x90 || status & 0xf0 == 0x80 => {
+                let idx = BUTTON_NOTES.iter().position(|n| *n == note)? as u8;
+                let state = if status & 0xf0 == 0x90 && velocity > 0 {
+                    ButtonState::Down
+                } else {
+                    ButtonState::Up
+                };
+                Some(ControllerEvent::GridButton(idx, idx / COLUMNS, idx % COLUMNS, state, velocity))
+            },
+            [status, cc, value] if status & 0xf0 == 0xb0 &&
+                                    (ENCODER_CC_FIRST..ENCODER_CC_FIRST + ENCODER_COUNT).contains(&cc) => {
+                // Sign and magnitude: 0x41 is one detent counter-clockwise.
+                let detents = (value & 0x3f) as i8;
+                let delta = if value & 0x40 != 0 { -detents } else { detents };
+                Some(ControllerEvent::Encoder(cc - ENCODER_CC_FIRST, delta))
+            },
+            [FADER_STATUS, _, msb] => Some(ControllerEvent::Fader(0, msb)),
+            _ => None,
+        }
+    }
+
+    fn send_leds(&self, leds: &LedBuffer, pads: &[u8], send: &mut dyn FnMut(&[u8])) {
+        for &idx in pads {
+            if let Some(note) = BUTTON_NOTES.get(idx as usize) {
+                let (r, g, b) = leds.led(idx);
+                let velocity = if r.max(g).max(b) > 0 { 0x7f } else { 0 };
+                send(&[0x90, *note, velocity]);
+            }
+        }
+    }
+
+    fn ring_message(&self, encoder: u8, mode: RingMode, level: u8) -> Option<[u8; 3]> {
+        if encoder >= ENCODER_COUNT {
+            return None;
+        }
+        let level = level.min(0x7f) as u32;
+        // Positions run 1 to 11; a fan at 0 is all off.
+        let (mode, position) = match mode {
+            RingMode::Single => (0, 1 + level * (RING_STEPS - 1) / 0x7f),
+            RingMode::Pan => (1, 1 + level * (RING_STEPS - 1) / 0x7f),
+            RingMode::Fan => (2, level * RING_STEPS / 0x7f),
+        };
+        Some([0xb0, RING_CC_FIRST + encoder, (mode << 4) | position as u8])
+    }
+}
+
+/// Finds all X-Touch Minis on the system and returns them in a vector.
+/// They need to be in MC mode: hold down the MC button while plugging
+/// them in.
+pub fn attach_xtouch_minis() -> Vec<Controller> {
+    Controller::attach_model(&[XTOUCH_MINI_PORT_PREFIX], Arc::new(XTouchMini))
+}
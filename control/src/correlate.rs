@@ -0,0 +1,104 @@
+//! Matching RQ1 reads up with the DT1 replies that answer them.  Replies
+//! carry no request id, so a reply belongs to a read if its address range
+//! falls in the read's; bytes are tracked individually so that replies split
+//! across messages, repeated, or interleaved with unrelated traffic all work
+//! out, and only what's still missing gets asked for again.
+
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+use crate::codec::DataSet;
+
+/// How long to wait for a read's replies and how many times to re-ask for
+/// whatever didn't arrive.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub timeout: Duration,
+    pub retries: u32,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            timeout: Duration::from_millis(500),
+            retries: 2,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ReadError {
+    UnknownParam(String),
+    /// Some of the range never arrived, even after retries.
+    NoReply { address: u32, size: u32, attempts: u32 },
+    /// The connection's event channel went away.
+    Closed,
+}
+
+impl fmt::Display for ReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadError::UnknownParam(name) => write!(f, "no param '{}'", name),
+            ReadError::NoReply { address, size, attempts } =>
+                write!(f, "no reply reading {} bytes at {:#x} after {} attempts", size, address, attempts),
+            ReadError::Closed => write!(f, "connection closed"),
+        }
+    }
+}
+
+impl Error for ReadError {}
+
+/// An outstanding read of `size` bytes at linear `address`.
+pub struct PendingRead {
+    address: u32,
+    data: Vec<Option<u8>>,
+}
+
+impl PendingRead {
+    pub fn new(address: u32, size: u32) -> Self {
+        PendingRead {
+            address,
+            data: vec![None; size as usize],
+        }
+    }
+
+    /// Take whatever part of `data_set` lands in our range.  Returns whether
+    /// any of it did.
+    pub fn accept(&mut self, data_set: &DataSet) -> bool {
+        let mut any = false;
+        for (i, byte) in data_set.data.iter().enumerate() {
+            let address = data_set.address + i as u32;
+            if address >= self.address && ((address - self.address) as usize) < self.data.len() {
+                self.data[(address - self.address) as usize] = Some(*byte);
+                any = true;
+            }
+        }
+        any
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.data.iter().all(|b| b.is_some())
+    }
+
+    /// The (address, size) runs that haven't arrived yet.
+    pub fn missing(&self) -> Vec<(u32, u32)> {
+        let mut runs: Vec<(u32, u32)> = vec![];
+        for (i, byte) in self.data.iter().enumerate() {
+            if byte.is_some() {
+                continue;
+            }
+            let address = self.address + i as u32;
+            match runs.last_mut() {
+                Some((start, size)) if *start + *size == address => *size += 1,
+                _ => runs.push((address, 1)),
+            }
+        }
+        runs
+    }
+
+    /// The bytes, if they all arrived.
+    pub fn bytes(&self) -> Option<Vec<u8>> {
+        self.data.iter().copied().collect()
+    }
+}
@@ -0,0 +1,126 @@
+//! At-rest encryption for snapshots that mustn't be shared in the clear, ex:
+//! from a commercial soundbank.  Encrypted files are `MAGIC`, a 12 byte
+//! nonce, and the .syx contents sealed with ChaCha20-Poly1305; they're only
+//! ever decrypted in memory, on the way to the synth.
+//!
+//! The key is 32 bytes given as 64 hex digits in `$MAPATRON_LIBRARY_KEY`, in
+//! the file named by `$MAPATRON_LIBRARY_KEY_FILE`, or in the OS keyring
+//! (Secret Service, the macOS Keychain or Windows' Credential Manager) under
+//! service "mapatron", user "library-key", checked in that order.
+//! `mapatron keygen` makes one, and `mapatron keygen --keyring` keeps it in
+//! the keyring.  Needs the "encrypt" feature.
+
+#[cfg(feature = "encrypt")]
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+#[cfg(feature = "encrypt")]
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+#[cfg(feature = "encrypt")]
+use std::env;
+#[cfg(feature = "encrypt")]
+use std::fs;
+
+/// What encrypted files start with.  Sysex starts with 0xf0, so these can't
+/// be mistaken for plain snapshots.
+pub const MAGIC: &[u8] = b"MAPATRON-ENC1\n";
+#[cfg(feature = "encrypt")]
+const NONCE_LEN: usize = 12;
+#[cfg(feature = "encrypt")]
+const KEYRING_SERVICE: &str = "mapatron";
+#[cfg(feature = "encrypt")]
+const KEYRING_USER: &str = "library-key";
+
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+#[cfg(feature = "encrypt")]
+fn parse_key(hex: &str) -> Result<[u8; 32], String> {
+    let hex = hex.trim();
+    // Checked for ASCII first so the slicing below is on char boundaries.
+    if !hex.is_ascii() || hex.len() != 64 {
+        return Err("the library key should be 64 hex digits".to_string());
+    }
+    let mut key = [0; 32];
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+            .map_err(|_| "the library key should be 64 hex digits".to_string())?;
+    }
+    Ok(key)
+}
+
+#[cfg(feature = "encrypt")]
+fn keyring_entry() -> Result<keyring::Entry, String> {
+    keyring::Entry::new(KEYRING_SERVICE, KEYRING_USER).map_err(|e| format!("can't open the keyring: {}", e))
+}
+
+/// The library key from the environment, or failing that the keyring.
+#[cfg(feature = "encrypt")]
+pub fn load_key() -> Result<[u8; 32], String> {
+    if let Ok(hex) = env::var("MAPATRON_LIBRARY_KEY") {
+        return parse_key(&hex);
+    }
+    if let Some(path) = env::var_os("MAPATRON_LIBRARY_KEY_FILE") {
+        let hex = fs::read_to_string(&path)
+            .map_err(|e| format!("can't read {}: {}", path.to_string_lossy(), e))?;
+        return parse_key(&hex);
+    }
+    match keyring_entry()?.get_password() {
+        Ok(hex) => parse_key(&hex),
+        Err(keyring::Error::NoEntry) => {
+            Err("no library key; set MAPATRON_LIBRARY_KEY or MAPATRON_LIBRARY_KEY_FILE, or run \
+                 `mapatron keygen --keyring`".to_string())
+        },
+        Err(e) => Err(format!("can't read the library key from the keyring: {}", e)),
+    }
+}
+
+/// Keep `hex` as the library key in the keyring.  A key already there is
+/// never replaced, since whatever it encrypted couldn't be read any more.
+#[cfg(feature = "encrypt")]
+pub fn store_key(hex: &str) -> Result<(), String> {
+    parse_key(hex)?;
+    let entry = keyring_entry()?;
+    match entry.get_password() {
+        Err(keyring::Error::NoEntry) => (),
+        Ok(_) => return Err("the keyring already has a library key".to_string()),
+        Err(e) => return Err(format!("can't read the keyring: {}", e)),
+    }
+    entry.set_password(hex.trim())
+        .map_err(|e| format!("can't store the library key in the keyring: {}", e))
+}
+
+/// A new random key, as hex.
+#[cfg(feature = "encrypt")]
+pub fn generate_key() -> String {
+    ChaCha20Poly1305::generate_key(&mut OsRng).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(feature = "encrypt")]
+pub fn encrypt(plain: &[u8], key: &[u8; 32]) -> Vec<u8> {
+    let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let sealed = cipher.encrypt(&nonce, plain).expect("encrypting to a Vec can't fail");
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&sealed);
+    out
+}
+
+/// The contents of an encrypted file, with the key from the environment.
+#[cfg(feature = "encrypt")]
+pub fn decrypt(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    let body = bytes.strip_prefix(MAGIC).ok_or("not an encrypted file")?;
+    if body.len() < NONCE_LEN {
+        return Err("encrypted file is truncated".to_string());
+    }
+    let (nonce, sealed) = body.split_at(NONCE_LEN);
+    let key = load_key()?;
+    ChaCha20Poly1305::new(Key::from_slice(&key)).decrypt(Nonce::from_slice(nonce), sealed)
+        .map_err(|_| "can't decrypt; wrong key or corrupt file".to_string())
+}
+
+#[cfg(not(feature = "encrypt"))]
+pub fn decrypt(_bytes: &[u8]) -> Result<Vec<u8>, String> {
+    Err("file is encrypted; rebuild with the \"encrypt\" feature to use it".to_string())
+}
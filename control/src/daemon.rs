@@ -0,0 +1,892 @@
+use log::{info, warn};
+use tokio::time;
+
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::audit::{now_ms, AuditLog, Source};
+use crate::backup::back_up;
+use crate::bindings::{BindingEngine, BindingsFile};
+use crate::broadcast::Broadcaster;
+use crate::browse::Browse;
+use crate::bus::{EngineEvent, EventBus, EventKind};
+use crate::clock::{Clock, ClockQueue};
+use crate::codec::{encode_param_dt1, encode_rq1};
+use crate::compare::Compare;
+use crate::compositor::{Compositor, Layer};
+use crate::config::Config;
+use crate::controllers::apc::attach_apc_minis;
+use crate::controllers::fire::attach_fires;
+use crate::controllers::launch_control::attach_launch_control_xls;
+use crate::controllers::profile::{attach_profiled, ControllerProfile};
+use crate::controllers::push2::attach_push2s;
+use crate::controllers::sysex_mapped::Controller;
+#[cfg(feature = "push2")]
+use crate::controllers::push2_display::Push2Display;
+use crate::controllers::xtouch::attach_xtouch_minis;
+use crate::controllers::{ButtonState, ControllerCaps, ControllerEvent, Phase};
+use crate::decode::DecodePool;
+use crate::display::Display;
+use crate::favorites::{Favorites, FAVORITES_PAGE};
+use crate::health::{Health, Notifier};
+use crate::heatmap::Heatmap;
+use crate::history::History;
+use crate::idle::IdleTimer;
+use crate::launcher::Launcher;
+use crate::marquee::Marquee;
+use crate::menu::{Menu, MenuAction, MenuValues};
+use crate::motion::Motions;
+use crate::pacing::{gap_for, Outbox, Priority};
+use crate::panic::{panic_messages, PanicCombo};
+use crate::param_store::ParamStore;
+use crate::plugin::{PluginContext, PluginRegistry};
+use crate::progress::{CancelPolicy, Dump, RecallDiff, Transfer, FEEDBACK_WINDOW, WRITES_PER_TICK};
+use crate::remote::{command_channel, CommandSender, RemoteCommand};
+use crate::router::{Router, Tapped, TappedMessage, Zones};
+use crate::rules::{RuleContext, Rules};
+use crate::scheduler;
+use crate::setlist::{Setlist, Song};
+use crate::snapshot::FingerprintPolicy;
+use crate::status::{Connection, Status};
+use crate::synth::Synth;
+use crate::sysex_map::SysexMap;
+use crate::timers::{Fired, Timers};
+
+/// How often the connection watchdogs get polled.
+const WATCHDOG_PERIOD: Duration = Duration::from_secs(1);
+/// How far a followed clock's tempo moves before it's published again.
+/// Smoothed, it still wanders by a fraction of a bpm.
+const TEMPO_STEP: f32 = 0.5;
+
+/// The first controller matching one of the profiles at `paths`.
+fn attach_from_profiles(paths: &[String]) -> Result<Option<Controller>, Box<dyn Error>> {
+    for path in paths {
+        let profile = ControllerProfile::load(path).map_err(|e| format!("can't load {}: {}", path, e))?;
+        if let Some(controller) = attach_profiled(&profile).into_iter().next() {
+            return Ok(Some(controller));
+        }
+    }
+    Ok(None)
+}
+
+/// Queue up `song`'s recall, and bring up the motions kept with it.  It goes
+/// through the same queue as remote ones so they're written in order.
+fn queue_recall(song: &Song, map: &SysexMap, store: &ParamStore, policy: FingerprintPolicy,
+                commands: &mut CommandSender, motions: &mut Motions) {
+    motions.set_scene(song.recall.snapshot.as_deref());
+    match song.recall.commands(map, store, policy) {
+        Ok(recall) => for command in recall {
+            if let Err(e) = commands.try_send(command) {
+                warn!("can't queue recall: {}", e);
+            }
+        },
+        Err(e) => warn!("{}: {}", song.name, e),
+    }
+}
+
+/// Load the bindings file again, for a new engine, rules and motion pads in
+/// place of the running ones.  Broadcast targets stay as they were.
+fn reload_bindings(config: &Config, map: &SysexMap, store: &Arc<ParamStore>, caps: ControllerCaps,
+                   router: Option<&Router>) -> Result<(BindingEngine, Rules, Motions), Box<dyn Error>> {
+    let path = config.bindings.as_ref().ok_or("no bindings file configured")?;
+    let mut bindings = BindingsFile::load(path)?;
+    config.apply_overrides(&mut bindings);
+    let mut engine = BindingEngine::with_caps(map, &bindings, store.clone(), caps)?;
+    match router {
+        Some(router) => engine.attach_zones(&bindings, router.zones())?,
+        None => engine.attach_zones(&bindings, &Zones::new())?,
+    }
+    let rules = Rules::new(map, &bindings, store, &caps)?;
+    let motions = Motions::new(&bindings, store.clone(), &caps)?;
+    Ok((engine, rules, motions))
+}
+
+/// The next message in on a tapped port, or never if nothing's tapped.
+async fn next_tapped(tapped: &mut Option<Tapped>) -> Option<TappedMessage> {
+    match tapped {
+        Some(tapped) => tapped.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Stop the recall or dump in progress, if there is one, for another to
+/// start, along with any recall waiting on the dump.  What it already queued
+/// is sent or dropped as `policy` says; either way the store only gets what
+/// went out.
+fn cancel_bulk(transfer: &mut Option<Transfer>, dump: &mut Option<Dump>,
+               recall_after_dump: &mut Option<Vec<(usize, u32)>>, outbox: &mut Outbox, policy: CancelPolicy) {
+    *recall_after_dump = None;
+    let queued = outbox.len(Priority::Bulk);
+    let cancelled = transfer.take().map(|t| t.progress()).into_iter().chain(dump.take().map(|d| d.progress()));
+    for progress in cancelled {
+        if !progress.is_finished() || queued > 0 {
+            info!("{} cut short after {} of {} messages, {:?} {} queued", progress.task.label(),
+                  progress.messages_done.saturating_sub(queued), progress.messages_total, policy, queued);
+        }
+    }
+    if policy == CancelPolicy::Drop {
+        outbox.clear(Priority::Bulk);
+    }
+}
+
+/// Run the mapper: Fire events go through the bindings and out to the synth,
+/// and whatever the synth tells us lands in the param store.  `plugins` are
+/// run alongside, on the same bus and store.  Returns when either device's
+/// event stream ends.
+pub async fn run(device: &str, config: &Config, plugins: PluginRegistry) -> Result<(), Box<dyn Error>> {
+    let map = config.load_map(device)?;
+    let bindings_path = config.bindings.as_ref().ok_or("no bindings file configured")?;
+    let mut bindings = BindingsFile::load(bindings_path)?;
+    config.apply_overrides(&mut bindings);
+    let bus = EventBus::new();
+
+    let mut synth = if config.simulate {
+        Synth::simulate(map.clone(), bus.clone())
+    } else {
+        Synth::attach(map.clone(), bus.clone()).ok_or("no synth connected")?
+    };
+    synth.set_pacing(gap_for(&map, config.pacing_ms));
+    if !plugins.is_empty() {
+        info!("running plugins {}", plugins.names().join(", "));
+        let ctx = PluginContext {
+            bus: bus.clone(),
+            store: synth.store().clone(),
+        };
+        // Goes until the runtime does.
+        tokio::spawn(plugins.run(ctx, scheduler::TICK));
+    }
+    synth.controller().set_sysex_limits(&config.sysex_limits);
+    let mut broadcaster = Broadcaster::attach(&bindings, synth.store().clone(), config.simulate)?;
+    // Dumps from the synths are decoded off this task, so they don't hold up
+    // the Fire.
+    let decode_pool = DecodePool::new();
+    synth.decode_in(decode_pool.lane());
+    broadcaster.decode_in(&decode_pool);
+    // Any of the other controllers stands in if there's no Fire, and then
+    // any the user has profiles for.
+    let builtin = attach_fires().into_iter().next()
+        .or_else(|| attach_push2s().into_iter().next())
+        .or_else(|| attach_apc_minis().into_iter().next())
+        .or_else(|| attach_launch_control_xls().into_iter().next())
+        .or_else(|| attach_xtouch_minis().into_iter().next());
+    let mut fire = match builtin {
+        Some(fire) => fire,
+        None => attach_from_profiles(&config.controller_profiles)?.ok_or("no supported controller connected")?,
+    };
+    #[cfg(feature = "push2")]
+    let mut push_display = Push2Display::for_controller(&fire);
+    // Bindings are checked against, and drawn for, what the Fire has.
+    let mut engine = BindingEngine::with_caps(&map, &bindings, synth.store().clone(), fire.caps())?;
+    let mut rules = Rules::new(&map, &bindings, synth.store(), &fire.caps())?;
+    let mut motions = Motions::new(&bindings, synth.store().clone(), &fire.caps())?;
+    let mut fire_events = fire.take_events().ok_or("Fire events already taken")?;
+    // The menu can change it.
+    let mut led_brightness = config.led_brightness;
+    fire.set_led_brightness(led_brightness);
+    fire.set_debounce(&config.debounce);
+    fire.set_calibration(&config.pad_calibration);
+    let mut display = Display::new(synth.store().clone(), engine.encoder_params());
+    let mut setlist = match &config.setlist {
+        Some(setlist) => Some(Setlist::load(setlist)?),
+        None => None,
+    };
+    display.set_song(setlist.as_ref().map(|s| s.label()));
+    motions.set_scene(setlist.as_ref().and_then(|s| s.current().recall.snapshot.as_deref()));
+    let mut panic = config.panic.as_ref().map(PanicCombo::new);
+    let mut history = config.history.as_ref().map(History::new);
+    let mut browse = match &config.browse {
+        Some(browse) => Some(Browse::new(browse)?),
+        None => None,
+    };
+    let mut audit = match &config.audit_log {
+        Some(path) => Some(AuditLog::open(path, synth.store())?),
+        None => None,
+    };
+    let mut compare = match &config.compare {
+        Some(compare) => Some(Compare::new(compare)?),
+        None => None,
+    };
+    // What the last snapshot wrote, for the panic button to put back.
+    let mut last_snapshot: Vec<(usize, u32)> = vec![];
+    // The snapshot being written out, a few params a tick and no faster
+    // than the synth's pacing allows.  Param changes are held back from the
+    // LEDs, display and remotes until it's done and the echoes have had
+    // `FEEDBACK_WINDOW` to arrive.
+    let mut transfer: Option<Transfer> = None;
+    // A patch being read, asked for at the same pace.  The synth's is read
+    // whenever it connects, so the pads only show it once they match.
+    let mut dump: Option<Dump> = None;
+    // A recall waiting for the dump of what it covers, see `RecallDiff::Live`.
+    let mut recall_after_dump: Option<Vec<(usize, u32)>> = None;
+    // Whether the patch read on connect is to be backed up once its replies
+    // are all in.
+    let mut backup_due = false;
+    synth.controller().set_sync_on_connect(true);
+    let mut menu = Menu::new();
+    let mut favorites = Favorites::load(&config.favorites_dir, device);
+    // Everything for the synth bar `synth.write`s goes through here, so
+    // notes and param writes go ahead of whatever's left of a recall.
+    let mut outbox = Outbox::new(synth.pacing());
+    let mut release_changes_at: Option<Instant> = None;
+
+    info!("mapping {} to {}", fire.port_name(), synth.controller().port_name());
+    bus.publish(EngineEvent::DeviceAttached(synth.controller().port_name().to_string()));
+    bus.publish(EngineEvent::DeviceAttached(fire.port_name().to_string()));
+
+    let mut launcher = match &config.launcher {
+        Some(launcher) => Some(Launcher::new(launcher, &fire.caps())?),
+        None => None,
+    };
+    // The clock `beats` motions follow, and their writes waiting for it.
+    let mut clock = config.clock.as_ref().map(|clock| (Clock::new(clock), ClockQueue::new(clock)));
+    // The tempo last published.
+    let mut tempo: Option<f32> = None;
+    let mut router = if config.routes.is_empty() && launcher.is_none() && clock.is_none() {
+        // Complains about any zone bindings.
+        engine.attach_zones(&bindings, &Zones::new())?;
+        None
+    } else {
+        let taps: Vec<String> = launcher.iter().map(|l| l.port().to_string())
+            .chain(config.clock.iter().map(|c| c.port.clone()))
+            .collect();
+        let router = Router::start(&config.routes, &taps)?;
+        engine.attach_zones(&bindings, router.zones())?;
+        Some(router)
+    };
+    // The launcher's LED messages from the DAW, and the clock.
+    let mut tapped = router.as_mut().and_then(|r| r.take_tapped());
+
+    // Remote front ends queue up writes for us; we hold a sender so that the
+    // channel stays open even with none running.
+    let (mut commands, mut remote_commands) = command_channel();
+    #[cfg(feature = "dbus")]
+    let _dbus = if config.dbus {
+        Some(crate::dbus::serve(map.clone(), synth.store().clone(), commands.clone(),
+                                 config.snapshot_mismatch)?)
+    } else {
+        None
+    };
+    #[cfg(feature = "mqtt")]
+    {
+        if let Some(mqtt) = &config.mqtt {
+            crate::mqtt::start(mqtt, synth.store().clone(), &bus, commands.clone());
+        }
+    }
+    #[cfg(feature = "grpc")]
+    {
+        if let Some(grpc) = &config.grpc {
+            let devices = vec![synth.controller().port_name().to_string(), fire.port_name().to_string()];
+            crate::grpc::start(grpc, map.clone(), synth.store().clone(), &bus, commands.clone(),
+                               config.snapshot_mismatch, devices)?;
+        }
+    }
+    let _program_changes = match &config.program_changes {
+        Some(pc) => Some(crate::program::listen(pc, map.clone(), synth.store().clone(),
+                                                commands.clone(), config.snapshot_mismatch)?),
+        None => None,
+    };
+
+    let mut watchdog = time::interval(WATCHDOG_PERIOD);
+    let mut ticker = time::interval(scheduler::TICK);
+    // LEDs and the display showing param state get redrawn at most once a
+    // tick.
+    let mut state_changes = bus.subscribe(&[EventKind::Param, EventKind::Page, EventKind::Progress,
+                                            EventKind::Tempo]);
+    let mut leds_dirty = true;
+    let mut display_dirty = true;
+    let mut idle = IdleTimer::new(&config.idle, Instant::now());
+    let mut screensaver_frame = None;
+    // Plays the splash while the synth's first read.
+    let mut status = Status::new(&config.status, fire.caps(), Instant::now());
+    let mut heatmap = Heatmap::new(&config.heatmap, fire.caps());
+    let mut compositor = Compositor::new();
+    // The menu can change it.
+    let mut palette = config.palette;
+    compositor.set_palette(palette);
+    let mut marquee = config.marquee.as_ref().filter(|_| !fire.caps().has_display)
+        .map(|marquee| Marquee::new(marquee, fire.caps()));
+    let mut timers = match &config.timers {
+        Some(timers) => Some(Timers::new(timers, &map, synth.store(), now_ms())?),
+        None => None,
+    };
+    let notifier = Notifier::from_env();
+    let mut health = Health::new(&config.health, Instant::now());
+    // What the last failed self-check said, so it's only logged once.
+    let mut unhealthy: Option<String> = None;
+    notifier.ready();
+    loop {
+        tokio::select! {
+            event = fire_events.recv() => match event {
+                Some(ControllerEvent::Recovered) => {
+                    fire.update_leds();
+                    fire.update_display();
+                    bus.publish(EngineEvent::DeviceAttached(fire.port_name().to_string()));
+                },
+                // Not something the user did.
+                Some(ControllerEvent::PhaseChanged(_)) => (),
+                Some(event) => {
+                    health.note_event(Instant::now());
+                    if idle.activity(Instant::now()) {
+                        fire.set_led_brightness(led_brightness);
+                        leds_dirty = true;
+                        display_dirty = true;
+                        screensaver_frame = None;
+                    }
+                    match (&mut panic, &mut setlist) {
+                        (Some(panic), _) if panic.claims(&event) => {
+                            if panic.handle(&event) {
+                                warn!("panic!");
+                                engine.cancel_ramps();
+                                motions.stop();
+                                let dumping = dump.is_some();
+                                cancel_bulk(&mut transfer, &mut dump, &mut recall_after_dump, &mut outbox,
+                                            CancelPolicy::Drop);
+                                // A read's harmless, so it starts over.
+                                if dumping {
+                                    dump = Some(Dump::new(&map, synth.store().index()));
+                                }
+                                synth.store().release_changes();
+                                release_changes_at = None;
+                                for msg in &panic_messages() {
+                                    synth.send(msg);
+                                    broadcaster.send_all(msg);
+                                    if let Some(router) = &router {
+                                        router.send_all(msg);
+                                    }
+                                }
+                                if panic.restores_snapshot() && !last_snapshot.is_empty() {
+                                    let restore = RemoteCommand::LoadSnapshot(last_snapshot.clone());
+                                    if let Err(e) = commands.try_send(restore) {
+                                        warn!("can't queue the snapshot restore: {}", e);
+                                    }
+                                }
+                            }
+                        },
+                        (_, Some(setlist)) if setlist.claims(&event) => {
+                            if let Some(song) = setlist.handle(&event) {
+                                queue_recall(song, &map, synth.store(), config.snapshot_mismatch,
+                                             &mut commands, &mut motions);
+                                display.set_song(Some(setlist.label()));
+                                display_dirty = true;
+                                leds_dirty = true;
+                            }
+                        },
+                        _ if motions.claims(&event) => {
+                            leds_dirty |= motions.handle(&event, Instant::now());
+                        },
+                        _ if matches!(&launcher, Some(launcher) if launcher.claims(&event)) => {
+                            if let (Some(launcher), Some(router)) = (&launcher, &router) {
+                                launcher.handle(&event, |msg| router.send_to(launcher.port(), msg));
+                            }
+                        },
+                        _ if matches!(&compare, Some(compare) if compare.claims(&event)) => {
+                            let writes = match &mut compare {
+                                Some(compare) => compare.handle(&event, synth.store()),
+                                None => vec![],
+                            };
+                            if !writes.is_empty() {
+                                // Written out like a recall.
+                                cancel_bulk(&mut transfer, &mut dump, &mut recall_after_dump, &mut outbox,
+                                            config.bulk_cancel);
+                                synth.store().hold_changes();
+                                release_changes_at = None;
+                                transfer = Some(Transfer::new(&map, synth.store().index(), &writes));
+                            }
+                            leds_dirty = true;
+                        },
+                        _ if matches!(&history, Some(history) if history.claims(&event)) => {
+                            if let Some(history) = &mut history {
+                                let writes = history.handle(&event, synth.store());
+                                if !writes.is_empty() {
+                                    cancel_bulk(&mut transfer, &mut dump, &mut recall_after_dump, &mut outbox,
+                                                config.bulk_cancel);
+                                    synth.store().hold_changes();
+                                    release_changes_at = None;
+                                    transfer = Some(Transfer::new(&map, synth.store().index(), &writes));
+                                }
+                                info!("history {}", history.label().as_deref().unwrap_or("now"));
+                            }
+                        },
+                        _ if matches!(&browse, Some(browse) if browse.claims(&event)) => {
+                            if let Some(browse) = &mut browse {
+                                match browse.handle(&event, Instant::now()) {
+                                    Ok(true) => {
+                                        display.set_song(Some(browse.label()));
+                                        display_dirty = true;
+                                    },
+                                    Ok(false) => (),
+                                    Err(e) => warn!("browse: {}", e),
+                                }
+                            }
+                        },
+                        _ if heatmap.claims(&event) => {
+                            heatmap.handle(&event, Instant::now());
+                            leds_dirty = true;
+                        },
+                        (_, setlist) if menu.claims(&event) => {
+                            if let ControllerEvent::SelectButton(ButtonState::Down) = event {
+                                let synths = synth.ports();
+                                let focused = synths.iter().position(|p| p == synth.controller().port_name());
+                                let pages = if favorites.is_empty() { vec!["MAIN"] } else { vec!["MAIN", "FAVORITES"] };
+                                menu.set_values(MenuValues {
+                                    pages: pages.into_iter().map(String::from).collect(),
+                                    page: engine.page(),
+                                    brightness: led_brightness,
+                                    palette,
+                                    synths,
+                                    synth: focused.unwrap_or(0),
+                                    scenes: setlist.as_ref().map(|s| s.names()).unwrap_or_default(),
+                                    scene: setlist.as_ref().map(|s| s.position()).unwrap_or(0),
+                                    heatmap: heatmap.is_on(),
+                                });
+                            }
+                            let (action, redraw) = menu.handle(&event);
+                            display_dirty |= redraw;
+                            let mut start_dump = false;
+                            match action {
+                                Some(MenuAction::Page(page)) => {
+                                    if page == FAVORITES_PAGE {
+                                        let top = favorites.top(synth.store().index(), engine.caps().encoders as usize);
+                                        engine.set_favorites(&map, &top);
+                                    }
+                                    engine.set_page(page);
+                                    display.set_encoders(engine.encoder_params());
+                                    bus.publish(EngineEvent::PageChanged(page));
+                                },
+                                Some(MenuAction::Brightness(percent)) => {
+                                    led_brightness = percent;
+                                    fire.set_led_brightness(percent);
+                                    leds_dirty = true;
+                                },
+                                Some(MenuAction::Palette(picked)) => {
+                                    palette = picked;
+                                    compositor.set_palette(picked);
+                                    leds_dirty = true;
+                                },
+                                Some(MenuAction::FocusSynth(port)) => {
+                                    info!("switching to {}", port);
+                                    synth.switch_to(&port);
+                                    // The store still has the old synth's values.
+                                    start_dump = true;
+                                },
+                                Some(MenuAction::Scene(position)) => {
+                                    if let Some(setlist) = setlist {
+                                        if let Some(song) = setlist.jump_to(position) {
+                                            queue_recall(song, &map, synth.store(), config.snapshot_mismatch,
+                                                         &mut commands, &mut motions);
+                                            leds_dirty = true;
+                                        }
+                                        display.set_song(Some(setlist.label()));
+                                    }
+                                },
+                                Some(MenuAction::Heatmap(on)) => {
+                                    heatmap.set_on(on);
+                                    leds_dirty = true;
+                                },
+                                Some(MenuAction::Dump) => start_dump = true,
+                                None => (),
+                            }
+                            if start_dump {
+                                // Like a recall, the replies are held back
+                                // until it's done.
+                                cancel_bulk(&mut transfer, &mut dump, &mut recall_after_dump, &mut outbox,
+                                            config.bulk_cancel);
+                                synth.store().hold_changes();
+                                release_changes_at = None;
+                                dump = Some(Dump::new(&map, synth.store().index()));
+                            }
+                        },
+                        _ => {
+                            engine.handle(&event, |msg| outbox.push(Priority::of(msg), msg));
+                            motions.note(&event, &engine.encoder_params(), Instant::now());
+                            if let Some(audit) = &mut audit {
+                                audit.note(Source::of(&event), synth.store());
+                            }
+                            for (param, value) in outbox.flush(Instant::now(), |msg| synth.send(msg)) {
+                                synth.store().set(param, value);
+                            }
+                            if let Some(audit) = &mut audit {
+                                // The rest of a recall that went out too.
+                                audit.note(Source::Recall, synth.store());
+                            }
+                        },
+                    }
+                },
+                None => break,
+            },
+            event = synth.next_event() => match event {
+                Some(ControllerEvent::Recovered) => {
+                    bus.publish(EngineEvent::DeviceAttached(
+                        synth.controller().port_name().to_string()));
+                },
+                Some(ControllerEvent::PhaseChanged(phase)) => {
+                    leds_dirty |= status.set_connection(Connection::of(phase));
+                    // It may have been power cycled or edited from its
+                    // panel while it was gone, so it's read every time.
+                    if phase == Phase::Syncing {
+                        cancel_bulk(&mut transfer, &mut dump, &mut recall_after_dump, &mut outbox,
+                                    config.bulk_cancel);
+                        synth.store().hold_changes();
+                        release_changes_at = None;
+                        dump = Some(Dump::new(&map, synth.store().index()));
+                    }
+                },
+                Some(_) => {
+                    if let Some(audit) = &mut audit {
+                        audit.note(Source::Synth, synth.store());
+                    }
+                },
+                None => break,
+            },
+            msg = next_tapped(&mut tapped) => match msg {
+                Some(TappedMessage { tap, at, msg }) => {
+                    if let Some(launcher) = launcher.as_mut().filter(|l| l.port() == tap) {
+                        leds_dirty |= launcher.feedback(&msg);
+                    }
+                    let clocked = config.clock.as_ref().is_some_and(|c| c.port == tap);
+                    if let Some((clock, queue)) = clock.as_mut().filter(|_| clocked) {
+                        clock.handle(&msg, at);
+                        if !clock.is_running() {
+                            queue.clear();
+                        }
+                        let moved = |bpm: &f32| tempo.is_none_or(|t| (bpm - t).abs() >= TEMPO_STEP);
+                        if let Some(bpm) = clock.bpm().filter(moved) {
+                            tempo = Some(bpm);
+                            bus.publish(EngineEvent::TempoChanged(bpm));
+                        }
+                    }
+                },
+                None => tapped = None,
+            },
+            command = remote_commands.recv() => match command {
+                Some(RemoteCommand::SetParam { param, value }) => {
+                    synth.write(param, value);
+                    if let Some(audit) = &mut audit {
+                        audit.note(Source::Remote, synth.store());
+                    }
+                },
+                Some(RemoteCommand::LoadSnapshot(values)) => {
+                    // A new recall replaces one still in progress.
+                    cancel_bulk(&mut transfer, &mut dump, &mut recall_after_dump, &mut outbox,
+                                config.bulk_cancel);
+                    synth.store().hold_changes();
+                    release_changes_at = None;
+                    match config.recall_diff {
+                        RecallDiff::Full => {
+                            transfer = Some(Transfer::new(&map, synth.store().index(), &values));
+                        },
+                        RecallDiff::Store => {
+                            let writes = synth.store().differing(&values);
+                            transfer = Some(Transfer::new(&map, synth.store().index(), &writes));
+                        },
+                        RecallDiff::Live => {
+                            let params: Vec<usize> = values.iter().map(|(param, _)| *param).collect();
+                            dump = Some(Dump::covering(&map, synth.store().index(), &params));
+                            recall_after_dump = Some(values.clone());
+                        },
+                    }
+                    last_snapshot = values;
+                },
+                Some(RemoteCommand::SendProgram(program)) => {
+                    for msg in program.messages() {
+                        synth.send(&msg);
+                    }
+                },
+                None => (),
+            },
+            event = state_changes.recv() => {
+                leds_dirty = true;
+                health.note_event(Instant::now());
+                match &event {
+                    Ok(EngineEvent::ParamChanged(change)) => {
+                        broadcaster.follow(change);
+                        favorites.note(synth.store().index(), change.param);
+                        if let Some(history) = &mut history {
+                            history.note_change(Instant::now(), true);
+                        }
+                    },
+                    Ok(EngineEvent::ParamsChanged(changes)) => {
+                        for change in changes {
+                            broadcaster.follow(change);
+                        }
+                        if let Some(history) = &mut history {
+                            history.note_change(Instant::now(), false);
+                        }
+                    },
+                    Ok(EngineEvent::TempoChanged(bpm)) => motions.set_bpm(*bpm),
+                    Ok(_) => (),
+                    Err(_) => broadcaster.resync(),
+                }
+                let context = RuleContext {
+                    song: setlist.as_ref().map(|s| s.current().name.as_str()),
+                    page: engine.page(),
+                };
+                let send = |msg: &[u8]| outbox.push(Priority::of(msg), msg);
+                leds_dirty |= rules.evaluate(synth.store(), &context, send);
+                if let (Some(marquee), Ok(event)) = (&mut marquee, &event) {
+                    leds_dirty |= marquee.handle(event, synth.store(), Instant::now());
+                }
+                display_dirty |= match event {
+                    Ok(event) => display.handle(&event),
+                    // Missed some; redraw from the store.
+                    Err(_) => true,
+                };
+            },
+            _ = ticker.tick() => {
+                let now = Instant::now();
+                fire.poll_debounce();
+                if let Some(song) = browse.as_mut().and_then(|browse| browse.poll(now)) {
+                    queue_recall(&song, &map, synth.store(), config.snapshot_mismatch, &mut commands,
+                                 &mut motions);
+                    leds_dirty = true;
+                }
+                if let Some(history) = &mut history {
+                    history.poll(now, synth.store());
+                }
+                for fired in timers.as_mut().map(|t| t.poll(now_ms())).unwrap_or_default() {
+                    match fired {
+                        Fired::Send(msg) => outbox.push(Priority::of(&msg), &msg),
+                        Fired::Command(command) => {
+                            if let Err(e) = commands.try_send(command) {
+                                warn!("can't queue timer's write: {}", e);
+                            }
+                        },
+                        Fired::Recall(recall) => {
+                            let song = Song { name: "timer".to_string(), recall };
+                            queue_recall(&song, &map, synth.store(), config.snapshot_mismatch, &mut commands,
+                                         &mut motions);
+                            leds_dirty = true;
+                        },
+                        Fired::Dump => {
+                            cancel_bulk(&mut transfer, &mut dump, &mut recall_after_dump, &mut outbox,
+                                        config.bulk_cancel);
+                            synth.store().hold_changes();
+                            release_changes_at = None;
+                            dump = Some(Dump::new(&map, synth.store().index()));
+                        },
+                        Fired::ReloadBindings => {
+                            match reload_bindings(config, &map, synth.store(), fire.caps(), router.as_ref()) {
+                                Ok((reloaded, reloaded_rules, mut reloaded_motions)) => {
+                                    info!("bindings reloaded");
+                                    reloaded_motions.set_scene(motions.scene());
+                                    if let Some(bpm) = tempo {
+                                        reloaded_motions.set_bpm(bpm);
+                                    }
+                                    engine = reloaded;
+                                    rules = reloaded_rules;
+                                    motions = reloaded_motions;
+                                    display.set_encoders(engine.encoder_params());
+                                    leds_dirty = true;
+                                    display_dirty = true;
+                                },
+                                Err(e) => warn!("can't reload bindings: {}", e),
+                            }
+                        },
+                    }
+                }
+                let ended = engine.tick(now, |msg| outbox.push(Priority::of(msg), msg));
+                for (param, value) in motions.tick(now) {
+                    let msg = encode_param_dt1(&map, &synth.store().index().params[param], value);
+                    outbox.push_write(Priority::Parameter, &msg, param, value);
+                }
+                if let Some((clock, queue)) = &mut clock {
+                    // `beats` motions land on the clock's next tick.
+                    let next = clock.position() + 1;
+                    let due = clock.time_of(next);
+                    motions.set_clocked(due.is_some());
+                    for (param, value) in due.map(|at| motions.tick_clocked(at)).unwrap_or_default() {
+                        let msg = encode_param_dt1(&map, &synth.store().index().params[param], value);
+                        queue.schedule(next, (msg, param, value));
+                    }
+                    queue.flush(clock, now, |(msg, param, value)| {
+                        outbox.push_write(Priority::Parameter, &msg, param, value)
+                    });
+                }
+                if let Some(audit) = &mut audit {
+                    audit.note(Source::Binding, synth.store());
+                }
+                if let Some(history) = &mut history {
+                    // One undo step per gesture.
+                    if !ended.is_empty() {
+                        history.gesture_end(synth.store());
+                    }
+                }
+                broadcaster.poll();
+                // Bulk messages are only queued a tick's worth at a time,
+                // so a recall or dump started later isn't stuck behind all
+                // of this one.
+                if let Some(t) = &mut transfer {
+                    while outbox.len(Priority::Bulk) < WRITES_PER_TICK {
+                        match t.next_write() {
+                            Some((param, value)) => {
+                                let msg = encode_param_dt1(&map, &synth.store().index().params[param], value);
+                                outbox.push_write(Priority::Bulk, &msg, param, value);
+                            },
+                            None => break,
+                        }
+                    }
+                }
+                if let Some(d) = &mut dump {
+                    while outbox.len(Priority::Bulk) < WRITES_PER_TICK {
+                        match d.next_request() {
+                            Some((address, size)) => outbox.push(Priority::Bulk, &encode_rq1(&map, address, size)),
+                            None => break,
+                        }
+                    }
+                }
+                for (param, value) in outbox.flush(now, |msg| synth.send(msg)) {
+                    synth.store().set(param, value);
+                }
+                if let Some(audit) = &mut audit {
+                    audit.note(Source::Recall, synth.store());
+                }
+                let bulk_sent = outbox.len(Priority::Bulk) == 0;
+                if let Some(t) = &transfer {
+                    bus.publish(EngineEvent::Progress(t.progress()));
+                    if t.progress().is_finished() && bulk_sent {
+                        transfer = None;
+                        release_changes_at = Some(now + FEEDBACK_WINDOW);
+                        // It cut short the read on connect, so that's
+                        // started over.
+                        if synth.controller().phase() == Phase::Syncing && dump.is_none() {
+                            synth.store().hold_changes();
+                            dump = Some(Dump::new(&map, synth.store().index()));
+                        }
+                    }
+                }
+                if let Some(d) = &dump {
+                    bus.publish(EngineEvent::Progress(d.progress()));
+                    if d.progress().is_finished() && bulk_sent {
+                        dump = None;
+                        release_changes_at = Some(now + FEEDBACK_WINDOW);
+                        // Only a whole patch read counts.
+                        if recall_after_dump.is_none() {
+                            synth.controller().synced();
+                            backup_due = config.backup.is_some();
+                        }
+                    }
+                }
+                if let Some(at) = release_changes_at {
+                    if now >= at {
+                        release_changes_at = None;
+                        match recall_after_dump.take() {
+                            // The dump's replies are in, so the store's what
+                            // the synth really has.
+                            Some(values) => {
+                                let writes = synth.store().differing(&values);
+                                info!("recall: {} of {} params differ", writes.len(), values.len());
+                                transfer = Some(Transfer::new(&map, synth.store().index(), &writes));
+                            },
+                            None => {
+                                synth.store().release_changes();
+                                if let (true, Some(backup)) = (backup_due, &config.backup) {
+                                    backup_due = false;
+                                    if let Err(e) = back_up(backup, device, &map, synth.store()) {
+                                        warn!("backup: {}", e);
+                                    }
+                                }
+                            },
+                        }
+                    }
+                }
+                if idle.poll(now) && idle.is_dimmed() {
+                    fire.set_led_brightness(config.idle.dim_brightness);
+                    leds_dirty = true;
+                }
+                leds_dirty |= status.poll(now);
+                leds_dirty |= heatmap.poll(now);
+                if let Some(marquee) = &mut marquee {
+                    leds_dirty |= marquee.poll(now);
+                }
+                if leds_dirty {
+                    compositor.clear();
+                    engine.render_pads(|i, r, g, b| compositor.set(Layer::Base, i, r, g, b));
+                    rules.render(|i, r, g, b| compositor.set(Layer::Base, i, r, g, b));
+                    motions.render(|i, r, g, b| compositor.set(Layer::Base, i, r, g, b));
+                    if let Some(launcher) = &launcher {
+                        launcher.render(|i, r, g, b| compositor.set(Layer::Base, i, r, g, b));
+                    }
+                    if let Some(compare) = &compare {
+                        compare.render(|i, r, g, b| compositor.set(Layer::Base, i, r, g, b));
+                    }
+                    if heatmap.is_on() {
+                        heatmap.render(now, |i, r, g, b| compositor.set(Layer::Overlay, i, r, g, b));
+                    } else if let Some(marquee) = &marquee {
+                        marquee.render(now, |i, r, g, b| compositor.set(Layer::Overlay, i, r, g, b));
+                    }
+                    status.render(now, |i, r, g, b| compositor.set(Layer::Status, i, r, g, b));
+                    compositor.render(|i, r, g, b| fire.set_led(i, r, g, b));
+                    engine.render_rings(|i, mode, level| fire.set_ring(i, mode, level));
+                    fire.update_leds();
+                    leds_dirty = false;
+                }
+                if idle.screensaver_on() {
+                    let frame = idle.frame(now);
+                    if screensaver_frame != Some(frame) {
+                        display.render_screensaver(fire.display_mut(), config.idle.animation, frame);
+                        fire.update_display();
+                        screensaver_frame = Some(frame);
+                    }
+                } else if display_dirty {
+                    if menu.is_open() {
+                        menu.render(fire.display_mut());
+                    } else {
+                        display.render(fire.display_mut());
+                    }
+                    fire.update_display();
+                    #[cfg(feature = "push2")]
+                    {
+                        if let Some(push_display) = &mut push_display {
+                            display.render_push(push_display.frame_mut());
+                            push_display.send();
+                        }
+                    }
+                    display_dirty = false;
+                }
+            },
+            _ = watchdog.tick() => {
+                // The Push 2's display blanks if it isn't sent frames.
+                #[cfg(feature = "push2")]
+                {
+                    if let Some(push_display) = &mut push_display {
+                        push_display.send();
+                    }
+                }
+                if let Err(e) = favorites.save() {
+                    warn!("can't save favorites: {}", e);
+                }
+                fire.poll_watchdog();
+                synth.controller().poll_watchdog();
+                broadcaster.poll_watchdogs();
+                let synth_phase = synth.controller().phase();
+                let synth_port = synth.controller().port_name().to_string();
+                let devices = [(fire.port_name(), fire.phase()), (synth_port.as_str(), synth_phase)];
+                let problem = health.check(Instant::now(), &devices).err();
+                if problem != unhealthy {
+                    match &problem {
+                        Some(problem) => {
+                            warn!("unhealthy: {}", problem);
+                            notifier.status(problem);
+                        },
+                        None => {
+                            info!("healthy again");
+                            notifier.status("running");
+                        },
+                    }
+                    unhealthy = problem;
+                }
+                // Left to time out while unhealthy, for systemd to restart
+                // us.
+                if unhealthy.is_none() {
+                    notifier.ping();
+                }
+            },
+        }
+    }
+
+    notifier.stopping();
+    drop(commands);
+    favorites.save()?;
+    Ok(())
+}
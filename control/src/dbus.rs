@@ -0,0 +1,100 @@
+//! A D-Bus service on the session bus so desktop automation (ex: stream deck
+//! software, `busctl` in scripts) can drive the mapper:
+//! ```shell
+//! busctl --user call org.mapatron.Mapatron /org/mapatron/Mapatron \
+//!     org.mapatron.Mapatron1 SetParam ss "Temporary Scene/Scene Common/Scene Level" 100
+//! ```
+
+use zbus::blocking::{Connection, ConnectionBuilder};
+use zbus::{dbus_interface, fdo};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::human::format_value;
+use crate::param_store::ParamStore;
+use crate::remote::{load_snapshot_command, set_param_command, CommandSender, RemoteCommand};
+use crate::snapshot::FingerprintPolicy;
+use crate::sysex_map::SysexMap;
+
+pub const BUS_NAME: &str = "org.mapatron.Mapatron";
+pub const OBJECT_PATH: &str = "/org/mapatron/Mapatron";
+
+struct Mapatron {
+    map: SysexMap,
+    store: Arc<ParamStore>,
+    commands: CommandSender,
+    policy: FingerprintPolicy,
+}
+
+impl Mapatron {
+    fn send(&mut self, command: RemoteCommand) -> fdo::Result<()> {
+        self.commands.try_send(command)
+            .map_err(|e| fdo::Error::Failed(format!("can't queue command: {}", e)))
+    }
+
+    fn human(&self, param: usize) -> String {
+        format_value(&self.store.display_entry(param), self.store.get(param))
+    }
+}
+
+#[dbus_interface(name = "org.mapatron.Mapatron1")]
+impl Mapatron {
+    /// Set a param from a human value, ex: "ON" or "-12".
+    fn set_param(&mut self, name: &str, value: &str) -> fdo::Result<()> {
+        let command = set_param_command(&self.store, name, value).map_err(fdo::Error::InvalidArgs)?;
+        self.send(command)
+    }
+
+    /// A param's current human value.
+    fn get_param(&self, name: &str) -> fdo::Result<String> {
+        let param = self.store.index().index_of(name)
+            .ok_or_else(|| fdo::Error::InvalidArgs(format!("no param '{}'", name)))?;
+        Ok(self.human(param))
+    }
+
+    /// Names of the params starting with `prefix`.
+    fn list_params(&self, prefix: &str) -> Vec<String> {
+        self.store.index().params.iter()
+            .filter(|p| p.name.starts_with(prefix))
+            .map(|p| p.name.clone())
+            .collect()
+    }
+
+    /// Every visible param's human value.
+    fn get_state(&self) -> HashMap<String, String> {
+        self.store.index().params.iter().enumerate()
+            .filter(|(idx, _)| self.store.is_visible(*idx))
+            .map(|(idx, p)| (p.name.clone(), self.human(idx)))
+            .collect()
+    }
+
+    /// Send a .syx snapshot (ex: a saved scene) to the synth.  Returns how
+    /// many params it set.
+    fn load_snapshot(&mut self, path: &str) -> fdo::Result<u32> {
+        let command = load_snapshot_command(&self.map, &self.store, path, self.policy)
+            .map_err(fdo::Error::Failed)?;
+        let count = match &command {
+            RemoteCommand::LoadSnapshot(values) => values.len() as u32,
+            _ => 0,
+        };
+        self.send(command)?;
+        Ok(count)
+    }
+}
+
+/// Claim `BUS_NAME` on the session bus and serve until the returned
+/// connection is dropped.
+pub fn serve(map: SysexMap, store: Arc<ParamStore>, commands: CommandSender, policy: FingerprintPolicy)
+             -> zbus::Result<Connection> {
+    let service = Mapatron {
+        map,
+        store,
+        commands,
+        policy,
+    };
+    ConnectionBuilder::session()?
+        .name(BUS_NAME)?
+        .serve_at(OBJECT_PATH, service)?
+        .build()
+}
@@ -0,0 +1,73 @@
+//! Decoding incoming sysex off the daemon's task.  A bulk dump can be
+//! thousands of params, and decoding it where the daemon reads events would
+//! hold up the Fire's.  Each synth instead gets a `Lane` on a shared pool of
+//! worker threads; a lane's jobs always run on the same worker, one after
+//! another, so a device's messages land in the store in the order it sent
+//! them while different devices decode side by side.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::isolate::guarded;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Most workers a pool starts, however many cores there are.
+const MAX_WORKERS: usize = 4;
+
+pub struct DecodePool {
+    workers: Vec<mpsc::Sender<Job>>,
+    next: AtomicUsize,
+}
+
+/// Where one device's decoding runs.
+#[derive(Clone)]
+pub struct Lane {
+    worker: mpsc::Sender<Job>,
+}
+
+impl DecodePool {
+    /// A pool with a worker per core, up to `MAX_WORKERS`.
+    pub fn new() -> Self {
+        let count = thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(MAX_WORKERS);
+        let workers = (0..count).map(|i| {
+            let (tx, rx) = mpsc::channel::<Job>();
+            thread::Builder::new()
+                .name(format!("mapatron-decode-{}", i))
+                .spawn(move || {
+                    for job in rx {
+                        guarded("sysex decoding", job);
+                    }
+                })
+                .expect("can't start decode worker");
+            tx
+        }).collect();
+        DecodePool {
+            workers,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// A lane for another device, on the workers in turn.
+    pub fn lane(&self) -> Lane {
+        let i = self.next.fetch_add(1, Ordering::Relaxed) % self.workers.len();
+        Lane {
+            worker: self.workers[i].clone(),
+        }
+    }
+}
+
+impl Default for DecodePool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lane {
+    /// Run `job` after everything already queued on the lane.  A job that
+    /// panics is logged and skipped.  Returns false if the pool is gone.
+    pub fn run<F: FnOnce() + Send + 'static>(&self, job: F) -> bool {
+        self.worker.send(Box::new(job)).is_ok()
+    }
+}
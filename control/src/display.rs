@@ -0,0 +1,223 @@
+use std::sync::Arc;
+
+use crate::bus::EngineEvent;
+use crate::controllers::push2::{rgb565, Frame, PUSH2_DISPLAY_WIDTH};
+use crate::controllers::{OledBuffer, CHAR_ADVANCE, GLYPH_HEIGHT, OLED_HEIGHT, OLED_WIDTH};
+use crate::favorites::FAVORITES_PAGE;
+use crate::human::format_value;
+use crate::idle::IdleAnimation;
+use crate::param_store::ParamStore;
+use crate::progress::Progress;
+
+/// Height of the bar for the last-touched param.
+const BAR_HEIGHT: usize = 8;
+/// Height of each encoder's mini-bar.
+const MINI_BAR_HEIGHT: usize = 5;
+/// Gap between the encoder columns.
+const COLUMN_GAP: usize = 4;
+/// What bounces around the screensaver.
+const SCREENSAVER_TEXT: &str = "MAPATRON";
+/// Colors on the Push 2's display: the encoder columns take these in turn.
+const PUSH_COLUMN_COLORS: [u16; 4] = [
+    rgb565(0xff, 0x60, 0x20),
+    rgb565(0x30, 0xc0, 0xff),
+    rgb565(0x80, 0xff, 0x40),
+    rgb565(0xff, 0x40, 0xc0),
+];
+const PUSH_TEXT: u16 = rgb565(0xe0, 0xe0, 0xe0);
+const PUSH_BAR_BACKGROUND: u16 = rgb565(0x30, 0x30, 0x30);
+
+/// What the controller's OLED shows: a page indicator, the last-touched
+/// param with its value and a bar, and mini-bars for each encoder's param.
+/// Fed `EngineEvent`s; `handle` says when it needs redrawing.
+pub struct Display {
+    store: Arc<ParamStore>,
+    encoders: Vec<Option<usize>>,
+    touched: Option<usize>,
+    page: usize,
+    song: Option<String>,
+    /// A transfer in progress.
+    progress: Option<Progress>,
+}
+
+/// Bounce back and forth between 0 and `max` one step per frame.
+fn bounce(frame: u32, max: usize) -> usize {
+    if max == 0 {
+        return 0;
+    }
+    let pos = frame as usize % (2 * max);
+    if pos > max { 2 * max - pos } else { pos }
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.chars().take(width / CHAR_ADVANCE).collect()
+}
+
+impl Display {
+    /// `encoders` is the param bound to each encoder, if any, left to right.
+    pub fn new(store: Arc<ParamStore>, encoders: Vec<Option<usize>>) -> Self {
+        Display {
+            store,
+            encoders,
+            touched: None,
+            page: 0,
+            song: None,
+            progress: None,
+        }
+    }
+
+    /// Change which param each encoder shows, ex: on a page change.
+    pub fn set_encoders(&mut self, encoders: Vec<Option<usize>>) {
+        self.encoders = encoders;
+    }
+
+    /// Show a setlist's current song at the top left.
+    pub fn set_song(&mut self, song: Option<String>) {
+        self.song = song;
+    }
+
+    /// Returns whether the display needs redrawing.
+    pub fn handle(&mut self, event: &EngineEvent) -> bool {
+        match event {
+            EngineEvent::ParamChanged(change) => {
+                self.touched = Some(change.param);
+                true
+            },
+            // A recall; the encoder bars need redrawing, but nothing in
+            // particular was touched.
+            EngineEvent::ParamsChanged(_) => true,
+            EngineEvent::PageChanged(page) => {
+                self.page = *page;
+                true
+            },
+            EngineEvent::Progress(progress) => {
+                self.progress = if progress.is_finished() { None } else { Some(*progress) };
+                true
+            },
+            _ => false,
+        }
+    }
+
+    fn page_label(&self) -> String {
+        if self.page == FAVORITES_PAGE {
+            "FAVORITES".to_string()
+        } else {
+            format!("PAGE {}", self.page + 1)
+        }
+    }
+
+    /// The part of a bar `width` wide that's filled for `param`'s value:
+    /// from the left, or from the center for bipolar params.
+    fn bar_fill(&self, param: usize, width: usize) -> (usize, usize) {
+        let entry = &self.store.index().params[param].entry;
+        let (low, high) = (entry.discrete_range_low, entry.discrete_range_high);
+        let pos = |value: u32| {
+            let span = (high - low).max(1) as usize;
+            (value.max(low).min(high) - low) as usize * width / span
+        };
+        let value = pos(self.store.get(param));
+        let start = match &entry.human_value_bipolar {
+            Some(bipolar) => pos(bipolar.center),
+            None => 0,
+        };
+        if value < start { (value, start) } else { (start, value) }
+    }
+
+    /// Draw a bar for `param`'s value in a box.
+    fn draw_bar(&self, oled: &mut OledBuffer, param: usize, x: usize, y: usize, w: usize, h: usize) {
+        let (from, to) = self.bar_fill(param, w.saturating_sub(2));
+        oled.draw_rect(x, y, w, h);
+        oled.fill_rect(x + 1 + from, y + 1, (to - from).max(1), h.saturating_sub(2), true);
+    }
+
+    pub fn render(&self, oled: &mut OledBuffer) {
+        oled.clear();
+
+        // Page indicator, dark text in a box at the top right.
+        let page = self.page_label();
+        let page_width = page.len() * CHAR_ADVANCE + 1;
+        let page_x = OLED_WIDTH - page_width;
+        oled.fill_rect(page_x, 0, page_width, GLYPH_HEIGHT + 2, true);
+        oled.draw_text(page_x + 1, 1, &page, false);
+        if let Some(song) = &self.song {
+            oled.draw_text(0, 1, &truncate(song, page_x.saturating_sub(CHAR_ADVANCE)), true);
+        }
+
+        if let Some(param) = self.touched {
+            let name = self.store.index().params[param].display_name();
+            let value = format_value(&self.store.display_entry(param), self.store.get(param));
+            oled.draw_text(0, 10, &truncate(name, OLED_WIDTH), true);
+            oled.draw_text(0, 18, &truncate(&value, OLED_WIDTH), true);
+            self.draw_bar(oled, param, 0, 25, OLED_WIDTH, BAR_HEIGHT);
+        }
+
+        if let Some(progress) = &self.progress {
+            let label = progress.task.label();
+            let bar_x = (label.len() + 1) * CHAR_ADVANCE;
+            let bar_width = OLED_WIDTH - bar_x;
+            oled.draw_text(0, 34, label, true);
+            oled.draw_rect(bar_x, 34, bar_width, GLYPH_HEIGHT);
+            let filled = ((bar_width - 2) as f32 * progress.fraction()) as usize;
+            oled.fill_rect(bar_x + 1, 35, filled, GLYPH_HEIGHT - 2, true);
+        }
+
+        let count = self.encoders.len().max(1);
+        let column = (OLED_WIDTH + COLUMN_GAP) / count - COLUMN_GAP;
+        for (i, param) in self.encoders.iter().enumerate() {
+            let param = match param {
+                Some(param) => *param,
+                None => continue,
+            };
+            let x = i * (column + COLUMN_GAP);
+            let name = self.store.index().params[param].display_name();
+            let value = format_value(&self.store.display_entry(param), self.store.get(param));
+            oled.draw_text(x, 40, &truncate(name, column), true);
+            self.draw_bar(oled, param, x, 47, column, MINI_BAR_HEIGHT);
+            oled.draw_text(x, 55, &truncate(&value, column), true);
+        }
+    }
+
+    /// The same for the Push 2's color display: a column per encoder, under
+    /// it, with the param's name, value and a bar in the column's color, and
+    /// the last-touched param across the top.
+    pub fn render_push(&self, frame: &mut Frame) {
+        frame.clear();
+        let top = format!("{}  {}", self.page_label(), self.song.as_deref().unwrap_or(""));
+        frame.draw_text(8, 8, &top, 2, PUSH_TEXT);
+        if let Some(param) = self.touched {
+            let name = self.store.index().params[param].display_name();
+            let value = format_value(&self.store.display_entry(param), self.store.get(param));
+            frame.draw_text(8, 28, &format!("{}  {}", name, value), 3, PUSH_TEXT);
+        }
+
+        let column = PUSH2_DISPLAY_WIDTH / self.encoders.len().max(1);
+        for (i, param) in self.encoders.iter().enumerate() {
+            let param = match param {
+                Some(param) => *param,
+                None => continue,
+            };
+            let (x, w) = (i * column + 4, column - 8);
+            let color = PUSH_COLUMN_COLORS[i % PUSH_COLUMN_COLORS.len()];
+            let name = self.store.index().params[param].display_name();
+            let value = format_value(&self.store.display_entry(param), self.store.get(param));
+            let chars = w / (CHAR_ADVANCE * 2);
+            frame.draw_text(x, 80, &name.chars().take(chars).collect::<String>(), 2, color);
+            frame.draw_text(x, 100, &value.chars().take(chars).collect::<String>(), 2, PUSH_TEXT);
+            let (from, to) = self.bar_fill(param, w);
+            frame.fill_rect(x, 130, w, 16, PUSH_BAR_BACKGROUND);
+            frame.fill_rect(x + from, 130, (to - from).max(2), 16, color);
+        }
+    }
+
+    /// Draw frame `frame` of the screensaver.
+    pub fn render_screensaver(&self, oled: &mut OledBuffer, animation: IdleAnimation, frame: u32) {
+        oled.clear();
+        if animation == IdleAnimation::Bounce {
+            let width = SCREENSAVER_TEXT.len() * CHAR_ADVANCE;
+            // Different periods on each axis so it wanders over the display.
+            let x = bounce(frame, OLED_WIDTH - width);
+            let y = bounce(frame, OLED_HEIGHT - GLYPH_HEIGHT);
+            oled.draw_text(x, y, SCREENSAVER_TEXT, true);
+        }
+    }
+}
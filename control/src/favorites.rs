@@ -0,0 +1,99 @@
+//! A page of the params edited most, for putting on the encoders without
+//! writing any bindings.  Edits are counted by param name as they happen and
+//! kept per synth in `favorites_dir`, ex: `favorites/jupx.json`.  Turning an
+//! encoder through a param counts as one edit, however many detents it
+//! takes.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use crate::sysex_map::ParamIndex;
+
+/// The page number the favorites are shown as.  The bindings are page 0.
+pub const FAVORITES_PAGE: usize = 1;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct FavoritesFile {
+    /// Edit counts by param name.
+    edits: BTreeMap<String, u32>,
+}
+
+pub struct Favorites {
+    path: PathBuf,
+    edits: BTreeMap<String, u32>,
+    /// The param edited last, so edits in a row only count once.
+    last: Option<usize>,
+    /// Whether there are counts that haven't been saved.
+    dirty: bool,
+}
+
+impl Favorites {
+    /// The counts for `device` kept in `dir`.  Starts from nothing if there
+    /// aren't any yet or they can't be read.
+    pub fn load(dir: &str, device: &str) -> Favorites {
+        let path = Path::new(dir).join(format!("{}.json", device));
+        let edits = match File::open(&path) {
+            Ok(file) => match serde_json::from_reader::<_, FavoritesFile>(BufReader::new(file)) {
+                Ok(file) => file.edits,
+                Err(e) => {
+                    warn!("{}: {}", path.display(), e);
+                    BTreeMap::new()
+                },
+            },
+            Err(_) => BTreeMap::new(),
+        };
+        Favorites {
+            path,
+            edits,
+            last: None,
+            dirty: false,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Count an edit of `param`, unless it's the one edited last.
+    pub fn note(&mut self, index: &ParamIndex, param: usize) {
+        if self.last == Some(param) {
+            return;
+        }
+        self.last = Some(param);
+        *self.edits.entry(index.params[param].name.clone()).or_insert(0) += 1;
+        self.dirty = true;
+    }
+
+    /// The `count` params edited most, most first.  Ties go by name.
+    /// Params the map no longer has are skipped.
+    pub fn top(&self, index: &ParamIndex, count: usize) -> Vec<usize> {
+        let mut edits: Vec<(&String, &u32)> = self.edits.iter().collect();
+        edits.sort_by(|a, b| b.1.cmp(a.1));
+        edits.into_iter()
+            .filter_map(|(name, _)| index.index_of(name))
+            .take(count)
+            .collect()
+    }
+
+    /// Write the counts out if they've changed since last time.
+    pub fn save(&mut self) -> Result<(), Box<dyn Error>> {
+        if !self.dirty {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = FavoritesFile {
+            edits: self.edits.clone(),
+        };
+        fs::write(&self.path, serde_json::to_string_pretty(&file)?)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
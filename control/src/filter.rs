@@ -0,0 +1,43 @@
+//! Filters on MIDI messages, shared by routes and a map's `input_filters`.
+//! Kept apart from the router so the map doesn't need midir.
+
+use serde::{Deserialize, Serialize};
+
+const TIMING_CLOCK: u8 = 0xf8;
+
+pub(crate) fn is_channel_message(status: u8) -> bool {
+    (0x80..0xf0).contains(&status)
+}
+
+/// What a route doesn't pass at all.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RouteFilter {
+    /// Drop timing clock, ex: when the destination should follow its own.
+    #[serde(default)]
+    pub drop_clock: bool,
+    #[serde(default)]
+    pub block_sysex: bool,
+    /// Pass nothing but sysex.
+    #[serde(default)]
+    pub sysex_only: bool,
+    /// Only pass channel messages on these channels (1-16).  Empty passes
+    /// every channel.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub channels: Vec<u8>,
+}
+
+impl RouteFilter {
+    pub fn passes(&self, msg: &[u8]) -> bool {
+        let status = match msg.first() {
+            Some(status) => *status,
+            None => return false,
+        };
+        if (self.drop_clock && status == TIMING_CLOCK) ||
+           (self.block_sysex && status == 0xf0) ||
+           (self.sysex_only && status != 0xf0) {
+            return false;
+        }
+        !is_channel_message(status) || self.channels.is_empty() ||
+            self.channels.contains(&((status & 0x0f) + 1))
+    }
+}
@@ -0,0 +1,156 @@
+//! A tiny arithmetic language for computed human values, ex: the map entry
+//! `"human_value_formula": "value * 3 / 2 - 64"`.
+//!
+//! Supports numbers, the variable `value` (the raw value), `+ - * / %`,
+//! unary minus and parentheses, with the usual precedence.  Division isn't
+//! integer division; results are rounded for display by the formatter.
+//! Formulas are parsed as the map is loaded, so a bad one fails the load.
+
+use serde::{Deserialize, Serialize};
+
+use std::convert::TryFrom;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Number(f64),
+    Value,
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Formula {
+    text: String,
+    expr: Expr,
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_space(&mut self) {
+        while let Some(c) = self.text[self.pos..].chars().next().filter(|c| c.is_whitespace()) {
+            self.pos += c.len_utf8();
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_space();
+        self.text[self.pos..].chars().next()
+    }
+
+    fn binary(&mut self, ops: &str, next: fn(&mut Self) -> Result<Expr, String>)
+              -> Result<Expr, String> {
+        let mut lhs = next(self)?;
+        while let Some(op) = self.peek().filter(|c| ops.contains(*c)) {
+            self.pos += 1;
+            let rhs = next(self)?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        self.binary("+-", Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        self.binary("*/%", Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                Ok(Expr::Neg(Box::new(self.unary()?)))
+            },
+            Some('(') => {
+                self.pos += 1;
+                let expr = self.sum()?;
+                if self.peek() != Some(')') {
+                    return Err(format!("expected ')' at {}", self.pos));
+                }
+                self.pos += 1;
+                Ok(expr)
+            },
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let len = self.text[self.pos..]
+                    .find(|c: char| !c.is_ascii_digit() && c != '.')
+                    .unwrap_or(self.text.len() - self.pos);
+                let number = &self.text[self.pos..self.pos + len];
+                self.pos += len;
+                number.parse().map(Expr::Number).map_err(|_| format!("bad number '{}'", number))
+            },
+            Some(c) if c.is_ascii_alphabetic() => {
+                let len = self.text[self.pos..]
+                    .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                    .unwrap_or(self.text.len() - self.pos);
+                let name = &self.text[self.pos..self.pos + len];
+                self.pos += len;
+                match name {
+                    "value" => Ok(Expr::Value),
+                    _ => Err(format!("unknown name '{}'", name)),
+                }
+            },
+            Some(c) => Err(format!("unexpected '{}' at {}", c, self.pos)),
+            None => Err("unexpected end of formula".to_string()),
+        }
+    }
+}
+
+fn eval(expr: &Expr, value: f64) -> f64 {
+    match expr {
+        Expr::Number(n) => *n,
+        Expr::Value => value,
+        Expr::Neg(e) => -eval(e, value),
+        Expr::Binary(op, a, b) => {
+            let (a, b) = (eval(a, value), eval(b, value));
+            match op {
+                '+' => a + b,
+                '-' => a - b,
+                '*' => a * b,
+                '/' => a / b,
+                _ => a % b,
+            }
+        },
+    }
+}
+
+impl Formula {
+    pub fn parse(text: &str) -> Result<Formula, String> {
+        let mut parser = Parser {
+            text,
+            pos: 0,
+        };
+        let expr = parser.sum()?;
+        if let Some(c) = parser.peek() {
+            return Err(format!("unexpected '{}' at {}", c, parser.pos));
+        }
+        Ok(Formula {
+            text: text.to_string(),
+            expr,
+        })
+    }
+
+    /// Evaluate with `value` bound to the raw value.
+    pub fn eval(&self, raw: u32) -> f64 {
+        eval(&self.expr, raw as f64)
+    }
+}
+
+impl TryFrom<String> for Formula {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        Formula::parse(&text).map_err(|e| format!("bad formula '{}': {}", text, e))
+    }
+}
+
+impl From<Formula> for String {
+    fn from(formula: Formula) -> String {
+        formula.text
+    }
+}
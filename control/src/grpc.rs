@@ -0,0 +1,182 @@
+//! A gRPC service for show control and other programs that want typed calls
+//! rather than D-Bus or MQTT, ex: `"grpc": { "address": "0.0.0.0:50051" }`.
+//! The service is in `proto/mapatron.proto`; other languages generate their
+//! clients from it (ex: `python -m grpc_tools.protoc`), and Rust programs
+//! can use `proto::mapatron_client::MapatronClient`.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::stream::Stream;
+use tokio::sync::broadcast::RecvError;
+use tokio::sync::mpsc;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use crate::bus::{EngineEvent, EventBus, EventKind};
+use crate::human::format_value;
+use crate::param_store::ParamStore;
+use crate::remote::{load_snapshot_command, set_param_command, CommandSender, RemoteCommand};
+use crate::snapshot::FingerprintPolicy;
+use crate::sysex_map::SysexMap;
+
+pub mod proto {
+    tonic::include_proto!("mapatron");
+}
+
+use proto::mapatron_server::{self, MapatronServer};
+use proto::{DeviceList, GetParamRequest, ListDevicesRequest, Param, RecallSnapshotReply,
+            RecallSnapshotRequest, SetParamReply, SetParamRequest, WatchParamsRequest};
+
+/// Changes that can queue up for a watcher before it's dropped.
+const WATCH_BUFFER: usize = 256;
+
+/// The `grpc` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GrpcConfig {
+    #[serde(default = "default_address")]
+    pub address: String,
+}
+
+fn default_address() -> String {
+    "127.0.0.1:50051".to_string()
+}
+
+struct Mapatron {
+    map: SysexMap,
+    store: Arc<ParamStore>,
+    commands: CommandSender,
+    policy: FingerprintPolicy,
+    /// Ports of the devices that have attached, oldest first.
+    devices: Arc<Mutex<Vec<String>>>,
+    bus: EventBus,
+}
+
+impl Mapatron {
+    fn send(&self, command: RemoteCommand) -> Result<(), String> {
+        self.commands.clone().try_send(command).map_err(|e| format!("can't queue command: {}", e))
+    }
+}
+
+fn param(store: &ParamStore, param: usize, value: u32) -> Param {
+    Param {
+        name: store.index().params[param].name.clone(),
+        value: format_value(&store.display_entry(param), value),
+        raw: value,
+    }
+}
+
+#[tonic::async_trait]
+impl mapatron_server::Mapatron for Mapatron {
+    async fn set_param(&self, request: Request<SetParamRequest>) -> Result<Response<SetParamReply>, Status> {
+        let request = request.into_inner();
+        let command = set_param_command(&self.store, &request.name, &request.value)
+            .map_err(Status::invalid_argument)?;
+        self.send(command).map_err(Status::unavailable)?;
+        Ok(Response::new(SetParamReply {}))
+    }
+
+    async fn get_param(&self, request: Request<GetParamRequest>) -> Result<Response<Param>, Status> {
+        let name = request.into_inner().name;
+        let idx = self.store.index().index_of(&name)
+            .ok_or_else(|| Status::not_found(format!("no param '{}'", name)))?;
+        Ok(Response::new(param(&self.store, idx, self.store.get(idx))))
+    }
+
+    async fn recall_snapshot(&self, request: Request<RecallSnapshotRequest>)
+                             -> Result<Response<RecallSnapshotReply>, Status> {
+        let path = request.into_inner().path;
+        let command = load_snapshot_command(&self.map, &self.store, &path, self.policy)
+            .map_err(Status::failed_precondition)?;
+        let params = match &command {
+            RemoteCommand::LoadSnapshot(values) => values.len() as u32,
+            _ => 0,
+        };
+        self.send(command).map_err(Status::unavailable)?;
+        Ok(Response::new(RecallSnapshotReply { params }))
+    }
+
+    async fn list_devices(&self, _request: Request<ListDevicesRequest>) -> Result<Response<DeviceList>, Status> {
+        let ports = self.devices.lock().unwrap().clone();
+        Ok(Response::new(DeviceList { ports }))
+    }
+
+    type WatchParamsStream = Pin<Box<dyn Stream<Item = Result<Param, Status>> + Send + Sync>>;
+
+    async fn watch_params(&self, request: Request<WatchParamsRequest>)
+                          -> Result<Response<Self::WatchParamsStream>, Status> {
+        let prefix = request.into_inner().prefix;
+        let store = self.store.clone();
+        let mut changes = self.bus.subscribe(&[EventKind::Param]);
+        let (mut tx, rx) = mpsc::channel(WATCH_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let changes = match changes.recv().await {
+                    Ok(EngineEvent::ParamChanged(change)) => vec![change],
+                    Ok(EngineEvent::ParamsChanged(changes)) => changes,
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(_)) => {
+                        let _ = tx.send(Err(Status::resource_exhausted("fell behind the changes"))).await;
+                        break;
+                    },
+                    Err(RecvError::Closed) => break,
+                };
+                for change in changes {
+                    if !store.index().params[change.param].name.starts_with(&prefix) {
+                        continue;
+                    }
+                    // The watcher hung up.
+                    if tx.send(Ok(param(&store, change.param, change.value))).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(rx)))
+    }
+}
+
+/// Serve on `config.address` until the process exits.  `devices` are the
+/// ports attached so far; ones that attach later are picked up from the bus.
+pub fn start(config: &GrpcConfig, map: SysexMap, store: Arc<ParamStore>, bus: &EventBus,
+             commands: CommandSender, policy: FingerprintPolicy, devices: Vec<String>)
+             -> Result<(), String> {
+    let address = config.address.parse()
+        .map_err(|e| format!("bad grpc address '{}': {}", config.address, e))?;
+    let devices = Arc::new(Mutex::new(devices));
+
+    let attached = devices.clone();
+    let mut events = bus.subscribe(&[EventKind::Device]);
+    tokio::spawn(async move {
+        loop {
+            match events.recv().await {
+                Ok(EngineEvent::DeviceAttached(port)) => {
+                    let mut devices = attached.lock().unwrap();
+                    if !devices.contains(&port) {
+                        devices.push(port);
+                    }
+                },
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+
+    let service = Mapatron {
+        map,
+        store,
+        commands,
+        policy,
+        devices,
+        bus: bus.clone(),
+    };
+    tokio::spawn(async move {
+        info!("serving gRPC on {}", address);
+        if let Err(e) = Server::builder().add_service(MapatronServer::new(service)).serve(address).await {
+            warn!("gRPC server stopped: {}", e);
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,161 @@
+//! Running under systemd for installations nobody's watching, ex: a unit
+//! with `Type=notify` and `WatchdogSec=10`.  The daemon says when it's
+//! ready, and pings the watchdog only while its self-checks pass, so a
+//! wedged or cut-off daemon gets restarted:
+//! ```json
+//! "health": { "max_event_age_s": 3600, "disconnected_s": 30 }
+//! ```
+//! Outside systemd (no `$NOTIFY_SOCKET`) the notifications go nowhere, but
+//! the checks still log.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant};
+
+use crate::controllers::Phase;
+
+/// The `health` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HealthConfig {
+    /// Unhealthy once nothing's come from the controller or the synth for
+    /// this long, ex: for an exhibit that's played all day.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_event_age_s: Option<u64>,
+    /// Unhealthy once a device has been disconnected for this long, rather
+    /// than waiting on the reconnect.
+    #[serde(default = "default_disconnected_s")]
+    pub disconnected_s: u64,
+}
+
+fn default_disconnected_s() -> u64 {
+    30
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig {
+            max_event_age_s: None,
+            disconnected_s: default_disconnected_s(),
+        }
+    }
+}
+
+/// sd_notify, without linking libsystemd.
+pub struct Notifier {
+    socket: Option<String>,
+    /// Whether systemd wants watchdog pings.
+    watchdog: bool,
+}
+
+impl Notifier {
+    /// A notifier for the socket systemd gave us, if it gave us one.
+    pub fn from_env() -> Notifier {
+        let socket = env::var("NOTIFY_SOCKET").ok().filter(|s| !s.is_empty());
+        let for_us = env::var("WATCHDOG_PID").ok()
+            .map(|pid| pid.parse() == Ok(std::process::id()))
+            .unwrap_or(true);
+        let watchdog = env::var("WATCHDOG_USEC").map(|us| us.parse::<u64>().is_ok()).unwrap_or(false);
+        Notifier {
+            socket,
+            watchdog: watchdog && for_us,
+        }
+    }
+
+    pub fn ready(&self) {
+        self.send("READY=1");
+    }
+
+    pub fn stopping(&self) {
+        self.send("STOPPING=1");
+    }
+
+    /// Show `status` in `systemctl status`.
+    pub fn status(&self, status: &str) {
+        self.send(&format!("STATUS={}", status));
+    }
+
+    pub fn ping(&self) {
+        if self.watchdog {
+            self.send("WATCHDOG=1");
+        }
+    }
+
+    #[cfg(unix)]
+    fn send(&self, state: &str) {
+        use std::os::unix::net::UnixDatagram;
+
+        let path = match &self.socket {
+            Some(path) => path,
+            None => return,
+        };
+        let sent = UnixDatagram::unbound().and_then(|socket| match path.strip_prefix('@') {
+            #[cfg(target_os = "linux")]
+            Some(name) => {
+                use std::os::linux::net::SocketAddrExt;
+                use std::os::unix::net::SocketAddr;
+                socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)
+            },
+            _ => socket.send_to(state.as_bytes(), path),
+        });
+        if let Err(e) = sent {
+            warn!("can't notify systemd: {}", e);
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn send(&self, _state: &str) {}
+}
+
+/// The daemon's self-checks.
+pub struct Health {
+    max_event_age: Option<Duration>,
+    disconnected: Duration,
+    last_event: Instant,
+    /// When each device, by port name, was last seen disconnected with
+    /// nothing since.
+    disconnected_since: HashMap<String, Instant>,
+}
+
+impl Health {
+    pub fn new(config: &HealthConfig, now: Instant) -> Self {
+        Health {
+            max_event_age: config.max_event_age_s.map(Duration::from_secs),
+            disconnected: Duration::from_secs(config.disconnected_s),
+            last_event: now,
+            disconnected_since: HashMap::new(),
+        }
+    }
+
+    /// Something came from the controller or the synth.
+    pub fn note_event(&mut self, now: Instant) {
+        self.last_event = now;
+    }
+
+    /// Check the devices, by port name and phase.  Returns what's wrong, if
+    /// anything is.
+    pub fn check(&mut self, now: Instant, devices: &[(&str, Phase)]) -> Result<(), String> {
+        let mut gone = None;
+        for &(port, phase) in devices {
+            if phase != Phase::Disconnected {
+                self.disconnected_since.remove(port);
+                continue;
+            }
+            let since = *self.disconnected_since.entry(port.to_string()).or_insert(now);
+            if now.saturating_duration_since(since) >= self.disconnected {
+                gone = gone.or(Some(port));
+            }
+        }
+        if let Some(port) = gone {
+            return Err(format!("{} disconnected for {}s", port, self.disconnected.as_secs()));
+        }
+        match self.max_event_age {
+            Some(age) if now.saturating_duration_since(self.last_event) >= age => {
+                Err(format!("nothing heard for {}s", age.as_secs()))
+            },
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! A diagnostic view, turned on from the menu's "HEAT" item, that paints
+//! each pad by how hard it was last hit or pressed, fading out over
+//! `decay_ms`, ex: `"heatmap": { "decay_ms": 3000 }`.  A pad that never
+//! lights, or never gets past blue however hard it's hit, wants cleaning or
+//! calibrating.  While it's on, the pads are its own and don't play the
+//! bindings.
+
+use serde::{Deserialize, Serialize};
+
+use std::time::{Duration, Instant};
+
+use crate::controllers::{ButtonState, ControllerCaps, ControllerEvent};
+
+/// The `heatmap` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HeatmapConfig {
+    #[serde(default = "default_decay_ms")]
+    pub decay_ms: u64,
+}
+
+fn default_decay_ms() -> u64 {
+    1500
+}
+
+impl Default for HeatmapConfig {
+    fn default() -> Self {
+        HeatmapConfig {
+            decay_ms: default_decay_ms(),
+        }
+    }
+}
+
+/// Blue through green and yellow to red as `level` goes from 0 to 0x7f.
+fn heat(level: u8) -> (u8, u8, u8) {
+    let level = level.min(0x7f) as u32;
+    let (sector, x) = (level / 0x2b, (level % 0x2b * 0x7f / 0x2a) as u8);
+    match sector {
+        0 => (0, x, 0x7f - x),
+        1 => (x, 0x7f, 0),
+        _ => (0x7f, 0x7f - x, 0),
+    }
+}
+
+pub struct Heatmap {
+    decay: Duration,
+    on: bool,
+    /// Each pad's last velocity or pressure, and when.
+    hits: Vec<Option<(u8, Instant)>>,
+}
+
+impl Heatmap {
+    pub fn new(config: &HeatmapConfig, caps: ControllerCaps) -> Self {
+        Heatmap {
+            decay: Duration::from_millis(config.decay_ms.max(1)),
+            on: false,
+            hits: vec![None; caps.pads()],
+        }
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    pub fn set_on(&mut self, on: bool) {
+        self.on = on;
+        self.hits.iter_mut().for_each(|hit| *hit = None);
+    }
+
+    /// Whether `event` is a pad's, while it's on.
+    pub fn claims(&self, event: &ControllerEvent) -> bool {
+        self.on && matches!(event, ControllerEvent::GridButton(..) | ControllerEvent::PadPressure(..))
+    }
+
+    pub fn handle(&mut self, event: &ControllerEvent, now: Instant) {
+        let (pad, level) = match *event {
+            ControllerEvent::GridButton(pad, _, _, ButtonState::Down, velocity) => (pad, velocity),
+            ControllerEvent::PadPressure(pad, pressure) if pressure > 0 => (pad, pressure),
+            _ => return,
+        };
+        if let Some(hit) = self.hits.get_mut(pad as usize) {
+            *hit = Some((level, now));
+        }
+    }
+
+    /// Returns whether the pads need redrawing: every tick while anything's
+    /// fading, and once more when it's gone.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        let decay = self.decay;
+        let mut fading = false;
+        for hit in &mut self.hits {
+            if let Some((_, at)) = *hit {
+                if now.saturating_duration_since(at) >= decay {
+                    *hit = None;
+                }
+                fading = true;
+            }
+        }
+        self.on && fading
+    }
+
+    /// Draw every pad through `set_led`, unlit if it hasn't been hit lately.
+    pub fn render<F: FnMut(u8, u8, u8, u8)>(&self, now: Instant, mut set_led: F) {
+        for (pad, hit) in self.hits.iter().enumerate() {
+            let (r, g, b) = match *hit {
+                Some((level, at)) => {
+                    let left = self.decay.saturating_sub(now.saturating_duration_since(at));
+                    let fade = |c: u8| (c as u128 * left.as_millis() / self.decay.as_millis()) as u8;
+                    let (r, g, b) = heat(level);
+                    (fade(r), fade(g), fade(b))
+                },
+                None => (0, 0, 0),
+            };
+            set_led(pad as u8, r, g, b);
+        }
+    }
+}
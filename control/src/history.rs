@@ -0,0 +1,144 @@
+//! A timeline of the synth's states to scrub back and forth through with an
+//! encoder, ex: `"history": { "encoder": 3, "interval_secs": 5, "length": 200 }`.
+//! A state is kept after each gesture, once the params have been left alone
+//! for a moment, or every `interval_secs` while they keep changing.
+//! Scrubbing writes whatever differs from the state scrubbed to.  Editing
+//! anything while scrubbed back carries on from there, and the states that
+//! were ahead stay on the timeline.
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::controllers::ControllerEvent;
+use crate::param_store::ParamStore;
+
+/// How long the params have to be left alone for a gesture to be over.
+const GESTURE_GAP: Duration = Duration::from_secs(1);
+
+/// The `history` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryConfig {
+    /// The encoder that scrubs.
+    pub encoder: u8,
+    #[serde(default = "default_interval_secs")]
+    pub interval_secs: u64,
+    /// The most states kept; the oldest go first.
+    #[serde(default = "default_length")]
+    pub length: usize,
+}
+
+fn default_interval_secs() -> u64 {
+    5
+}
+
+fn default_length() -> usize {
+    200
+}
+
+pub struct History {
+    encoder: u8,
+    interval: Duration,
+    length: usize,
+    states: VecDeque<Vec<u32>>,
+    /// The state scrubbed to, or None for wherever the edits have got to.
+    position: Option<usize>,
+    /// When the first change not in a state yet came, and the last.
+    pending: Option<(Instant, Instant)>,
+}
+
+impl History {
+    pub fn new(config: &HistoryConfig) -> Self {
+        History {
+            encoder: config.encoder,
+            interval: Duration::from_secs(config.interval_secs),
+            length: config.length.max(1),
+            states: VecDeque::new(),
+            position: None,
+            pending: None,
+        }
+    }
+
+    /// Whether `event` is the scrub encoder's.
+    pub fn claims(&self, event: &ControllerEvent) -> bool {
+        match *event {
+            ControllerEvent::Encoder(idx, _) => idx == self.encoder,
+            _ => false,
+        }
+    }
+
+    /// Note that params changed.  `edit` is for a change someone made
+    /// (rather than a batch, which while scrubbed back is our own writes),
+    /// and stops scrubbing.
+    pub fn note_change(&mut self, now: Instant, edit: bool) {
+        if self.position.is_some() {
+            if !edit {
+                return;
+            }
+            self.position = None;
+        }
+        let first = self.pending.map(|(first, _)| first).unwrap_or(now);
+        self.pending = Some((first, now));
+    }
+
+    /// Keep the current state if a gesture has finished or it's been
+    /// changing for `interval_secs`.  Call every tick or so.
+    pub fn poll(&mut self, now: Instant, store: &ParamStore) {
+        let (first, last) = match self.pending {
+            Some(pending) => pending,
+            None => return,
+        };
+        if now.duration_since(last) >= GESTURE_GAP || now.duration_since(first) >= self.interval {
+            self.keep(store);
+        }
+    }
+
+    /// Keep the current state now that a control's gesture has ended,
+    /// rather than waiting for the params to be left alone.
+    pub fn gesture_end(&mut self, store: &ParamStore) {
+        if self.pending.is_some() {
+            self.keep(store);
+        }
+    }
+
+    fn keep(&mut self, store: &ParamStore) {
+        self.pending = None;
+        let state = store.snapshot();
+        if self.states.back() == Some(&state) {
+            return;
+        }
+        if self.states.len() == self.length {
+            self.states.pop_front();
+        }
+        self.states.push_back(state);
+    }
+
+    /// Scrub for a turn of the encoder, returning the writes to get to the
+    /// state scrubbed to.  Scrubbing all the way forward gets back to the
+    /// edits.
+    pub fn handle(&mut self, event: &ControllerEvent, store: &ParamStore) -> Vec<(usize, u32)> {
+        let delta = match *event {
+            ControllerEvent::Encoder(_, delta) => delta as i64,
+            _ => return vec![],
+        };
+        if self.position.is_none() {
+            // So there's something to come back to.
+            self.keep(store);
+        }
+        let latest = self.states.len() as i64 - 1;
+        let from = self.position.map(|p| p as i64).unwrap_or(latest);
+        let to = (from + delta).max(0).min(latest);
+        self.position = if to == latest { None } else { Some(to as usize) };
+        match self.states.get(to as usize) {
+            Some(state) => store.writes_to(state),
+            None => vec![],
+        }
+    }
+
+    /// Where we are, ex: "-3/40", while scrubbed back.
+    pub fn label(&self) -> Option<String> {
+        let position = self.position?;
+        Some(format!("-{}/{}", self.states.len() - 1 - position, self.states.len()))
+    }
+}
@@ -0,0 +1,110 @@
+use crate::formula::Formula;
+use std::cmp::Ordering;
+
+use crate::sysex_map::{Bipolar, SysexMapValueEntry};
+
+/// Display precision for formula results.
+fn round(n: f64) -> f64 {
+    (n * 100.0).round() / 100.0
+}
+
+/// A formula's result, or the entry from the value list it indexes.
+fn formula_text(entry: &SysexMapValueEntry, formula: &Formula, raw: u32) -> String {
+    let n = round(formula.eval(raw));
+    match &entry.human_value_list {
+        Some(list) if n >= 0.0 && (n as usize) < list.len() => list[n as usize].clone(),
+        _ => n.to_string(),
+    }
+}
+
+fn bipolar_text(bipolar: &Bipolar, raw: u32) -> String {
+    match raw.cmp(&bipolar.center) {
+        Ordering::Less => format!("{}{}", bipolar.left, bipolar.center - raw),
+        Ordering::Equal => bipolar.center_label.clone(),
+        Ordering::Greater => format!("{}{}", bipolar.right, raw - bipolar.center),
+    }
+}
+
+fn strip_prefix_ignore_case<'a>(text: &'a str, prefix: &str) -> Option<&'a str> {
+    if !prefix.is_empty() && text.len() >= prefix.len() &&
+       text.is_char_boundary(prefix.len()) && text[..prefix.len()].eq_ignore_ascii_case(prefix) {
+        Some(&text[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+fn parse_bipolar(bipolar: &Bipolar, text: &str) -> Option<i64> {
+    let center = bipolar.center as i64;
+    if text.eq_ignore_ascii_case(&bipolar.center_label) {
+        Some(center)
+    } else if let Some(n) = strip_prefix_ignore_case(text, &bipolar.left) {
+        Some(center - n.trim().parse::<i64>().ok()?)
+    } else if let Some(n) = strip_prefix_ignore_case(text, &bipolar.right) {
+        Some(center + n.trim().parse::<i64>().ok()?)
+    } else {
+        Some(center + text.parse::<i64>().ok()?)
+    }
+}
+
+/// Format a raw value the way the synth's own display would, using the
+/// formula, bipolar labels, value list or base offset and units from the map.
+pub fn format_value(entry: &SysexMapValueEntry, raw: u32) -> String {
+    let offset = raw as i64 - entry.discrete_range_low as i64;
+    let text = if let Some(formula) = &entry.human_value_formula {
+        formula_text(entry, formula, raw)
+    } else if let Some(bipolar) = &entry.human_value_bipolar {
+        bipolar_text(bipolar, raw)
+    } else if let Some(list) = &entry.human_value_list {
+        if offset >= 0 && (offset as usize) < list.len() {
+            list[offset as usize].clone()
+        } else {
+            raw.to_string()
+        }
+    } else if let Some(base) = entry.human_value_base {
+        (offset + base as i64).to_string()
+    } else {
+        raw.to_string()
+    };
+
+    match &entry.human_value_units {
+        Some(units) => format!("{} {}", text, units),
+        None => text,
+    }
+}
+
+/// Inverse of `format_value`: accepts a list entry (case-insensitively) or a
+/// number in human terms, and returns the raw value if it's in range.
+pub fn parse_value(entry: &SysexMapValueEntry, text: &str) -> Option<u32> {
+    let text = text.trim();
+    let text = match &entry.human_value_units {
+        Some(units) => text.strip_suffix(units.as_str()).unwrap_or(text).trim(),
+        None => text,
+    };
+
+    // Formulas needn't be invertible, so look for a raw value that formats
+    // the same.
+    if let Some(formula) = &entry.human_value_formula {
+        let number = text.parse::<f64>().ok();
+        return (entry.discrete_range_low..=entry.discrete_range_high).find(|raw| {
+            formula_text(entry, formula, *raw).eq_ignore_ascii_case(text) ||
+            number == Some(round(formula.eval(*raw)))
+        });
+    }
+
+    let offset = if let Some(bipolar) = &entry.human_value_bipolar {
+        parse_bipolar(bipolar, text)? - entry.discrete_range_low as i64
+    } else if let Some(list) = &entry.human_value_list {
+        list.iter().position(|v| v.eq_ignore_ascii_case(text))? as i64
+    } else if let Some(base) = entry.human_value_base {
+        text.parse::<i64>().ok()? - base as i64
+    } else {
+        text.parse::<i64>().ok()? - entry.discrete_range_low as i64
+    };
+
+    let raw = entry.discrete_range_low as i64 + offset;
+    if raw < entry.discrete_range_low as i64 || raw > entry.discrete_range_high as i64 {
+        return None;
+    }
+    Some(raw as u32)
+}
@@ -0,0 +1,131 @@
+use serde::{Deserialize, Serialize};
+
+use std::time::{Duration, Instant};
+
+/// How often the screensaver animation moves.
+pub const FRAME: Duration = Duration::from_millis(100);
+
+/// What the display shows while the screensaver is on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum IdleAnimation {
+    /// Nothing at all.
+    #[default]
+    Blank,
+    /// A small logo drifting around, so no pixel stays lit.
+    Bounce,
+}
+
+/// The `idle` section of the config, ex:
+/// `{ "dim_after_minutes": 5, "dim_brightness": 10, "animation": "bounce" }`.
+/// A timeout of 0 turns that part off.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct IdleConfig {
+    /// Dim the pad LEDs after this long without input.
+    #[serde(default = "default_idle_minutes")]
+    pub dim_after_minutes: u32,
+    /// LED brightness percentage while dimmed.
+    #[serde(default = "default_dim_brightness")]
+    pub dim_brightness: u8,
+    /// Replace the display with `animation` after this long without input.
+    #[serde(default = "default_idle_minutes")]
+    pub screensaver_after_minutes: u32,
+    #[serde(default)]
+    pub animation: IdleAnimation,
+}
+
+fn default_idle_minutes() -> u32 {
+    10
+}
+
+fn default_dim_brightness() -> u8 {
+    10
+}
+
+impl Default for IdleConfig {
+    fn default() -> Self {
+        IdleConfig {
+            dim_after_minutes: default_idle_minutes(),
+            dim_brightness: default_dim_brightness(),
+            screensaver_after_minutes: default_idle_minutes(),
+            animation: IdleAnimation::default(),
+        }
+    }
+}
+
+fn passed(timeout: Option<Duration>, idle: Duration) -> bool {
+    match timeout {
+        Some(timeout) => idle >= timeout,
+        None => false,
+    }
+}
+
+fn timeout(minutes: u32) -> Option<Duration> {
+    if minutes == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(minutes as u64 * 60))
+    }
+}
+
+/// Tracks how long it's been since the user did anything.
+pub struct IdleTimer {
+    dim_after: Option<Duration>,
+    screensaver_after: Option<Duration>,
+    last_activity: Instant,
+    dimmed: bool,
+    screensaver: Option<Instant>,
+}
+
+impl IdleTimer {
+    pub fn new(config: &IdleConfig, now: Instant) -> Self {
+        IdleTimer {
+            dim_after: timeout(config.dim_after_minutes),
+            screensaver_after: timeout(config.screensaver_after_minutes),
+            last_activity: now,
+            dimmed: false,
+            screensaver: None,
+        }
+    }
+
+    /// Note input from the user.  Returns whether we were dimmed or showing
+    /// the screensaver, so everything needs to be put back.
+    pub fn activity(&mut self, now: Instant) -> bool {
+        self.last_activity = now;
+        let was_idle = self.dimmed || self.screensaver.is_some();
+        self.dimmed = false;
+        self.screensaver = None;
+        was_idle
+    }
+
+    /// Returns whether we just dimmed or started the screensaver.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        let idle = now.saturating_duration_since(self.last_activity);
+        let mut changed = false;
+        if !self.dimmed && passed(self.dim_after, idle) {
+            self.dimmed = true;
+            changed = true;
+        }
+        if self.screensaver.is_none() && passed(self.screensaver_after, idle) {
+            self.screensaver = Some(now);
+            changed = true;
+        }
+        changed
+    }
+
+    pub fn is_dimmed(&self) -> bool {
+        self.dimmed
+    }
+
+    pub fn screensaver_on(&self) -> bool {
+        self.screensaver.is_some()
+    }
+
+    /// How many animation frames into the screensaver we are.
+    pub fn frame(&self, now: Instant) -> u32 {
+        match self.screensaver {
+            Some(start) => (now.saturating_duration_since(start).as_millis() / FRAME.as_millis()) as u32,
+            None => 0,
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Working out a map entry from what a synth sends while one param changes.
+//! A `Capture` collects the bytes the synth reports as a series of states,
+//! ex: one per message while a knob is turned, and `infer` looks for the
+//! run of bytes that changed between them.  The bitmask and range are
+//! guesses from the values seen, so check them against the manual.
+
+use std::collections::BTreeMap;
+
+use crate::sysex_map::{linear_address, packed_address, ParamIndex, SysexMap, SysexMapValueEntry,
+                       WriteCost, ROOT_TABLE};
+
+/// Params wider than this are more likely two params changing together.
+pub const MAX_PARAM_BYTES: u32 = 4;
+
+/// The bytes seen at each linear address, as of each `mark`.
+#[derive(Clone, Debug, Default)]
+pub struct Capture {
+    image: BTreeMap<u32, u8>,
+    states: Vec<BTreeMap<u32, u8>>,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Capture::default()
+    }
+
+    /// Bytes the synth sent starting at linear `address`.
+    pub fn record(&mut self, address: u32, data: &[u8]) {
+        for (i, b) in data.iter().enumerate() {
+            self.image.insert(address + i as u32, *b);
+        }
+    }
+
+    /// Everything recorded so far makes up one state.
+    pub fn mark(&mut self) {
+        self.states.push(self.image.clone());
+    }
+
+    pub fn states(&self) -> usize {
+        self.states.len()
+    }
+
+    /// The addresses whose byte differed between states.
+    pub fn changed(&self) -> Vec<u32> {
+        let mut seen: BTreeMap<u32, u8> = BTreeMap::new();
+        let mut changed = vec![];
+        for state in &self.states {
+            for (address, b) in state {
+                match seen.insert(*address, *b) {
+                    Some(before) if before != *b && !changed.contains(address) => changed.push(*address),
+                    _ => (),
+                }
+            }
+        }
+        changed.sort_unstable();
+        changed
+    }
+}
+
+/// A param found in a capture.  `address` is linear.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Inferred {
+    pub address: u32,
+    pub size: u32,
+    pub bitmask: u32,
+    /// The lowest and highest values seen.
+    pub low: u32,
+    pub high: u32,
+}
+
+fn decode(bytes: &[u8], bitmask: u32) -> u32 {
+    let bits = (bitmask & 0x7f).count_ones();
+    bytes.iter().fold(0, |acc, b| (acc << bits) | (*b as u32 & bitmask))
+}
+
+impl Inferred {
+    /// The highest value the bitmask and size allow.
+    pub fn max_value(&self) -> u32 {
+        let bits = (self.bitmask & 0x7f).count_ones() * self.size;
+        if bits >= 32 { u32::MAX } else { (1 << bits) - 1 }
+    }
+
+    /// Stretch the range to everything the bytes can hold, for when the
+    /// values seen are unlikely to be the ends, ex: from two dumps.
+    pub fn widen(&mut self) {
+        self.low = 0;
+        self.high = self.max_value();
+    }
+
+    /// A value entry named `name` for the block starting at linear `base`.
+    pub fn entry(&self, name: &str, base: u32) -> SysexMapValueEntry {
+        let offset = self.address - base;
+        SysexMapValueEntry {
+            name: name.to_string(),
+            display_name: None,
+            first_offset_start: packed_address(offset),
+            last_offset_start: packed_address(offset + self.size - 1),
+            bitmask: self.bitmask,
+            discrete_range_low: self.low,
+            discrete_range_high: self.high,
+            human_value_list: None,
+            human_value_base: None,
+            human_value_units: None,
+            human_value_formula: None,
+            human_value_bipolar: None,
+            visible_when: None,
+            depends_on: vec![],
+            aliases: vec![],
+            write_cost: WriteCost::Volatile,
+            identifying: None,
+        }
+    }
+}
+
+/// The param that changed over the capture.  Multi-byte params are taken to
+/// be nibbles if every byte seen fits in four bits.
+pub fn infer(capture: &Capture) -> Result<Inferred, String> {
+    let changed = capture.changed();
+    let (first, last) = match (changed.first(), changed.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return Err("nothing changed".to_string()),
+    };
+    let size = last - first + 1;
+    if size > MAX_PARAM_BYTES {
+        return Err(format!("{} bytes changed from {:08x} to {:08x}; was it more than one param?",
+                           changed.len(), packed_address(first), packed_address(last)));
+    }
+
+    let values: Vec<Vec<u8>> = capture.states.iter()
+        .filter_map(|state| (first..=last).map(|a| state.get(&a).copied()).collect())
+        .collect();
+    let bitmask = if size > 1 && values.iter().flatten().all(|b| *b <= 0x0f) { 0x0f } else { 0x7f };
+    let decoded: Vec<u32> = values.iter().map(|bytes| decode(bytes, bitmask)).collect();
+    Ok(Inferred {
+        address: first,
+        size,
+        bitmask,
+        low: decoded.iter().copied().min().unwrap_or(0),
+        high: decoded.iter().copied().max().unwrap_or(0),
+    })
+}
+
+/// The names of params already at any of the inferred bytes.
+pub fn overlapping(index: &ParamIndex, inferred: &Inferred) -> Vec<String> {
+    let end = inferred.address + inferred.size;
+    index.params.iter()
+        .filter(|p| p.address < end && inferred.address < p.address + p.size)
+        .map(|p| p.name.clone())
+        .collect()
+}
+
+fn locate_in(map: &SysexMap, table: &str, base: u32, address: u32) -> (String, u32) {
+    let types = match map.type_entries.get(table) {
+        Some(types) if !map.value_entries.contains_key(table) => types,
+        _ => return (table.to_string(), base),
+    };
+    // The block starting closest below the address.
+    let mut best: Option<(u32, &str)> = None;
+    for entry in types {
+        let first = linear_address(entry.first_offset_start);
+        let last = linear_address(entry.last_offset_start);
+        let stride = entry.stride.map(linear_address).filter(|s| *s > 0);
+        let count = stride.map(|s| (last - first) / s + 1).unwrap_or(1);
+        for i in 0..count {
+            let start = base + first + i * stride.unwrap_or(0);
+            if start <= address && best.map(|(b, _)| start >= b).unwrap_or(true) {
+                best = Some((start, &entry.type_name));
+            }
+        }
+    }
+    match best {
+        Some((start, type_name)) => locate_in(map, type_name, start, address),
+        None => (table.to_string(), base),
+    }
+}
+
+/// The value table a new param at linear `address` belongs in, and the
+/// linear address its block starts at.  Repeated blocks share their table,
+/// so a param added to one part appears in every part.
+pub fn locate(map: &SysexMap, address: u32) -> (String, u32) {
+    locate_in(map, ROOT_TABLE, 0, address)
+}
+
+/// Add `entry` to `table`, keeping the table in address order.
+pub fn append(map: &mut SysexMap, table: &str, entry: SysexMapValueEntry) {
+    let entries = map.value_entries.entry(table.to_string()).or_default();
+    entries.push(entry);
+    entries.sort_by_key(|e| linear_address(e.first_offset_start));
+}
@@ -0,0 +1,59 @@
+//! Keeping a panic in one device's handling from taking down the rest.
+//! midir calls input callbacks on its own threads, where a panic would
+//! unwind through the backend and leave whatever the callback had locked
+//! poisoned.  Callbacks run their work through `guarded` instead, which
+//! catches the panic and logs it with the backtrace from where it happened;
+//! the caller then takes the device out of service.
+
+use log::error;
+
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Once;
+
+thread_local! {
+    /// The backtrace of the last panic on this thread.
+    static LAST_BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+static HOOK: Once = Once::new();
+
+/// Record a backtrace for every panic, for `guarded` to log, on top of
+/// whatever the existing hook does.
+fn install_hook() {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            LAST_BACKTRACE.with(|last| *last.borrow_mut() = Some(Backtrace::force_capture()));
+            previous(info);
+        }));
+    });
+}
+
+fn message(payload: &(dyn std::any::Any + Send)) -> &str {
+    if let Some(msg) = payload.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Run `f`, returning None if it panicked.  The panic is logged as coming
+/// from `what`, ex: a port name.
+pub fn guarded<R, F: FnOnce() -> R>(what: &str, f: F) -> Option<R> {
+    install_hook();
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => Some(result),
+        Err(payload) => {
+            let backtrace = LAST_BACKTRACE.with(|last| last.borrow_mut().take());
+            match backtrace {
+                Some(backtrace) => error!("{} panicked: {}\n{}", what, message(&*payload), backtrace),
+                None => error!("{} panicked: {}", what, message(&*payload)),
+            }
+            None
+        },
+    }
+}
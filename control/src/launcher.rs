@@ -0,0 +1,212 @@
+//! Launching a DAW's clips from a bank of pads while the rest of the
+//! controller goes on editing the synth, ex:
+//! ```json
+//! "launcher": { "port": "Bitwig", "pad": 0, "rows": 4, "columns": 8, "channel": 1,
+//!   "transport": [{ "control": { "pad": 14 }, "command": "play" },
+//!                 { "control": { "pad": 15 }, "command": "stop" }] }
+//! ```
+//! Each bank pad plays a note, counting from `first_note` at the top left
+//! along each row, so every row is one of the DAW's scenes.  The DAW's LED
+//! messages back come in on the same port through the router: note-ons whose
+//! velocity is the color, as it would send an APC mini.  Transport pads
+//! send MIDI Machine Control.  Bank pads take over from any bindings on
+//! them.
+
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::bindings::Control;
+use crate::controllers::{ButtonState, ControllerCaps, ControllerEvent};
+
+const GREEN: (u8, u8, u8) = (0x00, 0x7f, 0x00);
+const RED: (u8, u8, u8) = (0x7f, 0x00, 0x00);
+const YELLOW: (u8, u8, u8) = (0x7f, 0x60, 0x00);
+/// The APC mini's LED velocities: off, then green, red and yellow, each
+/// followed by its blinking version, which is shown steady.
+const COLORS: [(u8, u8, u8); 7] = [(0, 0, 0), GREEN, GREEN, RED, RED, YELLOW, YELLOW];
+/// For velocities past those.
+const LIT: (u8, u8, u8) = (0x40, 0x40, 0x40);
+
+/// A MIDI Machine Control command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mmc {
+    Stop,
+    Play,
+    FastForward,
+    Rewind,
+    /// Punch in.
+    Record,
+    /// Punch out.
+    RecordExit,
+    Pause,
+}
+
+impl Mmc {
+    /// The command, for every device on the port.
+    pub fn message(self) -> [u8; 6] {
+        let command = match self {
+            Mmc::Stop => 0x01,
+            Mmc::Play => 0x02,
+            Mmc::FastForward => 0x04,
+            Mmc::Rewind => 0x05,
+            Mmc::Record => 0x06,
+            Mmc::RecordExit => 0x07,
+            Mmc::Pause => 0x09,
+        };
+        [0xf0, 0x7f, 0x7f, 0x06, command, 0xf7]
+    }
+}
+
+/// A pad that sends `command` when pressed.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransportPad {
+    pub control: Control,
+    pub command: Mmc,
+}
+
+/// The `launcher` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LauncherConfig {
+    /// The DAW's port, both ways, matched like a route's `from` and `to`.
+    pub port: String,
+    /// The bank's top left pad.
+    pub pad: u8,
+    pub rows: u8,
+    pub columns: u8,
+    /// The channel the notes go out and the LED messages come back on, 1-16.
+    #[serde(default = "default_channel")]
+    pub channel: u8,
+    #[serde(default)]
+    pub first_note: u8,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transport: Vec<TransportPad>,
+}
+
+fn default_channel() -> u8 {
+    1
+}
+
+pub struct Launcher {
+    port: String,
+    caps: ControllerCaps,
+    /// The bank's top row and left column.
+    row: u8,
+    col: u8,
+    rows: u8,
+    columns: u8,
+    /// 0-15.
+    channel: u8,
+    first_note: u8,
+    transport: Vec<(u8, Mmc)>,
+    /// What the DAW last lit each bank pad, row by row.
+    colors: Vec<(u8, u8, u8)>,
+}
+
+impl Launcher {
+    pub fn new(config: &LauncherConfig, caps: &ControllerCaps) -> Result<Launcher, Box<dyn Error>> {
+        let (row, col) = caps.row_col(config.pad);
+        if config.rows == 0 || config.columns == 0 ||
+           row as usize + config.rows as usize > caps.rows as usize ||
+           col as usize + config.columns as usize > caps.columns as usize {
+            return Err(format!("launcher bank at {} doesn't fit the grid", config.pad).into());
+        }
+        if !(1..=16).contains(&config.channel) {
+            return Err(format!("launcher channel {} isn't 1-16", config.channel).into());
+        }
+        let pads = config.rows as usize * config.columns as usize;
+        if config.first_note as usize + pads > 0x80 {
+            return Err("launcher bank runs past the last note".into());
+        }
+        let mut launcher = Launcher {
+            port: config.port.clone(),
+            caps: *caps,
+            row,
+            col,
+            rows: config.rows,
+            columns: config.columns,
+            channel: config.channel - 1,
+            first_note: config.first_note,
+            transport: vec![],
+            colors: vec![(0, 0, 0); pads],
+        };
+        for transport in &config.transport {
+            match transport.control {
+                Control::Pad(pad) if (pad as usize) < caps.pads() && launcher.note_of(pad).is_none() => {
+                    launcher.transport.push((pad, transport.command));
+                },
+                control => return Err(format!("launcher transport can't be on {:?}", control).into()),
+            }
+        }
+        Ok(launcher)
+    }
+
+    pub fn port(&self) -> &str {
+        &self.port
+    }
+
+    /// The note bank pad `pad` plays, if it's in the bank.
+    fn note_of(&self, pad: u8) -> Option<u8> {
+        let (row, col) = self.caps.row_col(pad);
+        if row < self.row || row >= self.row + self.rows || col < self.col || col >= self.col + self.columns {
+            return None;
+        }
+        Some(self.first_note + (row - self.row) * self.columns + (col - self.col))
+    }
+
+    /// Whether `event` is for a bank or transport pad, so it shouldn't go
+    /// on to the bindings.
+    pub fn claims(&self, event: &ControllerEvent) -> bool {
+        match *event {
+            ControllerEvent::GridButton(pad, ..) => {
+                self.note_of(pad).is_some() || self.transport.iter().any(|(p, _)| *p == pad)
+            },
+            _ => false,
+        }
+    }
+
+    /// Pass what a bank or transport pad sends the DAW to `send`.
+    pub fn handle<F: FnMut(&[u8])>(&self, event: &ControllerEvent, mut send: F) {
+        let (pad, state, velocity) = match *event {
+            ControllerEvent::GridButton(pad, _, _, state, velocity) => (pad, state, velocity),
+            _ => return,
+        };
+        if let Some(note) = self.note_of(pad) {
+            match state {
+                ButtonState::Down => send(&[0x90 | self.channel, note, velocity.clamp(1, 0x7f)]),
+                ButtonState::Up => send(&[0x80 | self.channel, note, 0]),
+            }
+        } else if let Some((_, command)) = self.transport.iter().find(|(p, _)| *p == pad) {
+            if state == ButtonState::Down {
+                send(&command.message());
+            }
+        }
+    }
+
+    /// Take a message from the DAW.  Returns whether it changed a bank
+    /// pad's color.
+    pub fn feedback(&mut self, msg: &[u8]) -> bool {
+        let (note, velocity) = match *msg {
+            [status, note, velocity] if status == 0x90 | self.channel => (note, velocity),
+            [status, note, _] if status == 0x80 | self.channel => (note, 0),
+            _ => return false,
+        };
+        let i = match note.checked_sub(self.first_note) {
+            Some(i) if (i as usize) < self.colors.len() => i as usize,
+            _ => return false,
+        };
+        let color = COLORS.get(velocity as usize).copied().unwrap_or(LIT);
+        let changed = self.colors[i] != color;
+        self.colors[i] = color;
+        changed
+    }
+
+    /// Feed the bank pads' colors, as the DAW last set them, to `set_led`.
+    pub fn render<F: FnMut(u8, u8, u8, u8)>(&self, mut set_led: F) {
+        for (i, (r, g, b)) in self.colors.iter().enumerate() {
+            let (row, col) = (i as u8 / self.columns, i as u8 % self.columns);
+            set_led(self.caps.pad_at(self.row + row, self.col + col), *r, *g, *b);
+        }
+    }
+}
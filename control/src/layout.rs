@@ -0,0 +1,150 @@
+//! A starting point for a new synth's bindings: lay a map's params out on a
+//! controller by what kind of param they are.  Enums get radio rows from the
+//! top of the grid, switches get toggle pads packed in from the bottom, and
+//! everything else goes on the encoders, then knobs and faders, in map
+//! order.  There's only one page of bindings, so a big map won't fit; pick
+//! the part to lay out with a `params_matching` glob and generate a file per
+//! group.
+
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+
+use crate::bindings::{BindingEntry, BindingsFile, Control, PadAction, Takeover};
+use crate::controllers::{ControllerCaps, RingMode};
+use crate::sysex_map::{MappedParam, ParamIndex};
+
+/// The controls a controller has to lay bindings out on, ex:
+/// `{ "pads": 16, "columns": 8, "encoders": 0, "knobs": 24, "faders": 8 }`.
+/// The Fire's by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Surface {
+    pub pads: u8,
+    pub columns: u8,
+    pub encoders: u8,
+    #[serde(default)]
+    pub knobs: u8,
+    #[serde(default)]
+    pub faders: u8,
+}
+
+impl Default for Surface {
+    fn default() -> Self {
+        Surface::from(ControllerCaps::FIRE)
+    }
+}
+
+impl From<ControllerCaps> for Surface {
+    fn from(caps: ControllerCaps) -> Self {
+        Surface {
+            pads: caps.pads() as u8,
+            columns: caps.columns,
+            encoders: caps.encoders,
+            knobs: caps.knobs,
+            faders: caps.faders,
+        }
+    }
+}
+
+impl Surface {
+    pub fn load(path: &str) -> Result<Surface, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let surface = serde_json::from_reader(BufReader::new(file))?;
+        Ok(surface)
+    }
+
+    fn rows(&self) -> u8 {
+        self.pads / self.columns.max(1)
+    }
+}
+
+enum Kind {
+    Switch,
+    Enum,
+    Continuous,
+}
+
+fn kind_of(param: &MappedParam, surface: &Surface) -> Kind {
+    let entry = &param.entry;
+    let count = entry.discrete_range_high.saturating_sub(entry.discrete_range_low) + 1;
+    if count == 2 {
+        Kind::Switch
+    } else if entry.human_value_list.is_some() && count <= surface.columns as u32 {
+        Kind::Enum
+    } else {
+        Kind::Continuous
+    }
+}
+
+fn entry(control: Control, param: &MappedParam) -> BindingEntry {
+    BindingEntry {
+        control,
+        param: param.name.clone(),
+        value: None,
+        action: PadAction::default(),
+        param_y: None,
+        slew_ms: None,
+        min: None,
+        max: None,
+        invert: false,
+        takeover: Takeover::default(),
+        ring: RingMode::default(),
+        broadcast: vec![],
+    }
+}
+
+/// Bindings for `params` (indices into `index`) on `surface`, along with the
+/// names of the params that didn't fit.
+pub fn generate(index: &ParamIndex, params: &[usize], surface: &Surface) -> (BindingsFile, Vec<String>) {
+    let mut bindings = vec![];
+    let mut left_out = vec![];
+    let (mut encoders, mut knobs, mut faders) = (0, 0, 0);
+    // Radio rows fill from the top row down, toggle pads from the bottom
+    // right up, until they meet.
+    let mut next_row = 0;
+    let mut pads_from_end = 0;
+    let free_rows = |next_row: u8, pads_from_end: u8| {
+        let used_rows = (pads_from_end + surface.columns - 1) / surface.columns.max(1);
+        surface.rows().saturating_sub(next_row + used_rows)
+    };
+
+    for &idx in params {
+        let param = &index.params[idx];
+        match kind_of(param, surface) {
+            Kind::Enum if free_rows(next_row, pads_from_end) > 0 => {
+                bindings.push(entry(Control::RadioRow(next_row), param));
+                next_row += 1;
+            },
+            Kind::Switch if pads_from_end < surface.pads &&
+                            (free_rows(next_row, pads_from_end) > 0 ||
+                             pads_from_end % surface.columns != 0) => {
+                pads_from_end += 1;
+                let mut binding = entry(Control::Pad(surface.pads - pads_from_end), param);
+                binding.value = Some(param.entry.discrete_range_high);
+                binding.action = PadAction::Toggle { off: Some(param.entry.discrete_range_low) };
+                bindings.push(binding);
+            },
+            Kind::Continuous if encoders < surface.encoders => {
+                bindings.push(entry(Control::Encoder(encoders), param));
+                encoders += 1;
+            },
+            Kind::Continuous if knobs < surface.knobs => {
+                bindings.push(entry(Control::Knob(knobs), param));
+                knobs += 1;
+            },
+            Kind::Continuous if faders < surface.faders => {
+                bindings.push(entry(Control::Fader(faders), param));
+                faders += 1;
+            },
+            _ => left_out.push(param.name.clone()),
+        }
+    }
+
+    let file = BindingsFile {
+        bindings,
+        ..BindingsFile::default()
+    };
+    (file, left_out)
+}
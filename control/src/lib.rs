@@ -1,3 +1,155 @@
-mod sysex_mapped;
+pub mod annotate;
+#[cfg(feature = "runtime")]
+pub mod audit;
+#[cfg(feature = "runtime")]
+pub mod backup;
+pub mod bank;
+#[cfg(feature = "runtime")]
+pub mod bindings;
+#[cfg(feature = "runtime")]
+pub mod broadcast;
+#[cfg(feature = "runtime")]
+pub mod browse;
+#[cfg(feature = "runtime")]
+pub mod bus;
+pub mod clock;
+pub mod codec;
+pub mod command;
+#[cfg(feature = "maps")]
+pub mod community;
+#[cfg(feature = "runtime")]
+pub mod compare;
+#[cfg(feature = "runtime")]
+pub mod compositor;
+#[cfg(feature = "runtime")]
+pub mod config;
+#[cfg(feature = "runtime")]
+mod controllers;
+#[cfg(feature = "runtime")]
+pub mod correlate;
+#[cfg(feature = "runtime")]
+pub mod crypt;
+#[cfg(feature = "runtime")]
+pub mod daemon;
+#[cfg(feature = "dbus")]
+pub mod dbus;
+#[cfg(feature = "runtime")]
+pub mod decode;
+#[cfg(feature = "runtime")]
+pub mod display;
+#[cfg(feature = "runtime")]
+pub mod favorites;
+pub mod filter;
+pub mod formula;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "runtime")]
+pub mod health;
+#[cfg(feature = "runtime")]
+pub mod heatmap;
+#[cfg(feature = "runtime")]
+pub mod history;
+pub mod human;
+#[cfg(feature = "runtime")]
+pub mod idle;
+#[cfg(feature = "runtime")]
+pub mod infer;
+#[cfg(feature = "runtime")]
+pub mod isolate;
+#[cfg(feature = "runtime")]
+pub mod launcher;
+#[cfg(feature = "runtime")]
+pub mod layout;
+#[cfg(feature = "runtime")]
+pub mod marquee;
+#[cfg(feature = "runtime")]
+pub mod menu;
+#[cfg(feature = "runtime")]
+pub mod motion;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "runtime")]
+pub mod overlay;
+#[cfg(feature = "runtime")]
+pub mod pacing;
+#[cfg(feature = "runtime")]
+pub mod panic;
+#[cfg(feature = "runtime")]
+pub mod param_store;
+#[cfg(feature = "runtime")]
+pub mod plugin;
+#[cfg(feature = "runtime")]
+pub mod ports;
+#[cfg(feature = "runtime")]
+pub mod program;
+#[cfg(feature = "runtime")]
+pub mod progress;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "runtime")]
+pub mod remote;
+#[cfg(feature = "runtime")]
+pub mod router;
+#[cfg(feature = "runtime")]
+pub mod rules;
+#[cfg(feature = "runtime")]
+pub mod scheduler;
+pub mod scrub;
+#[cfg(feature = "runtime")]
+pub mod setlist;
+#[cfg(feature = "runtime")]
+pub mod simulate;
+#[cfg(feature = "runtime")]
+pub mod snapshot;
+#[cfg(feature = "runtime")]
+pub mod status;
+#[cfg(feature = "sync")]
+pub mod sync;
+#[cfg(feature = "runtime")]
+pub mod synth;
+pub mod sysex_map;
+pub mod template;
+#[cfg(feature = "runtime")]
+pub mod theme;
+#[cfg(feature = "runtime")]
+pub mod timers;
+#[cfg(feature = "ump")]
+pub mod ump;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "webmidi")]
+pub mod webmidi;
 
-pub use sysex_mapped::Controller as SysexController;
+#[cfg(feature = "runtime")]
+pub use controllers::apc::{attach_apc_minis, ApcMini};
+#[cfg(feature = "runtime")]
+pub use controllers::fire::{attach_fires, Fire, FIRE_PORT_PREFIX};
+#[cfg(feature = "runtime")]
+pub use controllers::launch_control::{attach_launch_control_xls, LaunchControlXl};
+#[cfg(feature = "runtime")]
+pub use controllers::profile::{attach_profiled, ControllerProfile, GenericController};
+#[cfg(feature = "runtime")]
+pub use controllers::push2::{attach_push2s, Push2};
+#[cfg(feature = "runtime")]
+pub use controllers::sysex_mapped::Controller as SysexController;
+#[cfg(feature = "runtime")]
+pub use controllers::sysex_mapped::VirtualDevice;
+#[cfg(feature = "runtime")]
+pub use controllers::xtouch::{attach_xtouch_minis, XTouchMini};
+#[cfg(feature = "runtime")]
+pub use controllers::{ButtonState, ControllerEvent, DebounceConfig};
+#[cfg(feature = "runtime")]
+pub use controllers::{BufferPool, SysexBuf, SysexLimits};
+#[cfg(feature = "runtime")]
+pub use controllers::{Calibrator, PadCalibration, PadRange};
+#[cfg(feature = "runtime")]
+pub use controllers::{ControllerCaps, Model, RingMode};
+#[cfg(feature = "runtime")]
+pub use controllers::{LedBuffer, GRID_LED_COUNT};
+#[cfg(feature = "runtime")]
+pub use controllers::{Lifecycle, Phase, Transition, IDENTIFY_TIMEOUT};
+#[cfg(feature = "runtime")]
+pub use controllers::{OledBuffer, OLED_HEIGHT, OLED_WIDTH};
+#[cfg(feature = "runtime")]
+pub use controllers::{set_take_over, LockError, PortLock};
+pub use sysex_map::SysexMap;
@@ -0,0 +1,120 @@
+//! The last-touched param's name and value scrolled across the pads in the
+//! display's 3x5 font, for controllers without a display, ex:
+//! `"marquee": { "row": 0, "color": [0, 64, 127] }`.  The text takes the
+//! five rows from `row` down (rows past the bottom of the grid are cut off)
+//! and covers the bindings there while it's scrolling.
+
+use serde::{Deserialize, Serialize};
+
+use std::time::{Duration, Instant};
+
+use crate::bus::EngineEvent;
+use crate::controllers::{glyph, ControllerCaps, CHAR_ADVANCE, GLYPH_HEIGHT, GLYPH_WIDTH};
+use crate::human::format_value;
+use crate::param_store::ParamStore;
+
+/// The `marquee` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MarqueeConfig {
+    /// The top row of the text.
+    #[serde(default)]
+    pub row: u8,
+    #[serde(default = "default_color")]
+    pub color: (u8, u8, u8),
+    /// How long the text stays at each column.
+    #[serde(default = "default_step_ms")]
+    pub step_ms: u64,
+}
+
+fn default_color() -> (u8, u8, u8) {
+    (0x7f, 0x7f, 0x7f)
+}
+
+fn default_step_ms() -> u64 {
+    120
+}
+
+pub struct Marquee {
+    row: u8,
+    color: (u8, u8, u8),
+    step: Duration,
+    caps: ControllerCaps,
+    /// The text scrolling, and when it started from the right edge.
+    text: Option<(Vec<char>, Instant)>,
+}
+
+impl Marquee {
+    pub fn new(config: &MarqueeConfig, caps: ControllerCaps) -> Self {
+        Marquee {
+            row: config.row,
+            color: config.color,
+            step: Duration::from_millis(config.step_ms.max(1)),
+            caps,
+            text: None,
+        }
+    }
+
+    /// Start scrolling a param change.  Returns whether the pads need
+    /// redrawing.
+    pub fn handle(&mut self, event: &EngineEvent, store: &ParamStore, now: Instant) -> bool {
+        let param = match event {
+            EngineEvent::ParamChanged(change) => change.param,
+            _ => return false,
+        };
+        let name = store.index().params[param].display_name();
+        let value = format_value(&store.display_entry(param), store.get(param));
+        self.text = Some((format!("{} {}", name, value).chars().collect(), now));
+        true
+    }
+
+    /// How many columns the text has moved in from the right edge.
+    fn offset(&self, started: Instant, now: Instant) -> usize {
+        (now.saturating_duration_since(started).as_millis() / self.step.as_millis()) as usize
+    }
+
+    /// Returns whether the pads need redrawing: every tick while the text's
+    /// scrolling, and once more when it's gone off the left.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        let gone = match &self.text {
+            Some((text, started)) => {
+                self.offset(*started, now) > self.caps.columns as usize + text.len() * CHAR_ADVANCE
+            },
+            None => return false,
+        };
+        if gone {
+            self.text = None;
+        }
+        true
+    }
+
+    /// Draw the text's rows of the grid through `set_led`, while there's
+    /// text.
+    pub fn render<F: FnMut(u8, u8, u8, u8)>(&self, now: Instant, mut set_led: F) {
+        let (text, started) = match &self.text {
+            Some(text) => text,
+            None => return,
+        };
+        let columns = self.caps.columns as usize;
+        let offset = self.offset(*started, now);
+        for row in 0..GLYPH_HEIGHT {
+            let pad_row = self.row as usize + row;
+            if pad_row >= self.caps.rows as usize {
+                break;
+            }
+            for col in 0..columns {
+                // Where this pad is along the text, if it's over it.
+                let x = (offset + col).checked_sub(columns);
+                let lit = x.and_then(|x| {
+                    let (c, glyph_col) = (text.get(x / CHAR_ADVANCE)?, x % CHAR_ADVANCE);
+                    if glyph_col >= GLYPH_WIDTH {
+                        return None;
+                    }
+                    let shift = (GLYPH_HEIGHT - 1 - row) * GLYPH_WIDTH + (GLYPH_WIDTH - 1 - glyph_col);
+                    Some(glyph(*c) & (1 << shift) != 0)
+                }).unwrap_or(false);
+                let (r, g, b) = if lit { self.color } else { (0, 0, 0) };
+                set_led(self.caps.pad_at(pad_row as u8, col as u8), r, g, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! Settings that can be changed from the controller: pushing the select
+//! encoder opens a menu on the display, turning it moves between the items,
+//! and pushing again changes the one selected.  Turning then changes its
+//! value and a last push takes it.  "Exit" closes the menu.
+
+use crate::controllers::{ButtonState, ControllerEvent, OledBuffer, CHAR_ADVANCE, GLYPH_HEIGHT, OLED_WIDTH};
+use crate::theme::Palette;
+
+/// How much one detent changes the LED brightness by, in percent.
+const BRIGHTNESS_STEP: i32 = 5;
+/// Height of each line of the menu.
+const LINE_HEIGHT: usize = GLYPH_HEIGHT + 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Item {
+    Page,
+    Brightness,
+    Palette,
+    Synth,
+    Scene,
+    Heatmap,
+    Dump,
+    Exit,
+}
+
+impl Item {
+    fn label(self) -> &'static str {
+        match self {
+            Item::Page => "PAGE",
+            Item::Brightness => "LEDS",
+            Item::Palette => "COLORS",
+            Item::Synth => "SYNTH",
+            Item::Scene => "SCENE",
+            Item::Heatmap => "HEAT",
+            Item::Dump => "DUMP",
+            Item::Exit => "EXIT",
+        }
+    }
+}
+
+/// What picking something in the menu asks for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MenuAction {
+    /// Show the page at this position.
+    Page(usize),
+    /// Set the LED brightness to this percent.  Sent on every detent, so
+    /// the change can be seen while turning.
+    Brightness(u8),
+    /// Show the pads in this palette.
+    Palette(Palette),
+    /// Talk to the synth on this port.
+    FocusSynth(String),
+    /// Recall the setlist song at this position.
+    Scene(usize),
+    /// Show pad hits on the pads instead of the bindings, or stop.
+    Heatmap(bool),
+    /// Read the whole patch from the synth.
+    Dump,
+}
+
+/// The values the menu shows, filled in from the daemon's state each time
+/// the menu opens.
+#[derive(Clone, Debug, Default)]
+pub struct MenuValues {
+    /// Names of the pages there are to show.
+    pub pages: Vec<String>,
+    pub page: usize,
+    pub brightness: u8,
+    pub palette: Palette,
+    /// Ports of the synths that could be talked to.
+    pub synths: Vec<String>,
+    pub synth: usize,
+    /// Names of the setlist's songs, if there is one.
+    pub scenes: Vec<String>,
+    pub scene: usize,
+    pub heatmap: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Closed,
+    Browsing(usize),
+    /// Changing the item, with the value it'll be set to.
+    Editing(usize, usize),
+}
+
+pub struct Menu {
+    state: State,
+    values: MenuValues,
+}
+
+/// `value` moved by `delta` and kept within 0..len.
+fn step(value: usize, delta: i32, len: usize) -> usize {
+    (value as i32 + delta).max(0).min(len.saturating_sub(1) as i32) as usize
+}
+
+impl Menu {
+    pub fn new() -> Self {
+        Menu {
+            state: State::Closed,
+            values: MenuValues::default(),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state != State::Closed
+    }
+
+    /// Refresh what the menu shows.  Takes effect the next time it opens.
+    pub fn set_values(&mut self, values: MenuValues) {
+        if !self.is_open() {
+            self.values = values;
+        }
+    }
+
+    /// Whether `event` is the select encoder's, so it shouldn't go on to
+    /// the bindings.
+    pub fn claims(&self, event: &ControllerEvent) -> bool {
+        matches!(event, ControllerEvent::Select(_) | ControllerEvent::SelectButton(_))
+    }
+
+    /// The items there's something to pick from.
+    fn items(&self) -> Vec<Item> {
+        let mut items = vec![];
+        if self.values.pages.len() > 1 {
+            items.push(Item::Page);
+        }
+        items.extend_from_slice(&[Item::Brightness, Item::Palette]);
+        if self.values.synths.len() > 1 {
+            items.push(Item::Synth);
+        }
+        if !self.values.scenes.is_empty() {
+            items.push(Item::Scene);
+        }
+        items.extend_from_slice(&[Item::Heatmap, Item::Dump, Item::Exit]);
+        items
+    }
+
+    /// Move through the menu for `event`.  Returns what's been picked, if
+    /// anything, and whether the display needs redrawing.
+    pub fn handle(&mut self, event: &ControllerEvent) -> (Option<MenuAction>, bool) {
+        let items = self.items();
+        match (event, self.state) {
+            (&ControllerEvent::SelectButton(ButtonState::Down), State::Closed) => {
+                self.state = State::Browsing(0);
+                (None, true)
+            },
+            (&ControllerEvent::SelectButton(ButtonState::Down), State::Browsing(i)) => {
+                let (state, action) = match items[i] {
+                    Item::Page => (State::Editing(i, self.values.page), None),
+                    Item::Brightness => (State::Editing(i, self.values.brightness as usize), None),
+                    Item::Palette => {
+                        let palette = Palette::ALL.iter().position(|&p| p == self.values.palette);
+                        (State::Editing(i, palette.unwrap_or(0)), None)
+                    },
+                    Item::Synth => (State::Editing(i, self.values.synth), None),
+                    Item::Scene => (State::Editing(i, self.values.scene), None),
+                    Item::Heatmap => {
+                        self.values.heatmap = !self.values.heatmap;
+                        (State::Browsing(i), Some(MenuAction::Heatmap(self.values.heatmap)))
+                    },
+                    Item::Dump => (State::Closed, Some(MenuAction::Dump)),
+                    Item::Exit => (State::Closed, None),
+                };
+                self.state = state;
+                (action, true)
+            },
+            (&ControllerEvent::SelectButton(ButtonState::Down), State::Editing(i, value)) => {
+                self.state = State::Browsing(i);
+                let action = match items[i] {
+                    Item::Page => {
+                        self.values.page = value;
+                        Some(MenuAction::Page(value))
+                    },
+                    Item::Palette => {
+                        self.values.palette = Palette::ALL[value];
+                        Some(MenuAction::Palette(Palette::ALL[value]))
+                    },
+                    Item::Synth if value != self.values.synth => {
+                        self.values.synth = value;
+                        Some(MenuAction::FocusSynth(self.values.synths[value].clone()))
+                    },
+                    Item::Scene => {
+                        self.values.scene = value;
+                        Some(MenuAction::Scene(value))
+                    },
+                    _ => None,
+                };
+                (action, true)
+            },
+            (&ControllerEvent::Select(delta), State::Browsing(i)) => {
+                self.state = State::Browsing(step(i, delta as i32, items.len()));
+                (None, true)
+            },
+            (&ControllerEvent::Select(delta), State::Editing(i, value)) => {
+                let delta = delta as i32;
+                match items[i] {
+                    Item::Brightness => {
+                        let brightness = (value as i32 + delta * BRIGHTNESS_STEP).clamp(0, 100) as u8;
+                        self.values.brightness = brightness;
+                        self.state = State::Editing(i, brightness as usize);
+                        (Some(MenuAction::Brightness(brightness)), true)
+                    },
+                    Item::Page => {
+                        self.state = State::Editing(i, step(value, delta, self.values.pages.len()));
+                        (None, true)
+                    },
+                    Item::Palette => {
+                        self.state = State::Editing(i, step(value, delta, Palette::ALL.len()));
+                        (None, true)
+                    },
+                    Item::Synth => {
+                        self.state = State::Editing(i, step(value, delta, self.values.synths.len()));
+                        (None, true)
+                    },
+                    Item::Scene => {
+                        self.state = State::Editing(i, step(value, delta, self.values.scenes.len()));
+                        (None, true)
+                    },
+                    _ => (None, false),
+                }
+            },
+            _ => (None, false),
+        }
+    }
+
+    /// What `item` is set to, or would be with `value`.
+    fn value_text(&self, item: Item, value: Option<usize>) -> String {
+        match item {
+            Item::Page => self.values.pages[value.unwrap_or(self.values.page)].clone(),
+            Item::Brightness => format!("{}%", value.unwrap_or(self.values.brightness as usize)),
+            Item::Palette => {
+                let palette = value.map(|value| Palette::ALL[value]).unwrap_or(self.values.palette);
+                palette.label().to_string()
+            },
+            Item::Synth => self.values.synths[value.unwrap_or(self.values.synth)].clone(),
+            Item::Scene => {
+                let scene = value.unwrap_or(self.values.scene);
+                format!("{}/{} {}", scene + 1, self.values.scenes.len(), self.values.scenes[scene])
+            },
+            Item::Heatmap => if self.values.heatmap { "ON" } else { "OFF" }.to_string(),
+            Item::Dump | Item::Exit => String::new(),
+        }
+    }
+
+    /// Draw the menu: the items down the left with their values beside
+    /// them, the selected one inverted.  While editing, only the value is.
+    pub fn render(&self, oled: &mut OledBuffer) {
+        oled.clear();
+        let (selected, editing) = match self.state {
+            State::Closed => return,
+            State::Browsing(i) => (i, None),
+            State::Editing(i, value) => (i, Some(value)),
+        };
+        let label_width = 6 * CHAR_ADVANCE;
+        for (i, item) in self.items().into_iter().enumerate() {
+            let y = i * LINE_HEIGHT;
+            let value = self.value_text(item, if i == selected { editing } else { None });
+            let value: String = value.chars().take((OLED_WIDTH - label_width) / CHAR_ADVANCE).collect();
+            if i == selected && editing.is_none() {
+                oled.fill_rect(0, y, OLED_WIDTH, LINE_HEIGHT - 1, true);
+            }
+            let on = i != selected || editing.is_some();
+            oled.draw_text(1, y + 1, item.label(), on);
+            if i == selected && editing.is_some() {
+                let width = value.chars().count() * CHAR_ADVANCE + 1;
+                oled.fill_rect(label_width - 1, y, width, LINE_HEIGHT - 1, true);
+                oled.draw_text(label_width, y + 1, &value, false);
+            } else {
+                oled.draw_text(label_width, y + 1, &value, on);
+            }
+        }
+    }
+}
+
+impl Default for Menu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
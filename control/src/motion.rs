@@ -0,0 +1,397 @@
+//! Motion sequencing, as on hardware synths: hold a bindings file's motion
+//! pad and turn an encoder, and the gesture's recorded as a curve of the
+//! param over time.  Tapping the pad plays it back, once or over and over,
+//! and tapping it again stops it, ex:
+//! ```json
+//! "motions": [
+//!   { "control": { "pad": 48 }, "playback": "loop", "beats": 8 },
+//!   { "control": { "pad": 49 } }
+//! ]
+//! ```
+//! With `beats` a curve's stretched to that many beats of the config's
+//! `clock`, once its tempo's known; otherwise it plays as fast as it was
+//! made.  Curves are kept
+//! with the scene, in a `.motions.json` next to the setlist song's snapshot,
+//! so each song has its own.  Params the synth saves to flash can't be
+//! recorded.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::bindings::{BindingsFile, Control};
+use crate::controllers::{ButtonState, ControllerCaps, ControllerEvent};
+use crate::param_store::ParamStore;
+
+/// Motion pad colors: nothing recorded, recorded, playing and recording.
+const EMPTY: (u8, u8, u8) = (0x04, 0x04, 0x04);
+const RECORDED: (u8, u8, u8) = (0x00, 0x10, 0x20);
+const PLAYING: (u8, u8, u8) = (0x00, 0x40, 0x7f);
+const RECORDING: (u8, u8, u8) = (0x7f, 0x00, 0x00);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Playback {
+    /// Play the curve through and stop at its end.
+    #[default]
+    Once,
+    /// Start over from the beginning until stopped.
+    Loop,
+}
+
+/// A pad that records and plays a motion.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MotionBinding {
+    pub control: Control,
+    #[serde(default)]
+    pub playback: Playback,
+    /// Play the curve over this many beats of the tempo, whatever its
+    /// length.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub beats: Option<u32>,
+}
+
+/// A recorded gesture: `param`'s raw value at each (ms from the start,
+/// value) point.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Curve {
+    pub param: String,
+    pub points: Vec<(u32, u32)>,
+}
+
+impl Curve {
+    fn length_ms(&self) -> u32 {
+        self.points.last().map(|(at, _)| *at).unwrap_or(0)
+    }
+
+    /// The value `ms` in, going straight between points.
+    fn value_at(&self, ms: f64) -> u32 {
+        let after = self.points.partition_point(|(at, _)| *at as f64 <= ms);
+        let (from, to) = match (after.checked_sub(1), self.points.get(after)) {
+            (Some(before), Some(to)) => (self.points[before], *to),
+            (Some(before), None) => return self.points[before].1,
+            (None, _) => return self.points.first().map(|(_, value)| *value).unwrap_or(0),
+        };
+        let t = (ms - from.0 as f64) / (to.0 - from.0) as f64;
+        (from.1 as f64 + (to.1 as f64 - from.1 as f64) * t).round() as u32
+    }
+}
+
+/// The `.motions.json` kept with a scene: each motion pad's curve.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MotionsFile {
+    pub motions: BTreeMap<u8, Curve>,
+}
+
+/// Where the curves for the scene saved in `snapshot` are kept, ex:
+/// "songs/intro.motions.json" for "songs/intro.syx".
+pub fn motions_path(snapshot: &str) -> PathBuf {
+    Path::new(snapshot).with_extension("motions.json")
+}
+
+struct Slot {
+    pad: u8,
+    playback: Playback,
+    beats: Option<u32>,
+    /// The curve and the index of its param.
+    curve: Option<(usize, Curve)>,
+    /// When it started playing, if it is.
+    playing: Option<Instant>,
+    /// The value last played, so repeats aren't sent.
+    last: Option<u32>,
+}
+
+/// A motion pad being held down.
+struct Recording {
+    pad: u8,
+    /// The param the first encoder turned moved.  Turns of others are left
+    /// out.
+    param: Option<usize>,
+    /// Whether that param can't be recorded.
+    refused: bool,
+    start: Instant,
+    points: Vec<(u32, u32)>,
+}
+
+pub struct Motions {
+    store: Arc<ParamStore>,
+    slots: Vec<Slot>,
+    recording: Option<Recording>,
+    /// The snapshot of the scene, if it has one to keep the curves with.
+    scene: Option<String>,
+    bpm: Option<f32>,
+    /// Whether `beats` motions are played by `tick_clocked`.
+    clocked: bool,
+}
+
+fn load(path: &Path) -> Result<MotionsFile, Box<dyn Error>> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+impl Motions {
+    /// Check `file`'s motion pads against the controller and the other
+    /// bindings.  They start out empty, see `set_scene`.
+    pub fn new(file: &BindingsFile, store: Arc<ParamStore>, caps: &ControllerCaps)
+               -> Result<Motions, Box<dyn Error>> {
+        let mut slots: Vec<Slot> = vec![];
+        for binding in &file.motions {
+            let pad = match binding.control {
+                Control::Pad(pad) if (pad as usize) < caps.pads() => pad,
+                control => return Err(format!("motions can't be bound to {:?}", control).into()),
+            };
+            if slots.iter().any(|slot| slot.pad == pad) ||
+               file.bindings.iter().any(|b| b.control == binding.control) ||
+               file.commands.iter().any(|c| c.control == binding.control) {
+                return Err(format!("pad {} is bound twice", pad).into());
+            }
+            if binding.beats == Some(0) {
+                return Err(format!("pad {}'s motion can't be 0 beats long", pad).into());
+            }
+            slots.push(Slot {
+                pad,
+                playback: binding.playback,
+                beats: binding.beats,
+                curve: None,
+                playing: None,
+                last: None,
+            });
+        }
+        Ok(Motions {
+            store,
+            slots,
+            recording: None,
+            scene: None,
+            bpm: None,
+            clocked: false,
+        })
+    }
+
+    /// Stop playing and load the curves kept with the scene saved in
+    /// `snapshot`.  Without a snapshot the pads start empty, and what's
+    /// recorded on them only lasts until the next scene.
+    pub fn set_scene(&mut self, snapshot: Option<&str>) {
+        self.recording = None;
+        let file = match snapshot.map(motions_path) {
+            Some(path) if path.exists() => load(&path).unwrap_or_else(|e| {
+                warn!("{}: {}", path.display(), e);
+                MotionsFile::default()
+            }),
+            _ => MotionsFile::default(),
+        };
+        let index = self.store.index();
+        for slot in &mut self.slots {
+            slot.playing = None;
+            slot.curve = file.motions.get(&slot.pad).and_then(|curve| {
+                match index.index_of(&curve.param) {
+                    Some(param) if !curve.points.is_empty() => Some((param, curve.clone())),
+                    Some(_) => None,
+                    None => {
+                        warn!("pad {}'s motion is of unknown param '{}'", slot.pad, curve.param);
+                        None
+                    },
+                }
+            });
+        }
+        self.scene = snapshot.map(String::from);
+    }
+
+    /// The snapshot of the scene the curves are from, if it has one.
+    pub fn scene(&self) -> Option<&str> {
+        self.scene.as_deref()
+    }
+
+    /// Stop every motion playing, ex: on panic.
+    pub fn stop(&mut self) {
+        for slot in &mut self.slots {
+            slot.playing = None;
+        }
+    }
+
+    /// Stretch `beats` motions to fit `bpm`.
+    pub fn set_bpm(&mut self, bpm: f32) {
+        self.bpm = Some(bpm).filter(|bpm| *bpm > 0.0);
+    }
+
+    /// Leave `beats` motions to `tick_clocked` while a clock's running, so
+    /// their writes can be scheduled against it.
+    pub fn set_clocked(&mut self, clocked: bool) {
+        self.clocked = clocked;
+    }
+
+    /// Whether `event` is for a motion pad, so it shouldn't go on to the
+    /// bindings.
+    pub fn claims(&self, event: &ControllerEvent) -> bool {
+        match *event {
+            ControllerEvent::GridButton(pad, ..) => self.slots.iter().any(|slot| slot.pad == pad),
+            _ => false,
+        }
+    }
+
+    /// Take a press or release of a motion pad.  A release after turning an
+    /// encoder keeps what was recorded; one without starts or stops
+    /// playback.  Returns whether the pads need redrawing.
+    pub fn handle(&mut self, event: &ControllerEvent, now: Instant) -> bool {
+        let (pad, state) = match *event {
+            ControllerEvent::GridButton(pad, _, _, state, _) => (pad, state),
+            _ => return false,
+        };
+        let i = match self.slots.iter().position(|slot| slot.pad == pad) {
+            Some(i) => i,
+            None => return false,
+        };
+        if state == ButtonState::Down {
+            self.recording = Some(Recording {
+                pad,
+                param: None,
+                refused: false,
+                start: now,
+                points: vec![],
+            });
+            return true;
+        }
+        let recorded = self.recording.take()
+            .filter(|recording| recording.pad == pad && recording.points.len() >= 2)
+            .and_then(|recording| Some((recording.param?, recording.points)));
+        let slot = &mut self.slots[i];
+        match recorded {
+            Some((param, points)) => {
+                let name = self.store.index().params[param].name.clone();
+                let length = points.last().map(|(at, _)| *at).unwrap_or(0);
+                info!("pad {} recorded a {}ms motion of {}", pad, length, name);
+                slot.curve = Some((param, Curve { param: name, points }));
+                slot.playing = None;
+                self.save();
+            },
+            None if slot.curve.is_some() => {
+                slot.playing = if slot.playing.is_some() { None } else { Some(now) };
+                slot.last = None;
+            },
+            None => (),
+        }
+        true
+    }
+
+    /// Record the value an encoder turn left its param at, if a motion pad's
+    /// held.  Call after the bindings have handled `event`, with their
+    /// `encoder_params`.
+    pub fn note(&mut self, event: &ControllerEvent, encoder_params: &[Option<usize>], now: Instant) {
+        let recording = match &mut self.recording {
+            Some(recording) if !recording.refused => recording,
+            _ => return,
+        };
+        let param = match *event {
+            ControllerEvent::Encoder(i, _) => match encoder_params.get(i as usize) {
+                Some(Some(param)) => *param,
+                _ => return,
+            },
+            _ => return,
+        };
+        match recording.param {
+            None => {
+                let p = &self.store.index().params[param];
+                if !p.entry.write_cost.is_volatile() {
+                    warn!("can't record a motion of '{}', which the synth saves to flash", p.name);
+                    recording.refused = true;
+                    return;
+                }
+                recording.param = Some(param);
+                recording.start = now;
+            },
+            Some(recorded) if recorded != param => return,
+            Some(_) => (),
+        }
+        let at = now.saturating_duration_since(recording.start).as_millis() as u32;
+        let value = self.store.get(param);
+        if recording.points.last().map(|(_, last)| *last) != Some(value) {
+            recording.points.push((at, value));
+        }
+    }
+
+    /// The (param, value) writes the playing motions have due at `now`.
+    /// Call every `scheduler::TICK`.
+    pub fn tick(&mut self, now: Instant) -> Vec<(usize, u32)> {
+        let clocked = self.clocked;
+        self.play(now, |slot| !clocked || slot.beats.is_none())
+    }
+
+    /// The writes the `beats` motions have due at `at`, the time of the
+    /// clock tick they're to be scheduled for.  Call every
+    /// `scheduler::TICK` while `set_clocked`.
+    pub fn tick_clocked(&mut self, at: Instant) -> Vec<(usize, u32)> {
+        if !self.clocked {
+            return vec![];
+        }
+        self.play(at, |slot| slot.beats.is_some())
+    }
+
+    fn play<F: Fn(&Slot) -> bool>(&mut self, now: Instant, plays: F) -> Vec<(usize, u32)> {
+        let mut writes = vec![];
+        for slot in self.slots.iter_mut().filter(|slot| plays(slot)) {
+            let (start, (param, curve)) = match (slot.playing, &slot.curve) {
+                (Some(start), Some(curve)) => (start, curve),
+                _ => continue,
+            };
+            let length = curve.length_ms().max(1) as f64;
+            // How much faster than it was recorded it plays.
+            let speed = match (slot.beats, self.bpm) {
+                (Some(beats), Some(bpm)) => length / (beats as f64 * 60_000.0 / bpm as f64),
+                _ => 1.0,
+            };
+            let mut ms = now.saturating_duration_since(start).as_secs_f64() * 1000.0 * speed;
+            if ms >= length {
+                match slot.playback {
+                    Playback::Once => {
+                        slot.playing = None;
+                        ms = length;
+                    },
+                    Playback::Loop => ms %= length,
+                }
+            }
+            let value = curve.value_at(ms);
+            if slot.last != Some(value) {
+                slot.last = Some(value);
+                writes.push((*param, value));
+            }
+        }
+        writes
+    }
+
+    /// Feed the motion pads' colors to `set_led`.
+    pub fn render<F: FnMut(u8, u8, u8, u8)>(&self, mut set_led: F) {
+        for slot in &self.slots {
+            let (r, g, b) = match (&self.recording, slot.playing, &slot.curve) {
+                (Some(recording), _, _) if recording.pad == slot.pad => RECORDING,
+                (_, Some(_), _) => PLAYING,
+                (_, _, Some(_)) => RECORDED,
+                _ => EMPTY,
+            };
+            set_led(slot.pad, r, g, b);
+        }
+    }
+
+    fn save(&self) {
+        let path = match &self.scene {
+            Some(snapshot) => motions_path(snapshot),
+            None => {
+                info!("no scene snapshot to keep motions with; they're kept until the scene changes");
+                return;
+            },
+        };
+        let file = MotionsFile {
+            motions: self.slots.iter()
+                .filter_map(|slot| Some((slot.pad, slot.curve.as_ref()?.1.clone())))
+                .collect(),
+        };
+        let saved = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())
+            .and_then(|json| fs::write(&path, json).map_err(|e| e.to_string()));
+        if let Err(e) = saved {
+            warn!("can't save motions to {}: {}", path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! An MQTT bridge for home studio automation (Node-RED, Home Assistant...).
+//!
+//! Param changes are published, retained, to `<prefix>/param/<name>` with the
+//! human value as the payload.  Publishing a human value to
+//! `<prefix>/set/<name>` sets the param.  Param names contain "/", so they
+//! show up as nested topics, ex: `mapatron/param/Temporary Scene/Scene Common/Scene Level`.
+
+use log::{info, warn};
+use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::RecvError;
+
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::bus::{EngineEvent, EventBus, EventKind};
+use crate::human::format_value;
+use crate::param_store::ParamStore;
+use crate::remote::{set_param_command, CommandSender};
+
+/// Outgoing messages that can queue up in the client.
+const CLIENT_BUFFER: usize = 256;
+/// How long to wait before retrying after a connection error.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// The `mqtt` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MqttConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    #[serde(default = "default_prefix")]
+    pub topic_prefix: String,
+    #[serde(default = "default_prefix")]
+    pub client_id: String,
+}
+
+fn default_port() -> u16 {
+    1883
+}
+
+fn default_prefix() -> String {
+    "mapatron".to_string()
+}
+
+/// Connect to the broker and bridge it with the store until the process
+/// exits.  Incoming sets run on their own thread since rumqttc brings its own
+/// runtime; outgoing changes are published from a task on ours.
+pub fn start(config: &MqttConfig, store: Arc<ParamStore>, bus: &EventBus,
+             commands: CommandSender) {
+    let mut options = MqttOptions::new(config.client_id.clone(), config.host.clone(), config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    let (client, mut connection) = Client::new(options, CLIENT_BUFFER);
+
+    let set_prefix = format!("{}/set/", config.topic_prefix);
+    let mut subscriber = client.clone();
+    let set_store = store.clone();
+    let mut commands = commands;
+    thread::spawn(move || {
+        for notification in connection.iter() {
+            match notification {
+                // Subscriptions don't survive reconnects.
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("connected to MQTT broker");
+                    if let Err(e) = subscriber.try_subscribe(format!("{}#", set_prefix),
+                                                             QoS::AtLeastOnce) {
+                        warn!("MQTT subscribe failed: {}", e);
+                    }
+                },
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    let name = match publish.topic.strip_prefix(&set_prefix) {
+                        Some(name) => name,
+                        None => continue,
+                    };
+                    let value = String::from_utf8_lossy(&publish.payload);
+                    let result = set_param_command(&set_store, name, &value)
+                        .and_then(|command| commands.try_send(command).map_err(|e| e.to_string()));
+                    if let Err(e) = result {
+                        warn!("MQTT set of '{}' failed: {}", name, e);
+                    }
+                },
+                Ok(_) => (),
+                Err(e) => {
+                    warn!("MQTT connection error: {}", e);
+                    thread::sleep(RETRY_DELAY);
+                },
+            }
+        }
+    });
+
+    let param_prefix = format!("{}/param/", config.topic_prefix);
+    let mut publisher = client;
+    let mut changes = bus.subscribe(&[EventKind::Param]);
+    tokio::spawn(async move {
+        loop {
+            let changes = match changes.recv().await {
+                Ok(EngineEvent::ParamChanged(change)) => vec![change],
+                Ok(EngineEvent::ParamsChanged(changes)) => changes,
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            };
+            for change in changes {
+                let p = &store.index().params[change.param];
+                let value = format_value(&store.display_entry(change.param), change.value);
+                if let Err(e) = publisher.try_publish(format!("{}{}", param_prefix, p.name),
+                                                      QoS::AtMostOnce, true, value) {
+                    warn!("MQTT publish failed: {}", e);
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,144 @@
+//! A printable overlay of the bindings for the Fire's top panel, ex:
+//! `mapatron overlay jupx --out jupx.svg`.  The SVG is laid out in
+//! millimetres, so it prints to scale from a browser, and
+//! `rsvg-convert -f pdf` turns it into a PDF.  Pads are labelled with the
+//! param they write and the value, radio rows with each value's name, and
+//! the encoders get a strip per page: the bindings, then the favorites.
+
+use std::error::Error;
+use std::fmt::Write;
+
+use crate::bindings::{expand_radio_rows, BindingsFile, Control, PadAction};
+use crate::controllers::ControllerCaps;
+use crate::human::format_value;
+use crate::sysex_map::ParamIndex;
+
+/// The Fire's top panel, in millimetres.
+const WIDTH: f32 = 432.0;
+const HEIGHT: f32 = 140.0;
+/// The top left of the pad grid, and the distance between pads.
+const GRID_X: f32 = 96.0;
+const GRID_Y: f32 = 52.0;
+const PAD_PITCH: f32 = 20.5;
+const PAD_SIZE: f32 = 17.0;
+/// The first encoder's centre, and the distance between encoders.
+const ENCODER_X: f32 = 118.0;
+const ENCODER_Y: f32 = 16.0;
+const ENCODER_PITCH: f32 = 24.0;
+/// Label text height, and how many characters fit across a pad or under an
+/// encoder at that size.
+const TEXT_SIZE: f32 = 2.4;
+const PAD_CHARS: usize = 11;
+const ENCODER_CHARS: usize = 14;
+
+fn truncate(text: &str, chars: usize) -> String {
+    text.chars().take(chars).collect()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Lines of text centred on (`x`, `y`).
+fn text(svg: &mut String, x: f32, y: f32, lines: &[String]) {
+    let line_height = TEXT_SIZE * 1.3;
+    let top = y - (lines.len() as f32 - 1.0) * line_height / 2.0 + TEXT_SIZE / 3.0;
+    for (i, line) in lines.iter().enumerate().filter(|(_, line)| !line.is_empty()) {
+        // Writing to a String can't fail.
+        let _ = writeln!(svg, r#"<text x="{:.1}" y="{:.1}">{}</text>"#,
+                         x, top + i as f32 * line_height, escape(line));
+    }
+}
+
+/// The top left corner of pad `pad`.
+fn pad_corner(caps: &ControllerCaps, pad: u8) -> (f32, f32) {
+    let (row, col) = caps.row_col(pad);
+    (GRID_X + col as f32 * PAD_PITCH, GRID_Y + row as f32 * PAD_PITCH)
+}
+
+/// The overlay for `file` as SVG, with `favorites` (ex: from
+/// `Favorites::top`) on the second encoder strip.  Also returns the params
+/// of bindings left off because the Fire doesn't have their controls.
+pub fn render(file: &BindingsFile, index: &ParamIndex, favorites: &[usize])
+              -> Result<(String, Vec<String>), Box<dyn Error>> {
+    let caps = ControllerCaps::FIRE;
+    let mut pads: Vec<Vec<String>> = vec![vec![]; caps.pads()];
+    let mut xy_pads = vec![];
+    let mut encoders: Vec<Option<String>> = vec![None; caps.encoders as usize];
+    let mut left_out = vec![];
+
+    for entry in expand_radio_rows(file, index, &caps)? {
+        let param = index.get(&entry.param)
+            .ok_or_else(|| format!("binding for unknown param '{}'", entry.param))?;
+        let name = param.display_name();
+        match entry.control {
+            Control::Pad(pad) if (pad as usize) < pads.len() => {
+                let value = |value| format_value(&param.entry, value);
+                let action = match (entry.action, entry.value) {
+                    (PadAction::Step { step, .. }, _) => format!("{:+}", step),
+                    (PadAction::Toggle { off }, Some(on)) => {
+                        let low = entry.min.unwrap_or(0).max(param.entry.discrete_range_low);
+                        format!("{}/{}", value(on), value(off.unwrap_or(low)))
+                    },
+                    (PadAction::Momentary, Some(on)) => format!("hold {}", value(on)),
+                    (_, Some(on)) => value(on),
+                    (_, None) => String::new(),
+                };
+                // Radio pads all share the param, so only the value matters.
+                pads[pad as usize] = match entry.action {
+                    PadAction::Radio => vec![truncate(&action, PAD_CHARS)],
+                    _ => vec![truncate(name, PAD_CHARS), truncate(&action, PAD_CHARS)],
+                };
+            },
+            Control::Xy { pad, size } => {
+                let y = entry.param_y.as_deref().and_then(|y| index.get(y)).map(|p| p.display_name());
+                let y = y.unwrap_or_default();
+                xy_pads.push((pad, size, format!("X: {}", name), format!("Y: {}", y)));
+            },
+            Control::Encoder(i) if i < caps.encoders => encoders[i as usize] = Some(name.to_string()),
+            _ => left_out.push(entry.param.clone()),
+        }
+    }
+    for zone in &file.zones {
+        let label = vec![truncate(&zone.zone, PAD_CHARS), format!("{:?} {:+}", zone.adjust, zone.step)];
+        match zone.control {
+            Control::Pad(pad) if (pad as usize) < pads.len() => pads[pad as usize] = label,
+            Control::Encoder(i) if i < caps.encoders => encoders[i as usize] = Some(label.join(" ")),
+            _ => left_out.push(format!("zone {}", zone.zone)),
+        }
+    }
+
+    let mut svg = String::new();
+    let _ = writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}mm" height="{h}mm" viewBox="0 0 {w} {h}">"#,
+                     w = WIDTH, h = HEIGHT);
+    let _ = writeln!(svg, r#"<style>rect {{ fill: none; stroke: #888; stroke-width: 0.2 }} text {{ font: {}px sans-serif; text-anchor: middle }}</style>"#,
+                     TEXT_SIZE);
+    let _ = writeln!(svg, r#"<rect x="0.1" y="0.1" width="{}" height="{}"/>"#, WIDTH - 0.2, HEIGHT - 0.2);
+
+    for (i, label) in encoders.iter().enumerate() {
+        let x = ENCODER_X + i as f32 * ENCODER_PITCH;
+        let _ = writeln!(svg, r##"<circle cx="{:.1}" cy="{:.1}" r="7" fill="none" stroke="#888" stroke-width="0.2"/>"##,
+                         x, ENCODER_Y);
+        let mut lines = vec![truncate(label.as_deref().unwrap_or(""), ENCODER_CHARS)];
+        if !favorites.is_empty() {
+            let favorite = favorites.get(i).map(|&p| index.params[p].display_name()).unwrap_or("");
+            lines.push(format!("★ {}", truncate(favorite, ENCODER_CHARS - 2)));
+        }
+        text(&mut svg, x, ENCODER_Y + 11.0, &lines);
+    }
+
+    for (pad, lines) in pads.iter().enumerate() {
+        let (x, y) = pad_corner(&caps, pad as u8);
+        let _ = writeln!(svg, r#"<rect x="{:.1}" y="{:.1}" width="{}" height="{}"/>"#, x, y, PAD_SIZE, PAD_SIZE);
+        text(&mut svg, x + PAD_SIZE / 2.0, y + PAD_SIZE / 2.0, lines);
+    }
+    for (pad, size, x_label, y_label) in xy_pads {
+        let (x, y) = pad_corner(&caps, pad);
+        let span = (size as f32 - 1.0) * PAD_PITCH + PAD_SIZE;
+        let _ = writeln!(svg, r#"<rect x="{:.1}" y="{:.1}" width="{:.1}" height="{:.1}" stroke-dasharray="1 1"/>"#,
+                         x - 1.0, y - 1.0, span + 2.0, span + 2.0);
+        text(&mut svg, x + span / 2.0, y + span / 2.0, &[x_label, y_label]);
+    }
+    svg.push_str("</svg>\n");
+    Ok((svg, left_out))
+}
@@ -0,0 +1,221 @@
+//! Safety caps on how fast bulk transfers (snapshot recalls and patch dumps)
+//! go out.  Plenty of synths drop or choke on sysex sent at full speed, so
+//! each message waits a minimum gap after the one before.  The gap comes
+//! from the first of:
+//! - `pacing_ms` in the config, for the user's own setup
+//! - `pacing_ms` in the map, for a device slower than its maker's others,
+//!   ex: vintage gear
+//! - the profile for the manufacturer ID the map's messages start with
+//! - `DEFAULT_GAP_MS`, for makers without a profile
+//!
+//! Only bulk messages are paced.  An `Outbox` keeps them in their own lane,
+//! behind realtime messages (notes, All Notes Off) and param writes, so
+//! those never wait for a recall or a dump to finish.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::scheduler::TICK;
+use crate::sysex_map::SysexMap;
+use crate::template::{Template, Token};
+
+/// How long to leave between bulk messages to one maker's gear.
+pub struct Profile {
+    /// The sysex manufacturer ID, one byte or `00` and two more.
+    pub manufacturer: &'static [u8],
+    pub name: &'static str,
+    pub gap_ms: u64,
+}
+
+pub const PROFILES: &[Profile] = &[
+    Profile { manufacturer: &[0x01], name: "Sequential", gap_ms: 20 },
+    Profile { manufacturer: &[0x10], name: "Oberheim", gap_ms: 100 },
+    Profile { manufacturer: &[0x40], name: "Kawai", gap_ms: 50 },
+    Profile { manufacturer: &[0x41], name: "Roland", gap_ms: 20 },
+    Profile { manufacturer: &[0x42], name: "Korg", gap_ms: 30 },
+    Profile { manufacturer: &[0x43], name: "Yamaha", gap_ms: 50 },
+    Profile { manufacturer: &[0x44], name: "Casio", gap_ms: 50 },
+    Profile { manufacturer: &[0x47], name: "Akai", gap_ms: 20 },
+    Profile { manufacturer: &[0x00, 0x20, 0x29], name: "Novation", gap_ms: 10 },
+    Profile { manufacturer: &[0x00, 0x20, 0x32], name: "Behringer", gap_ms: 10 },
+];
+
+/// For makers without a profile.  Erring on the slow side only costs time.
+pub const DEFAULT_GAP_MS: u64 = 50;
+
+/// The manufacturer ID the map's writes start with.
+pub fn manufacturer_id(map: &SysexMap) -> Vec<u8> {
+    let bytes: Vec<u8> = Template::write_for(map).tokens().iter()
+        .skip(1)
+        .map_while(|token| match token {
+            Token::Byte(b) => Some(*b),
+            _ => None,
+        })
+        .collect();
+    let len = if bytes.first() == Some(&0) { 3 } else { 1 };
+    bytes.into_iter().take(len).collect()
+}
+
+pub fn profile_for(manufacturer: &[u8]) -> Option<&'static Profile> {
+    PROFILES.iter().find(|p| p.manufacturer == manufacturer)
+}
+
+/// The gap between bulk messages to the map's device, with the user's
+/// `override_ms` if any.
+pub fn gap_for(map: &SysexMap, override_ms: Option<u64>) -> Duration {
+    let ms = override_ms.or(map.pacing_ms).unwrap_or_else(|| {
+        profile_for(&manufacturer_id(map)).map(|p| p.gap_ms).unwrap_or(DEFAULT_GAP_MS)
+    });
+    Duration::from_millis(ms)
+}
+
+/// Hands out send slots at most one `gap` apart.  A pacer that's been idle
+/// doesn't bank the slots it missed beyond a tick's worth.
+pub struct Pacer {
+    gap: Duration,
+    next: Option<Instant>,
+}
+
+impl Pacer {
+    pub fn new(gap: Duration) -> Self {
+        Pacer {
+            gap,
+            next: None,
+        }
+    }
+
+    pub fn gap(&self) -> Duration {
+        self.gap
+    }
+
+    /// Whether a message may go out at `now`, taking the slot if so.
+    pub fn ready(&mut self, now: Instant) -> bool {
+        let from = match self.next {
+            Some(next) if next > now => return false,
+            Some(next) => next.max(now.checked_sub(TICK).unwrap_or(now)),
+            None => now,
+        };
+        self.next = Some(from + self.gap);
+        true
+    }
+
+    /// How long until the next slot.
+    pub fn wait(&self, now: Instant) -> Duration {
+        self.next.map(|next| next.saturating_duration_since(now)).unwrap_or_default()
+    }
+}
+
+/// How urgent an outgoing message is.  Higher goes first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    /// Recall writes and dump requests, paced.
+    Bulk,
+    /// Param writes from the controller or a remote.
+    Parameter,
+    /// Notes, pressure, channel mode messages and system realtime.
+    Realtime,
+}
+
+impl Priority {
+    /// The lane for `msg` by what kind of message it is.
+    pub fn of(msg: &[u8]) -> Priority {
+        match msg {
+            [status, ..] if *status >= 0xf8 => Priority::Realtime,
+            [status, ..] if matches!(status & 0xf0, 0x80 | 0x90 | 0xa0 | 0xd0) => Priority::Realtime,
+            // All Sound Off, Reset All Controllers, All Notes Off and such.
+            [status, cc, ..] if status & 0xf0 == 0xb0 && *cc >= 120 => Priority::Realtime,
+            _ => Priority::Parameter,
+        }
+    }
+}
+
+/// A message waiting in an `Outbox`, and the param write it makes if it's
+/// one.
+struct Outgoing {
+    msg: Vec<u8>,
+    write: Option<(usize, u32)>,
+}
+
+/// Outgoing messages in a lane per `Priority`.  Bulk messages only go out
+/// one per pacing slot and only when nothing more urgent is waiting, so a
+/// long transfer is preempted between any two of its messages.
+pub struct Outbox {
+    realtime: VecDeque<Outgoing>,
+    parameter: VecDeque<Outgoing>,
+    bulk: VecDeque<Outgoing>,
+    pacer: Pacer,
+}
+
+impl Outbox {
+    pub fn new(gap: Duration) -> Self {
+        Outbox {
+            realtime: VecDeque::new(),
+            parameter: VecDeque::new(),
+            bulk: VecDeque::new(),
+            pacer: Pacer::new(gap),
+        }
+    }
+
+    fn lane(&mut self, priority: Priority) -> &mut VecDeque<Outgoing> {
+        match priority {
+            Priority::Realtime => &mut self.realtime,
+            Priority::Parameter => &mut self.parameter,
+            Priority::Bulk => &mut self.bulk,
+        }
+    }
+
+    pub fn push(&mut self, priority: Priority, msg: &[u8]) {
+        self.lane(priority).push_back(Outgoing {
+            msg: msg.to_vec(),
+            write: None,
+        });
+    }
+
+    /// Queue `msg`, which writes `value` to `param`.  `flush` says when it
+    /// went out, so the store only ever holds what the synth was sent.
+    pub fn push_write(&mut self, priority: Priority, msg: &[u8], param: usize, value: u32) {
+        self.lane(priority).push_back(Outgoing {
+            msg: msg.to_vec(),
+            write: Some((param, value)),
+        });
+    }
+
+    /// How many messages are waiting in `priority`'s lane.
+    pub fn len(&self, priority: Priority) -> usize {
+        match priority {
+            Priority::Realtime => self.realtime.len(),
+            Priority::Parameter => self.parameter.len(),
+            Priority::Bulk => self.bulk.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.realtime.is_empty() && self.parameter.is_empty() && self.bulk.is_empty()
+    }
+
+    /// Drop everything waiting in `priority`'s lane, ex: the rest of a
+    /// cancelled recall.
+    pub fn clear(&mut self, priority: Priority) {
+        self.lane(priority).clear();
+    }
+
+    /// Send what's due at `now` through `send`: every realtime message,
+    /// then every param write, then bulk messages as the pacing allows.
+    /// Returns the (param, value) writes from `push_write` that went out.
+    pub fn flush<F: FnMut(&[u8])>(&mut self, now: Instant, mut send: F) -> Vec<(usize, u32)> {
+        let mut written = vec![];
+        let mut sent = |outgoing: Outgoing| {
+            send(&outgoing.msg);
+            written.extend(outgoing.write);
+        };
+        for outgoing in self.realtime.drain(..).chain(self.parameter.drain(..)) {
+            sent(outgoing);
+        }
+        while !self.bulk.is_empty() && self.pacer.ready(now) {
+            if let Some(outgoing) = self.bulk.pop_front() {
+                sent(outgoing);
+            }
+        }
+        written
+    }
+}
@@ -0,0 +1,76 @@
+//! The emergency stop: holding down a combination of pads silences every
+//! output and optionally puts back the last snapshot, ex:
+//! `"panic": { "pads": [0, 15], "restore_snapshot": true }`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::controllers::{ButtonState, ControllerEvent};
+
+const ALL_SOUND_OFF: u8 = 120;
+const RESET_ALL_CONTROLLERS: u8 = 121;
+const ALL_NOTES_OFF: u8 = 123;
+
+/// The `panic` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PanicConfig {
+    /// Pads that all have to be held down at once.  They're reserved for
+    /// this and don't reach the bindings.
+    pub pads: Vec<u8>,
+    /// Rewrite the last snapshot recalled after silencing everything.
+    #[serde(default)]
+    pub restore_snapshot: bool,
+}
+
+/// All sound off, all notes off and reset controllers on every channel.
+pub fn panic_messages() -> Vec<[u8; 3]> {
+    let mut msgs = vec![];
+    for channel in 0..16 {
+        for cc in &[ALL_SOUND_OFF, ALL_NOTES_OFF, RESET_ALL_CONTROLLERS] {
+            msgs.push([0xb0 | channel, *cc, 0]);
+        }
+    }
+    msgs
+}
+
+/// Watches for the panic combination.
+pub struct PanicCombo {
+    pads: Vec<u8>,
+    held: Vec<bool>,
+    restore_snapshot: bool,
+}
+
+impl PanicCombo {
+    pub fn new(config: &PanicConfig) -> Self {
+        PanicCombo {
+            pads: config.pads.clone(),
+            held: vec![false; config.pads.len()],
+            restore_snapshot: config.restore_snapshot,
+        }
+    }
+
+    pub fn restores_snapshot(&self) -> bool {
+        self.restore_snapshot
+    }
+
+    /// Whether `event` is for one of the combination's pads.
+    pub fn claims(&self, event: &ControllerEvent) -> bool {
+        match *event {
+            ControllerEvent::GridButton(pad, ..) => self.pads.contains(&pad),
+            _ => false,
+        }
+    }
+
+    /// Track `event`, returning true when it completes the combination.
+    pub fn handle(&mut self, event: &ControllerEvent) -> bool {
+        let (pad, state) = match *event {
+            ControllerEvent::GridButton(pad, _, _, state, _) => (pad, state),
+            _ => return false,
+        };
+        let i = match self.pads.iter().position(|p| *p == pad) {
+            Some(i) => i,
+            None => return false,
+        };
+        self.held[i] = state == ButtonState::Down;
+        state == ButtonState::Down && !self.held.is_empty() && self.held.iter().all(|h| *h)
+    }
+}
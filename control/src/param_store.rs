@@ -0,0 +1,127 @@
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use crate::bus::{EngineEvent, EventBus};
+use crate::sysex_map::{ParamIndex, SysexMapValueEntry};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ParamChange {
+    /// Index into the store's `ParamIndex`.
+    pub param: usize,
+    pub value: u32,
+}
+
+/// The current raw value of every parameter in a map, readable and writable
+/// from any thread without locking.  Every parameter is a single `u32` so each
+/// gets its own atomic; there's no multi-parameter consistency to protect.
+///
+/// Changes are published on the `EventBus` as `EngineEvent::ParamChanged` so
+/// that anything displaying state (LEDs, display, remote clients) can
+/// subscribe instead of polling or being called directly by whoever made the
+/// change.  During bulk operations changes can be held back with
+/// `hold_changes` and published all at once as `EngineEvent::ParamsChanged`
+/// by `release_changes`, so subscribers redraw once instead of per param.
+pub struct ParamStore {
+    index: ParamIndex,
+    values: Vec<AtomicU32>,
+    bus: EventBus,
+    holding: AtomicBool,
+    /// Which params changed while holding.
+    held: Vec<AtomicBool>,
+}
+
+impl ParamStore {
+    /// Creates a store with every param at the bottom of its range, which is
+    /// the best we can do until the device tells us otherwise.
+    pub fn new(index: ParamIndex, bus: EventBus) -> Self {
+        let values = index.params.iter()
+            .map(|p| AtomicU32::new(p.entry.discrete_range_low))
+            .collect();
+        let held = index.params.iter().map(|_| AtomicBool::new(false)).collect();
+        ParamStore {
+            index,
+            values,
+            bus,
+            holding: AtomicBool::new(false),
+            held,
+        }
+    }
+
+    pub fn index(&self) -> &ParamIndex {
+        &self.index
+    }
+
+    pub fn get(&self, param: usize) -> u32 {
+        self.values[param].load(Ordering::Acquire)
+    }
+
+    pub fn get_by_name(&self, name: &str) -> Option<u32> {
+        self.index.index_of(name).map(|param| self.get(param))
+    }
+
+    /// Whether the param should be shown given the current values of the
+    /// params it depends on.
+    pub fn is_visible(&self, param: usize) -> bool {
+        self.index.is_visible(param, |p| self.get(p))
+    }
+
+    /// The entry to format the param with given the current values of the
+    /// params it depends on.
+    pub fn display_entry(&self, param: usize) -> Cow<'_, SysexMapValueEntry> {
+        self.index.display_entry(param, |p| self.get(p))
+    }
+
+    /// Set a value, notifying subscribers if it actually changed (or noting
+    /// it for `release_changes` while holding).  Returns whether it changed.
+    pub fn set(&self, param: usize, value: u32) -> bool {
+        let old = self.values[param].swap(value, Ordering::AcqRel);
+        if old == value {
+            return false;
+        }
+        if self.holding.load(Ordering::Acquire) {
+            self.held[param].store(true, Ordering::Release);
+        } else {
+            self.bus.publish(EngineEvent::ParamChanged(ParamChange { param, value }));
+        }
+        true
+    }
+
+    /// Stop publishing changes one at a time, ex: while a snapshot recall and
+    /// the synth's echoes of it are coming through.
+    pub fn hold_changes(&self) {
+        self.holding.store(true, Ordering::Release);
+    }
+
+    /// Go back to publishing changes as they happen, first publishing
+    /// everything that changed while holding as a single
+    /// `EngineEvent::ParamsChanged`, with current values.
+    pub fn release_changes(&self) {
+        self.holding.store(false, Ordering::Release);
+        let changes: Vec<ParamChange> = self.held.iter().enumerate()
+            .filter(|(_, held)| held.swap(false, Ordering::AcqRel))
+            .map(|(param, _)| ParamChange { param, value: self.get(param) })
+            .collect();
+        if !changes.is_empty() {
+            self.bus.publish(EngineEvent::ParamsChanged(changes));
+        }
+    }
+
+    /// Copy out all the current values, ex: for saving.
+    pub fn snapshot(&self) -> Vec<u32> {
+        self.values.iter().map(|v| v.load(Ordering::Acquire)).collect()
+    }
+
+    /// Just the `writes` that would change something.
+    pub fn differing(&self, writes: &[(usize, u32)]) -> Vec<(usize, u32)> {
+        writes.iter().filter(|(param, value)| self.get(*param) != *value).copied().collect()
+    }
+
+    /// The (param, value) writes that would take the store to `values`, a
+    /// `snapshot` from earlier: just the params that differ.
+    pub fn writes_to(&self, values: &[u32]) -> Vec<(usize, u32)> {
+        values.iter().enumerate()
+            .filter(|(param, value)| self.get(*param) != **value)
+            .map(|(param, value)| (param, *value))
+            .collect()
+    }
+}
@@ -0,0 +1,113 @@
+use tokio::sync::broadcast::RecvError;
+use tokio::time;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::bus::{EngineEvent, EventBus};
+use crate::param_store::ParamStore;
+
+/// What a plugin gets to work with.  Plugins react to the world via
+/// `EngineEvent`s and change it by publishing events or setting params.
+#[derive(Clone)]
+pub struct PluginContext {
+    pub bus: EventBus,
+    pub store: Arc<ParamStore>,
+}
+
+/// A behavior that can be bolted onto the engine without touching it, ex: a
+/// custom LED animation or support for some exotic device.
+///
+/// Out-of-tree plugins are just crates that depend on this one and implement
+/// this trait.  They're registered with the registry `daemon::run` is
+/// handed, ex: in `mapatron`'s `plugins()` behind a cargo feature, so that
+/// only the plugins you want get compiled in, or from a binary of your own:
+/// ```ignore
+/// let mut registry = PluginRegistry::new();
+/// registry.register(Box::new(sparkles::Sparkles::new()));
+/// control::daemon::run("jupx", &config, registry).await?;
+/// ```
+pub trait MapatronPlugin: Send {
+    /// Used in logging.
+    fn name(&self) -> &str;
+
+    /// Called once before any events or ticks.
+    fn init(&mut self, _ctx: &PluginContext) {}
+
+    /// Called for every engine event, in the order they were published.
+    fn handle_event(&mut self, _ctx: &PluginContext, _event: &EngineEvent) {}
+
+    /// Called at the registry's tick rate, for animations and timeouts.
+    fn tick(&mut self, _ctx: &PluginContext, _now: Instant) {}
+}
+
+pub struct PluginRegistry {
+    plugins: Vec<Box<dyn MapatronPlugin>>,
+}
+
+impl PluginRegistry {
+    pub fn new() -> Self {
+        PluginRegistry {
+            plugins: vec![],
+        }
+    }
+
+    pub fn register(&mut self, plugin: Box<dyn MapatronPlugin>) {
+        self.plugins.push(plugin);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plugins.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.plugins.iter().map(|p| p.name()).collect()
+    }
+
+    pub fn init_all(&mut self, ctx: &PluginContext) {
+        for plugin in self.plugins.iter_mut() {
+            plugin.init(ctx);
+        }
+    }
+
+    pub fn dispatch(&mut self, ctx: &PluginContext, event: &EngineEvent) {
+        for plugin in self.plugins.iter_mut() {
+            plugin.handle_event(ctx, event);
+        }
+    }
+
+    pub fn tick_all(&mut self, ctx: &PluginContext, now: Instant) {
+        for plugin in self.plugins.iter_mut() {
+            plugin.tick(ctx, now);
+        }
+    }
+
+    /// Initialize the plugins and then feed them bus events and ticks.
+    /// `ctx` keeps the bus open, so this never returns; to stop the plugins,
+    /// `select!` it against whatever should stop them.
+    pub async fn run(mut self, ctx: PluginContext, tick_every: Duration) {
+        let mut events = ctx.bus.subscribe_all();
+        let mut ticker = time::interval(tick_every);
+        self.init_all(&ctx);
+
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => self.dispatch(&ctx, &event),
+                    // Plugins that mirror state can re-read it on their next
+                    // tick; there's nothing useful to replay.
+                    Err(RecvError::Lagged(_)) => continue,
+                    // Can't happen while `ctx` holds a sender.
+                    Err(RecvError::Closed) => break,
+                },
+                _ = ticker.tick() => self.tick_all(&ctx, Instant::now()),
+            }
+        }
+    }
+}
+
+impl Default for PluginRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,94 @@
+//! Logical names for ports, since ALSA's change across boots and replugs:
+//! the client numbers in them move, and two of the same device only differ
+//! by those.  The config's `port_aliases` say how to find each port, ex:
+//! ```json
+//! "port_aliases": {
+//!   "jupX": { "contains": "JUPITER-X MIDI 1" },
+//!   "pedals": { "regex": "^FCB1010" },
+//!   "left fire": { "usb_path": "3-2" }
+//! },
+//! "synth_port": "jupX",
+//! "routes": [{ "from": "pedals", "to": "jupX" }]
+//! ```
+//! and anywhere else in the config that names a port can use the alias
+//! instead.  Aliases are swapped for the port names they match when the
+//! config's loaded.
+
+use midir::{MidiInput, MidiOutput};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fs;
+
+/// How to pick out an alias's port.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PortMatch {
+    /// The port name contains this.
+    Contains(String),
+    /// The port name matches this regex.
+    Regex(String),
+    /// The device is plugged in here, ex: "3-2" for port 2 of bus 3, as in
+    /// `/sys/bus/usb/devices`.  Only on Linux with ALSA.
+    UsbPath(String),
+}
+
+/// The `port_aliases` section of the config: logical names and how to find
+/// their ports.
+pub type PortAliases = BTreeMap<String, PortMatch>;
+
+/// Every MIDI port's name, inputs and then outputs that aren't also inputs.
+pub fn port_names() -> Vec<String> {
+    let mut names = vec![];
+    if let Ok(midi_in) = MidiInput::new("mapatron-ports") {
+        names.extend(midi_in.ports().iter().filter_map(|p| midi_in.port_name(p).ok()));
+    }
+    if let Ok(midi_out) = MidiOutput::new("mapatron-ports") {
+        for name in midi_out.ports().iter().filter_map(|p| midi_out.port_name(p).ok()) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    names
+}
+
+/// The USB path of the device behind an ALSA port name, which ends in
+/// "client:port".  Card clients are numbered from 16, four to a card.
+fn usb_path(port_name: &str) -> Option<String> {
+    let (client, _) = port_name.rsplit(' ').next()?.split_once(':')?;
+    let card = client.parse::<u32>().ok()?.checked_sub(16)? / 4;
+    let device = fs::canonicalize(format!("/sys/class/sound/card{}/device", card)).ok()?;
+    // ex: ".../usb3/3-2/3-2:1.0", the interface of device "3-2".
+    let interface = device.file_name()?.to_str()?;
+    Some(interface.split(':').next()?.to_string())
+}
+
+impl PortMatch {
+    pub fn matches(&self, port_name: &str) -> Result<bool, String> {
+        Ok(match self {
+            PortMatch::Contains(text) => port_name.contains(text.as_str()),
+            PortMatch::Regex(pattern) => {
+                let regex = Regex::new(pattern).map_err(|e| format!("bad port regex '{}': {}", pattern, e))?;
+                regex.is_match(port_name)
+            },
+            PortMatch::UsbPath(path) => usb_path(port_name).as_deref() == Some(path.as_str()),
+        })
+    }
+}
+
+/// `name`, or the first of `ports` it stands for if it's one of `aliases`.
+/// Errors if it's an alias that matches none of them.
+pub fn resolve(aliases: &PortAliases, name: &str, ports: &[String]) -> Result<String, String> {
+    let matcher = match aliases.get(name) {
+        Some(matcher) => matcher,
+        None => return Ok(name.to_string()),
+    };
+    for port in ports {
+        if matcher.matches(port)? {
+            return Ok(port.clone());
+        }
+    }
+    Err(format!("no port for '{}'", name))
+}
@@ -0,0 +1,151 @@
+//! Program changes in both directions: incoming ones (ex: from a DAW or foot
+//! controller) recall snapshots, and recalls can send the synth bank select
+//! and a program change before the snapshot's sysex, ex:
+//! ```json
+//! "program_changes": {
+//!   "port": "FCB1010",
+//!   "recalls": {
+//!     "0": { "snapshot": "songs/intro.syx", "program": { "bank_msb": 85, "program": 3 } },
+//!     "1": { "snapshot": "songs/verse.syx" }
+//!   }
+//! }
+//! ```
+
+use log::{info, warn};
+use midir::{Ignore, MidiInput, MidiInputConnection};
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::Arc;
+
+use crate::isolate::guarded;
+use crate::param_store::ParamStore;
+use crate::remote::{load_snapshot_command, CommandSender, RemoteCommand};
+use crate::snapshot::FingerprintPolicy;
+use crate::sysex_map::SysexMap;
+
+const BANK_SELECT_MSB: u8 = 0x00;
+const BANK_SELECT_LSB: u8 = 0x20;
+
+/// Bank select (if given) and program change to send.  Channels are 1-16.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProgramChange {
+    #[serde(default = "default_channel")]
+    pub channel: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bank_msb: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bank_lsb: Option<u8>,
+    pub program: u8,
+}
+
+fn default_channel() -> u8 {
+    1
+}
+
+impl ProgramChange {
+    /// The messages to send, bank select first.
+    pub fn messages(&self) -> Vec<Vec<u8>> {
+        let channel = self.channel.clamp(1, 16) - 1;
+        let mut msgs = vec![];
+        if let Some(msb) = self.bank_msb {
+            msgs.push(vec![0xb0 | channel, BANK_SELECT_MSB, msb & 0x7f]);
+        }
+        if let Some(lsb) = self.bank_lsb {
+            msgs.push(vec![0xb0 | channel, BANK_SELECT_LSB, lsb & 0x7f]);
+        }
+        msgs.push(vec![0xc0 | channel, self.program & 0x7f]);
+        msgs
+    }
+}
+
+/// What to do for one incoming program.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recall {
+    /// A .syx snapshot to load.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+    /// Sent to the synth before the snapshot.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub program: Option<ProgramChange>,
+}
+
+impl Recall {
+    /// The commands for the daemon loop to run, in order.
+    pub fn commands(&self, map: &SysexMap, store: &ParamStore, policy: FingerprintPolicy)
+                    -> Result<Vec<RemoteCommand>, String> {
+        let mut commands = vec![];
+        if let Some(program) = self.program {
+            commands.push(RemoteCommand::SendProgram(program));
+        }
+        if let Some(snapshot) = &self.snapshot {
+            commands.push(load_snapshot_command(map, store, snapshot, policy)?);
+        }
+        Ok(commands)
+    }
+}
+
+/// The `program_changes` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ProgramChangeConfig {
+    /// Listen on the first input port whose name starts with this.
+    pub port: String,
+    /// Only listen on this channel (1-16), or every channel if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<u8>,
+    /// By incoming program number.
+    pub recalls: HashMap<u8, Recall>,
+}
+
+/// The (channel 1-16, program) of a program change message.
+fn parse_program_change(msg: &[u8]) -> Option<(u8, u8)> {
+    match msg {
+        [status, program] if status & 0xf0 == 0xc0 => Some(((status & 0x0f) + 1, *program)),
+        _ => None,
+    }
+}
+
+/// Start recalling snapshots on incoming program changes, until the returned
+/// connection is dropped.  Snapshots are read at recall time so that
+/// re-saving one takes effect without a restart.
+pub fn listen(config: &ProgramChangeConfig, map: SysexMap, store: Arc<ParamStore>,
+              commands: CommandSender, policy: FingerprintPolicy)
+              -> Result<MidiInputConnection<()>, Box<dyn Error>> {
+    let mut midi_in = MidiInput::new("mapatron-program")?;
+    midi_in.ignore(Ignore::All);
+    let port = midi_in.ports().into_iter()
+        .find(|p| midi_in.port_name(p).map(|name| name.starts_with(&config.port)).unwrap_or(false))
+        .ok_or_else(|| format!("no input port matching '{}'", config.port))?;
+    info!("recalling snapshots on program changes from {}", config.port);
+
+    let channel = config.channel;
+    let recalls = config.recalls.clone();
+    let mut commands = commands;
+    let port_name = config.port.clone();
+    let conn = midi_in.connect(&port, "mapatron-program-in", move |_stamp, msg, _| {
+        guarded(&port_name, || {
+            let (ch, program) = match parse_program_change(msg) {
+                Some(pc) => pc,
+                None => return,
+            };
+            if channel.is_some() && channel != Some(ch) {
+                return;
+            }
+            let recall = match recalls.get(&program) {
+                Some(recall) => recall,
+                None => return,
+            };
+            let result = recall.commands(&map, &store, policy).and_then(|recall_commands| {
+                for command in recall_commands {
+                    commands.try_send(command).map_err(|e| e.to_string())?;
+                }
+                Ok(())
+            });
+            if let Err(e) = result {
+                warn!("program {} recall failed: {}", program, e);
+            }
+        });
+    }, ()).map_err(|e| format!("can't open input '{}': {}", config.port, e))?;
+    Ok(conn)
+}
@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::codec::encode_param_dt1;
+use crate::sysex_map::{ParamIndex, SysexMap};
+
+/// Snapshot writes sent per `scheduler::TICK`, so a recall is spread out
+/// rather than dumped on the port all at once.
+pub const WRITES_PER_TICK: usize = 4;
+
+/// How long param changes stay held back after a recall's last write, for
+/// the synth's echoes of it to come in.
+pub const FEEDBACK_WINDOW: Duration = Duration::from_millis(250);
+
+/// What happens to a recall's or dump's messages that are already queued
+/// when another one cuts it short, ex: the user picked a different snapshot
+/// halfway through.  Only what actually went out ends up in the store
+/// either way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelPolicy {
+    /// Send them, ahead of the new one's.
+    Flush,
+    #[default]
+    Drop,
+}
+
+/// How much of a snapshot a recall writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum RecallDiff {
+    /// Every param in it.
+    #[default]
+    Full,
+    /// Only the params the store says are different.
+    Store,
+    /// Read the parts of the synth the snapshot covers first, then write
+    /// only what's really different, in case it was edited from its panel
+    /// while the mapper wasn't listening.
+    Live,
+}
+
+/// What a long transfer is doing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Task {
+    /// Writing a snapshot to the synth.
+    Recall,
+    /// Reading a patch from the synth.
+    Dump,
+}
+
+impl Task {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Task::Recall => "Recall",
+            Task::Dump => "Dump",
+        }
+    }
+}
+
+/// How far along a transfer is, published on the bus as
+/// `EngineEvent::Progress` as it goes.  The last one for a transfer has
+/// everything done.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    pub task: Task,
+    pub messages_done: usize,
+    pub messages_total: usize,
+    pub bytes_done: usize,
+    pub bytes_total: usize,
+}
+
+impl Progress {
+    pub fn new(task: Task, messages_total: usize, bytes_total: usize) -> Self {
+        Progress {
+            task,
+            messages_done: 0,
+            messages_total,
+            bytes_done: 0,
+            bytes_total,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.messages_done >= self.messages_total
+    }
+
+    /// From 0 to 1, by bytes.
+    pub fn fraction(&self) -> f32 {
+        if self.bytes_total == 0 {
+            return 1.0;
+        }
+        (self.bytes_done as f32 / self.bytes_total as f32).min(1.0)
+    }
+}
+
+/// A snapshot recall being written out a few params at a time.
+pub struct Transfer {
+    writes: VecDeque<(usize, u32, usize)>,
+    progress: Progress,
+}
+
+impl Transfer {
+    pub fn new(map: &SysexMap, index: &ParamIndex, writes: &[(usize, u32)]) -> Self {
+        let writes: VecDeque<(usize, u32, usize)> = writes.iter()
+            .map(|(param, value)| {
+                (*param, *value, encode_param_dt1(map, &index.params[*param], *value).len())
+            })
+            .collect();
+        let bytes = writes.iter().map(|(_, _, len)| len).sum();
+        let progress = Progress::new(Task::Recall, writes.len(), bytes);
+        Transfer {
+            writes,
+            progress,
+        }
+    }
+
+    /// The next (param, value) to write, if any, counting it as sent.
+    pub fn next_write(&mut self) -> Option<(usize, u32)> {
+        let (param, value, len) = self.writes.pop_front()?;
+        self.progress.messages_done += 1;
+        self.progress.bytes_done += len;
+        Some((param, value))
+    }
+
+    pub fn progress(&self) -> Progress {
+        self.progress
+    }
+}
+
+/// A patch being read a dump span at a time.  The replies land in the store
+/// as they come in, like any other DT1.
+pub struct Dump {
+    spans: VecDeque<(u32, u32)>,
+    progress: Progress,
+}
+
+impl Dump {
+    pub fn new(map: &SysexMap, index: &ParamIndex) -> Self {
+        Self::of_spans(map.dump_spans(index).into_iter().collect())
+    }
+
+    /// Just the dump spans holding any of `params`, ex: the ones a recall's
+    /// about to write.
+    pub fn covering(map: &SysexMap, index: &ParamIndex, params: &[usize]) -> Self {
+        let spans = map.dump_spans(index).into_iter()
+            .filter(|(start, size)| params.iter().any(|&param| {
+                let p = &index.params[param];
+                p.address < start + size && *start < p.address + p.size
+            }))
+            .collect();
+        Self::of_spans(spans)
+    }
+
+    fn of_spans(spans: VecDeque<(u32, u32)>) -> Self {
+        let bytes = spans.iter().map(|(_, size)| *size as usize).sum();
+        let progress = Progress::new(Task::Dump, spans.len(), bytes);
+        Dump {
+            spans,
+            progress,
+        }
+    }
+
+    /// The next (address, size) to request, if any, counting it as done.
+    pub fn next_request(&mut self) -> Option<(u32, u32)> {
+        let (address, size) = self.spans.pop_front()?;
+        self.progress.messages_done += 1;
+        self.progress.bytes_done += size as usize;
+        Some((address, size))
+    }
+
+    pub fn progress(&self) -> Progress {
+        self.progress
+    }
+}
@@ -0,0 +1,202 @@
+//! Python bindings, built with the "python" feature into a `mapatron`
+//! extension module (ex: `maturin develop` next to `pyproject.toml`), for
+//! batch-processing .syx libraries and driving synths from scripts and
+//! notebooks with the same codecs as the mapper:
+//! ```python
+//! import mapatron
+//! jupx = mapatron.Map.load_device("jupx")
+//! patch = mapatron.Patch(jupx)
+//! patch.load(open("pad.syx", "rb").read())
+//! patch.set_human("Temporary Scene/Scene Common/Scene Level", "100")
+//! open("pad-louder.syx", "wb").write(b"".join(patch.to_sysex()))
+//! ```
+//! Messages are `bytes`, so they can go straight out through `mido` or
+//! `rtmidi` to a real synth, or to a `SimulatedSynth`.
+
+use pyo3::exceptions::{PyIOError, PyKeyError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use std::collections::BTreeMap;
+
+use crate::bus::EventBus;
+use crate::codec::{decode_dump, encode_param_dt1, encode_rq1, encode_spans};
+use crate::controllers::sysex_mapped::VirtualDevice;
+use crate::human::{format_value, parse_value};
+use crate::param_store::ParamStore;
+use crate::simulate;
+use crate::sysex_map::{ParamIndex, SysexMap};
+
+fn to_bytes(py: Python, messages: Vec<Vec<u8>>) -> Vec<Py<PyBytes>> {
+    messages.iter().map(|msg| PyBytes::new(py, msg).into()).collect()
+}
+
+/// A device's sysex map with its params resolved.
+#[pyclass]
+pub struct Map {
+    map: SysexMap,
+    index: ParamIndex,
+}
+
+impl Map {
+    fn new(map: SysexMap) -> Self {
+        let index = map.resolve();
+        Map { map, index }
+    }
+
+    fn param(&self, name: &str) -> PyResult<usize> {
+        self.index.index_of(name).ok_or_else(|| PyKeyError::new_err(format!("no param '{}'", name)))
+    }
+}
+
+#[pymethods]
+impl Map {
+    /// Load the map at `path`.
+    #[staticmethod]
+    fn load(path: &str) -> PyResult<Map> {
+        SysexMap::load(path).map(Map::new).map_err(|e| PyIOError::new_err(e.to_string()))
+    }
+
+    /// Load the map for a device, ex: "jupx".
+    #[staticmethod]
+    fn load_device(device: &str) -> PyResult<Map> {
+        SysexMap::load_device(device).map(Map::new).map_err(|e| PyIOError::new_err(e.to_string()))
+    }
+
+    /// Names of the params in address order, or just those matching a glob
+    /// like `"Temporary Scene/**/Part Level"`.
+    #[pyo3(signature = (pattern = None))]
+    fn params(&self, pattern: Option<&str>) -> Vec<String> {
+        match pattern {
+            Some(pattern) => self.index.params_matching(pattern).into_iter()
+                .map(|idx| self.index.params[idx].name.clone())
+                .collect(),
+            None => self.index.params.iter().map(|p| p.name.clone()).collect(),
+        }
+    }
+
+    /// Problems with the map, if any.
+    fn validate(&self) -> Vec<String> {
+        self.map.validate()
+    }
+
+    /// Every param in a dump (ex: the contents of a .syx file) by name.
+    fn decode_dump(&self, bytes: &[u8]) -> BTreeMap<String, u32> {
+        decode_dump(&self.map, &self.index, bytes)
+    }
+
+    /// The DT1 message that sets a param to a raw value.
+    fn encode_param(&self, py: Python, name: &str, value: u32) -> PyResult<Py<PyBytes>> {
+        let param = &self.index.params[self.param(name)?];
+        Ok(PyBytes::new(py, &encode_param_dt1(&self.map, param, value)).into())
+    }
+
+    /// The RQ1 messages that read a whole patch.
+    fn dump_requests(&self, py: Python) -> Vec<Py<PyBytes>> {
+        let requests = self.map.dump_spans(&self.index).into_iter()
+            .map(|(address, size)| encode_rq1(&self.map, address, size))
+            .collect();
+        to_bytes(py, requests)
+    }
+}
+
+/// Every param's value for one map, with human values worked out the way
+/// the display does.
+#[pyclass]
+pub struct Patch {
+    map: SysexMap,
+    store: ParamStore,
+}
+
+impl Patch {
+    fn param(&self, name: &str) -> PyResult<usize> {
+        self.store.index().index_of(name).ok_or_else(|| PyKeyError::new_err(format!("no param '{}'", name)))
+    }
+}
+
+#[pymethods]
+impl Patch {
+    /// A patch with every param at the bottom of its range.
+    #[new]
+    fn new(map: PyRef<Map>) -> Self {
+        Patch {
+            map: map.map.clone(),
+            store: ParamStore::new(map.map.resolve(), EventBus::new()),
+        }
+    }
+
+    /// A param's raw value.
+    fn get(&self, name: &str) -> PyResult<u32> {
+        Ok(self.store.get(self.param(name)?))
+    }
+
+    fn set(&self, name: &str, value: u32) -> PyResult<()> {
+        self.store.set(self.param(name)?, value);
+        Ok(())
+    }
+
+    /// A param's human value, ex: "ON" or "-12".
+    fn human(&self, name: &str) -> PyResult<String> {
+        let param = self.param(name)?;
+        Ok(format_value(&self.store.display_entry(param), self.store.get(param)))
+    }
+
+    fn set_human(&self, name: &str, value: &str) -> PyResult<()> {
+        let param = self.param(name)?;
+        let raw = parse_value(&self.store.display_entry(param), value)
+            .ok_or_else(|| PyValueError::new_err(format!("'{}' isn't a valid value for '{}'", value, name)))?;
+        self.store.set(param, raw);
+        Ok(())
+    }
+
+    /// Take the values of the params in a dump.  Returns how many it set.
+    fn load(&self, bytes: &[u8]) -> usize {
+        let values = decode_dump(&self.map, self.store.index(), bytes);
+        for (name, value) in &values {
+            if let Some(param) = self.store.index().index_of(name) {
+                self.store.set(param, *value);
+            }
+        }
+        values.len()
+    }
+
+    /// Every param's raw value by name.
+    fn to_dict(&self) -> BTreeMap<String, u32> {
+        self.store.index().params.iter().enumerate()
+            .map(|(idx, p)| (p.name.clone(), self.store.get(idx)))
+            .collect()
+    }
+
+    /// The DT1 messages that send the whole patch, as saved in a .syx.
+    fn to_sysex(&self, py: Python) -> Vec<Py<PyBytes>> {
+        let index = self.store.index();
+        let spans = self.map.dump_spans(index);
+        to_bytes(py, encode_spans(&self.map, index, &spans, |p| self.store.get(p)))
+    }
+}
+
+/// A synth that only exists in memory, answering reads and taking writes as
+/// the map says the real one would.
+#[pyclass]
+pub struct SimulatedSynth(simulate::SimulatedSynth);
+
+#[pymethods]
+impl SimulatedSynth {
+    #[new]
+    fn new(map: PyRef<Map>) -> Self {
+        SimulatedSynth(simulate::SimulatedSynth::new(map.map.clone()))
+    }
+
+    /// Send the synth a message, returning its replies.
+    fn send(&mut self, py: Python, msg: &[u8]) -> Vec<Py<PyBytes>> {
+        to_bytes(py, self.0.receive(msg))
+    }
+}
+
+#[pymodule]
+fn mapatron(_py: Python, module: &PyModule) -> PyResult<()> {
+    module.add_class::<Map>()?;
+    module.add_class::<Patch>()?;
+    module.add_class::<SimulatedSynth>()?;
+    Ok(())
+}
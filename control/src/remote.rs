@@ -0,0 +1,56 @@
+//! Plumbing shared by the remote control front ends (D-Bus, etc.).  They
+//! answer queries straight from the `ParamStore`, but anything that has to go
+//! out to the synth is sent to the daemon loop, which owns the connection.
+
+use tokio::sync::mpsc;
+
+use crate::codec::decode_dump;
+use crate::human::parse_value;
+use crate::param_store::ParamStore;
+use crate::program::ProgramChange;
+use crate::snapshot::{check_fingerprint, read_snapshot, FingerprintPolicy};
+use crate::sysex_map::SysexMap;
+
+/// How many commands can queue up before remotes are told to back off.
+pub const COMMAND_BUFFER: usize = 64;
+
+#[derive(Clone, Debug)]
+pub enum RemoteCommand {
+    /// Write a raw value to a param, by index.
+    SetParam { param: usize, value: u32 },
+    /// Write a whole set of (param, raw value) pairs, ex: a saved patch.
+    LoadSnapshot(Vec<(usize, u32)>),
+    /// Send bank select and program change.
+    SendProgram(ProgramChange),
+}
+
+pub type CommandSender = mpsc::Sender<RemoteCommand>;
+pub type CommandReceiver = mpsc::Receiver<RemoteCommand>;
+
+pub fn command_channel() -> (CommandSender, CommandReceiver) {
+    mpsc::channel(COMMAND_BUFFER)
+}
+
+/// Build the command to set a param from its name and human value.
+pub fn set_param_command(store: &ParamStore, name: &str, value: &str)
+                         -> Result<RemoteCommand, String> {
+    let param = store.index().index_of(name).ok_or_else(|| format!("no param '{}'", name))?;
+    let entry = store.display_entry(param);
+    let value = parse_value(&entry, value)
+        .ok_or_else(|| format!("'{}' isn't a valid value for '{}'", value, name))?;
+    Ok(RemoteCommand::SetParam { param, value })
+}
+
+/// Build the command to load a .syx snapshot, ex: one saved by
+/// `mapatron dump --syx`, checking it was saved with this version of the map
+/// as `policy` says.
+pub fn load_snapshot_command(map: &SysexMap, store: &ParamStore, path: &str, policy: FingerprintPolicy)
+                             -> Result<RemoteCommand, String> {
+    let bytes = read_snapshot(path)?;
+    let index = store.index();
+    check_fingerprint(map, index, path, &bytes, policy)?;
+    let values = decode_dump(map, index, &bytes).into_iter()
+        .filter_map(|(name, value)| index.index_of(&name).map(|param| (param, value)))
+        .collect();
+    Ok(RemoteCommand::LoadSnapshot(values))
+}
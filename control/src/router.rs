@@ -0,0 +1,427 @@
+//! Patchbay-style routing between arbitrary MIDI ports, ex:
+//! ```json
+//! "routes": [
+//!   { "from": "KeyStep", "to": "JUPITER-X",
+//!     "filter": { "drop_clock": true, "block_sysex": true },
+//!     "transforms": [ { "remap_channel": { "from": 1, "to": 4 } } ] }
+//! ]
+//! ```
+//! Messages are handled entirely on midir's callback threads, so routes don't
+//! wait on anything the daemon is doing.
+//!
+//! A route can also be a keyboard zone, ex: to split a master keyboard across
+//! two synths, `"zone": { "name": "lower", "high": 59, "transpose": 12, "channel": 2 }`.
+//! Zones can be moved around live from the Fire; see `ZoneBinding`.
+//!
+//! Ports can also be tapped, for the daemon to talk to something itself, ex:
+//! a DAW for the `launcher`, or whatever sends the `clock`.  What comes in on
+//! them is handed over on a channel as well as going wherever it's routed.
+
+use log::{info, warn};
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicI8, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+use crate::filter::is_channel_message;
+use crate::isolate::guarded;
+
+pub use crate::filter::RouteFilter;
+
+/// How far zones can be transposed, in semitones.
+const MAX_TRANSPOSE: i32 = 48;
+
+/// A change made to messages passing through a route.  Channels are 1-16.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transform {
+    RemapChannel { from: u8, to: u8 },
+    /// Renumber a control change.
+    MapCc { from: u8, to: u8 },
+    /// Scale note-on velocities, clamped to 1-127 so notes stay notes.
+    ScaleVelocity { percent: u16 },
+}
+
+/// A key range on a route.  Notes outside it are dropped; notes in it are
+/// transposed, and channel messages are moved to `channel` if given.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Zone {
+    /// For binding controls to the zone.  Routes with the same zone name
+    /// share its settings.
+    pub name: String,
+    #[serde(default)]
+    pub low: u8,
+    #[serde(default = "default_zone_high")]
+    pub high: u8,
+    #[serde(default)]
+    pub transpose: i8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<u8>,
+}
+
+fn default_zone_high() -> u8 {
+    127
+}
+
+/// What a control bound to a zone changes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ZoneAdjust {
+    Transpose,
+    /// The bottom key of the range.
+    Low,
+    /// The top key of the range.
+    High,
+}
+
+/// A zone's live settings, shared between the route's callback thread and
+/// whatever adjusts it.
+pub struct ZoneState {
+    low: AtomicU8,
+    high: AtomicU8,
+    transpose: AtomicI8,
+}
+
+impl ZoneState {
+    fn new(zone: &Zone) -> Self {
+        ZoneState {
+            low: AtomicU8::new(zone.low.min(0x7f)),
+            high: AtomicU8::new(zone.high.min(0x7f)),
+            transpose: AtomicI8::new(zone.transpose),
+        }
+    }
+
+    /// Move one of the settings by `delta`, keeping the range from crossing
+    /// over itself.
+    pub fn adjust(&self, what: ZoneAdjust, delta: i32) {
+        let low = self.low.load(Ordering::Relaxed) as i32;
+        let high = self.high.load(Ordering::Relaxed) as i32;
+        match what {
+            ZoneAdjust::Transpose => {
+                let transpose = self.transpose.load(Ordering::Relaxed) as i32 + delta;
+                self.transpose.store(transpose.clamp(-MAX_TRANSPOSE, MAX_TRANSPOSE) as i8,
+                                     Ordering::Relaxed);
+            },
+            ZoneAdjust::Low => self.low.store((low + delta).clamp(0, high) as u8, Ordering::Relaxed),
+            ZoneAdjust::High => self.high.store((high + delta).clamp(low, 0x7f) as u8, Ordering::Relaxed),
+        }
+    }
+
+    /// The key to send for `key`, if it's in the zone.
+    fn map_key(&self, key: u8) -> Option<u8> {
+        if key < self.low.load(Ordering::Relaxed) || key > self.high.load(Ordering::Relaxed) {
+            return None;
+        }
+        let key = key as i32 + self.transpose.load(Ordering::Relaxed) as i32;
+        if (0..=0x7f).contains(&key) { Some(key as u8) } else { None }
+    }
+}
+
+/// Zone states by name.
+pub type Zones = HashMap<String, Arc<ZoneState>>;
+
+/// The keys sent for keys held down through a zone, so that note-offs
+/// (and aftertouch) follow their note-on even if the zone changed since.
+struct Sounding([Option<u8>; 128]);
+
+/// A connection from the first input port whose name starts with `from` to
+/// the first output port whose name starts with `to`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Route {
+    pub from: String,
+    pub to: String,
+    #[serde(default)]
+    pub filter: RouteFilter,
+    /// Applied in order, after the zone.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transforms: Vec<Transform>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<Zone>,
+}
+
+impl Transform {
+    fn apply(&self, msg: &mut [u8]) {
+        let status = msg[0];
+        if !is_channel_message(status) {
+            return;
+        }
+        let channel = (status & 0x0f) + 1;
+        match *self {
+            Transform::RemapChannel { from, to } if channel == from => {
+                msg[0] = (status & 0xf0) | (to.clamp(1, 16) - 1);
+            },
+            Transform::MapCc { from, to } if status & 0xf0 == 0xb0 && msg.len() == 3 &&
+                                             msg[1] == from => {
+                msg[1] = to & 0x7f;
+            },
+            Transform::ScaleVelocity { percent } if status & 0xf0 == 0x90 && msg.len() == 3 &&
+                                                    msg[2] > 0 => {
+                let scaled = msg[2] as u32 * percent as u32 / 100;
+                msg[2] = scaled.clamp(1, 0x7f) as u8;
+            },
+            _ => (),
+        }
+    }
+}
+
+/// Put the zone's version of `msg` in `out`.  Returns false if it's outside
+/// the zone.
+fn apply_zone(zone: &Zone, state: &ZoneState, sounding: &mut Sounding, msg: &[u8],
+              out: &mut Vec<u8>) -> bool {
+    let status = msg[0];
+    out.clear();
+    out.extend_from_slice(msg);
+    if !is_channel_message(status) {
+        return true;
+    }
+    if let Some(channel) = zone.channel {
+        out[0] = (status & 0xf0) | (channel.clamp(1, 16) - 1);
+    }
+    if msg.len() != 3 {
+        return true;
+    }
+    let key = (msg[1] & 0x7f) as usize;
+    let sent = match status & 0xf0 {
+        0x90 if msg[2] > 0 => {
+            sounding.0[key] = state.map_key(msg[1]);
+            sounding.0[key]
+        },
+        0x80 | 0x90 => sounding.0[key].take(),
+        0xa0 => sounding.0[key],
+        _ => return true,
+    };
+    match sent {
+        Some(sent) => {
+            out[1] = sent;
+            true
+        },
+        None => false,
+    }
+}
+
+/// A route's handling of the messages on its input, with the keys it has
+/// sounding.
+pub struct RouteProcessor {
+    route: Route,
+    zone: Option<(Arc<ZoneState>, Sounding)>,
+}
+
+impl RouteProcessor {
+    /// `zones` has the live settings of each zone, by name; a route's is
+    /// added from its `Zone` if it's the first with that name.
+    pub fn new(route: &Route, zones: &mut Zones) -> Self {
+        let zone = route.zone.as_ref().map(|zone| {
+            let state = zones.entry(zone.name.clone())
+                .or_insert_with(|| Arc::new(ZoneState::new(zone)));
+            (state.clone(), Sounding([None; 128]))
+        });
+        RouteProcessor {
+            route: route.clone(),
+            zone,
+        }
+    }
+
+    pub fn route(&self) -> &Route {
+        &self.route
+    }
+
+    /// Put what the route sends for `msg` in `out`.  Returns false if it's
+    /// filtered out.
+    pub fn process(&mut self, msg: &[u8], out: &mut Vec<u8>) -> bool {
+        if !self.route.filter.passes(msg) {
+            return false;
+        }
+        match (&self.route.zone, &mut self.zone) {
+            (Some(zone), Some((state, sounding))) => {
+                if !apply_zone(zone, state, sounding, msg, out) {
+                    return false;
+                }
+            },
+            _ => {
+                out.clear();
+                out.extend_from_slice(msg);
+            },
+        }
+        for transform in &self.route.transforms {
+            transform.apply(out);
+        }
+        true
+    }
+}
+
+type SharedOutput = Arc<Mutex<MidiOutputConnection>>;
+
+/// A route as its input callback runs it.
+struct RunningRoute {
+    processor: RouteProcessor,
+    output: SharedOutput,
+}
+
+/// A message in on a tapped port.
+#[derive(Clone, Debug)]
+pub struct TappedMessage {
+    /// The name the port was tapped by.
+    pub tap: String,
+    /// When it arrived, for following a clock.
+    pub at: Instant,
+    pub msg: Vec<u8>,
+}
+
+/// What comes in on the tapped ports.
+pub type Tapped = mpsc::UnboundedReceiver<TappedMessage>;
+
+/// Running routes.  Dropping it disconnects them.
+pub struct Router {
+    _inputs: Vec<MidiInputConnection<()>>,
+    outputs: Vec<SharedOutput>,
+    /// The tapped ports' outputs, by the names they were tapped by.  Ones
+    /// that only send, ex: a drum machine's clock, have none.
+    taps: Vec<(String, Option<SharedOutput>)>,
+    tapped: Option<Tapped>,
+    zones: Zones,
+}
+
+fn open_output(prefix: &str) -> Result<MidiOutputConnection, Box<dyn Error>> {
+    let midi_out = MidiOutput::new("mapatron-router")?;
+    let port = midi_out.ports().into_iter()
+        .find(|p| midi_out.port_name(p).map(|name| name.starts_with(prefix)).unwrap_or(false))
+        .ok_or_else(|| format!("no output port matching '{}'", prefix))?;
+    let conn = midi_out.connect(&port, "mapatron-route-out")
+        .map_err(|e| format!("can't open output '{}': {}", prefix, e))?;
+    Ok(conn)
+}
+
+impl Router {
+    /// Open every port the routes and `taps` use, once each, and start
+    /// passing messages.  A tapped port with no output can still be listened
+    /// to.
+    pub fn start(routes: &[Route], taps: &[String]) -> Result<Router, Box<dyn Error>> {
+        let mut outputs: Vec<(&str, SharedOutput)> = vec![];
+        let mut by_input: Vec<(&str, Vec<RunningRoute>)> = vec![];
+        let mut zones = Zones::new();
+        for route in routes {
+            let output = match outputs.iter().find(|(to, _)| *to == route.to) {
+                Some((_, output)) => output.clone(),
+                None => {
+                    let output = Arc::new(Mutex::new(open_output(&route.to)?));
+                    outputs.push((&route.to, output.clone()));
+                    output
+                },
+            };
+            let running = RunningRoute {
+                processor: RouteProcessor::new(route, &mut zones),
+                output,
+            };
+            match by_input.iter_mut().find(|(from, _)| *from == route.from) {
+                Some((_, input_routes)) => input_routes.push(running),
+                None => by_input.push((&route.from, vec![running])),
+            }
+        }
+        let mut tap_outputs = vec![];
+        for tap in taps {
+            if tap_outputs.iter().any(|(name, _)| name == tap) {
+                continue;
+            }
+            let output = match outputs.iter().find(|(to, _)| *to == tap.as_str()) {
+                Some((_, output)) => Some(output.clone()),
+                None => match open_output(tap) {
+                    Ok(output) => Some(Arc::new(Mutex::new(output))),
+                    Err(e) => {
+                        info!("only listening to {}: {}", tap, e);
+                        None
+                    },
+                },
+            };
+            tap_outputs.push((tap.clone(), output));
+            if !by_input.iter().any(|(from, _)| *from == tap.as_str()) {
+                by_input.push((tap, vec![]));
+            }
+        }
+
+        let (tap_tx, tapped) = mpsc::unbounded_channel();
+        let mut inputs = vec![];
+        for (from, mut input_routes) in by_input {
+            let mut midi_in = MidiInput::new("mapatron-router")?;
+            // Whether clock and sysex get through is up to the filters.
+            midi_in.ignore(Ignore::None);
+            let port = midi_in.ports().into_iter()
+                .find(|p| midi_in.port_name(p).map(|name| name.starts_with(from)).unwrap_or(false))
+                .ok_or_else(|| format!("no input port matching '{}'", from))?;
+            if !input_routes.is_empty() {
+                let to: Vec<&str> = input_routes.iter().map(|r| r.processor.route().to.as_str()).collect();
+                info!("routing {} to {}", from, to.join(", "));
+            }
+            let tap = taps.iter().any(|tap| tap == from).then(|| tap_tx.clone());
+            let mut out = vec![];
+            let port_name = from.to_string();
+            let conn = midi_in.connect(&port, "mapatron-route-in", move |_stamp, msg, _| {
+                guarded(&port_name, || {
+                    if let Some(tap) = &tap {
+                        // Only fails once the daemon's gone.
+                        let _ = tap.send(TappedMessage {
+                            tap: port_name.clone(),
+                            at: Instant::now(),
+                            msg: msg.to_vec(),
+                        });
+                    }
+                    for running in input_routes.iter_mut() {
+                        if running.processor.process(msg, &mut out) {
+                            let mut output = running.output.lock().unwrap_or_else(PoisonError::into_inner);
+                            if let Err(e) = output.send(&out) {
+                                let route = running.processor.route();
+                                warn!("route {} -> {}: {}", route.from, route.to, e);
+                            }
+                        }
+                    }
+                });
+            }, ()).map_err(|e| format!("can't open input '{}': {}", from, e))?;
+            inputs.push(conn);
+        }
+
+        Ok(Router {
+            _inputs: inputs,
+            outputs: outputs.into_iter().map(|(_, output)| output).collect(),
+            taps: tap_outputs,
+            tapped: Some(tapped),
+            zones,
+        })
+    }
+
+    /// Send a message to a tapped port, by the name it was tapped by.
+    pub fn send_to(&self, tap: &str, msg: &[u8]) {
+        match self.taps.iter().find(|(name, _)| name == tap) {
+            Some((_, Some(output))) => {
+                if let Err(e) = output.lock().unwrap_or_else(PoisonError::into_inner).send(msg) {
+                    warn!("tap {}: {}", tap, e);
+                }
+            },
+            Some((_, None)) => warn!("tap {}: no output port to send to", tap),
+            None => (),
+        }
+    }
+
+    /// What comes in on the tapped ports, the first time it's asked for.
+    pub fn take_tapped(&mut self) -> Option<Tapped> {
+        self.tapped.take()
+    }
+
+    /// Send a message to every output a route goes to.
+    pub fn send_all(&self, msg: &[u8]) {
+        for output in &self.outputs {
+            // A route callback that panicked mid-send left this locked, but
+            // the connection is still fine.
+            if let Err(e) = output.lock().unwrap_or_else(PoisonError::into_inner).send(msg) {
+                warn!("router output: {}", e);
+            }
+        }
+    }
+
+    pub fn zones(&self) -> &Zones {
+        &self.zones
+    }
+}
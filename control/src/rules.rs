@@ -0,0 +1,207 @@
+//! Reactive behaviors that don't need a plugin: a bindings file's `rules`
+//! say what to do when everything in `when` holds, ex:
+//! ```json
+//! { "when": [{ "param": "Program/Common/Cutoff", "op": ">", "value": 100 },
+//!            { "song": "FX" }],
+//!   "then": [{ "pad": 5, "color": [127, 0, 0] },
+//!            { "send": "F0 41 {device_id} 00 00 00 65 12 01 00 00 00 {value} {checksum} F7",
+//!              "value": 1 },
+//!            { "set": "Program/Common/Resonance", "value": 0 }] }
+//! ```
+//! Rules are checked after every engine event.  Pads stay lit for as long as
+//! the rule holds; messages and param writes go out once each time it
+//! starts to, so a rule that writes a param it's watching can't loop.
+
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::bindings::BindingsFile;
+use crate::codec::encode_param_dt1;
+use crate::command::CommandTemplate;
+use crate::controllers::ControllerCaps;
+use crate::param_store::ParamStore;
+use crate::sysex_map::SysexMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compare {
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+}
+
+impl Compare {
+    fn holds(self, lhs: u32, rhs: u32) -> bool {
+        match self {
+            Compare::Eq => lhs == rhs,
+            Compare::Ne => lhs != rhs,
+            Compare::Gt => lhs > rhs,
+            Compare::Ge => lhs >= rhs,
+            Compare::Lt => lhs < rhs,
+            Compare::Le => lhs <= rhs,
+        }
+    }
+}
+
+/// One thing that has to hold for a rule to.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Condition {
+    /// A param's raw value against `value`.
+    Param { param: String, op: Compare, value: u32 },
+    /// The setlist is at the song with this name.
+    Song {
+        #[serde(alias = "scene")]
+        song: String,
+    },
+    /// The encoders are on this page.
+    Page { page: usize },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RuleAction {
+    /// Light a grid pad over its binding's color.
+    Pad { pad: u8, color: (u8, u8, u8) },
+    /// Send a raw message, as for a command binding.
+    Send {
+        send: CommandTemplate,
+        #[serde(default)]
+        value: u32,
+    },
+    /// Write a param.
+    Set { set: String, value: u32 },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Rule {
+    pub when: Vec<Condition>,
+    pub then: Vec<RuleAction>,
+}
+
+/// What the rules can see besides the params.
+pub struct RuleContext<'a> {
+    pub song: Option<&'a str>,
+    pub page: usize,
+}
+
+enum Check {
+    Param { param: usize, op: Compare, value: u32 },
+    Song(String),
+    Page(usize),
+}
+
+#[derive(Default)]
+struct ResolvedRule {
+    when: Vec<Check>,
+    pads: Vec<(u8, (u8, u8, u8))>,
+    /// The sends' messages, rendered in advance.
+    sends: Vec<Vec<u8>>,
+    /// (param, value, DT1) of each write.
+    sets: Vec<(usize, u32, Vec<u8>)>,
+    holds: bool,
+}
+
+pub struct Rules {
+    rules: Vec<ResolvedRule>,
+}
+
+impl Rules {
+    /// Look up `file`'s rules against the map.  Unknown params and pads the
+    /// controller doesn't have are errors.
+    pub fn new(map: &SysexMap, file: &BindingsFile, store: &ParamStore, caps: &ControllerCaps)
+               -> Result<Rules, Box<dyn Error>> {
+        let index = store.index();
+        let param = |name: &str| {
+            index.index_of(name).ok_or_else(|| format!("rule for unknown param '{}'", name))
+        };
+        let mut rules = vec![];
+        for rule in &file.rules {
+            let mut resolved = ResolvedRule::default();
+            for condition in &rule.when {
+                resolved.when.push(match condition {
+                    Condition::Param { param: name, op, value } => Check::Param {
+                        param: param(name)?,
+                        op: *op,
+                        value: *value,
+                    },
+                    Condition::Song { song } => Check::Song(song.clone()),
+                    Condition::Page { page } => Check::Page(*page),
+                });
+            }
+            for action in &rule.then {
+                match action {
+                    RuleAction::Pad { pad, color } => {
+                        if *pad as usize >= caps.pads() {
+                            return Err(format!("rule lights pad {}, which there isn't", pad).into());
+                        }
+                        resolved.pads.push((*pad, *color));
+                    },
+                    RuleAction::Send { send, value } => {
+                        let msg = send.render(map, *value).map_err(|e| format!("rule send: {}", e))?;
+                        resolved.sends.push(msg);
+                    },
+                    RuleAction::Set { set, value } => {
+                        let idx = param(set)?;
+                        let p = &index.params[idx];
+                        if *value < p.entry.discrete_range_low || *value > p.entry.discrete_range_high {
+                            return Err(format!("rule sets '{}' to {}, outside its range", set, value)
+                                       .into());
+                        }
+                        resolved.sets.push((idx, *value, encode_param_dt1(map, p, *value)));
+                    },
+                }
+            }
+            rules.push(resolved);
+        }
+        Ok(Rules {
+            rules,
+        })
+    }
+
+    /// Check every rule, passing the messages of the ones that have just
+    /// started to hold to `send` and writing their params to `store`.
+    /// Returns whether the pads need redrawing.
+    pub fn evaluate<F: FnMut(&[u8])>(&mut self, store: &ParamStore, context: &RuleContext, mut send: F)
+                                     -> bool {
+        let mut redraw = false;
+        for rule in &mut self.rules {
+            let holds = rule.when.iter().all(|check| match check {
+                Check::Param { param, op, value } => op.holds(store.get(*param), *value),
+                Check::Song(song) => context.song == Some(song.as_str()),
+                Check::Page(page) => context.page == *page,
+            });
+            if holds == rule.holds {
+                continue;
+            }
+            rule.holds = holds;
+            redraw |= !rule.pads.is_empty();
+            if holds {
+                rule.sends.iter().for_each(|msg| send(msg));
+                for (param, value, msg) in &rule.sets {
+                    store.set(*param, *value);
+                    send(msg);
+                }
+            }
+        }
+        redraw
+    }
+
+    /// Draw the pads of the rules that hold through `set_led`.
+    pub fn render<F: FnMut(u8, u8, u8, u8)>(&self, mut set_led: F) {
+        for rule in self.rules.iter().filter(|rule| rule.holds) {
+            for &(pad, (r, g, b)) in &rule.pads {
+                set_led(pad, r, g, b);
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::time::{Duration, Instant};
+
+/// How often the daemon ticks the scheduler.  10ms keeps ramps smooth
+/// without flooding a 31.25kbaud DIN link.
+pub const TICK: Duration = Duration::from_millis(10);
+
+/// A param moving toward a target value over time.
+struct Ramp {
+    param: usize,
+    from: u32,
+    to: u32,
+    start: Instant,
+    duration: Duration,
+    /// The value most recently handed out.
+    last: u32,
+}
+
+impl Ramp {
+    fn value_at(&self, now: Instant) -> u32 {
+        let elapsed = now.saturating_duration_since(self.start);
+        if elapsed >= self.duration {
+            return self.to;
+        }
+        let t = elapsed.as_secs_f64() / self.duration.as_secs_f64();
+        (self.from as f64 + (self.to as f64 - self.from as f64) * t).round() as u32
+    }
+}
+
+/// A write to a param the synth saves to flash, held until the gesture
+/// making it is over.
+struct Deferred {
+    param: usize,
+    value: u32,
+}
+
+/// Spreads writes out over time.  Callers say where params should end up and
+/// when; each `tick` hands back the writes that are due.
+pub struct Scheduler {
+    ramps: Vec<Ramp>,
+    deferred: Vec<Deferred>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler {
+            ramps: vec![],
+            deferred: vec![],
+        }
+    }
+
+    /// Move `param` from `from` to `to` over `duration`.  If it's already
+    /// ramping, the new ramp starts wherever the old one had got to.
+    pub fn ramp(&mut self, param: usize, from: u32, to: u32, duration: Duration, now: Instant) {
+        let from = match self.ramps.iter().position(|r| r.param == param) {
+            Some(i) => self.ramps.swap_remove(i).last,
+            None => from,
+        };
+        self.ramps.push(Ramp {
+            param,
+            from,
+            to,
+            start: now,
+            duration,
+            last: from,
+        });
+    }
+
+    /// Hold a write of `value` to `param` until `take_deferred`, ex: at the
+    /// end of a gesture, so the whole gesture costs the synth's flash one
+    /// write.  Cancels any ramp of the param.
+    pub fn defer(&mut self, param: usize, value: u32) {
+        self.ramps.retain(|r| r.param != param);
+        match self.deferred.iter_mut().find(|d| d.param == param) {
+            Some(deferred) => deferred.value = value,
+            None => self.deferred.push(Deferred { param, value }),
+        }
+    }
+
+    /// The write held for `param`, if there is one.
+    pub fn take_deferred(&mut self, param: usize) -> Option<u32> {
+        let i = self.deferred.iter().position(|d| d.param == param)?;
+        Some(self.deferred.swap_remove(i).value)
+    }
+
+    /// Where `param` is headed, if it's ramping or waiting to be written.
+    pub fn target(&self, param: usize) -> Option<u32> {
+        self.ramps.iter().find(|r| r.param == param).map(|r| r.to)
+            .or_else(|| self.deferred.iter().find(|d| d.param == param).map(|d| d.value))
+    }
+
+    /// Drop every ramp where it is, and any writes waiting to settle.
+    pub fn clear(&mut self) {
+        self.ramps.clear();
+        self.deferred.clear();
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.ramps.is_empty()
+    }
+
+    /// The (param, value) writes due at `now`.  Steps that wouldn't change
+    /// the value are skipped, and finished ramps are dropped after their
+    /// final value is handed out.
+    pub fn tick(&mut self, now: Instant) -> Vec<(usize, u32)> {
+        let mut due = vec![];
+        for ramp in self.ramps.iter_mut() {
+            let value = ramp.value_at(now);
+            if value != ramp.last {
+                ramp.last = value;
+                due.push((ramp.param, value));
+            }
+        }
+        self.ramps.retain(|r| r.last != r.to);
+        due
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
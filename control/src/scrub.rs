@@ -0,0 +1,51 @@
+//! Scrub what identifies a synth's owner from a dump before it's shared, ex:
+//! on a bug report, with `mapatron scrub jupx pad.syx`.  Params the map
+//! marks `identifying` are overwritten, names with spaces and anything else
+//! (ex: serial numbers) with the bottom of its range, and the messages
+//! holding them are re-encoded so their checksums still match.
+
+use crate::codec::{encode_dt1, encode_value, parse_dt1, split_sysex};
+use crate::sysex_map::{Identifying, MappedParam, ParamIndex, SysexMap};
+
+/// What an identifying param is scrubbed to.
+fn scrubbed(param: &MappedParam, kind: Identifying) -> u32 {
+    let entry = &param.entry;
+    match kind {
+        Identifying::Text => (b' ' as u32).max(entry.discrete_range_low).min(entry.discrete_range_high),
+        Identifying::Other => entry.discrete_range_low,
+    }
+}
+
+/// `bytes` with every identifying param scrubbed, and how many were.
+/// Messages the map doesn't decode are kept as they were; anything between
+/// messages is dropped.
+pub fn scrub(map: &SysexMap, index: &ParamIndex, bytes: &[u8]) -> (Vec<u8>, usize) {
+    let mut out = vec![];
+    let mut count = 0;
+    for msg in split_sysex(bytes) {
+        let data_set = match parse_dt1(map, msg) {
+            Some(data_set) => data_set,
+            None => {
+                out.extend_from_slice(msg);
+                continue;
+            },
+        };
+        let mut data = data_set.data.to_vec();
+        let mut changed = false;
+        for idx in index.covered_by(data_set.address, data.len() as u32) {
+            let param = &index.params[idx];
+            if let Some(kind) = param.entry.identifying {
+                let offset = (param.address - data_set.address) as usize;
+                encode_value(param, scrubbed(param, kind), &mut data[offset..offset + param.size as usize]);
+                changed = true;
+                count += 1;
+            }
+        }
+        if changed {
+            out.extend(encode_dt1(map, data_set.address, &data));
+        } else {
+            out.extend_from_slice(msg);
+        }
+    }
+    (out, count)
+}
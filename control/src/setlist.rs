@@ -0,0 +1,124 @@
+//! Stepping through a gig's songs from the controller.  A setlist file is an
+//! ordered list of recalls with names, ex:
+//! ```json
+//! { "songs": [
+//!   { "name": "Intro", "snapshot": "songs/intro.syx", "program": { "program": 3 } },
+//!   { "name": "Verse", "snapshot": "songs/verse.syx" }
+//! ] }
+//! ```
+
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+
+use crate::bindings::Control;
+use crate::controllers::{ButtonState, ControllerEvent};
+use crate::program::Recall;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Song {
+    pub name: String,
+    #[serde(flatten)]
+    pub recall: Recall,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SetlistFile {
+    pub songs: Vec<Song>,
+}
+
+/// The `setlist` section of the config: which file, and the pads that move
+/// through it, ex: `{ "file": "gig.json", "next": { "pad": 15 }, "prev": { "pad": 14 } }`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SetlistConfig {
+    pub file: String,
+    pub next: Control,
+    pub prev: Control,
+}
+
+pub struct Setlist {
+    songs: Vec<Song>,
+    position: usize,
+    next: u8,
+    prev: u8,
+}
+
+fn pad_of(control: Control) -> Result<u8, Box<dyn Error>> {
+    match control {
+        Control::Pad(pad) => Ok(pad),
+        control => Err(format!("setlist controls must be pads, not {:?}", control).into()),
+    }
+}
+
+impl Setlist {
+    /// Load the setlist, positioned at the first song.  Nothing is recalled
+    /// until the first next/prev press.
+    pub fn load(config: &SetlistConfig) -> Result<Setlist, Box<dyn Error>> {
+        let file = File::open(&config.file).map_err(|e| format!("{}: {}", config.file, e))?;
+        let setlist: SetlistFile = serde_json::from_reader(BufReader::new(file))?;
+        if setlist.songs.is_empty() {
+            return Err(format!("{} has no songs", config.file).into());
+        }
+        Ok(Setlist {
+            songs: setlist.songs,
+            position: 0,
+            next: pad_of(config.next)?,
+            prev: pad_of(config.prev)?,
+        })
+    }
+
+    pub fn current(&self) -> &Song {
+        &self.songs[self.position]
+    }
+
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// The songs' names, in order.
+    pub fn names(&self) -> Vec<String> {
+        self.songs.iter().map(|song| song.name.clone()).collect()
+    }
+
+    /// Move to the song at `position`, if there is one, and return it.
+    pub fn jump_to(&mut self, position: usize) -> Option<&Song> {
+        if position >= self.songs.len() {
+            return None;
+        }
+        self.position = position;
+        Some(self.current())
+    }
+
+    /// What to show for the current song, ex: "2/9 VERSE".
+    pub fn label(&self) -> String {
+        format!("{}/{} {}", self.position + 1, self.songs.len(), self.current().name)
+    }
+
+    /// If `event` is a press of the next or prev pad, move to that song and
+    /// return it.  Pressing past either end stays put.
+    pub fn handle(&mut self, event: &ControllerEvent) -> Option<&Song> {
+        let pad = match *event {
+            ControllerEvent::GridButton(pad, _, _, ButtonState::Down, _) => pad,
+            _ => return None,
+        };
+        if pad == self.next && self.position + 1 < self.songs.len() {
+            self.position += 1;
+        } else if pad == self.prev && self.position > 0 {
+            self.position -= 1;
+        } else {
+            return None;
+        }
+        Some(self.current())
+    }
+
+    /// Whether `event` is for one of the setlist's pads, so it shouldn't go
+    /// on to the bindings.
+    pub fn claims(&self, event: &ControllerEvent) -> bool {
+        match *event {
+            ControllerEvent::GridButton(pad, ..) => pad == self.next || pad == self.prev,
+            _ => false,
+        }
+    }
+}
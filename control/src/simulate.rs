@@ -0,0 +1,66 @@
+//! A synth that only exists in memory, for working on bindings and scenes
+//! without the hardware.  It honors the map: DT1 writes land in its memory
+//! and RQ1 reads are answered from it, so dumps, recalls and verified writes
+//! all behave as they would against the real thing.
+
+use std::collections::HashMap;
+
+use crate::codec::{encode_dt1, encode_value, parse_dt1};
+use crate::controllers::sysex_mapped::VirtualDevice;
+use crate::sysex_map::SysexMap;
+use crate::template::Template;
+
+/// Largest chunk of data the simulated synth puts in one DT1 when answering
+/// a read, like real devices splitting up big dumps.
+const MAX_REPLY_DATA: u32 = 128;
+
+pub struct SimulatedSynth {
+    map: SysexMap,
+    /// By linear address.  Addresses nothing has been written to read as 0.
+    memory: HashMap<u32, u8>,
+}
+
+impl SimulatedSynth {
+    /// A synth with every mapped param at the bottom of its range.
+    pub fn new(map: SysexMap) -> Self {
+        let mut memory = HashMap::new();
+        for param in &map.resolve().params {
+            let mut data = vec![0; param.size as usize];
+            encode_value(param, param.entry.discrete_range_low, &mut data);
+            for (i, byte) in data.into_iter().enumerate() {
+                memory.insert(param.address + i as u32, byte);
+            }
+        }
+        SimulatedSynth {
+            map,
+            memory,
+        }
+    }
+}
+
+impl VirtualDevice for SimulatedSynth {
+    fn receive(&mut self, msg: &[u8]) -> Vec<Vec<u8>> {
+        if let Some(data_set) = parse_dt1(&self.map, msg) {
+            for (i, byte) in data_set.data.iter().enumerate() {
+                self.memory.insert(data_set.address + i as u32, *byte);
+            }
+            return vec![];
+        }
+
+        let (address, size) = match Template::read_for(&self.map).parse(&self.map, msg) {
+            Some(matched) => (matched.address, matched.size),
+            None => return vec![],
+        };
+        let mut replies = vec![];
+        let mut start = address;
+        while start < address + size {
+            let len = MAX_REPLY_DATA.min(address + size - start);
+            let data: Vec<u8> = (start..start + len)
+                .map(|a| self.memory.get(&a).copied().unwrap_or(0))
+                .collect();
+            replies.push(encode_dt1(&self.map, start, &data));
+            start += len;
+        }
+        replies
+    }
+}
@@ -0,0 +1,131 @@
+//! Tying .syx snapshots to the map they were saved with.  A snapshot starts
+//! with a fingerprint of the map's params (names and encodings) in a
+//! non-commercial sysex message, `F0 7D 'm' 'a' 'p' <10 bytes> F7`, which
+//! devices ignore and the decoder skips.  If the map has changed since, the
+//! same bytes may now decode to different params, so recalls check it.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+
+use crate::codec::split_sysex;
+use crate::crypt::{decrypt, is_encrypted};
+use crate::sysex_map::{ParamIndex, SysexMap};
+
+/// The sysex ID set aside for non-commercial use.
+const NON_COMMERCIAL: u8 = 0x7d;
+const FINGERPRINT_TAG: &[u8] = b"map";
+/// A u64 seven bits at a time.
+const FINGERPRINT_BYTES: usize = 10;
+
+/// What to do when a snapshot's fingerprint doesn't match the map.  Snapshots
+/// without one (ex: from before fingerprints) are always loaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FingerprintPolicy {
+    /// Log it and load the snapshot anyway.
+    #[default]
+    Warn,
+    /// Don't load the snapshot.
+    Refuse,
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is the same everywhere and
+/// forever.
+struct Fnv(u64);
+
+impl Fnv {
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100_0000_01b3);
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.write(&n.to_le_bytes());
+    }
+}
+
+/// A hash of everything about the map that decides which bytes of a dump
+/// mean what.  Display details don't count.
+pub fn fingerprint(map: &SysexMap, index: &ParamIndex) -> u64 {
+    let mut hash = Fnv(0xcbf2_9ce4_8422_2325);
+    hash.write(&map.model_id);
+    for p in &index.params {
+        hash.write(p.name.as_bytes());
+        // Names can't contain NUL, so this keeps "ab"+"c" apart from "a"+"bc".
+        hash.write(&[0]);
+        for n in &[p.address, p.size, p.entry.bitmask, p.entry.discrete_range_low,
+                   p.entry.discrete_range_high] {
+            hash.write_u32(*n);
+        }
+    }
+    hash.0
+}
+
+/// The message to put at the start of a snapshot saved with this map.
+pub fn fingerprint_message(fingerprint: u64) -> Vec<u8> {
+    let mut msg = vec![0xf0, NON_COMMERCIAL];
+    msg.extend_from_slice(FINGERPRINT_TAG);
+    for i in 0..FINGERPRINT_BYTES {
+        msg.push(((fingerprint >> (7 * i)) & 0x7f) as u8);
+    }
+    msg.push(0xf7);
+    msg
+}
+
+/// The contents of the snapshot at `path`, decrypted if it's encrypted.
+/// Only for recalling it; anything that might write it back out uses
+/// `read_plain_snapshot`.
+pub fn read_snapshot(path: &str) -> Result<Vec<u8>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    if is_encrypted(&bytes) {
+        return decrypt(&bytes).map_err(|e| format!("{}: {}", path, e));
+    }
+    Ok(bytes)
+}
+
+/// The contents of the snapshot at `path`, which mustn't be encrypted.  For
+/// the file tools, so an encrypted snapshot never comes out of one in the
+/// clear.
+pub fn read_plain_snapshot(path: &str) -> Result<Vec<u8>, String> {
+    let bytes = fs::read(path).map_err(|e| format!("can't read {}: {}", path, e))?;
+    if is_encrypted(&bytes) {
+        return Err(format!("{}: encrypted snapshots can only be recalled", path));
+    }
+    Ok(bytes)
+}
+
+/// The fingerprint a snapshot was saved with, if it has one.
+pub fn find_fingerprint(bytes: &[u8]) -> Option<u64> {
+    split_sysex(bytes).into_iter().find_map(|msg| {
+        let body = msg.strip_prefix(&[0xf0, NON_COMMERCIAL][..])?.strip_prefix(FINGERPRINT_TAG)?;
+        let data = body.strip_suffix(&[0xf7])?;
+        if data.len() != FINGERPRINT_BYTES {
+            return None;
+        }
+        Some(data.iter().enumerate().fold(0, |fp, (i, b)| fp | (*b as u64 & 0x7f) << (7 * i)))
+    })
+}
+
+/// Check the snapshot `bytes` from `path` against the map, as `policy` says.
+pub fn check_fingerprint(map: &SysexMap, index: &ParamIndex, path: &str, bytes: &[u8],
+                         policy: FingerprintPolicy) -> Result<(), String> {
+    let saved = match find_fingerprint(bytes) {
+        Some(saved) => saved,
+        None => return Ok(()),
+    };
+    if saved == fingerprint(map, index) {
+        return Ok(());
+    }
+    let problem = format!("{} was saved with a different version of the map", path);
+    match policy {
+        FingerprintPolicy::Warn => {
+            warn!("{}; params may be wrong", problem);
+            Ok(())
+        },
+        FingerprintPolicy::Refuse => Err(problem),
+    }
+}
@@ -0,0 +1,199 @@
+//! What the pads show apart from the bindings: a splash when the mapper
+//! starts, then how the connection to the synth is doing, ex:
+//! `"status": { "splash": "color_cube", "pad": 63, "synced": [0, 127, 0] }`.
+//! By convention that's orange while connecting (reading the synth's patch),
+//! green once synced and red when the synth's gone quiet.  With a `pad`
+//! only that pad shows it; without one the whole grid does until the synth
+//! is synced, and then gives way to the bindings.
+
+use serde::{Deserialize, Serialize};
+
+use std::time::{Duration, Instant};
+
+use crate::controllers::{ControllerCaps, Phase};
+
+/// How long the splash plays for.
+pub const SPLASH: Duration = Duration::from_millis(800);
+
+/// What the pads show when the mapper starts.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Splash {
+    None,
+    /// A rainbow column sweeping across the grid.
+    #[default]
+    Sweep,
+    /// A 4x4x4 cube of colors, cut into four slices side by side.
+    ColorCube,
+}
+
+/// The `status` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatusConfig {
+    #[serde(default)]
+    pub splash: Splash,
+    /// The pad that shows the connection, instead of the whole grid.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pad: Option<u8>,
+    #[serde(default = "default_connecting")]
+    pub connecting: (u8, u8, u8),
+    #[serde(default = "default_synced")]
+    pub synced: (u8, u8, u8),
+    #[serde(default = "default_error")]
+    pub error: (u8, u8, u8),
+}
+
+fn default_connecting() -> (u8, u8, u8) {
+    (0x7f, 0x30, 0x00)
+}
+
+fn default_synced() -> (u8, u8, u8) {
+    (0x00, 0x7f, 0x00)
+}
+
+fn default_error() -> (u8, u8, u8) {
+    (0x7f, 0x00, 0x00)
+}
+
+impl Default for StatusConfig {
+    fn default() -> Self {
+        StatusConfig {
+            splash: Splash::default(),
+            pad: None,
+            connecting: default_connecting(),
+            synced: default_synced(),
+            error: default_error(),
+        }
+    }
+}
+
+/// How things stand with the synth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Connection {
+    /// Reading its patch, so the store doesn't match it yet.
+    Connecting,
+    Synced,
+    /// It's stopped sending Active Sensing, or the connection's lost.
+    Error,
+}
+
+impl Connection {
+    /// How the synth's controller being at `phase` is shown.
+    pub fn of(phase: Phase) -> Connection {
+        match phase {
+            Phase::Discovered | Phase::Connecting | Phase::Identifying | Phase::Syncing => Connection::Connecting,
+            Phase::Ready => Connection::Synced,
+            Phase::Degraded | Phase::Disconnected => Connection::Error,
+        }
+    }
+}
+
+/// A color from `hue` around the wheel, 0 to 6 * 0x7f.
+fn wheel(hue: u32) -> (u8, u8, u8) {
+    let (sector, x) = ((hue / 0x7f) % 6, (hue % 0x7f) as u8);
+    match sector {
+        0 => (0x7f, x, 0),
+        1 => (0x7f - x, 0x7f, 0),
+        2 => (0, 0x7f, x),
+        3 => (0, 0x7f - x, 0x7f),
+        4 => (x, 0, 0x7f),
+        _ => (0x7f, 0, 0x7f - x),
+    }
+}
+
+impl Splash {
+    /// Pad `i`'s color `elapsed` into the splash.
+    pub fn color(self, caps: &ControllerCaps, i: u8, elapsed: Duration) -> (u8, u8, u8) {
+        let (row, col) = caps.row_col(i);
+        match self {
+            Splash::None => (0, 0, 0),
+            Splash::Sweep => {
+                let columns = caps.columns.max(1) as u32;
+                let head = (elapsed.as_millis() as u32 * columns / SPLASH.as_millis() as u32) as u8;
+                if col == head {
+                    wheel(col as u32 * 6 * 0x7f / columns)
+                } else {
+                    (0, 0, 0)
+                }
+            },
+            Splash::ColorCube => {
+                let (x, y, z) = (col % 4, row, col / 4);
+                (x * 0x20, y * 0x20, z * 0x20)
+            },
+        }
+    }
+}
+
+/// Keeps track of the splash and the connection for drawing on the pads.
+pub struct Status {
+    config: StatusConfig,
+    caps: ControllerCaps,
+    started: Instant,
+    splash_over: bool,
+    connection: Connection,
+}
+
+impl Status {
+    pub fn new(config: &StatusConfig, caps: ControllerCaps, now: Instant) -> Self {
+        Status {
+            config: config.clone(),
+            caps,
+            started: now,
+            splash_over: config.splash == Splash::None,
+            connection: Connection::Connecting,
+        }
+    }
+
+    pub fn connection(&self) -> Connection {
+        self.connection
+    }
+
+    /// Returns whether that changed anything.
+    pub fn set_connection(&mut self, connection: Connection) -> bool {
+        let changed = self.connection != connection;
+        self.connection = connection;
+        changed
+    }
+
+    /// Returns whether the pads need redrawing: every tick of the splash,
+    /// and once more when it's over.
+    pub fn poll(&mut self, now: Instant) -> bool {
+        if self.splash_over {
+            return false;
+        }
+        self.splash_over = now.saturating_duration_since(self.started) >= SPLASH;
+        true
+    }
+
+    fn color(&self) -> (u8, u8, u8) {
+        match self.connection {
+            Connection::Connecting => self.config.connecting,
+            Connection::Synced => self.config.synced,
+            Connection::Error => self.config.error,
+        }
+    }
+
+    /// Draw the splash or the connection through `set_led`.
+    pub fn render<F: FnMut(u8, u8, u8, u8)>(&self, now: Instant, mut set_led: F) {
+        let pads = self.caps.pads() as u8;
+        if !self.splash_over {
+            let elapsed = now.saturating_duration_since(self.started);
+            for i in 0..pads {
+                let (r, g, b) = self.config.splash.color(&self.caps, i, elapsed);
+                set_led(i, r, g, b);
+            }
+            return;
+        }
+        let (r, g, b) = self.color();
+        match self.config.pad {
+            Some(pad) if pad < pads => set_led(pad, r, g, b),
+            Some(_) => {},
+            None if self.connection != Connection::Synced => {
+                for i in 0..pads {
+                    set_led(i, r, g, b);
+                }
+            },
+            None => {},
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Keeping snapshots, bindings and configs in a git repo so a setup can move
+//! between machines, ex:
+//! `"sync": { "dir": "/home/me/mapatron-library", "remote": "origin" }`.
+//!
+//! `mapatron sync` commits whatever changed in `dir` with a message listing
+//! it, then fast-forwards from and pushes to the remote, if there is one.
+//! Diverged histories are left for the user to merge by hand.
+
+use git2::build::CheckoutBuilder;
+use git2::{Cred, CredentialType, Delta, FetchOptions, IndexAddOption, Oid, PushOptions, RemoteCallbacks,
+           Repository, Signature};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+use std::path::Path;
+
+/// The `sync` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SyncConfig {
+    /// The library directory.  It's made a git repo if it isn't one.
+    pub dir: String,
+    /// Remote to pull from and push to, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    #[serde(default = "default_branch")]
+    pub branch: String,
+}
+
+fn default_branch() -> String {
+    "main".to_string()
+}
+
+/// What a sync did.
+#[derive(Clone, Debug, Default)]
+pub struct SyncReport {
+    pub committed: Option<Oid>,
+    pub pulled: bool,
+    pub pushed: bool,
+}
+
+pub struct Library {
+    repo: Repository,
+    remote: Option<String>,
+    branch: String,
+}
+
+/// What kind of file a path is, for commit messages.
+fn describe(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("syx") => "snapshot",
+        Some("json") => "settings",
+        _ => "file",
+    }
+}
+
+fn callbacks<'a>() -> RemoteCallbacks<'a> {
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(|url, username, allowed| {
+        if allowed.contains(CredentialType::SSH_KEY) {
+            Cred::ssh_key_from_agent(username.unwrap_or("git"))
+        } else {
+            Cred::credential_helper(&git2::Config::open_default()?, url, username)
+        }
+    });
+    callbacks
+}
+
+impl Library {
+    pub fn open(config: &SyncConfig) -> Result<Library, Box<dyn Error>> {
+        let repo = match Repository::open(&config.dir) {
+            Ok(repo) => repo,
+            Err(_) => {
+                info!("creating a git repo in {}", config.dir);
+                let repo = Repository::init(&config.dir)?;
+                repo.set_head(&format!("refs/heads/{}", config.branch))?;
+                repo
+            },
+        };
+        Ok(Library {
+            repo,
+            remote: config.remote.clone(),
+            branch: config.branch.clone(),
+        })
+    }
+
+    /// Commit every change in the library, returning the commit if there was
+    /// anything to commit.
+    pub fn commit(&self) -> Result<Option<Oid>, Box<dyn Error>> {
+        let mut index = self.repo.index()?;
+        index.add_all(["*"].iter(), IndexAddOption::DEFAULT, None)?;
+        index.update_all(["*"].iter(), None)?;
+        index.write()?;
+        let tree = self.repo.find_tree(index.write_tree()?)?;
+        let parent = self.repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+        let parent_tree = match &parent {
+            Some(parent) => Some(parent.tree()?),
+            None => None,
+        };
+        if parent_tree.as_ref().map(|t| t.id()) == Some(tree.id()) {
+            return Ok(None);
+        }
+
+        let diff = self.repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
+        let mut lines = vec![];
+        let mut kinds: Vec<&str> = vec![];
+        for delta in diff.deltas() {
+            let (status, file) = match delta.status() {
+                Delta::Added => ("A", delta.new_file()),
+                Delta::Deleted => ("D", delta.old_file()),
+                _ => ("M", delta.new_file()),
+            };
+            let path = match file.path() {
+                Some(path) => path,
+                None => continue,
+            };
+            let kind = describe(path);
+            if !kinds.contains(&kind) {
+                kinds.push(kind);
+            }
+            lines.push(format!("{} {}", status, path.display()));
+        }
+        let summary = format!("Update {} ({} file{})", kinds.join(", "), lines.len(),
+                              if lines.len() == 1 { "" } else { "s" });
+        let message = format!("{}\n\n{}\n", summary, lines.join("\n"));
+
+        let signature = self.repo.signature()
+            .or_else(|_| Signature::now("mapatron", "mapatron@localhost"))?;
+        let parents: Vec<_> = parent.iter().collect();
+        let oid = self.repo.commit(Some("HEAD"), &signature, &signature, &message, &tree, &parents)?;
+        info!("committed {}: {}", oid, summary);
+        Ok(Some(oid))
+    }
+
+    /// Fast-forward to the remote branch.  Returns whether anything came in.
+    pub fn pull(&self) -> Result<bool, Box<dyn Error>> {
+        let name = match &self.remote {
+            Some(name) => name,
+            None => return Ok(false),
+        };
+        let mut remote = self.repo.find_remote(name)?;
+        let mut options = FetchOptions::new();
+        options.remote_callbacks(callbacks());
+        remote.fetch(&[&self.branch], Some(&mut options), None)?;
+
+        let fetch_head = match self.repo.find_reference("FETCH_HEAD") {
+            Ok(fetch_head) => fetch_head,
+            // The remote doesn't have the branch yet.
+            Err(_) => return Ok(false),
+        };
+        let incoming = self.repo.reference_to_annotated_commit(&fetch_head)?;
+        let (analysis, _) = self.repo.merge_analysis(&[&incoming])?;
+        if analysis.is_up_to_date() {
+            return Ok(false);
+        }
+        if !analysis.is_fast_forward() && !analysis.is_unborn() {
+            return Err(format!("the library and {}/{} have diverged; merge them by hand",
+                               name, self.branch).into());
+        }
+        let refname = format!("refs/heads/{}", self.branch);
+        match self.repo.find_reference(&refname) {
+            Ok(mut branch) => {
+                branch.set_target(incoming.id(), "mapatron sync: fast-forward")?;
+            },
+            Err(_) => {
+                self.repo.reference(&refname, incoming.id(), true, "mapatron sync: fast-forward")?;
+            },
+        }
+        self.repo.set_head(&refname)?;
+        self.repo.checkout_head(Some(CheckoutBuilder::default().force()))?;
+        Ok(true)
+    }
+
+    /// Push the branch to the remote.  Returns whether there was a remote.
+    pub fn push(&self) -> Result<bool, Box<dyn Error>> {
+        let name = match &self.remote {
+            Some(name) => name,
+            None => return Ok(false),
+        };
+        let mut remote = self.repo.find_remote(name)?;
+        let mut options = PushOptions::new();
+        options.remote_callbacks(callbacks());
+        remote.push(&[format!("refs/heads/{0}:refs/heads/{0}", self.branch)], Some(&mut options))?;
+        Ok(true)
+    }
+
+    /// Commit, pull and push.
+    pub fn sync(&self) -> Result<SyncReport, Box<dyn Error>> {
+        let committed = self.commit()?;
+        let pulled = self.pull()?;
+        let pushed = self.push()?;
+        Ok(SyncReport {
+            committed,
+            pulled,
+            pushed,
+        })
+    }
+}
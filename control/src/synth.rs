@@ -0,0 +1,424 @@
+use log::warn;
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time;
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::bus::{EngineEvent, EventBus};
+use crate::codec::{decode_data_set, decode_value, encode_dt1, encode_param_dt1, encode_rq1, parse_dt1,
+                   DataSet};
+use crate::correlate::{PendingRead, ReadError, RetryPolicy};
+use crate::controllers::ControllerEvent;
+use crate::decode::Lane;
+use crate::pacing::{gap_for, Pacer};
+use crate::param_store::ParamStore;
+use crate::progress::{Progress, Task};
+use crate::simulate::SimulatedSynth;
+use crate::sysex_map::SysexMap;
+use crate::SysexController;
+
+/// How long to wait for the device to answer a read, by default.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Whether to read params back after writing them, for finding out which
+/// addresses the device actually accepts writes to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VerifyMode {
+    Off,
+    /// Log mismatches.
+    Log,
+    /// Log mismatches and rewrite up to this many times.
+    Retry(u32),
+}
+
+/// A write whose read-back didn't match.  `read` is None if the device
+/// didn't answer.
+#[derive(Clone, Copy, Debug)]
+pub struct Mismatch {
+    pub param: usize,
+    pub wrote: u32,
+    pub read: Option<u32>,
+}
+
+/// Apply any DT1 in `event` to `store`, returning the params it set.
+fn apply_to_store(map: &SysexMap, store: &ParamStore, event: &ControllerEvent) -> Vec<(usize, u32)> {
+    let msg = match event {
+        ControllerEvent::Sysex(msg) => msg,
+        _ => return vec![],
+    };
+    let data_set = match parse_dt1(map, msg) {
+        Some(data_set) => data_set,
+        None => return vec![],
+    };
+    let values = decode_data_set(store.index(), &data_set);
+    for (param, value) in &values {
+        store.set(*param, *value);
+    }
+    values
+}
+
+/// A connected synth and everything needed to talk to it in terms of its map:
+/// writes go out as DT1, reads go out as RQ1 and wait for the DT1 replies, and
+/// any DT1 the synth sends us (ex: front panel edits) lands in the store.
+pub struct Synth {
+    map: SysexMap,
+    store: Arc<ParamStore>,
+    controller: SysexController,
+    events: mpsc::Receiver<ControllerEvent>,
+    verify: VerifyMode,
+    read_policy: RetryPolicy,
+    /// The gap between bulk messages, see `pacing`.
+    pacing: Duration,
+    /// Whether events arrive already applied to the store, see `decode_in`.
+    decoded: bool,
+    bus: EventBus,
+}
+
+impl Synth {
+    /// Connect to the first attached device matching the map.
+    pub fn attach(map: SysexMap, bus: EventBus) -> Option<Synth> {
+        let mut controller = SysexController::attach_to_all(&map).into_iter().next()?;
+        let events = controller.take_events()?;
+        let store = Arc::new(ParamStore::new(map.resolve(), bus.clone()));
+        let pacing = gap_for(&map, None);
+        Some(Synth {
+            map,
+            store,
+            controller,
+            events,
+            verify: VerifyMode::Off,
+            read_policy: RetryPolicy::default(),
+            pacing,
+            decoded: false,
+            bus,
+        })
+    }
+
+    /// A synth simulated in memory from the map, for working without the
+    /// hardware.
+    pub fn simulate(map: SysexMap, bus: EventBus) -> Synth {
+        let name = format!("{} (simulated)", map.port_names.first().map_or("synth", |n| n.as_str()));
+        let mut controller = SysexController::attach_virtual(&name,
+                                                             Box::new(SimulatedSynth::new(map.clone())));
+        let events = controller.take_events().expect("new controller has its events");
+        let store = Arc::new(ParamStore::new(map.resolve(), bus.clone()));
+        let pacing = gap_for(&map, None);
+        Synth {
+            map,
+            store,
+            controller,
+            events,
+            verify: VerifyMode::Off,
+            read_policy: RetryPolicy::default(),
+            pacing,
+            decoded: false,
+            bus,
+        }
+    }
+
+    pub fn map(&self) -> &SysexMap {
+        &self.map
+    }
+
+    pub fn store(&self) -> &Arc<ParamStore> {
+        &self.store
+    }
+
+    pub fn controller(&mut self) -> &mut SysexController {
+        &mut self.controller
+    }
+
+    pub fn set_verify(&mut self, verify: VerifyMode) {
+        self.verify = verify;
+    }
+
+    /// How `read_param` and `read_range` wait and retry.
+    pub fn set_read_policy(&mut self, policy: RetryPolicy) {
+        self.read_policy = policy;
+    }
+
+    /// The gap to leave between bulk messages, ex: the writes of a recall.
+    pub fn pacing(&self) -> Duration {
+        self.pacing
+    }
+
+    /// Override the gap from the map's manufacturer profile.
+    pub fn set_pacing(&mut self, gap: Duration) {
+        self.pacing = gap;
+    }
+
+    /// Send a raw message.
+    pub fn send(&mut self, msg: &[u8]) {
+        self.controller.send(msg);
+    }
+
+    /// Write a raw value to a param (by index) and record it in the store.
+    pub fn write(&mut self, param: usize, value: u32) {
+        let msg = encode_param_dt1(&self.map, &self.store.index().params[param], value);
+        self.controller.send(&msg);
+        self.store.set(param, value);
+    }
+
+    /// Ask for `size` bytes at linear `address` without waiting for the
+    /// reply, which lands in the store as it comes in like any other DT1.
+    pub fn request(&mut self, address: u32, size: u32) {
+        let msg = encode_rq1(&self.map, address, size);
+        self.controller.send(&msg);
+    }
+
+    /// The ports of every connected device the map matches, for picking
+    /// which one to talk to.  Just the one for a simulated synth.
+    pub fn ports(&self) -> Vec<String> {
+        if self.controller.is_virtual() {
+            return vec![self.controller.port_name().to_string()];
+        }
+        SysexController::matching_ports(&self.map.port_names, &self.map.ignore_port_names)
+    }
+
+    /// Talk to the device on `port_name` instead, one of `ports`.  The
+    /// store keeps what it had until the new device is read.
+    pub fn switch_to(&mut self, port_name: &str) {
+        if port_name != self.controller.port_name() {
+            self.controller.retarget(port_name);
+        }
+    }
+
+    /// Write raw bytes at a linear address without going through the map's
+    /// params, ex: when probing addresses the map doesn't know about.
+    pub fn write_raw(&mut self, address: u32, data: &[u8]) {
+        let msg = encode_dt1(&self.map, address, data);
+        self.controller.send(&msg);
+    }
+
+    /// Write a batch of (param, value) pairs and then, if verification is on,
+    /// read them all back.  Returns the writes that still didn't stick after
+    /// any retries.  The store ends up with what was read back.
+    pub async fn write_verified(&mut self, writes: &[(usize, u32)]) -> Vec<Mismatch> {
+        for (param, value) in writes {
+            self.write(*param, *value);
+        }
+        let retries = match self.verify {
+            VerifyMode::Off => return vec![],
+            VerifyMode::Log => 0,
+            VerifyMode::Retry(retries) => retries,
+        };
+
+        let mut mismatches = self.check(writes).await;
+        for _ in 0..retries {
+            if mismatches.is_empty() {
+                break;
+            }
+            let again: Vec<(usize, u32)> = mismatches.iter().map(|m| (m.param, m.wrote)).collect();
+            for (param, value) in &again {
+                self.write(*param, *value);
+            }
+            mismatches = self.check(&again).await;
+        }
+        mismatches
+    }
+
+    async fn check(&mut self, writes: &[(usize, u32)]) -> Vec<Mismatch> {
+        let mut mismatches = vec![];
+        for (param, wrote) in writes {
+            let read = self.read_param_at(*param).await.ok();
+            if read != Some(*wrote) {
+                warn!("{}: wrote {}, read back {:?}",
+                      self.store.index().params[*param].name, wrote, read);
+                mismatches.push(Mismatch {
+                    param: *param,
+                    wrote: *wrote,
+                    read,
+                });
+            }
+        }
+        mismatches
+    }
+
+    /// Apply any DT1 in `event` to the store, returning the params it set.
+    pub fn apply_incoming(&self, event: &ControllerEvent) -> Vec<(usize, u32)> {
+        apply_to_store(&self.map, &self.store, event)
+    }
+
+    /// Apply an event that just came off the channel, unless a decode lane
+    /// already has.
+    fn received(&self, event: &ControllerEvent) {
+        if !self.decoded {
+            self.apply_incoming(event);
+        }
+    }
+
+    /// Decode what the synth sends on `lane` instead of wherever its events
+    /// are read, so a big dump doesn't hold up the reader.  Events still come
+    /// out in order, each after it's been applied to the store.  Needs to be
+    /// called from within the runtime.
+    pub fn decode_in(&mut self, lane: Lane) {
+        if self.decoded {
+            return;
+        }
+        let (mut tx, rx) = mpsc::channel::<ControllerEvent>(100);
+        let mut raw = std::mem::replace(&mut self.events, rx);
+        let map = Arc::new(self.map.clone());
+        let store = self.store.clone();
+        tokio::spawn(async move {
+            while let Some(event) = raw.recv().await {
+                let (done_tx, done_rx) = oneshot::channel();
+                let (map, store) = (map.clone(), store.clone());
+                let queued = lane.run(move || {
+                    apply_to_store(&map, &store, &event);
+                    let _ = done_tx.send(event);
+                });
+                if !queued {
+                    break;
+                }
+                let event = match done_rx.await {
+                    Ok(event) => event,
+                    // Decoding it panicked; the worker carries on.
+                    Err(_) => continue,
+                };
+                if tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        });
+        self.decoded = true;
+    }
+
+    /// Wait for the next event from the synth, applying it to the store
+    /// first.  None means the connection's event channel is gone.
+    pub async fn next_event(&mut self) -> Option<ControllerEvent> {
+        let event = self.events.recv().await?;
+        self.received(&event);
+        Some(event)
+    }
+
+    /// Apply whatever events have already arrived without waiting, for synths
+    /// that aren't otherwise being listened to.  Returns false once the
+    /// connection's event channel is gone.
+    pub fn poll_events(&mut self) -> bool {
+        loop {
+            match self.events.try_recv() {
+                Ok(event) => self.received(&event),
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Closed) => return false,
+            }
+        }
+    }
+
+    /// Request `size` bytes at linear `address` and collect the DT1 replies,
+    /// as (linear address, data) pairs, until all the bytes arrived or
+    /// `timeout` elapsed.  Values are also applied to the store.  Unrelated
+    /// events that arrive meanwhile are dropped.
+    pub async fn read_raw(&mut self, address: u32, size: u32, timeout: Duration)
+                          -> Vec<(u32, Vec<u8>)> {
+        self.controller.send(&encode_rq1(&self.map, address, size));
+
+        let deadline = Instant::now() + timeout;
+        let mut received = 0;
+        let mut replies = vec![];
+        while received < size {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let event = match time::timeout(remaining, self.events.recv()).await {
+                Ok(Some(event)) => event,
+                _ => break,
+            };
+            if let ControllerEvent::Sysex(msg) = &event {
+                if let Some(data_set) = parse_dt1(&self.map, msg) {
+                    let end = data_set.address + data_set.data.len() as u32;
+                    if data_set.address >= address && end <= address + size {
+                        received += data_set.data.len() as u32;
+                        replies.push((data_set.address, data_set.data.to_vec()));
+                    }
+                }
+            }
+            self.received(&event);
+        }
+        replies
+    }
+
+    /// Like `read_raw`, but returns the params that were fully covered.
+    pub async fn read(&mut self, address: u32, size: u32, timeout: Duration) -> Vec<(usize, u32)> {
+        let replies = self.read_raw(address, size, timeout).await;
+        let index = self.store.index();
+        replies.iter()
+            .flat_map(|(start, data)| decode_data_set(index, &DataSet {
+                address: *start,
+                data,
+            }))
+            .collect()
+    }
+
+    /// Read a whole patch, one request per dump span of the map, publishing
+    /// progress as each span comes in.  Requests are paced like writes.
+    pub async fn read_patch(&mut self) -> Vec<(usize, u32)> {
+        let spans = self.map.dump_spans(self.store.index());
+        let bytes = spans.iter().map(|(_, size)| *size as usize).sum();
+        let mut progress = Progress::new(Task::Dump, spans.len(), bytes);
+        let mut pacer = Pacer::new(self.pacing);
+        let mut values = vec![];
+        for (address, size) in spans {
+            while !pacer.ready(Instant::now()) {
+                time::delay_for(pacer.wait(Instant::now())).await;
+            }
+            values.extend(self.read(address, size, READ_TIMEOUT).await);
+            progress.messages_done += 1;
+            progress.bytes_done += size as usize;
+            self.bus.publish(EngineEvent::Progress(progress));
+        }
+        values
+    }
+
+    /// Read exactly `size` bytes at linear `address`, re-requesting whatever
+    /// hasn't arrived within the read policy's timeout up to its number of
+    /// retries.  Replies are also applied to the store.
+    pub async fn read_range(&mut self, address: u32, size: u32) -> Result<Vec<u8>, ReadError> {
+        let policy = self.read_policy;
+        let mut pending = PendingRead::new(address, size);
+        for _ in 0..=policy.retries {
+            for (start, len) in pending.missing() {
+                self.controller.send(&encode_rq1(&self.map, start, len));
+            }
+            let deadline = Instant::now() + policy.timeout;
+            while !pending.is_complete() {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                let event = match time::timeout(remaining, self.events.recv()).await {
+                    Ok(Some(event)) => event,
+                    Ok(None) => return Err(ReadError::Closed),
+                    Err(_) => break,
+                };
+                if let ControllerEvent::Sysex(msg) = &event {
+                    if let Some(data_set) = parse_dt1(&self.map, msg) {
+                        pending.accept(&data_set);
+                    }
+                }
+                self.received(&event);
+            }
+            if let Some(bytes) = pending.bytes() {
+                return Ok(bytes);
+            }
+        }
+        Err(ReadError::NoReply {
+            address,
+            size,
+            attempts: policy.retries + 1,
+        })
+    }
+
+    /// Read a single param's current value from the synth, by index.
+    pub async fn read_param_at(&mut self, param: usize) -> Result<u32, ReadError> {
+        let (address, size) = {
+            let p = &self.store.index().params[param];
+            (p.address, p.size)
+        };
+        let bytes = self.read_range(address, size).await?;
+        Ok(decode_value(&self.store.index().params[param], &bytes))
+    }
+
+    /// Read a single param's current value from the synth, by name.
+    pub async fn read_param(&mut self, name: &str) -> Result<u32, ReadError> {
+        let param = self.store.index().index_of(name)
+            .ok_or_else(|| ReadError::UnknownParam(name.to_string()))?;
+        self.read_param_at(param).await
+    }
+}
@@ -0,0 +1,812 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::bank::BankLayout;
+use crate::codec::{Checksum, ChecksumPolicy};
+use crate::formula::Formula;
+use crate::filter::RouteFilter;
+use crate::template::{MessageTemplates, Token};
+
+/// A row from a type table: a named block at an offset whose contents are
+/// described by another table (`type`).  Rows that were followed by ellipsis
+/// rows in the PDF repeat every `stride` bytes until `last_offset_start`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SysexMapTypeEntry {
+    pub name: String,
+    pub first_offset_start: u32,
+    pub last_offset_start: u32,
+    #[serde(rename = "type")]
+    pub type_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stride: Option<u32>,
+}
+
+/// Display style for values either side of a center, ex: pan as "L63", "C",
+/// "R63" or a detune as "-50" through "+50" (the default labels).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bipolar {
+    /// The raw value that's displayed as the center.
+    pub center: u32,
+    #[serde(default = "default_left")]
+    pub left: String,
+    #[serde(default = "default_right")]
+    pub right: String,
+    #[serde(default = "default_center_label")]
+    pub center_label: String,
+}
+
+fn default_left() -> String {
+    "-".to_string()
+}
+
+fn default_right() -> String {
+    "+".to_string()
+}
+
+fn default_center_label() -> String {
+    "0".to_string()
+}
+
+/// A condition on the raw value of another param in the same block, named
+/// without the block path, ex: `{"param": "LFO Sync", "values": [1]}`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ParamCondition {
+    pub param: String,
+    pub values: Vec<u32>,
+}
+
+/// Display fields that replace a value entry's own while `when` holds, ex: an
+/// LFO rate that shows note divisions when sync is on.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DisplayOverride {
+    pub when: ParamCondition,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub human_value_list: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub human_value_base: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub human_value_units: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub human_value_formula: Option<Formula>,
+}
+
+/// A row from a value table: a parameter stored in the bytes from
+/// `first_offset_start` through `last_offset_start`, each byte contributing the
+/// bits in `bitmask`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SysexMapValueEntry {
+    pub name: String,
+    /// What to show instead of `name`, ex: from a translation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    pub first_offset_start: u32,
+    pub last_offset_start: u32,
+    pub bitmask: u32,
+    pub discrete_range_low: u32,
+    pub discrete_range_high: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub human_value_list: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub human_value_base: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub human_value_units: Option<String>,
+    /// An expression computing the displayed value from the raw `value`,
+    /// see `formula`.  With a value list, the result indexes into the list.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub human_value_formula: Option<Formula>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub human_value_bipolar: Option<Bipolar>,
+    /// Only show the param when this holds, ex: a mode switch is on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visible_when: Option<ParamCondition>,
+    /// Alternate displays, the first whose condition holds wins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<DisplayOverride>,
+    /// Names the entry went by before, so bindings written against them keep
+    /// working.  Using one logs a deprecation warning.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(default, skip_serializing_if = "WriteCost::is_volatile")]
+    pub write_cost: WriteCost,
+    /// Says something about the synth's owner, so it's scrubbed from dumps
+    /// before they're shared.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identifying: Option<Identifying>,
+}
+
+/// What kind of identifying param an entry is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Identifying {
+    /// A character of a name, ex: a patch's, scrubbed to a space.
+    Text,
+    /// Anything else, ex: part of a serial number, scrubbed to the bottom of
+    /// its range.
+    Other,
+}
+
+/// Display strings for a map kept outside it, ex: a translation of a shared
+/// map, merged over the map's own at load.  Entries are keyed by value table
+/// and name like in the map, ex:
+/// `{ "value_entries": { "SceneCommon": { "Scene Level": { "display_name": "Szenenpegel" } } } }`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MapStrings {
+    #[serde(default)]
+    pub value_entries: BTreeMap<String, BTreeMap<String, EntryStrings>>,
+}
+
+/// The strings for one value entry; anything left out keeps the map's.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct EntryStrings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub human_value_list: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub human_value_units: Option<String>,
+}
+
+impl MapStrings {
+    pub fn load(path: &str) -> Result<MapStrings, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let strings = serde_json::from_reader(BufReader::new(file))?;
+        Ok(strings)
+    }
+}
+
+/// What writing a param costs the synth.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteCost {
+    #[default]
+    Volatile,
+    /// Every write goes to flash, so writes are held until a gesture is
+    /// over rather than sent on every detent.
+    Persistent,
+}
+
+impl WriteCost {
+    pub fn is_volatile(&self) -> bool {
+        *self == WriteCost::Volatile
+    }
+}
+
+/// A block of the address space the device sends as one dump message, ex: the
+/// common part of a scene.  `address` and `size` are packed like offsets.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SysexMapDumpRegion {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+}
+
+/// A check of the map against the device for `mapatron test-map`, ex:
+/// `{ "write": { "param": "Temporary Scene/Scene Common/Scene Level", "value": 100 } }`,
+/// `{ "read": { "param": "Temporary Scene/Scene Common/Scene Tempo", "expect": 1200 } }` or
+/// `{ "dump": { "region": "Scene Common", "expect_bytes": 64 } }`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MapTest {
+    /// Write `value`, read it back expecting `expect` (`value` by default),
+    /// then put back what was there.
+    Write {
+        param: String,
+        value: u32,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        expect: Option<u32>,
+    },
+    Read { param: String, expect: u32 },
+    /// Request a dump region, or the whole patch without one, and expect
+    /// this many bytes of data back.
+    Dump {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        region: Option<String>,
+        expect_bytes: u32,
+    },
+}
+
+/// What to take from the device's input ports whose names start with `port`,
+/// ex: `{ "port": "UM-ONE", "drop_clock": true }` for a synth on a shared
+/// interface, or `{ "port": "JUPITER-X MIDI", "sysex_only": true }`.
+/// Applied in the port's input callback, so filtered messages never reach
+/// the engine.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputFilter {
+    pub port: String,
+    #[serde(flatten)]
+    pub filter: RouteFilter,
+}
+
+/// The JSON sysex map as produced by `implporter/src/schemify.py`.  Tables are
+/// keyed by their type name; "ROOT" is the top-level table of the address map.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SysexMap {
+    pub port_names: Vec<String>,
+    pub ignore_port_names: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub input_filters: Vec<InputFilter>,
+    /// Roland "Device ID", 0x10 by default on pretty much everything.
+    #[serde(default = "default_device_id")]
+    pub device_id: u8,
+    /// The model ID bytes that follow the device ID in Roland sysex.
+    #[serde(default)]
+    pub model_id: Vec<u8>,
+    /// Roland unless the device says otherwise.
+    #[serde(default)]
+    pub checksum: Checksum,
+    /// What to do with incoming messages that fail the checksum.
+    #[serde(default)]
+    pub checksum_policy: ChecksumPolicy,
+    /// The messages that make up a full patch.  Without these, dumps request
+    /// each contiguous run of params, which may not match what the device
+    /// is willing to send in one go.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dump_regions: Vec<SysexMapDumpRegion>,
+    /// Framing for devices that don't use Roland DT1/RQ1.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message_templates: Option<MessageTemplates>,
+    /// Milliseconds between bulk messages, for a device slower than its
+    /// manufacturer's profile in `pacing` allows for.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pacing_ms: Option<u64>,
+    /// Where the programs are in the device's all-programs dump, for
+    /// `mapatron split-bank` and `join-bank`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bank: Option<BankLayout>,
+    pub type_entries: BTreeMap<String, Vec<SysexMapTypeEntry>>,
+    pub value_entries: BTreeMap<String, Vec<SysexMapValueEntry>>,
+    /// Checks for `mapatron test-map` to run against the hardware.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tests: Vec<MapTest>,
+}
+
+fn default_device_id() -> u8 {
+    0x10
+}
+
+pub const ROOT_TABLE: &str = "ROOT";
+
+/// Where `schemify.py` writes maps, relative to the repository root.
+const DEFAULT_MAP_DIR: &str = "sysex-maps";
+
+/// The path of the map for a short device name like "jupx".  Maps are looked
+/// for in `$MAPATRON_MAP_DIR` if set, otherwise `sysex-maps/`.
+pub fn map_path_for(device: &str) -> PathBuf {
+    let dir = env::var_os("MAPATRON_MAP_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_MAP_DIR));
+    dir.join(format!("{}.json", device))
+}
+
+/// The path of `device`'s strings for `locale`, ex: "de", next to its map:
+/// `jupx.de.strings.json`.
+pub fn strings_path_for(device: &str, locale: &str) -> PathBuf {
+    map_path_for(device).with_file_name(format!("{}.{}.strings.json", device, locale))
+}
+
+/// Separator between the names of the nested blocks that make up a parameter
+/// name, ex: "Temporary Scene/Scene Common/Scene Level".
+pub const NAME_SEPARATOR: &str = "/";
+/// Separates the segments of a param's dotted name, ex:
+/// "temporary_scene.scene_part_1.part_level".
+pub const DOTTED_SEPARATOR: char = '.';
+
+/// The dotted form of a full param name: each segment lowercased, with runs
+/// of anything but letters and digits turned into "_".
+pub fn dotted_name(name: &str) -> String {
+    name.split(NAME_SEPARATOR).map(|segment| {
+        let mut dotted = String::new();
+        let mut gap = false;
+        for c in segment.chars() {
+            if c.is_alphanumeric() {
+                if gap && !dotted.is_empty() {
+                    dotted.push('_');
+                }
+                gap = false;
+                dotted.extend(c.to_lowercase());
+            } else {
+                gap = true;
+            }
+        }
+        dotted
+    }).collect::<Vec<_>>().join(&DOTTED_SEPARATOR.to_string())
+}
+
+/// Whether `text` matches `pattern`, where "*" matches any run of
+/// characters and "?" any one.
+fn wildcard(pattern: &[char], text: &[char]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some(('*', rest)) => (0..=text.len()).any(|i| wildcard(rest, &text[i..])),
+        Some((p, rest)) => match text.split_first() {
+            Some((t, text)) => (*p == '?' || p == t) && wildcard(rest, text),
+            None => false,
+        },
+    }
+}
+
+/// Whether path `segments` match the `pattern` segments, where a "**"
+/// segment matches any number of segments.
+fn glob_segments(pattern: &[Vec<char>], segments: &[Vec<char>]) -> bool {
+    match pattern.split_first() {
+        None => segments.is_empty(),
+        Some((p, rest)) if p.iter().collect::<String>() == "**" => {
+            (0..=segments.len()).any(|i| glob_segments(rest, &segments[i..]))
+        },
+        Some((p, rest)) => match segments.split_first() {
+            Some((s, segments)) => wildcard(p, s) && glob_segments(rest, segments),
+            None => false,
+        },
+    }
+}
+
+/// Roland addresses are written as hex bytes that each only use 7 bits, so
+/// "00 00 01 00" immediately follows "00 00 00 7F".  The map stores these as
+/// packed integers (one address byte per 8 bits); this converts them to a
+/// linear address space where arithmetic works.
+pub fn linear_address(packed: u32) -> u32 {
+    ((packed >> 24) & 0x7f) << 21 |
+    ((packed >> 16) & 0x7f) << 14 |
+    ((packed >> 8) & 0x7f) << 7 |
+    (packed & 0x7f)
+}
+
+/// Inverse of `linear_address`.
+pub fn packed_address(linear: u32) -> u32 {
+    ((linear >> 21) & 0x7f) << 24 |
+    ((linear >> 14) & 0x7f) << 16 |
+    ((linear >> 7) & 0x7f) << 8 |
+    (linear & 0x7f)
+}
+
+/// A `ParamCondition` with the param resolved to its index.
+#[derive(Clone, Debug)]
+pub struct Condition {
+    pub param: usize,
+    pub values: Vec<u32>,
+}
+
+impl Condition {
+    pub fn holds<F: Fn(usize) -> u32>(&self, value_of: F) -> bool {
+        self.values.contains(&value_of(self.param))
+    }
+}
+
+/// A single parameter from the map with its absolute address resolved.
+#[derive(Clone, Debug)]
+pub struct MappedParam {
+    pub name: String,
+    /// Linear address of the first byte of the parameter.
+    pub address: u32,
+    /// Number of address bytes the value is spread across.
+    pub size: u32,
+    pub entry: SysexMapValueEntry,
+    /// `entry.visible_when`, if it names a param that exists.
+    pub visible_when: Option<Condition>,
+    /// `entry.depends_on` conditions that name params that exist, with the
+    /// index of the override they belong to.
+    pub overrides: Vec<(Condition, usize)>,
+}
+
+impl MappedParam {
+    /// The entry's `display_name`, or the last part of the param's name,
+    /// which is what fits on a screen.
+    pub fn display_name(&self) -> &str {
+        match &self.entry.display_name {
+            Some(name) => name,
+            None => self.name.rsplit(NAME_SEPARATOR).next().unwrap_or(&self.name),
+        }
+    }
+}
+
+/// All of a map's parameters, flattened and sorted by address so that incoming
+/// data can be matched up with the parameters it covers.
+pub struct ParamIndex {
+    pub params: Vec<MappedParam>,
+    by_name: HashMap<String, usize>,
+    /// Full names built from entries' `aliases`.
+    by_alias: HashMap<String, usize>,
+    by_dotted: HashMap<String, usize>,
+    /// Aliases we've already warned about, so each is only mentioned once.
+    warned: Mutex<HashSet<String>>,
+}
+
+impl ParamIndex {
+    pub fn get(&self, name: &str) -> Option<&MappedParam> {
+        self.index_of(name).map(|idx| &self.params[idx])
+    }
+
+    /// Look up a param by its name, its dotted name, or one of its deprecated
+    /// aliases.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        if let Some(idx) = self.by_name.get(name).or_else(|| self.by_dotted.get(name)) {
+            return Some(*idx);
+        }
+        let idx = *self.by_alias.get(name)?;
+        if self.warned.lock().unwrap().insert(name.to_string()) {
+            warn!("'{}' is deprecated, use '{}'", name, self.params[idx].name);
+        }
+        Some(idx)
+    }
+
+    /// The params whose names match the glob `pattern`, in address order.
+    /// Patterns with a "/" in them match full names, anything else dotted
+    /// names, ex: `"temporary_scene.scene_part_*.part_level"` or
+    /// `"Temporary Scene/**/Part Mute Switch"`.  Within a segment "*" matches
+    /// anything and "?" any one character; a "**" segment matches any number
+    /// of segments.
+    pub fn params_matching(&self, pattern: &str) -> Vec<usize> {
+        let chars = |s: &str| s.chars().collect::<Vec<char>>();
+        let full = pattern.contains(NAME_SEPARATOR);
+        let split = |s: &str| -> Vec<Vec<char>> {
+            if full {
+                s.split(NAME_SEPARATOR).map(chars).collect()
+            } else {
+                s.split(DOTTED_SEPARATOR).map(chars).collect()
+            }
+        };
+        let pattern = split(pattern);
+        self.params.iter().enumerate()
+            .filter(|(_, p)| {
+                let name = if full { p.name.clone() } else { dotted_name(&p.name) };
+                glob_segments(&pattern, &split(&name))
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// The current name for `name` if it's a deprecated alias.
+    pub fn renamed(&self, name: &str) -> Option<&str> {
+        self.by_alias.get(name).map(|idx| self.params[*idx].name.as_str())
+    }
+
+    /// Whether a param's `visible_when` condition holds, given a way to get
+    /// current values (ex: `ParamStore::get`).
+    pub fn is_visible<F: Fn(usize) -> u32>(&self, param: usize, value_of: F) -> bool {
+        match &self.params[param].visible_when {
+            Some(condition) => condition.holds(value_of),
+            None => true,
+        }
+    }
+
+    /// The entry to format a param's value with, after applying the first
+    /// of its `depends_on` overrides whose condition holds.
+    pub fn display_entry<F>(&self, param: usize, value_of: F) -> Cow<'_, SysexMapValueEntry>
+            where F: Fn(usize) -> u32 {
+        let p = &self.params[param];
+        let over = match p.overrides.iter().find(|(c, _)| c.holds(&value_of)) {
+            Some((_, i)) => &p.entry.depends_on[*i],
+            None => return Cow::Borrowed(&p.entry),
+        };
+        let mut entry = p.entry.clone();
+        entry.human_value_list = over.human_value_list.clone();
+        entry.human_value_base = over.human_value_base;
+        entry.human_value_units = over.human_value_units.clone();
+        entry.human_value_formula = over.human_value_formula.clone();
+        entry.human_value_bipolar = None;
+        Cow::Owned(entry)
+    }
+
+    /// Indices of the params whose bytes are entirely within the `len` bytes
+    /// starting at linear address `start`.
+    pub fn covered_by(&self, start: u32, len: u32) -> impl Iterator<Item = usize> + '_ {
+        let end = start + len;
+        let first = self.params.partition_point(|p| p.address < start);
+        self.params[first..].iter().enumerate()
+            .take_while(move |(_, p)| p.address < end)
+            .filter(move |(_, p)| p.address + p.size <= end)
+            .map(move |(i, _)| first + i)
+    }
+}
+
+impl SysexMap {
+    pub fn load(path: &str) -> Result<SysexMap, Box<dyn Error>> {
+        let file = File::open(path)?;
+        let map = serde_json::from_reader(BufReader::new(file))?;
+        Ok(map)
+    }
+
+    /// Load the map for a short device name, see `map_path_for`.
+    pub fn load_device(device: &str) -> Result<SysexMap, Box<dyn Error>> {
+        let path = map_path_for(device);
+        Self::load(path.to_str().ok_or("map path isn't UTF-8")?)
+    }
+
+    /// Merge `strings` over the map's own.  Returns the entries it names that
+    /// the map doesn't have.
+    pub fn apply_strings(&mut self, strings: &MapStrings) -> Vec<String> {
+        let mut unknown = vec![];
+        for (table, entries) in &strings.value_entries {
+            for (name, over) in entries {
+                let entry = self.value_entries.get_mut(table)
+                    .and_then(|values| values.iter_mut().find(|e| &e.name == name));
+                let entry = match entry {
+                    Some(entry) => entry,
+                    None => {
+                        unknown.push(format!("{}/{}", table, name));
+                        continue;
+                    },
+                };
+                if over.display_name.is_some() {
+                    entry.display_name = over.display_name.clone();
+                }
+                if over.human_value_list.is_some() {
+                    entry.human_value_list = over.human_value_list.clone();
+                }
+                if over.human_value_units.is_some() {
+                    entry.human_value_units = over.human_value_units.clone();
+                }
+            }
+        }
+        unknown
+    }
+
+    /// Look for problems that would make the map misbehave at runtime,
+    /// returning a description of each.  An empty result means the map looks
+    /// sane, not that it matches the device!
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        if !self.type_entries.contains_key(ROOT_TABLE) {
+            problems.push(format!("no {} table", ROOT_TABLE));
+        }
+        for (table, entries) in &self.type_entries {
+            for entry in entries {
+                if !self.type_entries.contains_key(&entry.type_name) &&
+                   !self.value_entries.contains_key(&entry.type_name) {
+                    problems.push(format!("{}/{}: unknown type '{}'", table, entry.name, entry.type_name));
+                }
+                if linear_address(entry.last_offset_start) < linear_address(entry.first_offset_start) {
+                    problems.push(format!("{}/{}: last offset before first", table, entry.name));
+                }
+            }
+        }
+        for (table, entries) in &self.value_entries {
+            for entry in entries {
+                let what = format!("{}/{}", table, entry.name);
+                if entry.bitmask == 0 || entry.bitmask > 0x7f {
+                    problems.push(format!("{}: bitmask {:#x} isn't 7-bit", what, entry.bitmask));
+                }
+                if linear_address(entry.last_offset_start) < linear_address(entry.first_offset_start) {
+                    problems.push(format!("{}: last offset before first", what));
+                    continue;
+                }
+                if entry.discrete_range_low > entry.discrete_range_high {
+                    problems.push(format!("{}: range low above high", what));
+                }
+                let bytes = linear_address(entry.last_offset_start) -
+                            linear_address(entry.first_offset_start) + 1;
+                let bits = (32 - (entry.bitmask & 0x7f).leading_zeros()) * bytes;
+                if bits < 32 && entry.discrete_range_high >= 1 << bits {
+                    problems.push(format!("{}: range high {} doesn't fit in {} bits", what,
+                                          entry.discrete_range_high, bits));
+                }
+                if let Some(bipolar) = &entry.human_value_bipolar {
+                    if bipolar.center < entry.discrete_range_low ||
+                       bipolar.center > entry.discrete_range_high {
+                        problems.push(format!("{}: bipolar center out of range", what));
+                    }
+                }
+                if entry.human_value_formula.is_some() {
+                    // The list is indexed by the formula, not the raw value.
+                } else if let (Some(list), true) = (&entry.human_value_list,
+                                                    entry.discrete_range_low <= entry.discrete_range_high) {
+                    let count = (entry.discrete_range_high - entry.discrete_range_low) as usize + 1;
+                    if list.len() != count {
+                        problems.push(format!("{}: {} human values for {} raw values", what,
+                                              list.len(), count));
+                    }
+                }
+            }
+        }
+
+        for input in &self.input_filters {
+            if self.ignore_port_names.iter().any(|ignored| input.port.starts_with(ignored.as_str())) {
+                problems.push(format!("input filter for {} is on an ignored port", input.port));
+            }
+            if input.filter.channels.iter().any(|c| !(1..=16).contains(c)) {
+                problems.push(format!("input filter for {} has channels outside 1-16", input.port));
+            }
+        }
+
+        if let Some(templates) = &self.message_templates {
+            for (kind, template, needed) in &[("write", &templates.write, Token::Data),
+                                              ("read", &templates.read, Token::Size)] {
+                for token in &[Token::Address, *needed] {
+                    if !template.tokens().contains(token) {
+                        problems.push(format!("{} template has no {}", kind, token));
+                    }
+                }
+            }
+        }
+
+        let mut regions: Vec<(u32, u32, &str)> = self.dump_regions.iter()
+            .map(|r| (linear_address(r.address), linear_address(r.size), r.name.as_str()))
+            .collect();
+        regions.sort();
+        for (_, size, name) in &regions {
+            if *size == 0 {
+                problems.push(format!("dump region {} is empty", name));
+            }
+        }
+        for pair in regions.windows(2) {
+            if pair[0].0 + pair[0].1 > pair[1].0 {
+                problems.push(format!("dump region {} overlaps {}", pair[0].2, pair[1].2));
+            }
+        }
+
+        let index = self.resolve();
+        for pair in index.params.windows(2) {
+            if pair[0].address + pair[0].size > pair[1].address {
+                problems.push(format!("{} overlaps {}", pair[0].name, pair[1].name));
+            }
+        }
+        for p in &index.params {
+            if p.entry.visible_when.is_some() && p.visible_when.is_none() {
+                problems.push(format!("{}: visible_when names an unknown param", p.name));
+            }
+            if p.overrides.len() != p.entry.depends_on.len() {
+                problems.push(format!("{}: depends_on names an unknown param", p.name));
+            }
+        }
+        if index.by_name.len() != index.params.len() {
+            problems.push("duplicate parameter names".to_string());
+        } else if index.by_dotted.len() != index.params.len() {
+            problems.push("parameter names that differ only in case or punctuation".to_string());
+        }
+        for alias in index.by_alias.keys() {
+            if index.by_name.contains_key(alias) {
+                problems.push(format!("alias {} is also a parameter name", alias));
+            }
+        }
+        for test in &self.tests {
+            match test {
+                MapTest::Write { param, .. } | MapTest::Read { param, .. } if index.get(param).is_none() => {
+                    problems.push(format!("test of unknown param '{}'", param));
+                },
+                MapTest::Dump { region: Some(region), .. }
+                        if !self.dump_regions.iter().any(|r| &r.name == region) => {
+                    problems.push(format!("test of unknown dump region '{}'", region));
+                },
+                _ => {},
+            }
+        }
+
+        problems
+    }
+
+    /// The (linear address, size) of each message in a full patch dump:
+    /// `dump_regions` if the map has them, otherwise each contiguous run of
+    /// params in `index`.
+    pub fn dump_spans(&self, index: &ParamIndex) -> Vec<(u32, u32)> {
+        if !self.dump_regions.is_empty() {
+            return self.dump_regions.iter()
+                .map(|r| (linear_address(r.address), linear_address(r.size)))
+                .collect();
+        }
+
+        let mut spans: Vec<(u32, u32)> = vec![];
+        for p in &index.params {
+            match spans.last_mut() {
+                Some((start, size)) if *start + *size == p.address => *size += p.size,
+                _ => spans.push((p.address, p.size)),
+            }
+        }
+        spans
+    }
+
+    /// Walk the type tables from the root, expanding strided blocks, to
+    /// produce every parameter in the map with its absolute address.
+    pub fn resolve(&self) -> ParamIndex {
+        let mut params = vec![];
+        let mut path = vec![];
+        self.resolve_table(ROOT_TABLE, 0, &mut path, &mut params);
+
+        params.sort_by_key(|p| p.address);
+        let by_name: HashMap<String, usize> =
+            params.iter().enumerate().map(|(i, p)| (p.name.clone(), i)).collect();
+        let by_dotted: HashMap<String, usize> =
+            params.iter().enumerate().map(|(i, p)| (dotted_name(&p.name), i)).collect();
+
+        // Aliases, like conditions, name params in the same block, so look
+        // them up with the block's path in front.
+        let mut by_alias = HashMap::new();
+        for (i, p) in params.iter().enumerate() {
+            let prefix = &p.name[..p.name.len() - p.entry.name.len()];
+            for alias in &p.entry.aliases {
+                by_alias.insert(format!("{}{}", prefix, alias), i);
+            }
+        }
+        for p in params.iter_mut() {
+            let prefix = &p.name[..p.name.len() - p.entry.name.len()];
+            let resolve = |c: &ParamCondition| {
+                by_name.get(&format!("{}{}", prefix, c.param)).map(|param| Condition {
+                    param: *param,
+                    values: c.values.clone(),
+                })
+            };
+            p.visible_when = p.entry.visible_when.as_ref().and_then(&resolve);
+            p.overrides = p.entry.depends_on.iter().enumerate()
+                .filter_map(|(i, over)| resolve(&over.when).map(|c| (c, i)))
+                .collect();
+        }
+
+        ParamIndex {
+            params,
+            by_name,
+            by_alias,
+            by_dotted,
+            warned: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn resolve_table(&self, table: &str, base: u32, path: &mut Vec<String>,
+                     params: &mut Vec<MappedParam>) {
+        if let Some(values) = self.value_entries.get(table) {
+            for value in values {
+                let first = linear_address(value.first_offset_start);
+                let last = linear_address(value.last_offset_start);
+                // `validate` reports these.
+                let size = match last.checked_sub(first) {
+                    Some(span) => span + 1,
+                    None => {
+                        warn!("{}/{}: last offset before first, leaving it out", table, value.name);
+                        continue;
+                    },
+                };
+                path.push(value.name.clone());
+                params.push(MappedParam {
+                    name: path.join(NAME_SEPARATOR),
+                    address: base + first,
+                    size,
+                    entry: value.clone(),
+                    visible_when: None,
+                    overrides: vec![],
+                });
+                path.pop();
+            }
+            return;
+        }
+
+        let types = match self.type_entries.get(table) {
+            Some(types) => types,
+            None => return,
+        };
+        for entry in types {
+            let first = linear_address(entry.first_offset_start);
+            let last = linear_address(entry.last_offset_start);
+            let span = match last.checked_sub(first) {
+                Some(span) => span,
+                None => {
+                    warn!("{}/{}: last offset before first, leaving it out", table, entry.name);
+                    continue;
+                },
+            };
+            // Checked after packing, so a stride like 0x80 doesn't divide by zero.
+            let (stride, count) = match entry.stride.map(linear_address) {
+                Some(stride) if stride > 0 => (stride, span / stride + 1),
+                _ => (0, 1),
+            };
+            for i in 0..count {
+                // schemify strips the trailing " 1" off repeated blocks, so
+                // put the (1-based) number back.
+                if count > 1 {
+                    path.push(format!("{} {}", entry.name, i + 1));
+                } else {
+                    path.push(entry.name.clone());
+                }
+                self.resolve_table(&entry.type_name, base + first + i * stride, path, params);
+                path.pop();
+            }
+        }
+    }
+}
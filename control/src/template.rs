@@ -0,0 +1,236 @@
+use serde::{Deserialize, Serialize};
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
+
+use crate::codec::{ADDRESS_LEN, DT1, ROLAND_ID, RQ1};
+use crate::sysex_map::{linear_address, packed_address, SysexMap};
+
+/// One piece of a message template.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Token {
+    /// A literal byte, written as two hex digits.
+    Byte(u8),
+    /// `{device_id}`: the map's device ID.
+    DeviceId,
+    /// `{address}`: the packed 4-byte address.  The checksum covers
+    /// everything from here up to the `{checksum}`.
+    Address,
+    /// `{size}`: the packed 4-byte length of a read request.
+    Size,
+    /// `{data}`: the data bytes of a write, however many there are.
+    Data,
+    /// `{checksum}`: the map's checksum, or nothing if it has none.
+    Checksum,
+}
+
+impl Token {
+    fn parse(text: &str) -> Result<Token, String> {
+        Ok(match text {
+            "{device_id}" => Token::DeviceId,
+            "{address}" => Token::Address,
+            "{size}" => Token::Size,
+            "{data}" => Token::Data,
+            "{checksum}" => Token::Checksum,
+            _ => Token::Byte(u8::from_str_radix(text, 16)
+                             .map_err(|_| format!("bad template token '{}'", text))?),
+        })
+    }
+
+    /// Bytes this token takes up in a message, None for `{data}`.
+    fn len(self, map: &SysexMap) -> Option<usize> {
+        match self {
+            Token::Byte(_) | Token::DeviceId => Some(1),
+            Token::Address | Token::Size => Some(ADDRESS_LEN),
+            Token::Data => None,
+            Token::Checksum => Some(if map.checksum.is_present() { 1 } else { 0 }),
+        }
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Byte(b) => write!(f, "{:02X}", b),
+            Token::DeviceId => f.write_str("{device_id}"),
+            Token::Address => f.write_str("{address}"),
+            Token::Size => f.write_str("{size}"),
+            Token::Data => f.write_str("{data}"),
+            Token::Checksum => f.write_str("{checksum}"),
+        }
+    }
+}
+
+/// The framing of one kind of message, written in the map as a string like
+/// `"F0 41 {device_id} 00 00 00 65 12 {address} {data} {checksum} F7"`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Template {
+    tokens: Vec<Token>,
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        let tokens = text.split_whitespace().map(Token::parse).collect::<Result<Vec<_>, _>>()?;
+        Ok(Template {
+            tokens,
+        })
+    }
+}
+
+impl From<Template> for String {
+    fn from(template: Template) -> String {
+        template.tokens.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(" ")
+    }
+}
+
+/// The `message_templates` section of a map.  Maps without one get Roland
+/// DT1/RQ1 framing built from `device_id` and `model_id`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MessageTemplates {
+    pub write: Template,
+    pub read: Template,
+}
+
+/// Where things landed in a rendered message.
+pub struct Rendered {
+    pub msg: Vec<u8>,
+    /// Start of the checksummed bytes.
+    pub body_start: usize,
+    pub data_start: usize,
+    pub data_end: usize,
+}
+
+/// The fields pulled out of a message that matched a template.
+pub struct Matched<'a> {
+    pub address: u32,
+    pub size: u32,
+    pub data: &'a [u8],
+    /// The checksummed bytes and the checksum that came with them, if the
+    /// message has one.
+    pub checksum: Option<(&'a [u8], u8)>,
+}
+
+fn roland(map: &SysexMap, command: u8, last: Token) -> Template {
+    let mut tokens = vec![Token::Byte(0xf0), Token::Byte(ROLAND_ID), Token::DeviceId];
+    tokens.extend(map.model_id.iter().map(|b| Token::Byte(*b)));
+    tokens.extend_from_slice(&[Token::Byte(command), Token::Address, last, Token::Checksum,
+                               Token::Byte(0xf7)]);
+    Template {
+        tokens,
+    }
+}
+
+impl Template {
+    pub fn write_for(map: &SysexMap) -> Template {
+        match &map.message_templates {
+            Some(templates) => templates.write.clone(),
+            None => roland(map, DT1, Token::Data),
+        }
+    }
+
+    pub fn read_for(map: &SysexMap) -> Template {
+        match &map.message_templates {
+            Some(templates) => templates.read.clone(),
+            None => roland(map, RQ1, Token::Size),
+        }
+    }
+
+    pub fn tokens(&self) -> &[Token] {
+        &self.tokens
+    }
+
+    /// Fill in the template.  `address` and `size` are linear.
+    pub fn render(&self, map: &SysexMap, address: u32, size: u32, data: &[u8]) -> Rendered {
+        let mut msg = vec![];
+        let mut body_start = 0;
+        let (mut data_start, mut data_end) = (0, 0);
+        for token in &self.tokens {
+            match *token {
+                Token::Byte(b) => msg.push(b),
+                Token::DeviceId => msg.push(map.device_id),
+                Token::Address => {
+                    body_start = msg.len();
+                    msg.extend_from_slice(&packed_address(address).to_be_bytes());
+                },
+                Token::Size => msg.extend_from_slice(&packed_address(size).to_be_bytes()),
+                Token::Data => {
+                    data_start = msg.len();
+                    msg.extend_from_slice(data);
+                    data_end = msg.len();
+                },
+                Token::Checksum => {
+                    if let Some(checksum) = map.checksum.compute(&msg[body_start..]) {
+                        msg.push(checksum);
+                    }
+                },
+            }
+        }
+        Rendered {
+            msg,
+            body_start,
+            data_start,
+            data_end,
+        }
+    }
+
+    /// Where each token falls in `msg`, if the fixed bytes line up.
+    pub fn layout(&self, map: &SysexMap, msg: &[u8]) -> Option<Vec<(Token, Range<usize>)>> {
+        let fixed: usize = self.tokens.iter().filter_map(|t| t.len(map)).sum();
+        let data_len = msg.len().checked_sub(fixed)?;
+        if data_len > 0 && !self.tokens.contains(&Token::Data) {
+            return None;
+        }
+
+        let mut at = 0;
+        let mut layout = vec![];
+        for token in &self.tokens {
+            let len = token.len(map).unwrap_or(data_len);
+            match *token {
+                Token::Byte(b) if msg[at] != b => return None,
+                Token::DeviceId if msg[at] != map.device_id => return None,
+                _ => (),
+            }
+            layout.push((*token, at..at + len));
+            at += len;
+        }
+        Some(layout)
+    }
+
+    /// Match a message against the template, returning its fields if the
+    /// fixed bytes line up.  Verifying the checksum is left to the caller.
+    pub fn parse<'a>(&self, map: &SysexMap, msg: &'a [u8]) -> Option<Matched<'a>> {
+        let mut body_start = 0;
+        let mut matched = Matched {
+            address: 0,
+            size: 0,
+            data: &[],
+            checksum: None,
+        };
+        for (token, range) in self.layout(map, msg)? {
+            let bytes = &msg[range.clone()];
+            match token {
+                Token::Address => {
+                    body_start = range.start;
+                    matched.address = unpack(bytes);
+                },
+                Token::Size => matched.size = unpack(bytes),
+                Token::Data => matched.data = bytes,
+                Token::Checksum if !bytes.is_empty() => {
+                    matched.checksum = Some((&msg[body_start..range.start], bytes[0]));
+                },
+                _ => (),
+            }
+        }
+        Some(matched)
+    }
+}
+
+pub(crate) fn unpack(bytes: &[u8]) -> u32 {
+    let mut packed = [0; ADDRESS_LEN];
+    packed.copy_from_slice(bytes);
+    linear_address(u32::from_be_bytes(packed))
+}
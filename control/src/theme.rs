@@ -0,0 +1,66 @@
+//! Palettes for whoever can't tell the pads' colors apart, ex:
+//! `"palette": "deuteranopia"`, or picked from the menu's "COLORS" item.
+//! A palette maps every color the pads are given as the `Compositor` puts
+//! the frame together, so the bindings, status, heatmap and the rest all
+//! change the same way without knowing about it: reds go to orange and
+//! greens to blue for the colorblind palettes, and everything's either fully
+//! on or off for high contrast.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Palette {
+    #[default]
+    Default,
+    /// Safe for red-green colorblindness where green's weak.
+    Deuteranopia,
+    /// Same, for where red's weak, so reds are brightened too.
+    Protanopia,
+    /// White or off, nothing in between.
+    HighContrast,
+}
+
+/// How much of the red, green and blue asked for goes into each of red,
+/// green and blue, in sixteenths.
+type Mix = [[u16; 3]; 3];
+
+const DEUTERANOPIA: Mix = [[16, 0, 0], [7, 9, 3], [0, 16, 16]];
+const PROTANOPIA: Mix = [[16, 4, 0], [10, 8, 2], [0, 16, 16]];
+/// How bright a color has to be to be on in high contrast.
+const CONTRAST_THRESHOLD: u16 = 0x0c;
+
+fn mix(mix: &Mix, (r, g, b): (u8, u8, u8)) -> (u8, u8, u8) {
+    let channel = |[kr, kg, kb]: [u16; 3]| ((kr * r as u16 + kg * g as u16 + kb * b as u16) / 16).min(0x7f) as u8;
+    (channel(mix[0]), channel(mix[1]), channel(mix[2]))
+}
+
+impl Palette {
+    /// In the order the menu goes through them.
+    pub const ALL: [Palette; 4] = [Palette::Default, Palette::Deuteranopia, Palette::Protanopia,
+                                   Palette::HighContrast];
+
+    /// The name the menu shows.
+    pub fn label(self) -> &'static str {
+        match self {
+            Palette::Default => "DEFAULT",
+            Palette::Deuteranopia => "DEUTAN",
+            Palette::Protanopia => "PROTAN",
+            Palette::HighContrast => "CONTRAST",
+        }
+    }
+
+    /// What `color`, 7 bits a channel, is shown as.
+    pub fn apply(self, color: (u8, u8, u8)) -> (u8, u8, u8) {
+        match self {
+            Palette::Default => color,
+            Palette::Deuteranopia => mix(&DEUTERANOPIA, color),
+            Palette::Protanopia => mix(&PROTANOPIA, color),
+            Palette::HighContrast => {
+                let (r, g, b) = color;
+                let luma = (5 * r as u16 + 9 * g as u16 + 2 * b as u16) / 16;
+                if luma >= CONTRAST_THRESHOLD { (0x7f, 0x7f, 0x7f) } else { (0, 0, 0) }
+            },
+        }
+    }
+}
@@ -0,0 +1,182 @@
+//! Actions on a clock rather than a pad, for installations left running
+//! unattended, ex:
+//! ```json
+//! "timers": { "utc_offset_min": 60, "timers": [
+//!   { "when": { "at": "02:00" }, "action": { "send": { "message": "F0 41 {device_id} ... F7" } } },
+//!   { "when": { "after_s": 30 }, "action": "reload_bindings" },
+//!   { "when": { "every_s": 3600 }, "action": { "recall": { "snapshot": "exhibit.syx" } } }
+//! ] }
+//! ```
+//! `at` times are local to `utc_offset_min`, every day.  `after_s` and
+//! `every_s` count from when the daemon started.
+
+use serde::{Deserialize, Serialize};
+
+use std::error::Error;
+
+use crate::command::CommandTemplate;
+use crate::param_store::ParamStore;
+use crate::program::Recall;
+use crate::remote::{set_param_command, RemoteCommand};
+use crate::sysex_map::SysexMap;
+
+const DAY_MS: i64 = 86_400_000;
+
+/// The `timers` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimersConfig {
+    /// Minutes ahead of UTC that `at` times are in, ex: -300 for US
+    /// Eastern.  There's no daylight saving.
+    #[serde(default)]
+    pub utc_offset_min: i32,
+    pub timers: Vec<TimerConfig>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TimerConfig {
+    pub when: When,
+    pub action: TimerAction,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum When {
+    /// Every day at "HH:MM".
+    At(String),
+    /// Once, this many seconds after starting.
+    AfterS(u64),
+    /// Over and over, this many seconds apart.
+    EveryS(u64),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimerAction {
+    /// A raw message, as for a command binding.
+    Send {
+        message: CommandTemplate,
+        #[serde(default)]
+        value: u32,
+    },
+    /// Write a param, with a human value as for `mapatron set`.
+    Set { param: String, value: String },
+    /// Recall a snapshot and/or program, as for a setlist song.
+    Recall(Recall),
+    /// Read the whole patch back from the synth.
+    Dump,
+    /// Load the bindings file again, ex: after it's been updated remotely.
+    ReloadBindings,
+}
+
+/// What a timer going off wants done, with its message or command worked
+/// out when the timers were loaded.
+#[derive(Clone, Debug)]
+pub enum Fired {
+    Send(Vec<u8>),
+    Command(RemoteCommand),
+    Recall(Recall),
+    Dump,
+    ReloadBindings,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Schedule {
+    /// Minutes past local midnight.
+    At(i64),
+    After(u64),
+    Every(u64),
+}
+
+struct Timer {
+    schedule: Schedule,
+    fired: Fired,
+    /// When it next goes off, in ms since the epoch.
+    next: Option<u64>,
+}
+
+pub struct Timers {
+    timers: Vec<Timer>,
+    offset_ms: i64,
+}
+
+/// "HH:MM" as minutes past midnight.
+fn parse_time(text: &str) -> Option<i64> {
+    let (hours, minutes) = text.split_once(':')?;
+    let (hours, minutes) = (hours.parse::<i64>().ok()?, minutes.parse::<i64>().ok()?);
+    if (0..24).contains(&hours) && (0..60).contains(&minutes) && text.len() == 5 {
+        Some(hours * 60 + minutes)
+    } else {
+        None
+    }
+}
+
+impl Timers {
+    /// Check every timer's time and action against the map, and set them
+    /// going from `now_ms`.
+    pub fn new(config: &TimersConfig, map: &SysexMap, store: &ParamStore, now_ms: u64)
+               -> Result<Timers, Box<dyn Error>> {
+        let offset_ms = config.utc_offset_min as i64 * 60_000;
+        let mut timers = vec![];
+        for timer in &config.timers {
+            let schedule = match &timer.when {
+                When::At(at) => {
+                    Schedule::At(parse_time(at).ok_or_else(|| format!("bad timer time '{}'", at))?)
+                },
+                When::AfterS(s) => Schedule::After(*s * 1000),
+                When::EveryS(0) => return Err("timers can't go off every 0s".into()),
+                When::EveryS(s) => Schedule::Every(*s * 1000),
+            };
+            let fired = match &timer.action {
+                TimerAction::Send { message, value } => {
+                    Fired::Send(message.render(map, *value).map_err(|e| format!("timer send: {}", e))?)
+                },
+                TimerAction::Set { param, value } => Fired::Command(set_param_command(store, param, value)?),
+                TimerAction::Recall(recall) => Fired::Recall(recall.clone()),
+                TimerAction::Dump => Fired::Dump,
+                TimerAction::ReloadBindings => Fired::ReloadBindings,
+            };
+            let next = match schedule {
+                Schedule::At(minute) => next_at(minute, offset_ms, now_ms),
+                Schedule::After(ms) | Schedule::Every(ms) => now_ms + ms,
+            };
+            timers.push(Timer {
+                schedule,
+                fired,
+                next: Some(next),
+            });
+        }
+        Ok(Timers {
+            timers,
+            offset_ms,
+        })
+    }
+
+    /// What's gone off since the last poll.  A timer that's missed several
+    /// goes, ex: over a suspend, goes off once.
+    pub fn poll(&mut self, now_ms: u64) -> Vec<Fired> {
+        let mut fired = vec![];
+        for timer in &mut self.timers {
+            match timer.next {
+                Some(next) if now_ms >= next => (),
+                _ => continue,
+            }
+            fired.push(timer.fired.clone());
+            timer.next = match timer.schedule {
+                Schedule::At(minute) => Some(next_at(minute, self.offset_ms, now_ms)),
+                Schedule::After(_) => None,
+                Schedule::Every(ms) => Some(now_ms + ms),
+            };
+        }
+        fired
+    }
+}
+
+/// The first time after `now_ms` that's `minute` past local midnight.
+fn next_at(minute: i64, offset_ms: i64, now_ms: u64) -> u64 {
+    let local = now_ms as i64 + offset_ms;
+    let mut at = local - local.rem_euclid(DAY_MS) + minute * 60_000;
+    if at <= local {
+        at += DAY_MS;
+    }
+    (at - offset_ms) as u64
+}
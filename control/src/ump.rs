@@ -0,0 +1,214 @@
+//! MIDI 2.0 Universal MIDI Packet framing for sysex.
+//!
+//! midir only speaks MIDI 1.0 byte streams, so nothing here is wired to a port
+//! yet.  This is the framing layer so that once an OS backend exposes UMP
+//! endpoints the mapping engine can hand it the same sysex it sends today and
+//! let `Transport` decide how it goes over the wire.
+//!
+//! All sysex in and out of this module includes the leading 0xf0 and trailing
+//! 0xf7 like everywhere else in the crate; UMP leaves them implicit.
+
+use log::warn;
+
+use std::collections::HashMap;
+
+use crate::controllers::SysexLimits;
+
+/// Message type nibble for 64-bit data messages, which carry sysex7.
+const MT_DATA64: u32 = 0x3;
+/// Message type nibble for 128-bit data messages, which carry sysex8.
+const MT_DATA128: u32 = 0x5;
+
+const STATUS_COMPLETE: u32 = 0x0;
+const STATUS_START: u32 = 0x1;
+const STATUS_CONTINUE: u32 = 0x2;
+const STATUS_END: u32 = 0x3;
+
+/// Payload bytes per sysex7 packet.
+const SYSEX7_CHUNK: usize = 6;
+/// Payload bytes per sysex8 packet; the 14th byte slot is the stream ID.
+const SYSEX8_CHUNK: usize = 13;
+
+/// How a given endpoint wants its sysex.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transport {
+    /// A classic MIDI 1.0 port; sysex goes out as-is.
+    Bytestream,
+    /// A UMP endpoint that only does 64-bit sysex7 packets.
+    UmpSysex7 { group: u8 },
+    /// A UMP endpoint that does 128-bit sysex8 packets.
+    UmpSysex8 { group: u8, stream_id: u8 },
+}
+
+impl Transport {
+    /// Pick the best transport an endpoint supports, downgrading to sysex7 for
+    /// UMP endpoints that don't advertise sysex8 and to plain bytes for
+    /// non-UMP ports.
+    pub fn negotiate(is_ump: bool, supports_sysex8: bool, group: u8) -> Transport {
+        match (is_ump, supports_sysex8) {
+            (false, _) => Transport::Bytestream,
+            (true, false) => Transport::UmpSysex7 { group },
+            (true, true) => Transport::UmpSysex8 { group, stream_id: 0 },
+        }
+    }
+
+    /// Frame a complete sysex message for this transport.  UMP packets come
+    /// back as 32-bit words, packet after packet.
+    pub fn frame(&self, msg: &[u8]) -> Framed {
+        match *self {
+            Transport::Bytestream => Framed::Bytes(msg.to_vec()),
+            Transport::UmpSysex7 { group } => Framed::Words(
+                sysex7_packets(group, msg).iter().flatten().copied().collect()),
+            Transport::UmpSysex8 { group, stream_id } => Framed::Words(
+                sysex8_packets(group, stream_id, msg).iter().flatten().copied().collect()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Framed {
+    Bytes(Vec<u8>),
+    Words(Vec<u32>),
+}
+
+fn payload(msg: &[u8]) -> &[u8] {
+    let msg = msg.strip_prefix(&[0xf0]).unwrap_or(msg);
+    msg.strip_suffix(&[0xf7]).unwrap_or(msg)
+}
+
+fn chunk_status(i: usize, count: usize) -> u32 {
+    match (i, count) {
+        (_, 1) => STATUS_COMPLETE,
+        (0, _) => STATUS_START,
+        (i, count) if i == count - 1 => STATUS_END,
+        _ => STATUS_CONTINUE,
+    }
+}
+
+/// Split sysex into 64-bit sysex7 packets.
+pub fn sysex7_packets(group: u8, msg: &[u8]) -> Vec<[u32; 2]> {
+    let data = payload(msg);
+    let chunks: Vec<&[u8]> = if data.is_empty() { vec![&[]] } else { data.chunks(SYSEX7_CHUNK).collect() };
+    chunks.iter().enumerate().map(|(i, chunk)| {
+        let mut bytes = [0u8; 8];
+        bytes[0] = ((MT_DATA64 << 4) | (group as u32 & 0xf)) as u8;
+        bytes[1] = ((chunk_status(i, chunks.len()) << 4) | chunk.len() as u32) as u8;
+        bytes[2..2 + chunk.len()].copy_from_slice(chunk);
+        [u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+         u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]])]
+    }).collect()
+}
+
+/// Split sysex into 128-bit sysex8 packets on the given stream.
+pub fn sysex8_packets(group: u8, stream_id: u8, msg: &[u8]) -> Vec<[u32; 4]> {
+    let data = payload(msg);
+    let chunks: Vec<&[u8]> = if data.is_empty() { vec![&[]] } else { data.chunks(SYSEX8_CHUNK).collect() };
+    chunks.iter().enumerate().map(|(i, chunk)| {
+        let mut bytes = [0u8; 16];
+        bytes[0] = ((MT_DATA128 << 4) | (group as u32 & 0xf)) as u8;
+        // The byte count includes the stream ID.
+        bytes[1] = ((chunk_status(i, chunks.len()) << 4) | (chunk.len() as u32 + 1)) as u8;
+        bytes[2] = stream_id;
+        bytes[3..3 + chunk.len()].copy_from_slice(chunk);
+        let mut words = [0u32; 4];
+        for (w, word) in words.iter_mut().enumerate() {
+            let b = &bytes[w * 4..w * 4 + 4];
+            *word = u32::from_be_bytes([b[0], b[1], b[2], b[3]]);
+        }
+        words
+    }).collect()
+}
+
+/// Reassembles sysex from incoming UMP data packets.  Packets for different
+/// groups (and sysex8 streams) may be interleaved.  Messages that grow past
+/// the limit are dropped.
+pub struct SysexAssembler {
+    /// (message type, group, stream id) -> payload so far
+    pending: HashMap<(u32, u8, u8), Vec<u8>>,
+    max_message: usize,
+}
+
+impl SysexAssembler {
+    pub fn new() -> Self {
+        SysexAssembler {
+            pending: HashMap::new(),
+            max_message: SysexLimits::default().max_message,
+        }
+    }
+
+    /// The longest message to reassemble, 0xf0 and 0xf7 included.
+    pub fn set_limit(&mut self, max_message: usize) {
+        self.max_message = max_message;
+    }
+
+    /// Feed one packet (2 words for sysex7, 4 for sysex8).  Returns the
+    /// complete message once its last packet arrives.
+    pub fn push(&mut self, words: &[u32]) -> Option<Vec<u8>> {
+        let bytes: Vec<u8> = words.iter().flat_map(|w| w.to_be_bytes().to_vec()).collect();
+        if bytes.len() < 8 {
+            return None;
+        }
+        let mt = (bytes[0] >> 4) as u32;
+        let group = bytes[0] & 0xf;
+        let status = (bytes[1] >> 4) as u32;
+        let count = (bytes[1] & 0xf) as usize;
+        let (stream_id, data) = match mt {
+            MT_DATA64 if count <= SYSEX7_CHUNK => (0, &bytes[2..2 + count]),
+            MT_DATA128 if bytes.len() >= 16 && (1..=SYSEX8_CHUNK + 1).contains(&count) => {
+                (bytes[2], &bytes[3..2 + count])
+            },
+            _ => return None,
+        };
+
+        let key = (mt, group, stream_id);
+        match status {
+            STATUS_COMPLETE => {
+                self.pending.remove(&key);
+                Some(framed(data))
+            },
+            STATUS_START => {
+                self.pending.insert(key, data.to_vec());
+                None
+            },
+            STATUS_CONTINUE => {
+                let over = match self.pending.get_mut(&key) {
+                    Some(buf) if buf.len() + data.len() + 2 <= self.max_message => {
+                        buf.extend_from_slice(data);
+                        false
+                    },
+                    Some(_) => true,
+                    None => false,
+                };
+                if over {
+                    warn!("dropping sysex over {} bytes", self.max_message);
+                    self.pending.remove(&key);
+                }
+                None
+            },
+            STATUS_END => {
+                let mut buf = self.pending.remove(&key)?;
+                if buf.len() + data.len() + 2 > self.max_message {
+                    warn!("dropping sysex over {} bytes", self.max_message);
+                    return None;
+                }
+                buf.extend_from_slice(data);
+                Some(framed(&buf))
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Default for SysexAssembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn framed(data: &[u8]) -> Vec<u8> {
+    let mut msg = Vec::with_capacity(data.len() + 2);
+    msg.push(0xf0);
+    msg.extend_from_slice(data);
+    msg.push(0xf7);
+    msg
+}
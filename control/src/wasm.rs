@@ -0,0 +1,93 @@
+//! The map and codec for browser editors, built with
+//! `cargo build --target wasm32-unknown-unknown --no-default-features --features wasm`
+//! and then `wasm-bindgen`.  Maps come in as JSON text and lists and
+//! objects go back out as JSON, ex:
+//! ```js
+//! let map;
+//! try {
+//!   map = new Map(await (await fetch("sysex-maps/jupx.json")).text());
+//! } catch (problems) {
+//!   console.error(JSON.parse(problems));
+//! }
+//! midiOutput.send(map.encode_param("Temporary Scene/Scene Common/Scene Level", 100));
+//! ```
+//! Messages are `Uint8Array`s ready for Web MIDI.
+
+use wasm_bindgen::prelude::*;
+
+use crate::codec::{decode_dump, encode_param_dt1, encode_rq1};
+use crate::human::{format_value, parse_value};
+use crate::sysex_map::{ParamIndex, SysexMap};
+
+fn to_json<T: serde::Serialize>(value: &T) -> String {
+    // Only ever strings and numbers, which always serialize.
+    serde_json::to_string(value).unwrap()
+}
+
+/// A device's sysex map with its params resolved.
+#[wasm_bindgen]
+pub struct Map {
+    map: SysexMap,
+    index: ParamIndex,
+}
+
+impl Map {
+    fn param(&self, name: &str) -> Result<usize, JsValue> {
+        self.index.index_of(name).ok_or_else(|| JsValue::from_str(&format!("no param '{}'", name)))
+    }
+}
+
+#[wasm_bindgen]
+impl Map {
+    /// Parse a map from its JSON.  Throws the problems with it as a JSON
+    /// array of strings if it doesn't parse or validate.
+    #[wasm_bindgen(constructor)]
+    pub fn new(json: &str) -> Result<Map, JsValue> {
+        let map: SysexMap = serde_json::from_str(json)
+            .map_err(|e| JsValue::from_str(&to_json(&[e.to_string()])))?;
+        let problems = map.validate();
+        if !problems.is_empty() {
+            return Err(JsValue::from_str(&to_json(&problems)));
+        }
+        let index = map.resolve();
+        Ok(Map { map, index })
+    }
+
+    /// The names of the params in address order, as a JSON array.
+    pub fn params(&self) -> String {
+        to_json(&self.index.params.iter().map(|p| &p.name).collect::<Vec<_>>())
+    }
+
+    /// A raw value as the display shows it, ex: "ON" or "-12", leaving out
+    /// `depends_on` overrides since there are no other values to check.
+    pub fn format_value(&self, name: &str, raw: u32) -> Result<String, JsValue> {
+        let param = self.param(name)?;
+        Ok(format_value(&self.index.params[param].entry, raw))
+    }
+
+    /// The raw value for a human one, if it's valid for the param.
+    pub fn parse_value(&self, name: &str, text: &str) -> Result<Option<u32>, JsValue> {
+        let param = self.param(name)?;
+        Ok(parse_value(&self.index.params[param].entry, text))
+    }
+
+    /// Every param in a dump (ex: a .syx file's contents) as a JSON object
+    /// of names to raw values.
+    pub fn decode_dump(&self, bytes: &[u8]) -> String {
+        to_json(&decode_dump(&self.map, &self.index, bytes))
+    }
+
+    /// The DT1 message that sets a param to a raw value.
+    pub fn encode_param(&self, name: &str, value: u32) -> Result<Vec<u8>, JsValue> {
+        let param = self.param(name)?;
+        Ok(encode_param_dt1(&self.map, &self.index.params[param], value))
+    }
+
+    /// The RQ1 messages that read a whole patch, one after another; each
+    /// ends with 0xF7.
+    pub fn dump_requests(&self) -> Vec<u8> {
+        self.map.dump_spans(&self.index).into_iter()
+            .flat_map(|(address, size)| encode_rq1(&self.map, address, size))
+            .collect()
+    }
+}
@@ -0,0 +1,290 @@
+//! A bridge between browser editors and the local MIDI ports, for hardware
+//! the browser's Web MIDI can't see (or browsers without Web MIDI at all).
+//! `mapatron bridge` serves a WebSocket that speaks JSON, ex:
+//! ```json
+//! { "type": "list" }                                -> { "type": "ports", "ports": ["JUPITER-X MIDI 1"] }
+//! { "type": "open", "port": "JUPITER-X MIDI 1" }    -> { "type": "opened", "port": "JUPITER-X MIDI 1" }
+//! { "type": "send", "port": "JUPITER-X MIDI 1", "data": [240, 65, 16, 247] }
+//! { "type": "midi", "port": "JUPITER-X MIDI 1", "data": [240, 65, 16, 247] } (from the port)
+//! { "type": "close", "port": "JUPITER-X MIDI 1" }
+//! ```
+//! Clients are told apart by their page's origin.  An origin listed in the
+//! config only sees and opens its own ports, ex:
+//! `"bridge": { "clients": [{ "origin": "https://editor.example", "ports": ["JUPITER-X"] }] }`;
+//! any other origin is asked about on the terminal the first time it opens
+//! each port, unless `prompt` is off, in which case it's refused.
+
+use futures_util::{SinkExt, StreamExt};
+use log::{info, warn};
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::Message;
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::io::{self, BufRead, Write};
+use std::sync::{Arc, Mutex};
+
+/// A configured client and the ports it may use.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BridgeClient {
+    pub origin: String,
+    /// Prefixes of the port names.
+    pub ports: Vec<String>,
+}
+
+/// The `bridge` section of the config.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BridgeConfig {
+    #[serde(default = "default_listen")]
+    pub listen: String,
+    #[serde(default)]
+    pub clients: Vec<BridgeClient>,
+    /// Ask on the terminal before letting unlisted origins open a port.
+    #[serde(default = "default_prompt")]
+    pub prompt: bool,
+}
+
+fn default_listen() -> String {
+    "127.0.0.1:8765".to_string()
+}
+
+fn default_prompt() -> bool {
+    true
+}
+
+impl Default for BridgeConfig {
+    fn default() -> Self {
+        BridgeConfig {
+            listen: default_listen(),
+            clients: vec![],
+            prompt: default_prompt(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    List,
+    Open { port: String },
+    Close { port: String },
+    Send { port: String, data: Vec<u8> },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Ports { ports: Vec<String> },
+    Opened { port: String },
+    Denied { port: String, reason: String },
+    Closed { port: String },
+    Midi { port: String, data: Vec<u8> },
+    Error { message: String },
+}
+
+/// Who may use which ports, shared by every client.
+struct Permissions {
+    config: BridgeConfig,
+    /// (origin, port) pairs allowed at the prompt, for as long as we run.
+    granted: Mutex<HashSet<(String, String)>>,
+    /// So only one question is on the terminal at a time.
+    prompting: tokio::sync::Mutex<()>,
+}
+
+impl Permissions {
+    fn scope(&self, origin: &str) -> Option<&[String]> {
+        self.config.clients.iter().find(|c| c.origin == origin).map(|c| c.ports.as_slice())
+    }
+
+    fn visible(&self, origin: &str, port: &str) -> bool {
+        match self.scope(origin) {
+            Some(prefixes) => prefixes.iter().any(|prefix| port.starts_with(prefix.as_str())),
+            None => true,
+        }
+    }
+
+    async fn allow(&self, origin: &str, port: &str) -> Result<(), String> {
+        if self.scope(origin).is_some() {
+            return if self.visible(origin, port) {
+                Ok(())
+            } else {
+                Err("not one of this client's ports".to_string())
+            };
+        }
+        let key = (origin.to_string(), port.to_string());
+        if self.granted.lock().unwrap().contains(&key) {
+            return Ok(());
+        }
+        if !self.config.prompt {
+            return Err("origin isn't in the bridge config".to_string());
+        }
+        let _prompting = self.prompting.lock().await;
+        let question = format!("allow {} to use {}? [y/N] ", origin, port);
+        let answer = tokio::task::spawn_blocking(move || {
+            print!("{}", question);
+            io::stdout().flush().ok();
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line).map(|_| line)
+        }).await.map_err(|e| e.to_string())?.map_err(|e| e.to_string())?;
+        if answer.trim().eq_ignore_ascii_case("y") {
+            self.granted.lock().unwrap().insert(key);
+            Ok(())
+        } else {
+            Err("refused".to_string())
+        }
+    }
+}
+
+/// Names of every input and output port, once each.
+fn port_names() -> Result<Vec<String>, Box<dyn Error>> {
+    let midi_in = MidiInput::new("mapatron-bridge")?;
+    let midi_out = MidiOutput::new("mapatron-bridge")?;
+    let mut names: Vec<String> = midi_in.ports().iter().filter_map(|p| midi_in.port_name(p).ok()).collect();
+    for name in midi_out.ports().iter().filter_map(|p| midi_out.port_name(p).ok()) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    Ok(names)
+}
+
+/// One client's open ports.
+struct Session {
+    origin: String,
+    permissions: Arc<Permissions>,
+    inputs: HashMap<String, MidiInputConnection<()>>,
+    outputs: HashMap<String, MidiOutputConnection>,
+    /// Where input callbacks send what comes in.
+    incoming: mpsc::UnboundedSender<ServerMessage>,
+}
+
+impl Session {
+    async fn handle(&mut self, msg: ClientMessage) -> Option<ServerMessage> {
+        match msg {
+            ClientMessage::List => Some(match port_names() {
+                Ok(ports) => ServerMessage::Ports {
+                    ports: ports.into_iter().filter(|port| self.permissions.visible(&self.origin, port)).collect(),
+                },
+                Err(e) => ServerMessage::Error { message: e.to_string() },
+            }),
+            ClientMessage::Open { port } => {
+                if let Err(reason) = self.permissions.allow(&self.origin, &port).await {
+                    return Some(ServerMessage::Denied { port, reason });
+                }
+                Some(match self.open(&port) {
+                    Ok(()) => {
+                        info!("{} opened {}", self.origin, port);
+                        ServerMessage::Opened { port }
+                    },
+                    Err(e) => ServerMessage::Denied { port, reason: e.to_string() },
+                })
+            },
+            ClientMessage::Close { port } => {
+                self.inputs.remove(&port);
+                self.outputs.remove(&port);
+                Some(ServerMessage::Closed { port })
+            },
+            ClientMessage::Send { port, data } => {
+                let output = match self.outputs.get_mut(&port) {
+                    Some(output) => output,
+                    None => return Some(ServerMessage::Error { message: format!("{} isn't open", port) }),
+                };
+                output.send(&data).err().map(|e| ServerMessage::Error { message: format!("{}: {}", port, e) })
+            },
+        }
+    }
+
+    /// Open the input and output called `port`; ports are often only one.
+    fn open(&mut self, port: &str) -> Result<(), Box<dyn Error>> {
+        let mut midi_in = MidiInput::new("mapatron-bridge")?;
+        midi_in.ignore(Ignore::None);
+        let midi_out = MidiOutput::new("mapatron-bridge")?;
+        let in_port = midi_in.ports().into_iter().find(|p| midi_in.port_name(p).ok().as_deref() == Some(port));
+        let out_port = midi_out.ports().into_iter().find(|p| midi_out.port_name(p).ok().as_deref() == Some(port));
+        if in_port.is_none() && out_port.is_none() {
+            return Err(format!("no port '{}'", port).into());
+        }
+        if let (Some(in_port), false) = (in_port, self.inputs.contains_key(port)) {
+            let incoming = self.incoming.clone();
+            let name = port.to_string();
+            let conn = midi_in.connect(&in_port, "mapatron-bridge-in", move |_stamp, msg, _| {
+                // The client's gone if this fails; its session closes the port.
+                let _ = incoming.send(ServerMessage::Midi { port: name.clone(), data: msg.to_vec() });
+            }, ()).map_err(|e| format!("can't open input '{}': {}", port, e))?;
+            self.inputs.insert(port.to_string(), conn);
+        }
+        if let (Some(out_port), false) = (out_port, self.outputs.contains_key(port)) {
+            let conn = midi_out.connect(&out_port, "mapatron-bridge-out")
+                .map_err(|e| format!("can't open output '{}': {}", port, e))?;
+            self.outputs.insert(port.to_string(), conn);
+        }
+        Ok(())
+    }
+}
+
+async fn client(stream: TcpStream, permissions: Arc<Permissions>) -> Result<(), Box<dyn Error>> {
+    let mut origin = None;
+    // The error response's size is up to tungstenite.
+    #[allow(clippy::result_large_err)]
+    let note_origin = |request: &Request, response: Response| {
+        origin = request.headers().get("origin").and_then(|o| o.to_str().ok()).map(str::to_string);
+        Ok(response)
+    };
+    let ws = tokio_tungstenite::accept_hdr_async(stream, note_origin).await?;
+    // Programs that aren't browsers don't send one.
+    let origin = origin.unwrap_or_else(|| "a local program".to_string());
+    info!("{} connected", origin);
+
+    let (incoming, mut from_ports) = mpsc::unbounded_channel();
+    let mut session = Session {
+        origin,
+        permissions,
+        inputs: HashMap::new(),
+        outputs: HashMap::new(),
+        incoming,
+    };
+    let (mut to_client, mut from_client) = ws.split();
+    loop {
+        let reply = tokio::select! {
+            msg = from_client.next() => match msg {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(msg) => session.handle(msg).await,
+                    Err(e) => Some(ServerMessage::Error { message: e.to_string() }),
+                },
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => None,
+                Some(Err(e)) => return Err(e.into()),
+            },
+            Some(msg) = from_ports.recv() => Some(msg),
+        };
+        if let Some(reply) = reply {
+            to_client.send(Message::Text(serde_json::to_string(&reply)?)).await?;
+        }
+    }
+    info!("{} disconnected", session.origin);
+    Ok(())
+}
+
+/// Accept clients on `config.listen` until the process exits.
+pub async fn serve(config: &BridgeConfig) -> Result<(), Box<dyn Error>> {
+    let mut listener = TcpListener::bind(&config.listen).await?;
+    info!("bridging MIDI on ws://{}", config.listen);
+    let permissions = Arc::new(Permissions {
+        config: config.clone(),
+        granted: Mutex::new(HashSet::new()),
+        prompting: tokio::sync::Mutex::new(()),
+    });
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let permissions = permissions.clone();
+        tokio::spawn(async move {
+            if let Err(e) = client(stream, permissions).await {
+                warn!("bridge client {}: {}", peer, e);
+            }
+        });
+    }
+}
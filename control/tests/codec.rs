@@ -0,0 +1,62 @@
+//! Checksums, and what happens to incoming messages that fail them.
+
+mod common;
+
+use control::codec::{encode_dt1, parse_dt1, Checksum, ChecksumPolicy};
+
+use common::fixture_map;
+
+#[test]
+fn roland_checksum_covers_address_and_data() {
+    // The classic example from Roland's manuals: 40 00 7f 00 sums to 0x3f.
+    assert_eq!(Checksum::Roland.compute(&[0x40, 0x00, 0x7f, 0x00]), Some(0x41));
+    assert_eq!(Checksum::Roland.compute(&[0x40, 0x00, 0x7f, 0x00, 0x01]), Some(0x40));
+}
+
+#[test]
+fn twos_complement_checksum_skips_the_address() {
+    // Yamaha-style: only the data counts, so the address makes no difference.
+    assert_eq!(Checksum::Kawai.compute(&[0x40, 0x00, 0x7f, 0x00, 0x10, 0x20]), Some(0x50));
+    assert_eq!(Checksum::Kawai.compute(&[0x00, 0x00, 0x00, 0x00, 0x10, 0x20]), Some(0x50));
+    assert_eq!(Checksum::Kawai.compute(&[0x40, 0x00, 0x7f, 0x00]), Some(0x00));
+}
+
+#[test]
+fn xor_checksum_is_masked_to_seven_bits() {
+    assert_eq!(Checksum::Xor.compute(&[0x40, 0x00, 0x7f, 0x00]), Some(0x3f));
+    assert_eq!(Checksum::Xor.compute(&[0x7f, 0x7f]), Some(0x00));
+}
+
+#[test]
+fn no_checksum_means_no_byte() {
+    assert!(!Checksum::None.is_present());
+    assert_eq!(Checksum::None.compute(&[0x40, 0x00, 0x7f, 0x00]), None);
+}
+
+#[test]
+fn mismatched_checksum_follows_the_policy() {
+    let mut map = fixture_map("jupx");
+    let msg = encode_dt1(&map, 0x100, &[0x12, 0x34]);
+    let mut bad = msg.clone();
+    let checksum = bad.len() - 2;
+    bad[checksum] ^= 0x01;
+
+    assert_eq!(map.checksum_policy, ChecksumPolicy::Reject);
+    assert_eq!(parse_dt1(&map, &msg).map(|set| set.data.to_vec()), Some(vec![0x12, 0x34]));
+    assert!(parse_dt1(&map, &bad).is_none());
+
+    for policy in &[ChecksumPolicy::Warn, ChecksumPolicy::Accept] {
+        map.checksum_policy = *policy;
+        let set = parse_dt1(&map, &bad).unwrap();
+        assert_eq!((set.address, set.data), (0x100, &[0x12, 0x34][..]));
+    }
+}
+
+#[test]
+fn no_checksum_round_trips() {
+    let mut map = fixture_map("jupx");
+    map.checksum = Checksum::None;
+    let msg = encode_dt1(&map, 0x100, &[0x12, 0x34]);
+    assert_eq!(msg.len(), encode_dt1(&fixture_map("jupx"), 0x100, &[0x12, 0x34]).len() - 1);
+    assert_eq!(parse_dt1(&map, &msg).map(|set| set.data.to_vec()), Some(vec![0x12, 0x34]));
+}
@@ -0,0 +1,17 @@
+//! Fixtures for tests that don't need the whole `harness`.
+
+// Not every test file uses every helper.
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+
+use control::SysexMap;
+
+pub fn fixture(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(path)
+}
+
+/// `device`'s fixture map, ex: "jupx".
+pub fn fixture_map(device: &str) -> SysexMap {
+    SysexMap::load(fixture(device).join("map.json").to_str().unwrap()).unwrap()
+}
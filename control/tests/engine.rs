@@ -0,0 +1,657 @@
+//! End-to-end tests of the binding engine against a simulated Jupiter-X:
+//! controller events in, sysex and LEDs out, and the synth's memory checked
+//! over RQ1 afterwards.
+
+mod common;
+mod harness;
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use control::annotate::{annotate_sysex, MessageKind, SpanKind};
+use control::bindings::{BindingEvent, Control};
+use control::bus::{EngineEvent, EventBus};
+use control::clock::{Clock, ClockConfig, ClockQueue};
+use control::compositor::{Compositor, Layer};
+use control::decode::DecodePool;
+use control::favorites::{Favorites, FAVORITES_PAGE};
+use control::health::Health;
+use control::launcher::Launcher;
+use control::motion::Motions;
+use control::pacing::{Outbox, Priority};
+use control::plugin::{MapatronPlugin, PluginContext, PluginRegistry};
+use control::ports::{resolve, PortAliases};
+use control::progress::WRITES_PER_TICK;
+use control::rules::{RuleContext, Rules};
+use control::scheduler::TICK;
+use control::timers::{Fired, Timers};
+use control::{ApcMini, ButtonState, ControllerCaps, ControllerEvent, LedBuffer, Model, Phase};
+
+use harness::{fixture, Rig};
+
+const LEVEL: &str = "Temporary Scene/Scene Common/Scene Level";
+const PART_LEVEL: &str = "Temporary Scene/Scene Part 1/Part Level";
+const MUTE: &str = "Temporary Scene/Scene Part 2/Part Mute Switch";
+const COARSE: &str = "Temporary Scene/Scene Part 1/Part Coarse Tune";
+/// Marked `persistent` in the fixture map.
+const TEMPO: &str = "Temporary Scene/Scene Common/Scene Tempo";
+
+#[tokio::test]
+async fn pad_writes_value() {
+    let mut rig = Rig::new("jupx", &format!(r#"{{ "bindings": [
+        {{ "control": {{ "pad": 3 }}, "param": "{}", "value": 100 }}
+    ] }}"#, LEVEL));
+
+    rig.press(3);
+    rig.release(3);
+    assert_eq!(rig.take_sent(), vec![rig.dt1(LEVEL, 100)]);
+    assert_eq!(rig.value(LEVEL), 100);
+    assert_eq!(rig.synth_value(LEVEL).await, 100);
+}
+
+#[tokio::test]
+async fn encoder_clamps_to_range() {
+    let mut rig = Rig::new("jupx", &format!(r#"{{ "bindings": [
+        {{ "control": {{ "encoder": 0 }}, "param": "{}", "max": 10 }}
+    ] }}"#, PART_LEVEL));
+
+    rig.turn(0, 4);
+    rig.turn(0, 4);
+    rig.turn(0, 4);
+    assert_eq!(rig.take_sent(), vec![rig.dt1(PART_LEVEL, 4), rig.dt1(PART_LEVEL, 8),
+                                     rig.dt1(PART_LEVEL, 10)]);
+
+    // Already at the top: nothing more goes out.
+    rig.turn(0, 1);
+    assert!(rig.take_sent().is_empty());
+    assert_eq!(rig.synth_value(PART_LEVEL).await, 10);
+
+    rig.turn(0, -3);
+    assert_eq!(rig.take_sent(), vec![rig.dt1(PART_LEVEL, 7)]);
+    assert_eq!(rig.synth_value(PART_LEVEL).await, 7);
+}
+
+#[tokio::test]
+async fn momentary_pad_restores_on_release() {
+    let mut rig = Rig::new("jupx", &format!(r#"{{ "bindings": [
+        {{ "control": {{ "pad": 0 }}, "param": "{0}", "value": 1, "action": "momentary" }},
+        {{ "control": {{ "pad": 1 }}, "param": "{0}", "value": 1, "action": {{ "toggle": {{}} }} }}
+    ] }}"#, MUTE));
+
+    rig.press(0);
+    assert_eq!(rig.synth_value(MUTE).await, 1);
+    rig.release(0);
+    assert_eq!(rig.synth_value(MUTE).await, 0);
+
+    rig.press(1);
+    rig.release(1);
+    assert_eq!(rig.synth_value(MUTE).await, 1);
+    rig.press(1);
+    assert_eq!(rig.synth_value(MUTE).await, 0);
+    assert_eq!(rig.take_sent().len(), 4);
+}
+
+#[test]
+fn toggle_pad_stays_in_binding_range() {
+    let mut rig = Rig::new("jupx", &format!(r#"{{ "bindings": [
+        {{ "control": {{ "pad": 0 }}, "param": "{}", "value": 120, "min": 20, "max": 100,
+           "action": {{ "toggle": {{}} }} }}
+    ] }}"#, PART_LEVEL));
+
+    // On is clamped to the max, and off is the min rather than the param's 0.
+    rig.press(0);
+    rig.press(0);
+    rig.press(0);
+    assert_eq!(rig.take_sent(), vec![rig.dt1(PART_LEVEL, 100), rig.dt1(PART_LEVEL, 20),
+                                     rig.dt1(PART_LEVEL, 100)]);
+}
+
+#[test]
+fn controls_bound_twice_are_refused() {
+    let twice = |control: &str| {
+        Rig::try_new("jupx", &format!(r#"{{ "bindings": [
+            {{ "control": {}, "param": "{}", "value": 1 }},
+            {{ "control": {}, "param": "{}", "value": 2 }}
+        ] }}"#, control, LEVEL, control, PART_LEVEL)).err()
+    };
+    assert_eq!(twice(r#"{ "pad": 3 }"#).as_deref(), Some("pad 3 is bound twice"));
+    assert_eq!(twice(r#"{ "encoder": 1 }"#).as_deref(), Some("Encoder(1) is bound twice"));
+    // Radio rows are pads too.
+    let overlapping = Rig::try_new("jupx", &format!(r#"{{ "bindings": [
+        {{ "control": {{ "pad": 3 }}, "param": "{}", "value": 1 }},
+        {{ "control": {{ "radio_row": 0 }}, "param": "{}", "min": 60, "max": 67 }}
+    ] }}"#, LEVEL, COARSE)).err();
+    assert_eq!(overlapping.as_deref(), Some("pad 3 is bound twice"));
+
+    // So are the pads under an XY square, whichever comes first.
+    let xy = format!(r#"{{ "control": {{ "xy": {{ "pad": 0, "size": 4 }} }},
+        "param": "{}", "param_y": "{}" }}"#, LEVEL, PART_LEVEL);
+    let pad = format!(r#"{{ "control": {{ "pad": 17 }}, "param": "{}", "value": 1 }}"#, COARSE);
+    for (first, second) in [(&xy, &pad), (&pad, &xy)] {
+        let err = Rig::try_new("jupx", &format!(r#"{{ "bindings": [{}, {}] }}"#, first, second)).err();
+        assert_eq!(err.as_deref(), Some("pad 17 is bound twice"));
+    }
+}
+
+#[test]
+fn radio_row_lights_current_value() {
+    let mut rig = Rig::new("jupx", &format!(r#"{{ "bindings": [
+        {{ "control": {{ "radio_row": 2 }}, "param": "{}", "min": 60, "max": 67 }}
+    ] }}"#, COARSE));
+
+    rig.press(32 + 4);
+    assert_eq!(rig.take_sent(), vec![rig.dt1(COARSE, 64)]);
+    rig.render_leds();
+    let on = rig.led(32 + 4);
+    for pad in 32..40 {
+        if pad != 32 + 4 {
+            assert_ne!(rig.led(pad), on, "pad {} is lit", pad);
+        }
+    }
+
+    rig.press(32);
+    rig.render_leds();
+    assert_eq!(rig.led(32), on);
+    assert_ne!(rig.led(32 + 4), on);
+    // Pads outside the row are left alone.
+    assert_eq!(rig.led(40), (0, 0, 0));
+}
+
+#[test]
+fn xy_pad_sets_both_axes() {
+    let mut rig = Rig::new("jupx", &format!(r#"{{ "bindings": [
+        {{ "control": {{ "xy": {{ "pad": 0, "size": 4 }} }}, "param": "{}", "param_y": "{}" }}
+    ] }}"#, LEVEL, PART_LEVEL));
+
+    // Top right of the square: both at the top of their ranges.
+    rig.press(3);
+    assert_eq!(rig.take_sent(), vec![rig.dt1(LEVEL, 127), rig.dt1(PART_LEVEL, 127)]);
+
+    // Bottom left: both at the bottom.
+    rig.press(48);
+    assert_eq!(rig.take_sent(), vec![rig.dt1(LEVEL, 0), rig.dt1(PART_LEVEL, 0)]);
+    rig.render_leds();
+    assert_ne!(rig.led(48), rig.led(3));
+}
+
+#[tokio::test]
+async fn fader_picks_up_before_writing() {
+    let mut rig = Rig::with_caps("jupx", &format!(r#"{{ "bindings": [
+        {{ "control": {{ "pad": 3 }}, "param": "{0}", "value": 100 }},
+        {{ "control": {{ "fader": 0 }}, "param": "{0}" }}
+    ] }}"#, LEVEL), ApcMini.caps());
+
+    rig.press(3);
+    rig.take_sent();
+
+    // Below the param's value, so the fader hasn't got hold of it yet.
+    rig.slide(0, 0x20);
+    rig.slide(0, 0x40);
+    assert!(rig.take_sent().is_empty());
+
+    // Passing 100 picks it up.
+    rig.slide(0, 0x70);
+    rig.slide(0, 0x60);
+    assert_eq!(rig.take_sent(), vec![rig.dt1(LEVEL, 0x70), rig.dt1(LEVEL, 0x60)]);
+    assert_eq!(rig.synth_value(LEVEL).await, 0x60);
+
+    // A pad moving the param away makes the fader pick it up again.
+    rig.press(3);
+    rig.take_sent();
+    rig.slide(0, 0x50);
+    assert!(rig.take_sent().is_empty());
+}
+
+#[test]
+fn favorites_page_puts_most_edited_on_encoders() {
+    let mut rig = Rig::new("jupx", &format!(r#"{{ "bindings": [
+        {{ "control": {{ "encoder": 0 }}, "param": "{}" }}
+    ] }}"#, PART_LEVEL));
+    // Empty, so no counts from another run get in.
+    let dir = std::env::temp_dir().join(format!("mapatron-favorites-test-{}", std::process::id()));
+    let mut favorites = Favorites::load(dir.to_str().unwrap(), "none");
+    let index = rig.synth.store().index();
+    // Edits in a row count once.
+    for name in &[COARSE, COARSE, COARSE, LEVEL, COARSE, LEVEL] {
+        favorites.note(index, rig.param(name));
+    }
+    let top = favorites.top(index, 2);
+    assert_eq!(top, vec![rig.param(LEVEL), rig.param(COARSE)]);
+
+    rig.engine.set_favorites(rig.synth.map(), &top);
+    rig.engine.set_page(FAVORITES_PAGE);
+    rig.turn(0, 3);
+    assert_eq!(rig.take_sent(), vec![rig.dt1(LEVEL, 3)]);
+
+    rig.engine.set_page(0);
+    rig.turn(0, 3);
+    assert_eq!(rig.take_sent(), vec![rig.dt1(PART_LEVEL, 3)]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[tokio::test]
+async fn slewed_binding_ramps_on_tick() {
+    let mut rig = Rig::new("jupx", &format!(r#"{{ "bindings": [
+        {{ "control": {{ "pad": 0 }}, "param": "{}", "value": 120, "slew_ms": 100 }}
+    ] }}"#, LEVEL));
+    let start = Instant::now();
+
+    rig.press(0);
+    assert!(rig.take_sent().is_empty(), "slewed writes come out of tick");
+
+    rig.tick(start + Duration::from_millis(50));
+    let midway = rig.take_sent();
+    assert_eq!(midway.len(), 1);
+    assert!(rig.value(LEVEL) > 0 && rig.value(LEVEL) < 120);
+
+    rig.tick(start + Duration::from_millis(200));
+    assert_eq!(rig.take_sent(), vec![rig.dt1(LEVEL, 120)]);
+    assert_eq!(rig.synth_value(LEVEL).await, 120);
+
+    // Nothing left to ramp.
+    rig.tick(start + Duration::from_millis(300));
+    assert!(rig.take_sent().is_empty());
+}
+
+#[tokio::test]
+async fn persistent_param_is_written_once_settled() {
+    let mut rig = Rig::new("jupx", &format!(r#"{{ "bindings": [
+        {{ "control": {{ "encoder": 0 }}, "param": "{}" }}
+    ] }}"#, TEMPO));
+    let start = Instant::now();
+
+    rig.turn(0, 10);
+    rig.turn(0, 10);
+    assert!(rig.take_sent().is_empty(), "persistent writes come out of tick");
+    assert_eq!(rig.value(TEMPO), 220);
+
+    // Still turning.
+    assert!(rig.tick(start).is_empty());
+    assert!(rig.take_sent().is_empty());
+
+    let ended = rig.tick(start + Duration::from_secs(1));
+    assert_eq!(ended, vec![BindingEvent::GestureEnd { control: Control::Encoder(0), param: rig.param(TEMPO) }]);
+    assert_eq!(rig.take_sent(), vec![rig.dt1(TEMPO, 220)]);
+    assert_eq!(rig.synth_value(TEMPO).await, 220);
+}
+
+#[tokio::test]
+async fn scene_recall_is_paced_and_lands() {
+    let mut rig = Rig::new("jupx", "{ \"bindings\": [] }");
+    let dump = fixture("jupx/scene-dump.syx");
+    let expected: std::collections::BTreeMap<String, u32> = serde_json::from_str(
+        &std::fs::read_to_string(fixture("jupx/scene-dump.json")).unwrap()).unwrap();
+
+    // Roland gets 20ms between writes, so one every other tick.
+    let ticks = rig.recall(&dump);
+    let sent = rig.take_sent();
+    assert_eq!(sent.len(), expected.len());
+    assert_eq!(ticks, 2 * (sent.len() - 1) + 1);
+
+    // Unpaced, it's as fast as the daemon goes: every tick but the last is
+    // full.
+    rig.synth.set_pacing(Duration::from_millis(0));
+    let ticks = rig.recall(&dump);
+    let sent = rig.take_sent().len();
+    assert!((ticks - 1) * WRITES_PER_TICK < sent && sent <= ticks * WRITES_PER_TICK);
+
+    for (name, value) in &expected {
+        assert_eq!(rig.value(name), *value, "{} in the store", name);
+        assert_eq!(rig.synth_value(name).await, *value, "{} on the synth", name);
+    }
+}
+
+#[test]
+fn notes_go_ahead_of_a_recall() {
+    let mut outbox = Outbox::new(Duration::from_millis(20));
+    let writes: Vec<Vec<u8>> = (0..3).map(|i| vec![0xf0, 0x41, i, 0xf7]).collect();
+    for write in &writes {
+        outbox.push(Priority::Bulk, write);
+    }
+    let (note_on, all_notes_off) = ([0x90, 60, 100], [0xb0, 123, 0]);
+    outbox.push(Priority::of(&note_on), &note_on);
+
+    // The note first, then a write for the one pacing slot that's due.
+    let now = Instant::now();
+    let mut sent = vec![];
+    outbox.flush(now, |msg| sent.push(msg.to_vec()));
+    assert_eq!(sent, vec![note_on.to_vec(), writes[0].clone()]);
+
+    // Realtime messages don't wait for a slot.
+    outbox.push(Priority::of(&all_notes_off), &all_notes_off);
+    sent.clear();
+    outbox.flush(now + TICK, |msg| sent.push(msg.to_vec()));
+    assert_eq!(sent, vec![all_notes_off.to_vec()]);
+    assert_eq!(outbox.len(Priority::Bulk), 2);
+}
+
+#[test]
+fn cancelled_recall_only_reports_what_went_out() {
+    let rig = Rig::new("jupx", "{ \"bindings\": [] }");
+    let (level, part_level) = (rig.param(LEVEL), rig.param(PART_LEVEL));
+    let mut outbox = Outbox::new(Duration::from_millis(20));
+    outbox.push_write(Priority::Bulk, &rig.dt1(LEVEL, 100), level, 100);
+    outbox.push_write(Priority::Bulk, &rig.dt1(PART_LEVEL, 50), part_level, 50);
+
+    let now = Instant::now();
+    assert_eq!(outbox.flush(now, |_| ()), vec![(level, 100)]);
+    outbox.clear(Priority::Bulk);
+    assert!(outbox.flush(now + Duration::from_millis(20), |_| ()).is_empty());
+}
+
+#[test]
+fn only_changed_pads_are_sent() {
+    let mut compositor = Compositor::new();
+    let mut leds = LedBuffer::new();
+    compositor.set(Layer::Base, 0, 0x7f, 0, 0);
+    compositor.set(Layer::Base, 1, 0x7f, 0, 0);
+    compositor.render(|i, r, g, b| leds.set_led(i, r, g, b));
+    let sent = leds.clone();
+
+    // The status covers a binding's pad, so only that pad goes out.
+    compositor.clear();
+    compositor.set(Layer::Base, 0, 0x7f, 0, 0);
+    compositor.set(Layer::Status, 1, 0, 0x7f, 0);
+    compositor.render(|i, r, g, b| leds.set_led(i, r, g, b));
+    assert_eq!(leds.led(1), (0, 0x7f, 0));
+    assert_eq!(leds.changed_since(Some(&sent)), vec![1]);
+    assert_eq!(leds.message_for(&[1]), vec![0xf0, 0x47, 0x7f, 0x43, 0x65, 0, 4, 1, 0, 0x7f, 0, 0xf7]);
+    assert_eq!(leds.changed_since(None).len(), 64);
+}
+
+#[test]
+fn writes_are_annotated_with_their_params() {
+    let rig = Rig::new("jupx", "{ \"bindings\": [] }");
+    let mut msg = rig.dt1(LEVEL, 100);
+    let annotated = annotate_sysex(rig.synth.map(), rig.synth.store().index(), &msg);
+    assert_eq!(annotated.kind, MessageKind::Write);
+    assert_eq!(annotated.params(), vec![(LEVEL, 100)]);
+    assert!(annotated.spans.iter().any(|span| span.kind == SpanKind::Checksum { ok: true }));
+
+    let checksum = msg.len() - 2;
+    msg[checksum] ^= 1;
+    let annotated = annotate_sysex(rig.synth.map(), rig.synth.store().index(), &msg);
+    assert!(annotated.spans.iter().any(|span| span.kind == SpanKind::Checksum { ok: false }));
+}
+
+#[test]
+fn command_pad_sends_its_message() {
+    // Spelled out by hand, a command writing the level is the same as the
+    // DT1.
+    let address = {
+        let rig = Rig::new("jupx", "{ \"bindings\": [] }");
+        rig.dt1(LEVEL, 100)[8..12].iter().map(|b| format!("{:02X}", b)).collect::<Vec<_>>().join(" ")
+    };
+    let mut rig = Rig::new("jupx", &format!(r#"{{ "bindings": [], "commands": [
+        {{ "control": {{ "pad": 15 }}, "value": 100,
+           "message": "F0 41 {{device_id}} 00 00 00 65 12 {} {{value:7}} {{checksum}} F7" }}
+    ] }}"#, address));
+
+    rig.press(15);
+    rig.release(15);
+    assert_eq!(rig.take_sent(), vec![rig.dt1(LEVEL, 100)]);
+}
+
+#[test]
+fn rule_fires_once_when_it_starts_to_hold() {
+    let bindings = format!(r#"{{ "bindings": [
+        {{ "control": {{ "encoder": 0 }}, "param": "{level}" }}
+    ], "rules": [
+        {{ "when": [{{ "param": "{level}", "op": ">", "value": 100 }}, {{ "page": 0 }}],
+           "then": [{{ "pad": 5, "color": [127, 0, 0] }}, {{ "set": "{part}", "value": 0 }}] }}
+    ] }}"#, level = LEVEL, part = PART_LEVEL);
+    let mut rig = Rig::new("jupx", &bindings);
+    let file = serde_json::from_str(&bindings).unwrap();
+    let mut rules = Rules::new(rig.synth.map(), &file, rig.synth.store(), &ControllerCaps::FIRE).unwrap();
+    let context = RuleContext { song: None, page: 0 };
+    let mut sent = vec![];
+
+    rig.synth.store().set(rig.param(LEVEL), 100);
+    assert!(!rules.evaluate(rig.synth.store(), &context, |msg| sent.push(msg.to_vec())));
+    rig.synth.store().set(rig.param(LEVEL), 101);
+    assert!(rules.evaluate(rig.synth.store(), &context, |msg| sent.push(msg.to_vec())));
+    assert_eq!(sent, vec![rig.dt1(PART_LEVEL, 0)]);
+    assert_eq!(rig.value(PART_LEVEL), 0);
+
+    // Still holding: nothing more goes out.
+    rig.turn(0, 1);
+    rig.take_sent();
+    assert!(!rules.evaluate(rig.synth.store(), &context, |msg| sent.push(msg.to_vec())));
+    assert_eq!(sent.len(), 1);
+    let mut lit = vec![];
+    rules.render(|i, r, g, b| lit.push((i, (r, g, b))));
+    assert_eq!(lit, vec![(5, (127, 0, 0))]);
+}
+
+#[test]
+fn timers_go_off_at_their_times() {
+    let rig = Rig::new("jupx", "{ \"bindings\": [] }");
+    let config = serde_json::from_str(r#"{ "utc_offset_min": 60, "timers": [
+        { "when": { "at": "02:00" }, "action": "dump" },
+        { "when": { "after_s": 30 }, "action": "reload_bindings" }
+    ] }"#).unwrap();
+    // 2021-01-01 00:30 UTC, 01:30 local.
+    let start = 1_609_461_000_000;
+    let mut timers = Timers::new(&config, rig.synth.map(), rig.synth.store(), start).unwrap();
+    let minute = 60_000;
+
+    assert!(timers.poll(start + 29_000).is_empty());
+    assert!(matches!(timers.poll(start + 30_000)[..], [Fired::ReloadBindings]));
+    assert!(timers.poll(start + 29 * minute).is_empty());
+    assert!(matches!(timers.poll(start + 30 * minute)[..], [Fired::Dump]));
+    // Not again until tomorrow.
+    assert!(timers.poll(start + 23 * 60 * minute).is_empty());
+    assert!(matches!(timers.poll(start + 24 * 60 * minute + 30 * minute)[..], [Fired::Dump]));
+}
+
+#[test]
+fn unhealthy_after_a_long_disconnect() {
+    let config = serde_json::from_str(r#"{ "disconnected_s": 30, "max_event_age_s": 600 }"#).unwrap();
+    let start = Instant::now();
+    let mut health = Health::new(&config, start);
+    let at = |s| start + Duration::from_secs(s);
+
+    assert!(health.check(at(1), &[("FL STUDIO FIRE", Phase::Disconnected)]).is_ok());
+    assert!(health.check(at(31), &[("FL STUDIO FIRE", Phase::Disconnected)]).is_err());
+    // Back, so the count starts over.
+    assert!(health.check(at(32), &[("FL STUDIO FIRE", Phase::Ready)]).is_ok());
+    assert!(health.check(at(33), &[("FL STUDIO FIRE", Phase::Disconnected)]).is_ok());
+
+    health.note_event(at(100));
+    assert!(health.check(at(699), &[]).is_ok());
+    assert!(health.check(at(700), &[]).is_err());
+}
+
+#[test]
+fn port_aliases_resolve_to_todays_names() {
+    let aliases: PortAliases = serde_json::from_str(r#"{
+        "jupX": { "contains": "JUPITER-X MIDI 1" },
+        "pedals": { "regex": "^FCB1010 .* [0-9]+:0$" }
+    }"#).unwrap();
+    let ports = vec!["JUPITER-X:JUPITER-X MIDI 1 28:0".to_string(),
+                     "FCB1010 MIDI:FCB1010 MIDI 1 32:0".to_string()];
+
+    assert_eq!(resolve(&aliases, "jupX", &ports).unwrap(), ports[0]);
+    assert_eq!(resolve(&aliases, "pedals", &ports).unwrap(), ports[1]);
+    // Not an alias, so left as it is.
+    assert_eq!(resolve(&aliases, "FL STUDIO", &ports).unwrap(), "FL STUDIO");
+    assert!(resolve(&aliases, "jupX", &ports[1..]).is_err());
+}
+
+#[test]
+fn launcher_bank_plays_clips_and_shows_the_daws_leds() {
+    let config = serde_json::from_str(r#"{ "port": "Bitwig", "pad": 18, "rows": 2, "columns": 4,
+        "channel": 2, "first_note": 36,
+        "transport": [{ "control": { "pad": 0 }, "command": "play" }] }"#).unwrap();
+    let mut launcher = Launcher::new(&config, &ControllerCaps::FIRE).unwrap();
+    let pad = |pad: u8, state| ControllerEvent::GridButton(pad, pad / 16, pad % 16, state, 0x64);
+    let mut sent = vec![];
+
+    // Second row, second column of the bank.
+    assert!(launcher.claims(&pad(35, ButtonState::Down)));
+    assert!(!launcher.claims(&pad(17, ButtonState::Down)));
+    launcher.handle(&pad(35, ButtonState::Down), |msg| sent.push(msg.to_vec()));
+    launcher.handle(&pad(35, ButtonState::Up), |msg| sent.push(msg.to_vec()));
+    launcher.handle(&pad(0, ButtonState::Down), |msg| sent.push(msg.to_vec()));
+    assert_eq!(sent, vec![vec![0x91, 41, 0x64], vec![0x81, 41, 0], vec![0xf0, 0x7f, 0x7f, 0x06, 0x02, 0xf7]]);
+
+    // Green, then another channel's, which isn't ours.
+    assert!(launcher.feedback(&[0x91, 41, 1]));
+    assert!(!launcher.feedback(&[0x91, 41, 1]));
+    assert!(!launcher.feedback(&[0x90, 42, 3]));
+    let mut lit = vec![];
+    launcher.render(|i, r, g, b| if (r, g, b) != (0, 0, 0) { lit.push(i) });
+    assert_eq!(lit, vec![35]);
+
+    let too_big = serde_json::from_str(r#"{ "port": "Bitwig", "pad": 14, "rows": 1, "columns": 4 }"#)
+        .unwrap();
+    assert!(Launcher::new(&too_big, &ControllerCaps::FIRE).is_err());
+}
+
+#[test]
+fn motion_pad_plays_back_what_was_recorded() {
+    let bindings = format!(r#"{{ "bindings": [
+        {{ "control": {{ "encoder": 0 }}, "param": "{}" }}
+    ], "motions": [{{ "control": {{ "pad": 48 }}, "playback": "loop" }}] }}"#, LEVEL);
+    let mut rig = Rig::new("jupx", &bindings);
+    let file = serde_json::from_str(&bindings).unwrap();
+    let mut motions = Motions::new(&file, rig.synth.store().clone(), &ControllerCaps::FIRE).unwrap();
+    let pad = |state| ControllerEvent::GridButton(48, 3, 0, state, 0);
+    let start = Instant::now();
+    let at = |ms| start + Duration::from_millis(ms);
+    rig.synth.store().set(rig.param(LEVEL), 0);
+
+    // Held while turning: recorded.
+    assert!(motions.claims(&pad(ButtonState::Down)));
+    motions.handle(&pad(ButtonState::Down), at(0));
+    for (ms, delta) in [(100, 10), (200, 10), (300, -20)] {
+        rig.turn(0, delta);
+        motions.note(&ControllerEvent::Encoder(0, delta), &rig.engine.encoder_params(), at(ms));
+    }
+    motions.handle(&pad(ButtonState::Up), at(400));
+    rig.take_sent();
+
+    // Tapped: played, over and over.
+    motions.handle(&pad(ButtonState::Down), at(1000));
+    motions.handle(&pad(ButtonState::Up), at(1000));
+    assert_eq!(motions.tick(at(1000)), vec![(rig.param(LEVEL), 10)]);
+    assert_eq!(motions.tick(at(1050)), vec![(rig.param(LEVEL), 15)]);
+    assert_eq!(motions.tick(at(1150)), vec![(rig.param(LEVEL), 10)]);
+    // Back to the start, which is 10 too.
+    assert!(motions.tick(at(1200)).is_empty());
+    assert_eq!(motions.tick(at(1250)), vec![(rig.param(LEVEL), 15)]);
+
+    // Twice as fast at 4 beats of 480bpm, which is 500ms.
+    let file = serde_json::from_str(&bindings.replace("\"loop\"", "\"loop\", \"beats\": 4")).unwrap();
+    let mut synced = Motions::new(&file, rig.synth.store().clone(), &ControllerCaps::FIRE).unwrap();
+    synced.set_bpm(480.0);
+    synced.handle(&pad(ButtonState::Down), at(0));
+    rig.turn(0, 10);
+    synced.note(&ControllerEvent::Encoder(0, 10), &rig.engine.encoder_params(), at(0));
+    rig.turn(0, 10);
+    synced.note(&ControllerEvent::Encoder(0, 10), &rig.engine.encoder_params(), at(1000));
+    synced.handle(&pad(ButtonState::Up), at(1000));
+    synced.handle(&pad(ButtonState::Down), at(2000));
+    synced.handle(&pad(ButtonState::Up), at(2000));
+    assert_eq!(synced.tick(at(2250)), vec![(rig.param(LEVEL), 15)]);
+
+    // Following a clock, they're played for the time of its next tick.
+    synced.set_clocked(true);
+    assert!(synced.tick(at(2375)).is_empty());
+    assert_eq!(synced.tick_clocked(at(2375)), vec![(rig.param(LEVEL), 18)]);
+}
+
+#[test]
+fn clocked_writes_go_out_a_lookahead_early() {
+    let config = ClockConfig { port: "TR-8S".to_string(), lookahead_ms: 5, smoothing: 0.1 };
+    let (mut clock, mut queue) = (Clock::new(&config), ClockQueue::new(&config));
+    let start = Instant::now();
+    // 120bpm is a tick every 20.833ms; these arrive a millisecond either side.
+    let at = |tick: u64| {
+        start + Duration::from_micros(tick * 20_833 + if tick.is_multiple_of(2) { 1000 } else { 0 })
+    };
+    clock.handle(&[0xfa], start);
+    for tick in 0..=48 {
+        clock.handle(&[0xf8], at(tick));
+    }
+    assert_eq!(clock.position(), 48);
+    assert!((clock.bpm().unwrap() - 120.0).abs() < 3.0, "{:?}", clock.bpm());
+
+    let mut sent = vec![];
+    queue.schedule(72, vec![0xb0, 1, 2]);
+    queue.schedule(60, vec![0xb0, 1, 1]);
+    queue.flush(&clock, at(48), |msg| sent.push(msg));
+    assert!(sent.is_empty());
+    // Tick 60 is 250ms on, so it goes out 5ms before that.
+    queue.flush(&clock, at(48) + Duration::from_millis(246), |msg| sent.push(msg));
+    assert_eq!(sent, vec![vec![0xb0, 1, 1]]);
+    assert_eq!(queue.len(), 1);
+
+    clock.handle(&[0xfc], at(49));
+    queue.flush(&clock, at(100), |msg| sent.push(msg));
+    assert_eq!(queue.len(), 1, "nothing goes while stopped");
+}
+
+#[tokio::test]
+async fn reads_land_when_decoded_on_a_lane() {
+    let mut rig = Rig::new("jupx", "{ \"bindings\": [] }");
+    let pool = DecodePool::new();
+    rig.synth.decode_in(pool.lane());
+    let expected: std::collections::BTreeMap<String, u32> = serde_json::from_str(
+        &std::fs::read_to_string(fixture("jupx/scene-dump.json")).unwrap()).unwrap();
+
+    rig.recall(&fixture("jupx/scene-dump.syx"));
+    for (name, value) in &expected {
+        assert_eq!(rig.synth_value(name).await, *value, "{} on the synth", name);
+        assert_eq!(rig.value(name), *value, "{} in the store", name);
+    }
+}
+
+/// Notes what it's given.
+struct Recorder(Arc<Mutex<Vec<String>>>);
+
+impl MapatronPlugin for Recorder {
+    fn name(&self) -> &str {
+        "recorder"
+    }
+
+    fn init(&mut self, _ctx: &PluginContext) {
+        self.0.lock().unwrap().push("init".to_string());
+    }
+
+    fn handle_event(&mut self, _ctx: &PluginContext, event: &EngineEvent) {
+        self.0.lock().unwrap().push(format!("{:?}", event));
+    }
+
+    fn tick(&mut self, _ctx: &PluginContext, _now: Instant) {
+        let mut seen = self.0.lock().unwrap();
+        if seen.last().map(String::as_str) != Some("tick") {
+            seen.push("tick".to_string());
+        }
+    }
+}
+
+#[tokio::test]
+async fn registered_plugins_get_events_and_ticks() {
+    let rig = Rig::new("jupx", "{ \"bindings\": [] }");
+    let seen = Arc::new(Mutex::new(vec![]));
+    let mut registry = PluginRegistry::new();
+    registry.register(Box::new(Recorder(seen.clone())));
+    assert_eq!(registry.names(), vec!["recorder"]);
+    let bus = EventBus::new();
+    let ctx = PluginContext { bus: bus.clone(), store: rig.synth.store().clone() };
+
+    let publish = async {
+        tokio::time::delay_for(Duration::from_millis(30)).await;
+        bus.publish(EngineEvent::TempoChanged(120.0));
+        tokio::time::delay_for(Duration::from_millis(30)).await;
+    };
+    tokio::select! {
+        _ = registry.run(ctx, Duration::from_millis(10)) => unreachable!("plugins don't stop on their own"),
+        _ = publish => (),
+    }
+    let seen = seen.lock().unwrap().clone();
+    assert_eq!(seen[..2], ["init".to_string(), "tick".to_string()]);
+    let event = seen.iter().position(|s| s == "TempoChanged(120.0)").expect("no event");
+    assert_eq!(seen.get(event + 1).map(String::as_str), Some("tick"));
+}
@@ -0,0 +1,66 @@
+//! The human value formula language, and maps that use it.
+
+use control::formula::Formula;
+use control::SysexMap;
+
+fn eval(text: &str, raw: u32) -> f64 {
+    Formula::parse(text).unwrap().eval(raw)
+}
+
+#[test]
+fn products_bind_tighter_than_sums() {
+    assert_eq!(eval("1 + 2 * 3", 0), 7.0);
+    assert_eq!(eval("10 - 4 - 3", 0), 3.0);
+    assert_eq!(eval("value * 3 / 2 - 64", 100), 86.0);
+    assert_eq!(eval("value % 12 + 1", 25), 2.0);
+}
+
+#[test]
+fn unary_minus_and_parentheses() {
+    assert_eq!(eval("-value", 5), -5.0);
+    assert_eq!(eval("--value", 5), 5.0);
+    assert_eq!(eval("-(value - 64) * 2", 60), 8.0);
+    assert_eq!(eval("(1 + 2) * (3 + 4)", 0), 21.0);
+    assert_eq!(eval("((value))", 7), 7.0);
+}
+
+#[test]
+fn division_isnt_integer_division() {
+    assert_eq!(eval("value / 4", 10), 2.5);
+    assert_eq!(eval(".5 * value", 3), 1.5);
+}
+
+#[test]
+fn any_whitespace_is_skipped() {
+    // A no-break space and an ideographic space, which are more than a byte.
+    assert_eq!(eval("value\u{a0}+\u{3000}1", 1), 2.0);
+}
+
+#[test]
+fn mistakes_are_errors() {
+    for text in &["", "1 +", "(1 + 2", "1 + 2)", "value value", "velocity * 2", "1..2", "2 $ 3", "é"] {
+        assert!(Formula::parse(text).is_err(), "'{}' parsed", text);
+    }
+    assert_eq!(Formula::parse("x"), Err("unknown name 'x'".to_string()));
+    assert_eq!(Formula::parse("(1"), Err("expected ')' at 2".to_string()));
+}
+
+#[test]
+fn maps_with_bad_formulas_dont_load() {
+    let map = |formula: &str| format!(r#"{{
+        "port_names": [], "ignore_port_names": [], "model_id": [],
+        "type_entries": {{ "ROOT": [] }},
+        "value_entries": {{ "Common": [{{ "name": "Level", "first_offset_start": 0, "last_offset_start": 0,
+            "bitmask": 127, "discrete_range_low": 0, "discrete_range_high": 127,
+            "human_value_formula": "{}" }}] }}
+    }}"#, formula);
+
+    let good: SysexMap = serde_json::from_str(&map("value - 64")).unwrap();
+    let formula = good.value_entries["Common"][0].human_value_formula.as_ref().unwrap();
+    assert_eq!(formula.eval(70), 6.0);
+    // It's saved as it was written.
+    assert!(serde_json::to_string(&good).unwrap().contains(r#""human_value_formula":"value - 64""#));
+
+    let err = serde_json::from_str::<SysexMap>(&map("value -")).err().unwrap().to_string();
+    assert!(err.contains("bad formula 'value -'"), "{}", err);
+}
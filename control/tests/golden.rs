@@ -0,0 +1,61 @@
+//! Golden tests: decode every `.syx` file under `fixtures/<device>/` with that
+//! device's `map.json` and compare against the `.json` file of the same name.
+//!
+//! Run with `UPDATE_GOLDEN=1` to rewrite the expected files from the current
+//! decoder output after an intentional change; review the diff before
+//! committing!
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use control::codec::decode_dump;
+use control::SysexMap;
+
+fn check_device(dir: &Path) -> Vec<String> {
+    let map = SysexMap::load(dir.join("map.json").to_str().unwrap()).unwrap();
+    let index = map.resolve();
+    let update = env::var_os("UPDATE_GOLDEN").is_some();
+
+    let mut failures = vec![];
+    let mut syx_paths: Vec<_> = fs::read_dir(dir).unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.extension().map(|e| e == "syx").unwrap_or(false))
+        .collect();
+    syx_paths.sort();
+    assert!(!syx_paths.is_empty(), "no .syx fixtures in {:?}", dir);
+
+    for syx_path in syx_paths {
+        let bytes = fs::read(&syx_path).unwrap();
+        let decoded = decode_dump(&map, &index, &bytes);
+        let expected_path = syx_path.with_extension("json");
+
+        if update {
+            let json = serde_json::to_string_pretty(&decoded).unwrap();
+            fs::write(&expected_path, json + "\n").unwrap();
+            continue;
+        }
+
+        let expected: BTreeMap<String, u32> = serde_json::from_str(
+            &fs::read_to_string(&expected_path).unwrap()).unwrap();
+        if decoded != expected {
+            failures.push(format!(
+                "{:?}:\n  expected: {:?}\n  decoded:  {:?}", syx_path, expected, decoded));
+        }
+    }
+    failures
+}
+
+#[test]
+fn golden_fixtures() {
+    let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures");
+    let mut failures = vec![];
+    for entry in fs::read_dir(&fixtures).unwrap() {
+        let path = entry.unwrap().path();
+        if path.join("map.json").exists() {
+            failures.extend(check_device(&path));
+        }
+    }
+    assert!(failures.is_empty(), "golden mismatches:\n{}", failures.join("\n"));
+}
@@ -0,0 +1,174 @@
+//! A headless rig for end-to-end tests: the binding engine wired to a synth
+//! simulated from a fixture map, with controller events injected by hand.
+//! Everything the engine sends is both recorded and delivered to the
+//! simulated synth, so tests can assert on the exact sysex, on the LED
+//! buffer, and on what the synth ends up holding.
+
+// Not every test file uses every helper.
+#![allow(dead_code)]
+
+use std::path::Path;
+use std::time::Instant;
+
+use control::bindings::{BindingEngine, BindingEvent, BindingsFile};
+use control::bus::EventBus;
+use control::codec::encode_param_dt1;
+use control::pacing::{Outbox, Priority};
+use control::progress::{Transfer, WRITES_PER_TICK};
+use control::remote::{load_snapshot_command, RemoteCommand};
+use control::scheduler::TICK;
+use control::snapshot::FingerprintPolicy;
+use control::synth::Synth;
+use control::{ButtonState, ControllerCaps, ControllerEvent, LedBuffer};
+
+pub use crate::common::{fixture, fixture_map};
+
+pub struct Rig {
+    pub synth: Synth,
+    pub engine: BindingEngine,
+    pub leds: LedBuffer,
+    /// Everything sent to the synth since the last `take_sent`.
+    sent: Vec<Vec<u8>>,
+}
+
+impl Rig {
+    /// A rig for `device`'s fixture map with the bindings in `bindings`,
+    /// given as json.
+    pub fn new(device: &str, bindings: &str) -> Rig {
+        Rig::with_caps(device, bindings, ControllerCaps::FIRE)
+    }
+
+    /// Like `new`, for a controller with `caps`.
+    pub fn with_caps(device: &str, bindings: &str, caps: ControllerCaps) -> Rig {
+        Rig::try_with_caps(device, bindings, caps).unwrap()
+    }
+
+    /// Like `new`, but with what the engine makes of bad bindings.
+    pub fn try_new(device: &str, bindings: &str) -> Result<Rig, String> {
+        Rig::try_with_caps(device, bindings, ControllerCaps::FIRE)
+    }
+
+    fn try_with_caps(device: &str, bindings: &str, caps: ControllerCaps) -> Result<Rig, String> {
+        let map = fixture_map(device);
+        let file: BindingsFile = serde_json::from_str(bindings).unwrap();
+        let synth = Synth::simulate(map, EventBus::new());
+        let engine = BindingEngine::with_caps(synth.map(), &file, synth.store().clone(), caps)
+            .map_err(|e| e.to_string())?;
+        Ok(Rig {
+            synth,
+            engine,
+            leds: LedBuffer::new(),
+            sent: vec![],
+        })
+    }
+
+    pub fn param(&self, name: &str) -> usize {
+        self.synth.store().index().index_of(name)
+            .unwrap_or_else(|| panic!("no param '{}'", name))
+    }
+
+    /// What the engine thinks `name` is set to.
+    pub fn value(&self, name: &str) -> u32 {
+        self.synth.store().get(self.param(name))
+    }
+
+    /// What the simulated synth actually holds for `name`, read back over
+    /// RQ1.
+    pub async fn synth_value(&mut self, name: &str) -> u32 {
+        self.synth.read_param(name).await.unwrap()
+    }
+
+    /// The DT1 that writes `value` to `name`.
+    pub fn dt1(&self, name: &str, value: u32) -> Vec<u8> {
+        encode_param_dt1(self.synth.map(), &self.synth.store().index().params[self.param(name)], value)
+    }
+
+    pub fn event(&mut self, event: ControllerEvent) {
+        let Rig { synth, engine, sent, .. } = self;
+        engine.handle(&event, |msg| {
+            sent.push(msg.to_vec());
+            synth.send(msg);
+        });
+    }
+
+    pub fn press(&mut self, pad: u8) {
+        self.event(ControllerEvent::GridButton(pad, pad / 16, pad % 16, ButtonState::Down, 0x7f));
+    }
+
+    pub fn release(&mut self, pad: u8) {
+        self.event(ControllerEvent::GridButton(pad, pad / 16, pad % 16, ButtonState::Up, 0));
+    }
+
+    pub fn turn(&mut self, encoder: u8, delta: i8) {
+        self.event(ControllerEvent::Encoder(encoder, delta));
+    }
+
+    pub fn slide(&mut self, fader: u8, position: u8) {
+        self.event(ControllerEvent::Fader(fader, position));
+    }
+
+    /// Run the engine's scheduler as of `now`, returning the gestures that
+    /// ended.
+    pub fn tick(&mut self, now: Instant) -> Vec<BindingEvent> {
+        let Rig { synth, engine, sent, .. } = self;
+        engine.tick(now, |msg| {
+            sent.push(msg.to_vec());
+            synth.send(msg);
+        })
+    }
+
+    /// Recall the `.syx` snapshot at `path` the way the daemon does, a few
+    /// writes per tick as the synth's pacing allows.  Returns how many ticks
+    /// it took.
+    pub fn recall(&mut self, path: &Path) -> usize {
+        let values = match load_snapshot_command(self.synth.map(), self.synth.store(),
+                                                 path.to_str().unwrap(), FingerprintPolicy::Refuse).unwrap() {
+            RemoteCommand::LoadSnapshot(values) => values,
+            command => panic!("not a snapshot: {:?}", command),
+        };
+        let mut transfer = Transfer::new(self.synth.map(), self.synth.store().index(), &values);
+        let mut outbox = Outbox::new(self.synth.pacing());
+        let start = Instant::now();
+        let mut ticks = 0;
+        while !transfer.progress().is_finished() || outbox.len(Priority::Bulk) > 0 {
+            let now = start + TICK * ticks as u32;
+            while outbox.len(Priority::Bulk) < WRITES_PER_TICK {
+                match transfer.next_write() {
+                    Some((param, value)) => {
+                        let index = self.synth.store().index();
+                        let msg = encode_param_dt1(self.synth.map(), &index.params[param], value);
+                        outbox.push_write(Priority::Bulk, &msg, param, value);
+                    },
+                    None => break,
+                }
+            }
+            let Rig { synth, sent, .. } = self;
+            let written = outbox.flush(now, |msg| {
+                sent.push(msg.to_vec());
+                synth.send(msg);
+            });
+            for (param, value) in written {
+                synth.store().set(param, value);
+            }
+            ticks += 1;
+        }
+        ticks
+    }
+
+    pub fn take_sent(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.sent)
+    }
+
+    /// Render the engine's pads into the LED buffer.
+    pub fn render_leds(&mut self) {
+        let Rig { engine, leds, .. } = self;
+        engine.render_pads(|i, r, g, b| leds.set_led(i, r, g, b));
+    }
+
+    /// The (r, g, b) of pad `i` as last rendered.
+    pub fn led(&self, i: u8) -> (u8, u8, u8) {
+        let base = 7 + i as usize * 4;
+        let bytes = self.leds.as_bytes();
+        (bytes[base + 1], bytes[base + 2], bytes[base + 3])
+    }
+}
@@ -0,0 +1,96 @@
+//! Finding a param from what changed while it was turned.
+
+mod common;
+
+use control::codec::{encode_dt1, parse_dt1, split_sysex};
+use control::infer::{append, infer, locate, overlapping, Capture};
+
+use common::fixture_map;
+
+#[test]
+fn a_turned_byte_is_found_with_the_range_seen() {
+    let mut capture = Capture::new();
+    for value in &[40, 20, 90] {
+        // The synth reports its neighbours too, which don't move.
+        capture.record(0x100, &[0x01, *value, 0x7f]);
+        capture.mark();
+    }
+    assert_eq!(capture.changed(), vec![0x101]);
+    let inferred = infer(&capture).unwrap();
+    assert_eq!((inferred.address, inferred.size, inferred.bitmask), (0x101, 1, 0x7f));
+    assert_eq!((inferred.low, inferred.high), (20, 90));
+
+    let entry = inferred.entry("Cutoff", 0x80);
+    assert_eq!((entry.first_offset_start, entry.last_offset_start), (0x0101, 0x0101));
+    assert_eq!((entry.discrete_range_low, entry.discrete_range_high), (20, 90));
+}
+
+#[test]
+fn bytes_that_stay_in_four_bits_are_nibbles() {
+    let mut capture = Capture::new();
+    for bytes in &[[0x00, 0x08], [0x01, 0x00], [0x0f, 0x0f]] {
+        capture.record(0x200, bytes);
+        capture.mark();
+    }
+    let inferred = infer(&capture).unwrap();
+    assert_eq!((inferred.size, inferred.bitmask), (2, 0x0f));
+    assert_eq!((inferred.low, inferred.high), (0x08, 0xff));
+    assert_eq!(inferred.max_value(), 0xff);
+}
+
+#[test]
+fn too_much_or_nothing_changing_is_refused() {
+    let mut capture = Capture::new();
+    capture.record(0x100, &[1, 2, 3, 4, 5]);
+    capture.mark();
+    assert_eq!(infer(&capture).unwrap_err(), "nothing changed");
+    capture.record(0x100, &[2, 3, 4, 5, 6]);
+    capture.mark();
+    assert!(infer(&capture).unwrap_err().contains("more than one param"));
+}
+
+#[test]
+fn appended_entries_land_in_the_block_they_were_found_in() {
+    let mut map = fixture_map("jupx");
+    let index = map.resolve();
+    let existing = &index.params[index.params.len() / 2];
+    let mut capture = Capture::new();
+    for value in &[0, 64] {
+        capture.record(existing.address, &[*value]);
+        capture.mark();
+    }
+    let inferred = infer(&capture).unwrap();
+    assert_eq!(overlapping(&index, &inferred), vec![existing.name.clone()]);
+
+    let (table, base) = locate(&map, inferred.address);
+    append(&mut map, &table, inferred.entry("Wiggled", base));
+    let index = map.resolve();
+    let wiggled: Vec<u32> = index.params.iter()
+        .filter(|p| p.name.ends_with("/Wiggled"))
+        .map(|p| p.address)
+        .collect();
+    assert!(wiggled.contains(&existing.address), "{:x?}", wiggled);
+}
+
+#[test]
+fn two_dumps_give_the_whole_range() {
+    let map = fixture_map("jupx");
+    let dump = |cutoff: u8| {
+        let mut bytes = encode_dt1(&map, 0x100, &[0x01, cutoff, 0x7f]);
+        bytes.extend(encode_dt1(&map, 0x200, &[0x05; 8]));
+        bytes
+    };
+    let mut capture = Capture::new();
+    for bytes in &[dump(40), dump(41)] {
+        for msg in split_sysex(bytes) {
+            let data_set = parse_dt1(&map, msg).unwrap();
+            capture.record(data_set.address, data_set.data);
+        }
+        capture.mark();
+    }
+    let mut inferred = infer(&capture).unwrap();
+    assert_eq!((inferred.address, inferred.low, inferred.high), (0x101, 40, 41));
+    // Two values say little about the ends.
+    inferred.widen();
+    assert_eq!((inferred.low, inferred.high), (0, 0x7f));
+}
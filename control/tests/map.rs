@@ -0,0 +1,32 @@
+//! Maps with mistakes in them.
+
+mod common;
+
+use control::SysexMap;
+
+use common::fixture_map;
+
+#[test]
+fn backwards_offsets_are_reported_and_left_out() {
+    let mut map = fixture_map("jupx");
+    assert!(map.validate().is_empty());
+    let named = |map: &SysexMap| {
+        map.resolve().params.iter().filter(|p| p.name.ends_with("/Scene Name 1")).count()
+    };
+    assert_eq!(named(&map), 1);
+
+    let entry = &mut map.value_entries.get_mut("SceneCommon").unwrap()[0];
+    assert_eq!(entry.name, "Scene Name 1");
+    entry.first_offset_start = 0x01;
+    assert_eq!(map.validate(), vec!["SceneCommon/Scene Name 1: last offset before first".to_string()]);
+    assert_eq!(named(&map), 0);
+}
+
+#[test]
+fn backwards_blocks_are_reported_and_left_out() {
+    let mut map = fixture_map("jupx");
+    let entry = &mut map.type_entries.get_mut("ROOT").unwrap()[0];
+    entry.first_offset_start += 1;
+    assert_eq!(map.validate(), vec!["ROOT/Temporary Scene: last offset before first".to_string()]);
+    assert!(map.resolve().params.is_empty());
+}
@@ -0,0 +1,76 @@
+//! What routes do to the messages passing through them.
+
+use control::router::{Route, RouteProcessor, ZoneAdjust, Zones};
+
+fn processor(route: &str, zones: &mut Zones) -> RouteProcessor {
+    let route: Route = serde_json::from_str(route).unwrap();
+    RouteProcessor::new(&route, zones)
+}
+
+/// What `processor` sends for each of `msgs`, if anything.
+fn run(processor: &mut RouteProcessor, msgs: &[&[u8]]) -> Vec<Option<Vec<u8>>> {
+    let mut out = vec![];
+    msgs.iter().map(|msg| processor.process(msg, &mut out).then(|| out.clone())).collect()
+}
+
+#[test]
+fn transforms_apply_in_order() {
+    let mut zones = Zones::new();
+    let mut route = processor(r#"{ "from": "KeyStep", "to": "JUPITER-X", "transforms": [
+        { "remap_channel": { "from": 1, "to": 4 } },
+        { "map_cc": { "from": 1, "to": 74 } },
+        { "scale_velocity": { "percent": 50 } }
+    ] }"#, &mut zones);
+    assert_eq!(run(&mut route, &[&[0xb0, 1, 64], &[0xb1, 1, 64], &[0x90, 60, 100], &[0x90, 60, 1],
+                                 &[0x90, 60, 0], &[0xf8]]),
+               vec![Some(vec![0xb3, 74, 64]), Some(vec![0xb1, 74, 64]), Some(vec![0x93, 60, 50]),
+                    // Still a note-on, and note-offs stay note-offs.
+                    Some(vec![0x93, 60, 1]), Some(vec![0x93, 60, 0]), Some(vec![0xf8])]);
+}
+
+#[test]
+fn filters_drop_before_transforms() {
+    let mut zones = Zones::new();
+    let mut route = processor(r#"{ "from": "KeyStep", "to": "JUPITER-X",
+        "filter": { "drop_clock": true, "block_sysex": true },
+        "transforms": [ { "remap_channel": { "from": 1, "to": 2 } } ] }"#, &mut zones);
+    assert_eq!(run(&mut route, &[&[0xf8], &[0xf0, 0x41, 0xf7], &[0x80, 60, 0]]),
+               vec![None, None, Some(vec![0x81, 60, 0])]);
+}
+
+#[test]
+fn zones_split_and_transpose() {
+    let mut zones = Zones::new();
+    let zone = r#""zone": { "name": "lower", "high": 59, "transpose": 12, "channel": 2 }"#;
+    let mut lower = processor(&format!(r#"{{ "from": "KeyStep", "to": "JUPITER-X", {} }}"#, zone),
+                              &mut zones);
+    let mut upper = processor(r#"{ "from": "KeyStep", "to": "JD-Xi",
+        "zone": { "name": "upper", "low": 60 } }"#, &mut zones);
+    let msgs: &[&[u8]] = &[&[0x90, 48, 100], &[0x90, 72, 100], &[0xb0, 7, 100]];
+    assert_eq!(run(&mut lower, msgs), vec![Some(vec![0x91, 60, 100]), None, Some(vec![0xb1, 7, 100])]);
+    assert_eq!(run(&mut upper, msgs), vec![None, Some(vec![0x90, 72, 100]), Some(vec![0xb0, 7, 100])]);
+
+    // Routes with the same zone name share its settings.
+    let mut again = processor(&format!(r#"{{ "from": "KeyStep", "to": "JD-Xi", {} }}"#, zone), &mut zones);
+    zones["lower"].adjust(ZoneAdjust::Transpose, -12);
+    assert_eq!(run(&mut again, &[&[0x90, 50, 100]]), vec![Some(vec![0x91, 50, 100])]);
+    zones["lower"].adjust(ZoneAdjust::High, -20);
+    assert_eq!(run(&mut again, &[&[0x90, 50, 100]]), vec![None]);
+}
+
+#[test]
+fn note_offs_follow_their_note_on_after_the_zone_changes() {
+    let mut zones = Zones::new();
+    let mut route = processor(r#"{ "from": "KeyStep", "to": "JUPITER-X",
+        "zone": { "name": "lower", "high": 59, "transpose": 12 } }"#, &mut zones);
+    assert_eq!(run(&mut route, &[&[0x90, 48, 100]]), vec![Some(vec![0x90, 60, 100])]);
+    zones["lower"].adjust(ZoneAdjust::Transpose, 12);
+    zones["lower"].adjust(ZoneAdjust::High, -24);
+    // Aftertouch and the note-off go to the key that's sounding, even though
+    // 48 is now out of the zone; after that, it's dropped.
+    assert_eq!(run(&mut route, &[&[0xa0, 48, 30], &[0x80, 48, 0], &[0x80, 48, 0]]),
+               vec![Some(vec![0xa0, 60, 30]), Some(vec![0x80, 60, 0]), None]);
+    // A note-on of velocity 0 is a note-off too.
+    assert_eq!(run(&mut route, &[&[0x90, 30, 100], &[0x90, 30, 0]]),
+               vec![Some(vec![0x90, 54, 100]), Some(vec![0x90, 54, 0])]);
+}
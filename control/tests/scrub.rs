@@ -0,0 +1,38 @@
+//! Scrubbing identifying params out of dumps.
+
+mod common;
+
+use control::codec::{encode_dt1, parse_dt1, split_sysex};
+use control::scrub::scrub;
+
+use common::fixture_map;
+
+#[test]
+fn names_are_blanked_and_checksums_still_match() {
+    let map = fixture_map("jupx");
+    let index = map.resolve();
+    let address = |name: &str| {
+        index.params.iter().find(|p| p.name.ends_with(name)).map(|p| p.address).unwrap()
+    };
+    let name = address("/Scene Name 1");
+    assert_eq!(address("/Scene Name 4"), name + 3);
+    let level = address("/Scene Level");
+
+    let named = encode_dt1(&map, name, b"Mine");
+    let untouched = encode_dt1(&map, level, &[100]);
+    let mut dump = named.clone();
+    // Stray bytes between messages go.
+    dump.push(0x00);
+    dump.extend_from_slice(&untouched);
+
+    let (scrubbed, count) = scrub(&map, &index, &dump);
+    assert_eq!(count, 4);
+    let msgs = split_sysex(&scrubbed);
+    assert_eq!(msgs.len(), 2);
+    assert_eq!(msgs[0].len(), named.len());
+    // The map rejects bad checksums, so this only parses if it was redone.
+    let data_set = parse_dt1(&map, msgs[0]).unwrap();
+    assert_eq!((data_set.address, data_set.data), (name, &b"    "[..]));
+    assert_eq!(msgs[1], &untouched[..]);
+    assert_eq!(scrubbed.len(), dump.len() - 1);
+}
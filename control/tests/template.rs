@@ -0,0 +1,72 @@
+//! Message framing from the map's templates.
+
+mod common;
+
+use control::codec::{encode_dt1, encode_rq1, parse_dt1, Checksum};
+use control::template::{MessageTemplates, Template};
+use control::SysexMap;
+
+use common::fixture_map;
+
+/// The Jupiter-X map with its framing written out as templates instead.
+fn templated(write: &str, read: &str) -> SysexMap {
+    let mut map = fixture_map("jupx");
+    map.message_templates = Some(serde_json::from_value(serde_json::json!({
+        "write": write,
+        "read": read,
+    })).unwrap());
+    map
+}
+
+#[test]
+fn maps_without_templates_get_roland_framing() {
+    let map = fixture_map("jupx");
+    let mut expected = vec![0xf0, 0x41, map.device_id];
+    expected.extend_from_slice(&map.model_id);
+    // 0x100 packs to 00 00 02 00.
+    expected.extend_from_slice(&[0x12, 0x00, 0x00, 0x02, 0x00, 0x12, 0x34]);
+    expected.push(map.checksum.compute(&[0x00, 0x00, 0x02, 0x00, 0x12, 0x34]).unwrap());
+    expected.push(0xf7);
+    assert_eq!(encode_dt1(&map, 0x100, &[0x12, 0x34]), expected);
+
+    let read = String::from(Template::read_for(&map));
+    assert!(read.starts_with("F0 41 {device_id} "), "{}", read);
+    assert!(read.ends_with(" 11 {address} {size} {checksum} F7"), "{}", read);
+}
+
+#[test]
+fn templates_frame_writes_and_reads() {
+    let mut map = templated("F0 43 {device_id} 7F 1C {address} {data} {checksum} F7",
+                            "F0 43 {device_id} 7F 1D {address} {size} F7");
+    map.checksum = Checksum::Kawai;
+    let dev = map.device_id;
+    let msg = encode_dt1(&map, 0x100, &[0x10, 0x20]);
+    assert_eq!(msg, vec![0xf0, 0x43, dev, 0x7f, 0x1c, 0x00, 0x00, 0x02, 0x00, 0x10, 0x20, 0x50, 0xf7]);
+    let set = parse_dt1(&map, &msg).unwrap();
+    assert_eq!((set.address, set.data), (0x100, &[0x10, 0x20][..]));
+    assert_eq!(encode_rq1(&map, 0x100, 0x80),
+               vec![0xf0, 0x43, dev, 0x7f, 0x1d, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x00, 0xf7]);
+
+    // Another device's, or another kind of message, doesn't match.
+    let mut other = msg.clone();
+    other[2] ^= 0x01;
+    assert!(parse_dt1(&map, &other).is_none());
+    other = msg;
+    other[4] = 0x1d;
+    assert!(parse_dt1(&map, &other).is_none());
+}
+
+#[test]
+fn templates_round_trip_through_the_map_file() {
+    let text = "F0 43 {device_id} 7F 1C {address} {data} {checksum} F7";
+    let templates: MessageTemplates = serde_json::from_value(serde_json::json!({
+        "write": text,
+        "read": "f0 43 {device_id} 7f 1d {address} {size} f7",
+    })).unwrap();
+    assert_eq!(String::from(templates.write), text);
+    // Hex is written back out in capitals.
+    assert_eq!(String::from(templates.read), "F0 43 {device_id} 7F 1D {address} {size} F7");
+
+    let bad = serde_json::from_str::<Template>(r#""F0 43 {device} F7""#).unwrap_err();
+    assert!(bad.to_string().contains("bad template token '{device}'"), "{}", bad);
+}
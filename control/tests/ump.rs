@@ -0,0 +1,93 @@
+//! Sysex framed into UMP packets and reassembled again.
+
+use control::ump::{sysex7_packets, sysex8_packets, SysexAssembler};
+
+/// A DT1 long enough to take several packets of either kind.
+const LONG: &[u8] = &[0xf0, 0x41, 0x10, 0x00, 0x00, 0x00, 0x65, 0x12, 0x18, 0x00, 0x00, 0x00,
+                      0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0a, 0x0b, 0x0c,
+                      0x0d, 0x0e, 0x0f, 0x10, 0x11, 0x12, 0x13, 0x14, 0x2b, 0xf7];
+
+fn reassemble<P: AsRef<[u32]>>(packets: &[P]) -> Vec<Option<Vec<u8>>> {
+    let mut assembler = SysexAssembler::new();
+    packets.iter().map(|packet| assembler.push(packet.as_ref())).collect()
+}
+
+#[test]
+fn short_sysex_fits_in_one_packet() {
+    let msg = [0xf0, 0x7e, 0x7f, 0x06, 0x01, 0xf7];
+    let packets = sysex7_packets(0, &msg);
+    assert_eq!(packets.len(), 1);
+    // Complete, four bytes.
+    assert_eq!(packets[0][0] >> 16, 0x3004);
+    assert_eq!(reassemble(&packets), vec![Some(msg.to_vec())]);
+
+    let packets = sysex8_packets(0, 0, &msg);
+    assert_eq!(packets.len(), 1);
+    assert_eq!(packets[0][0] >> 16, 0x5005);
+    assert_eq!(reassemble(&packets), vec![Some(msg.to_vec())]);
+}
+
+#[test]
+fn long_sysex7_goes_start_continue_end() {
+    let packets = sysex7_packets(3, LONG);
+    let statuses: Vec<u32> = packets.iter().map(|p| p[0] >> 20 & 0xf).collect();
+    assert_eq!(statuses, vec![1, 2, 2, 2, 2, 3]);
+    assert!(packets.iter().all(|p| p[0] >> 24 == 0x33));
+
+    let mut out = reassemble(&packets);
+    assert_eq!(out.pop().unwrap(), Some(LONG.to_vec()));
+    assert!(out.iter().all(Option::is_none));
+}
+
+#[test]
+fn long_sysex8_goes_start_continue_end() {
+    let packets = sysex8_packets(0, 9, LONG);
+    let statuses: Vec<u32> = packets.iter().map(|p| p[0] >> 20 & 0xf).collect();
+    assert_eq!(statuses, vec![1, 2, 3]);
+    // The stream ID rides along in every packet.
+    assert!(packets.iter().all(|p| p[0] >> 8 & 0xff == 9));
+
+    let mut out = reassemble(&packets);
+    assert_eq!(out.pop().unwrap(), Some(LONG.to_vec()));
+    assert!(out.iter().all(Option::is_none));
+}
+
+#[test]
+fn interleaved_groups_reassemble_separately() {
+    let other = [0xf0, 0x43, 0x10, 0x4c, 0x00, 0x00, 0x7e, 0x40, 0x01, 0x02, 0xf7];
+    let (a, b) = (sysex7_packets(0, LONG), sysex7_packets(1, &other));
+    let mut assembler = SysexAssembler::new();
+    let mut done = vec![];
+    for i in 0..a.len().max(b.len()) {
+        for packets in &[&a, &b] {
+            if let Some(msg) = packets.get(i).and_then(|p| assembler.push(p)) {
+                done.push(msg);
+            }
+        }
+    }
+    assert_eq!(done, vec![other.to_vec(), LONG.to_vec()]);
+}
+
+#[test]
+fn truncated_packets_are_ignored() {
+    let mut assembler = SysexAssembler::new();
+    assert_eq!(assembler.push(&[]), None);
+    assert_eq!(assembler.push(&[0x3004_7e7f]), None);
+
+    // A sysex8 packet cut down to sysex7's length.
+    let packet = sysex8_packets(0, 0, &[0xf0, 0x01, 0xf7])[0];
+    assert_eq!(assembler.push(&packet[..2]), None);
+    assert_eq!(assembler.push(&packet), Some(vec![0xf0, 0x01, 0xf7]));
+
+    // An end with no start.
+    let packets = sysex7_packets(0, LONG);
+    assert_eq!(assembler.push(packets.last().unwrap()), None);
+}
+
+#[test]
+fn oversized_sysex_is_dropped() {
+    let mut assembler = SysexAssembler::new();
+    assembler.set_limit(16);
+    let packets = sysex7_packets(0, LONG);
+    assert!(packets.iter().all(|p| assembler.push(p).is_none()));
+}